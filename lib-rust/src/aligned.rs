@@ -0,0 +1,225 @@
+// Hands frames to GPU libraries (e.g. TensorRT, or texture uploads) in the
+// row-aligned layout they want, so they don't each have to repack them.
+
+use crate::error::{Error, Result};
+use crate::pipeline::PixelFormat;
+use crate::trigger::{Frame, Trigger};
+use image::GenericImageView;
+use std::sync::{Arc, Mutex};
+
+pub type AlignedTriggerCallback = Arc<dyn Fn(&Frame, &AlignedFrame) + Send + Sync>;
+
+/// The pixels of a frame, with each row padded to a multiple of some
+/// alignment, and the first row starting at an address with that alignment.
+/// Padding bytes are always zero.
+#[derive(Debug, Clone, Default)]
+pub struct AlignedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub bytes_per_pixel: u32,
+
+    /// Distance between the start of one row and the next, in bytes. A
+    /// multiple of `alignment`, and at least `width * bytes_per_pixel`.
+    pub stride: usize,
+
+    /// Alignment of the rows (and the buffer itself), in bytes.
+    pub alignment: usize,
+
+    /// Over-allocated by up to `alignment` bytes, so the buffer can start at
+    /// an aligned address
+    data: Vec<u8>,
+
+    /// Where the aligned buffer starts in `data`
+    offset: usize,
+}
+
+impl AlignedFrame {
+    /// The whole buffer, `stride * height` bytes, including the padding.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[self.offset..self.offset + self.stride * self.height as usize]
+    }
+
+    /// The pixels of a single row, without the padding.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride;
+        let row_bytes = self.width as usize * self.bytes_per_pixel as usize;
+        &self.as_bytes()[start..start + row_bytes]
+    }
+
+    /// Copy a `(x, y, width, height)` region of a packed image into this
+    /// buffer, reusing its allocation when it's big enough.
+    fn fill(
+        &mut self,
+        bytes: &[u8],
+        image_width: u32,
+        bytes_per_pixel: u32,
+        (x, y, width, height): (u32, u32, u32, u32),
+        alignment: usize,
+    ) {
+        let row_bytes = width as usize * bytes_per_pixel as usize;
+        let stride = row_bytes.div_ceil(alignment) * alignment;
+        let len = stride * height as usize;
+        self.data.resize(len + alignment, 0);
+        self.offset = self.data.as_ptr().align_offset(alignment);
+        self.width = width;
+        self.height = height;
+        self.bytes_per_pixel = bytes_per_pixel;
+        self.stride = stride;
+        self.alignment = alignment;
+
+        let source_stride = image_width as usize * bytes_per_pixel as usize;
+        let source_x = x as usize * bytes_per_pixel as usize;
+        let buffer = &mut self.data[self.offset..self.offset + len];
+        for (row, destination) in buffer.chunks_exact_mut(stride).enumerate() {
+            let start = (y as usize + row) * source_stride + source_x;
+            destination[..row_bytes].copy_from_slice(&bytes[start..start + row_bytes]);
+            destination[row_bytes..].fill(0);
+        }
+    }
+}
+
+/// A Trigger that copies each frame into a buffer with row-aligned strides
+/// (see `AlignedFrame`), and passes it to a callback along with the frame,
+/// e.g. to upload it to a texture or a TensorRT input without repacking it
+/// first. The buffer is reused from one frame to the next.
+///
+/// ffmpeg's `pad` filter can only pad rows by whole pixels, which can't reach
+/// most alignments for 3 byte pixels (like `Rgb24`), so this copies instead.
+pub struct AlignedTrigger {
+    pub callback: AlignedTriggerCallback,
+
+    /// Alignment of each row, in bytes. Must be a power of two.
+    pub alignment: usize,
+
+    /// Optionally only copy this region of each frame, as
+    /// `(x, y, width, height)` in pixels.
+    pub region: Option<(u32, u32, u32, u32)>,
+
+    /// The pixel format to receive frames in, if not the pipeline's own.
+    pub pixel_format: Option<PixelFormat>,
+
+    /// The buffer the frames are copied into
+    pub buffer: Mutex<AlignedFrame>,
+}
+
+impl Trigger for AlignedTrigger {
+    fn on_frame(&self, frame: &Frame) -> Result<()> {
+        let (width, height) = frame.image.dimensions();
+        let region = self.region.unwrap_or((0, 0, width, height));
+        let (x, y, region_width, region_height) = region;
+        if x + region_width > width || y + region_height > height {
+            return Err(Error::from_display(format!(
+                "the region {:?} doesn't fit in the {}x{} frame",
+                region, width, height
+            )));
+        }
+        let bytes_per_pixel = frame.image.color().bytes_per_pixel() as u32;
+        let mut buffer = self.buffer.lock()?;
+        buffer.fill(
+            frame.image.as_bytes(),
+            width,
+            bytes_per_pixel,
+            region,
+            self.alignment,
+        );
+        (self.callback)(frame, &buffer);
+        Ok(())
+    }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.pixel_format
+    }
+
+    fn get_region(&self, _width: u32, _height: u32) -> Option<(u32, u32, u32, u32)> {
+        self.region
+            .map(|(x, y, width, height)| (x, y, x + width, y + height))
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.alignment.is_power_of_two() {
+            true => Ok(()),
+            false => Err(Error::from_display(format!(
+                "the row alignment must be a power of two, not {}",
+                self.alignment
+            ))),
+        }
+    }
+}
+
+impl AlignedTrigger {
+    pub fn new<F>(alignment: usize, callback: F) -> Self
+    where
+        F: Fn(&Frame, &AlignedFrame) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+            alignment,
+            region: None,
+            pixel_format: None,
+            buffer: Mutex::new(AlignedFrame::default()),
+        }
+    }
+
+    /// Setter for the region of each frame to copy
+    pub fn set_region(mut self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.region = Some((x, y, width, height));
+        self
+    }
+
+    /// Setter for the pixel format to receive frames in
+    pub fn set_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = Some(pixel_format);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AlignedTrigger;
+    use crate::{
+        error::Result,
+        trigger::{Frame, Trigger},
+    };
+    use image::{DynamicImage, RgbImage};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn aligned_trigger() -> Result<()> {
+        let image = RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8, y as u8, 9]));
+        let frame = Frame {
+            image: DynamicImage::ImageRgb8(image),
+            frame_num: 0,
+            timestamp: 0.0,
+            input_index: 0,
+        };
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let trigger = AlignedTrigger::new(16, move |_, aligned| {
+            assert_eq!(aligned.as_bytes().as_ptr() as usize % 16, 0);
+            received_clone.lock().unwrap().push((
+                aligned.stride,
+                aligned.as_bytes().len(),
+                aligned.row(1).to_vec(),
+                aligned.as_bytes()[15],
+            ));
+        });
+        trigger.validate()?;
+        trigger.on_frame(&frame)?;
+        let trigger = trigger.set_region(3, 1, 2, 2);
+        trigger.on_frame(&frame)?;
+
+        // 15 bytes per row are padded to 16, then 6 to 16
+        let received = received.lock()?;
+        assert_eq!(received[0].0, 16);
+        assert_eq!(received[0].1, 48);
+        assert_eq!(received[0].2[..6], [0, 1, 9, 1, 1, 9]);
+        assert_eq!(received[0].3, 0);
+        assert_eq!(received[1].0, 16);
+        assert_eq!(received[1].2, [3, 2, 9, 4, 2, 9]);
+
+        assert!(AlignedTrigger::new(24, |_, _| {}).validate().is_err());
+        let outside = AlignedTrigger::new(16, |_, _| {}).set_region(4, 0, 2, 1);
+        assert!(outside.on_frame(&frame).is_err());
+        Ok(())
+    }
+}
@@ -1,12 +1,32 @@
+use crate::audio::AudioChunk;
+use crate::buffer_pool::BufferPool;
+use crate::color_filter::ColorFilter;
 use crate::error::{Error, Result};
-use crate::trigger::{Frame, Trigger};
+use crate::log::{self, current_session_id};
+#[cfg(feature = "photon")]
+use crate::photon::CropRegion;
+use crate::pipeline::{current_event_sink, enter_event_sink, EventSink, PixelFormat, Transpose};
+use crate::trigger::{current_trigger_id, enter_trigger, Frame, Trigger};
+use crate::trigger_config::TriggerConfig;
 use std::{
-    sync::{mpsc::SyncSender, Arc},
+    any::Any,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
+pub type TriggerThreadErrorCallback = Arc<dyn Fn(&Error) + Send + Sync>;
+
 /// A wrapper around any other Trigger that sends it across a channel to run on
-/// a separate thread.
+/// a separate thread. Each frame is copied to send it, since the pipeline
+/// reuses its buffer for the next frame straight away; the copies are made
+/// into buffers from the `TriggerThread`'s `pool`. By the time the wrapped
+/// Trigger runs, the pipeline has moved on, so it can't stop the pipeline with
+/// `on_frame_control`. Events it reports with `on_frame_events` are passed on
+/// to `Hypetrigger::on_trigger_event` from the worker, as they're found.
 #[derive(Clone)]
 pub struct AsyncTrigger {
     pub trigger: Arc<dyn Trigger>,
@@ -15,13 +35,97 @@ pub struct AsyncTrigger {
 
 impl Trigger for AsyncTrigger {
     fn on_frame(&self, frame: &Frame) -> Result<()> {
-        self.runner_thread
-            .tx
-            .send(TriggerCommand::Packet(TriggerPacket {
-                frame: frame.clone(),
-                trigger: self.trigger.clone(),
-            }))
-            .map_err(Error::from_std)
+        let command = TriggerCommand::Packet(TriggerPacket {
+            frame: self.runner_thread.pool.copy_frame(frame),
+            trigger: self.trigger.clone(),
+            trigger_id: current_trigger_id(),
+            session_id: current_session_id(),
+            events: current_event_sink(),
+        });
+        let queue = &self.runner_thread.queue;
+        let skipped = match self.runner_thread.policy {
+            BackpressurePolicy::Block => queue.send(command).map(|_| None)?,
+            BackpressurePolicy::DropNewest => queue.try_send(command)?,
+            BackpressurePolicy::DropOldest => queue.send_evicting(command)?,
+        };
+        // Either this frame or the oldest queued one was skipped, so the
+        // queue is no longer than before
+        if let Some(TriggerCommand::Packet(packet)) = skipped {
+            self.runner_thread.pool.recycle(packet.frame);
+            return Ok(());
+        }
+        metric!(gauge!("hypetrigger_trigger_thread_queue_depth").increment(1));
+        Ok(())
+    }
+
+    fn get_fps(&self) -> Option<f64> {
+        self.trigger.get_fps()
+    }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.trigger.get_pixel_format()
+    }
+
+    fn get_color_filter(&self) -> Option<ColorFilter> {
+        self.trigger.get_color_filter()
+    }
+
+    fn get_invert(&self) -> bool {
+        self.trigger.get_invert()
+    }
+
+    fn get_debounce(&self) -> Option<Duration> {
+        self.trigger.get_debounce()
+    }
+
+    fn get_scale(&self) -> Option<(u32, u32)> {
+        self.trigger.get_scale()
+    }
+
+    fn get_transpose(&self) -> Option<Transpose> {
+        self.trigger.get_transpose()
+    }
+
+    fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        self.trigger.get_region(width, height)
+    }
+
+    fn get_enabled(&self) -> bool {
+        self.trigger.get_enabled()
+    }
+
+    fn get_output_dirs(&self) -> Vec<PathBuf> {
+        self.trigger.get_output_dirs()
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.trigger.validate()
+    }
+
+    fn is_audio(&self) -> bool {
+        self.trigger.is_audio()
+    }
+
+    /// Audio is already read on a thread of its own, so it's passed straight
+    /// through rather than being queued.
+    fn on_audio(&self, chunk: &AudioChunk) -> Result<()> {
+        self.trigger.on_audio(chunk)
+    }
+
+    /// Saved as the Trigger it wraps, which runs on the pipeline's own thread
+    /// once it's loaded again.
+    fn to_config(&self) -> Option<TriggerConfig> {
+        self.trigger.to_config()
+    }
+
+    /// The copy runs on the same thread as this one.
+    #[cfg(feature = "photon")]
+    fn with_crop(&self, crop: CropRegion) -> Option<Arc<dyn Trigger>> {
+        let trigger = self.trigger.with_crop(crop)?;
+        Some(Arc::new(AsyncTrigger {
+            trigger,
+            runner_thread: self.runner_thread.clone(),
+        }))
     }
 }
 
@@ -39,9 +143,46 @@ impl AsyncTrigger {
 
 /// A separate thread that runs one or more `AsyncTriggers`, by receiving them
 /// over a channel, paired with the frame to process.
+///
+/// It can also be a pool of several worker threads draining the same queue
+/// (see `spawn_pool`), so that e.g. many Triggers doing expensive inference
+/// aren't all held up behind one another.
 pub struct TriggerThread {
-    pub tx: SyncSender<TriggerCommand>,
-    pub join_handle: JoinHandle<()>,
+    pub queue: Arc<TriggerQueue>,
+    pub join_handles: Vec<JoinHandle<()>>,
+    pub policy: BackpressurePolicy,
+
+    /// Number of commands that can wait in the queue, not counting the ones
+    /// the workers are running. See `spawn_with`.
+    pub capacity: usize,
+
+    /// Callback when one of the Triggers returns an error or panics. The
+    /// workers carry on with the next frame either way. See `on_error`.
+    pub on_error_callback: Arc<Mutex<Option<TriggerThreadErrorCallback>>>,
+
+    /// Buffers for the copies of the frames that are queued or running, which
+    /// the workers put back once they're done with them. It keeps enough for
+    /// a full queue, so frames aren't allocated once it's warmed up.
+    pub pool: Arc<BufferPool>,
+}
+
+/// What to do when Triggers are sent to a `TriggerThread` faster than it can
+/// run them, and its queue fills up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackpressurePolicy {
+    /// Block until there's room in the queue. This holds up the rest of the
+    /// pipeline, which in turn pauses ffmpeg until the thread catches up, so
+    /// every frame is processed.
+    #[default]
+    Block,
+
+    /// Skip the frame that doesn't fit in the queue.
+    DropNewest,
+
+    /// Make room for the new frame by skipping the oldest one in the queue,
+    /// and skip any queued frames for a Trigger when a newer one is
+    /// available, so the thread always works on the most recent frame.
+    DropOldest,
 }
 
 impl TriggerThread {
@@ -49,27 +190,314 @@ impl TriggerThread {
     /// communication channels, spawning the thread itself, and wrapping the
     /// whole struct in an `Arc`.
     pub fn spawn() -> Arc<Self> {
-        let (tx, rx) = std::sync::mpsc::sync_channel::<TriggerCommand>(100);
-        let join_handle = thread::spawn(move || {
-            println!("[trigger_thread] Listening for async trigger commands.");
-            while let Ok(command) = rx.recv() {
-                match command {
-                    TriggerCommand::Stop => {
-                        println!("[trigger_thread] Received stop command.");
-                        break;
+        Self::spawn_with(DEFAULT_QUEUE_CAPACITY, BackpressurePolicy::default())
+    }
+
+    /// Like `spawn()`, but with a custom queue size, and what to do when the
+    /// queue is full.
+    ///
+    /// The queue is always bounded, so memory use stays predictable under
+    /// load: at most `capacity` frames are waiting, plus one being run by each
+    /// worker. With a `capacity` of 0 there's no queue at all, and each frame
+    /// is handed straight to a free worker; with `BackpressurePolicy::Block`
+    /// the pipeline waits until one is free, and with `DropNewest` the frame
+    /// is skipped unless one is already waiting. With a `capacity` of N, up to
+    /// N frames are buffered before the policy applies.
+    ///
+    /// Only `Block` ever holds up the pipeline. With `DropOldest`, a full
+    /// queue skips its oldest frame to make room for the new one; with a
+    /// `capacity` of 0 there's nothing queued to skip, so it skips the new
+    /// frame like `DropNewest` does.
+    pub fn spawn_with(capacity: usize, policy: BackpressurePolicy) -> Arc<Self> {
+        Self::spawn_pool(1, capacity, policy)
+    }
+
+    /// Like `spawn_with()`, but with `workers` threads taking commands from the
+    /// same queue, whichever is free first. Frames can finish out of order,
+    /// and the same Trigger can run on several frames at once. Each worker
+    /// exits on its own `TriggerCommand::Stop`.
+    pub fn spawn_pool(workers: usize, capacity: usize, policy: BackpressurePolicy) -> Arc<Self> {
+        let workers = workers.max(1);
+        let queue = Arc::new(TriggerQueue::new(capacity, workers));
+        let on_error_callback = Arc::new(Mutex::new(None));
+        // One for each queued frame, each running one, and the one being sent
+        let pool = Arc::new(BufferPool::new(capacity + workers + 1));
+        let join_handles = (0..workers)
+            .map(|worker| {
+                let queue = queue.clone();
+                let on_error_callback = on_error_callback.clone();
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    run_worker(worker, &queue, policy, &on_error_callback, &pool);
+                    queue.worker_exited();
+                })
+            })
+            .collect();
+        Arc::new(Self {
+            queue,
+            join_handles,
+            policy,
+            capacity,
+            on_error_callback,
+            pool,
+        })
+    }
+
+    /// Call the given function when one of the Triggers returns an error or
+    /// panics. By then the pipeline has moved on, so errors can't stop it
+    /// (or go to its `on_trigger_error`); they're logged either way.
+    pub fn on_error<F>(&self, callback: F)
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        if let Ok(mut on_error_callback) = self.on_error_callback.lock() {
+            *on_error_callback = Some(Arc::new(callback));
+        }
+    }
+}
+
+/// The queue of commands for the workers of a `TriggerThread`. It's bounded,
+/// with room for `capacity` commands, plus one for each worker that's waiting
+/// for a command (which takes it straight away). See
+/// `TriggerThread::spawn_with`.
+pub struct TriggerQueue {
+    state: Mutex<QueueState>,
+
+    /// Signalled when a command is queued
+    available: Condvar,
+
+    /// Signalled when there's more room in the queue, or a worker exits
+    room: Condvar,
+
+    capacity: usize,
+}
+
+struct QueueState {
+    commands: VecDeque<TriggerCommand>,
+
+    /// Workers waiting for a command
+    idle_workers: usize,
+
+    /// Workers that haven't exited yet
+    running_workers: usize,
+}
+
+impl TriggerQueue {
+    fn new(capacity: usize, workers: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                commands: VecDeque::with_capacity(capacity),
+                idle_workers: 0,
+                running_workers: workers,
+            }),
+            available: Condvar::new(),
+            room: Condvar::new(),
+            capacity,
+        }
+    }
+
+    fn has_room(&self, state: &QueueState) -> bool {
+        state.commands.len() < self.capacity + state.idle_workers
+    }
+
+    /// Queue a command, waiting until there's room for it. A `Stop` is always
+    /// queued straight away. Fails once all the workers have exited.
+    pub fn send(&self, command: TriggerCommand) -> Result<()> {
+        let mut state = self.state.lock()?;
+        while !matches!(command, TriggerCommand::Stop)
+            && !self.has_room(&state)
+            && state.running_workers > 0
+        {
+            state = self.room.wait(state)?;
+        }
+        self.push(&mut state, command)
+    }
+
+    /// Queue a command if there's room for it, or else give it back.
+    pub fn try_send(&self, command: TriggerCommand) -> Result<Option<TriggerCommand>> {
+        let mut state = self.state.lock()?;
+        if !self.has_room(&state) {
+            return Ok(Some(command));
+        }
+        self.push(&mut state, command).map(|_| None)
+    }
+
+    /// Queue a command, making room for it if needed by taking out the oldest
+    /// queued packet, which is given back. If there's no packet to take out,
+    /// the command itself is given back instead.
+    pub fn send_evicting(&self, command: TriggerCommand) -> Result<Option<TriggerCommand>> {
+        let mut state = self.state.lock()?;
+        if self.has_room(&state) {
+            return self.push(&mut state, command).map(|_| None);
+        }
+        let oldest_packet = state
+            .commands
+            .iter()
+            .position(|command| matches!(command, TriggerCommand::Packet(_)));
+        let Some(evicted) = oldest_packet.and_then(|i| state.commands.remove(i)) else {
+            return Ok(Some(command));
+        };
+        self.push(&mut state, command)?;
+        Ok(Some(evicted))
+    }
+
+    fn push(&self, state: &mut QueueState, command: TriggerCommand) -> Result<()> {
+        if state.running_workers == 0 {
+            return Err(Error::from_display("the TriggerThread has stopped"));
+        }
+        state.commands.push_back(command);
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Wait for the next command. With `until_stop`, also take every command
+    /// queued after it, up to the next `Stop`.
+    fn recv(&self, until_stop: bool) -> Result<Vec<TriggerCommand>> {
+        let mut state = self.state.lock()?;
+        state.idle_workers += 1;
+        // An idle worker makes room for one more command
+        self.room.notify_all();
+        while state.commands.is_empty() {
+            state = self.available.wait(state)?;
+        }
+        state.idle_workers -= 1;
+        let mut commands = vec![];
+        while let Some(command) = state.commands.pop_front() {
+            let is_stop = matches!(command, TriggerCommand::Stop);
+            commands.push(command);
+            if is_stop || !until_stop {
+                break;
+            }
+        }
+        self.room.notify_all();
+        Ok(commands)
+    }
+
+    /// Stop accepting commands once every worker has exited, waking up any
+    /// senders that are waiting for room.
+    fn worker_exited(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.running_workers -= 1;
+        }
+        self.room.notify_all();
+    }
+}
+
+/// Run commands from the shared queue until a `Stop`. A Trigger that panics
+/// is reported like one that returned an error, so that it doesn't take down
+/// the worker, and every other Trigger sharing it.
+fn run_worker(
+    worker: usize,
+    queue: &TriggerQueue,
+    policy: BackpressurePolicy,
+    on_error_callback: &Mutex<Option<TriggerThreadErrorCallback>>,
+    pool: &BufferPool,
+) {
+    log_debug!(
+        "[trigger_thread] Worker {} listening for async trigger commands.",
+        worker
+    );
+    'listen: loop {
+        let commands = {
+            // Leave any other workers' `Stop` commands in the queue
+            let Ok(mut commands) = queue.recv(policy == BackpressurePolicy::DropOldest) else {
+                break;
+            };
+            metric!(gauge!("hypetrigger_trigger_thread_queue_depth").decrement(
+                commands
+                    .iter()
+                    .filter(|command| matches!(command, TriggerCommand::Packet(_)))
+                    .count() as f64
+            ));
+            if policy == BackpressurePolicy::DropOldest {
+                commands = keep_latest_packets(commands, pool);
+            }
+            commands
+        };
+
+        for command in commands {
+            match command {
+                TriggerCommand::Stop => {
+                    log_debug!("[trigger_thread] Received stop command.");
+                    break 'listen;
+                }
+                TriggerCommand::Packet(payload) => {
+                    let _session = log::enter_session(payload.session_id.as_deref());
+                    let _trigger = enter_trigger(payload.trigger_id);
+                    let _events = enter_event_sink(payload.events.clone());
+                    let mut payloads = vec![];
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        payload
+                            .trigger
+                            .on_frame_events(&payload.frame, &mut payloads)
+                            .map(|_| ())
+                    }))
+                    .unwrap_or_else(|panic| {
+                        Err(Error::from_display(format!(
+                            "Trigger panicked: {}",
+                            panic_message(&*panic)
+                        )))
+                    });
+                    if let Some(events) = &payload.events {
+                        events.report(&payload.frame, &mut payloads);
                     }
-                    TriggerCommand::Packet(payload) => {
-                        let result = payload.trigger.on_frame(&payload.frame);
-                        if let Err(e) = result {
-                            eprintln!("Error in async trigger: {}", e);
+                    pool.recycle(payload.frame);
+                    if let Err(e) = result {
+                        log_warn!("Error in async trigger: {}", e);
+                        let callback = on_error_callback.lock().ok().and_then(|c| c.clone());
+                        if let Some(callback) = callback {
+                            callback(&e);
                         }
                     }
                 }
             }
-            println!("[trigger_thread] Exiting.");
-        });
-        Arc::new(Self { tx, join_handle })
+        }
     }
+    log_debug!("[trigger_thread] Worker {} exiting.", worker);
+}
+
+/// The message a thread panicked with, if it was a string, as it usually is.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("(no message)", String::as_str),
+    }
+}
+
+/// Number of commands that can be queued up for a `TriggerThread` by default
+pub const DEFAULT_QUEUE_CAPACITY: usize = 100;
+
+/// Drop all but the most recent packet for each Trigger, keeping the order of
+/// the remaining commands. The frames of the dropped ones go back to the pool.
+fn keep_latest_packets(commands: Vec<TriggerCommand>, pool: &BufferPool) -> Vec<TriggerCommand> {
+    let is_superseded = |i: usize, packet: &TriggerPacket| {
+        commands[i + 1..].iter().any(|later| match later {
+            TriggerCommand::Packet(later) => Arc::ptr_eq(&later.trigger, &packet.trigger),
+            TriggerCommand::Stop => false,
+        })
+    };
+    let keep: Vec<bool> = commands
+        .iter()
+        .enumerate()
+        .map(|(i, command)| match command {
+            TriggerCommand::Packet(packet) => !is_superseded(i, packet),
+            TriggerCommand::Stop => true,
+        })
+        .collect();
+    commands
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(command, keep)| match (command, keep) {
+            (command, true) => Some(command),
+            (TriggerCommand::Packet(packet), false) => {
+                pool.recycle(packet.frame);
+                None
+            }
+            (TriggerCommand::Stop, false) => None,
+        })
+        .collect()
 }
 
 /// A command send over a channel to a `TriggerThread`
@@ -86,4 +514,346 @@ pub enum TriggerCommand {
 pub struct TriggerPacket {
     frame: Frame,
     trigger: Arc<dyn Trigger>,
+
+    /// The index of the `AsyncTrigger` in its pipeline, if it was sent by one
+    trigger_id: Option<usize>,
+
+    /// The `session_id` of the pipeline, to tag the worker's log messages with
+    /// while it runs the Trigger, since one worker can serve several sessions
+    session_id: Option<String>,
+
+    /// Where to report the events the Trigger finds, if the pipeline has an
+    /// `on_trigger_event` callback
+    events: Option<EventSink>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        keep_latest_packets, AsyncTrigger, BackpressurePolicy, TriggerCommand, TriggerPacket,
+        TriggerThread,
+    };
+    use crate::{
+        buffer_pool::BufferPool,
+        error::{Error, Result},
+        frame_source::MockFrameSource,
+        log::current_session_id,
+        pipeline::{Hypetrigger, PixelFormat},
+        simple_trigger::SimpleTrigger,
+        trigger::Frame,
+        trigger::{current_trigger_id, PipelineEvent, Trigger, TriggerPayload},
+    };
+    use image::DynamicImage;
+    use std::{
+        collections::HashSet,
+        ops::ControlFlow,
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn pool() -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let trigger = SimpleTrigger::new(move |_| {
+            thread::sleep(Duration::from_millis(50));
+            tx.lock().unwrap().send(thread::current().id()).unwrap();
+        });
+        let pool = TriggerThread::spawn_pool(2, 10, BackpressurePolicy::Block);
+        let trigger = AsyncTrigger::from_trigger(trigger, pool.clone());
+        let frame = Frame {
+            image: DynamicImage::new_rgb8(1, 1),
+            frame_num: 0,
+            timestamp: 0.0,
+            input_index: 0,
+        };
+        for _ in 0..4 {
+            trigger.on_frame(&frame)?;
+        }
+
+        let mut workers = HashSet::new();
+        for _ in 0..4 {
+            workers.insert(
+                rx.recv_timeout(Duration::from_secs(5))
+                    .map_err(Error::from_std)?,
+            );
+        }
+        assert_eq!(workers.len(), 2);
+        for _ in &pool.join_handles {
+            pool.queue.send(TriggerCommand::Stop)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pooled_frames() -> Result<()> {
+        // Lots of frames through a short queue, with slow workers
+        let mut source = MockFrameSource::new(64, 36, PixelFormat::Rgb24);
+        for i in 0..500 {
+            source.push_fill(i as u8);
+        }
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let trigger = SimpleTrigger::new(move |frame| {
+            thread::sleep(Duration::from_micros(200));
+            let first_byte = frame.image.as_bytes()[0];
+            tx.lock()
+                .unwrap()
+                .send((frame.frame_num, first_byte))
+                .unwrap();
+        });
+        let pool = TriggerThread::spawn_pool(2, 4, BackpressurePolicy::Block);
+        let summary = Hypetrigger::new()
+            .add_trigger(AsyncTrigger::from_trigger(trigger, pool.clone()))
+            .attach_source(source)?;
+        assert_eq!(summary.frames_read, 500);
+
+        // Every frame arrives intact, in a bounded number of buffers
+        let mut received: Vec<(u64, u8)> = (0..500)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(Error::from_std)?;
+        received.sort();
+        assert!(received.iter().all(|&(n, byte)| byte == n as u8));
+        assert!(pool.pool.allocations() <= 4 + 2 + 1);
+        for _ in &pool.join_handles {
+            pool.queue.send(TriggerCommand::Stop)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn trigger_id() -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let trigger = SimpleTrigger::new(move |_| {
+            tx.lock()
+                .unwrap()
+                .send((current_trigger_id(), current_session_id()))
+                .unwrap();
+        });
+        let pool = TriggerThread::spawn();
+        let mut source = MockFrameSource::new(4, 4, PixelFormat::Rgb24);
+        source.push_fill(0);
+        Hypetrigger::new()
+            .set_session_id("stream-1")
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .add_trigger(AsyncTrigger::from_trigger(trigger, pool.clone()))
+            .attach_source(source)?;
+
+        // The worker knows which of the pipeline's Triggers it's running, and
+        // for which session
+        let (trigger_id, session_id) = rx
+            .recv_timeout(Duration::from_secs(5))
+            .map_err(Error::from_std)?;
+        assert_eq!(trigger_id, Some(1));
+        assert_eq!(session_id.as_deref(), Some("stream-1"));
+        pool.queue.send(TriggerCommand::Stop)?;
+        Ok(())
+    }
+
+    /// Reports the number of each frame it runs on as an event
+    struct FrameNumTrigger;
+    impl Trigger for FrameNumTrigger {
+        fn on_frame(&self, _frame: &Frame) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_frame_events(
+            &self,
+            frame: &Frame,
+            payloads: &mut Vec<TriggerPayload>,
+        ) -> Result<ControlFlow<()>> {
+            payloads.push(TriggerPayload::Text(frame.frame_num.to_string()));
+            Ok(ControlFlow::Continue(()))
+        }
+    }
+
+    #[test]
+    fn events() -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let pool = TriggerThread::spawn();
+        let mut source = MockFrameSource::new(4, 4, PixelFormat::Rgb24);
+        source.push_fill(0);
+        source.push_fill(1);
+        Hypetrigger::new()
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .add_trigger(AsyncTrigger::from_trigger(FrameNumTrigger, pool.clone()))
+            .on_trigger_event(move |event| tx.lock().unwrap().send(event.clone()).unwrap())
+            .attach_source(source)?;
+
+        // The worker reports the events, as the AsyncTrigger in the pipeline
+        let events: Vec<(usize, u64)> = (0..2)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)))
+            .collect::<std::result::Result<Vec<PipelineEvent>, _>>()
+            .map_err(Error::from_std)?
+            .iter()
+            .map(|event| (event.trigger_id, event.frame_num))
+            .collect();
+        assert_eq!(events, vec![(1, 0), (1, 1)]);
+        pool.queue.send(TriggerCommand::Stop)?;
+        Ok(())
+    }
+
+    #[test]
+    fn panicking_trigger() -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let (error_tx, error_rx) = mpsc::channel();
+        let error_tx = Mutex::new(error_tx);
+        let runner_thread = TriggerThread::spawn();
+        runner_thread.on_error(move |e| error_tx.lock().unwrap().send(e.to_string()).unwrap());
+        let buggy = AsyncTrigger::from_trigger(
+            SimpleTrigger::new(|frame| {
+                if frame.frame_num == 1 {
+                    panic!("bad frame");
+                }
+            }),
+            runner_thread.clone(),
+        );
+        let healthy = AsyncTrigger::from_trigger(
+            SimpleTrigger::new(move |frame| tx.lock().unwrap().send(frame.frame_num).unwrap()),
+            runner_thread.clone(),
+        );
+        for frame_num in 0..3 {
+            let frame = Frame {
+                image: DynamicImage::new_rgb8(1, 1),
+                frame_num,
+                timestamp: 0.0,
+                input_index: 0,
+            };
+            buggy.on_frame(&frame)?;
+            healthy.on_frame(&frame)?;
+        }
+
+        // The worker survives the panic, and keeps running both Triggers
+        let recv = || {
+            rx.recv_timeout(Duration::from_secs(5))
+                .map_err(Error::from_std)
+        };
+        assert_eq!([recv()?, recv()?, recv()?], [0, 1, 2]);
+        let error = error_rx
+            .recv_timeout(Duration::from_secs(5))
+            .map_err(Error::from_std)?;
+        assert!(error.contains("bad frame"));
+        runner_thread.queue.send(TriggerCommand::Stop)?;
+        Ok(())
+    }
+
+    #[test]
+    fn drop_newest() -> Result<()> {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let release_rx = Mutex::new(release_rx);
+        let trigger = SimpleTrigger::new(move |frame| {
+            started_tx.lock().unwrap().send(frame.frame_num).unwrap();
+            release_rx.lock().unwrap().recv().unwrap();
+        });
+        let runner_thread = TriggerThread::spawn_with(1, BackpressurePolicy::DropNewest);
+        assert_eq!(runner_thread.capacity, 1);
+        let trigger = AsyncTrigger::from_trigger(trigger, runner_thread.clone());
+        let frame = |frame_num| Frame {
+            image: DynamicImage::new_rgb8(1, 1),
+            frame_num,
+            timestamp: 0.0,
+            input_index: 0,
+        };
+
+        // Frame 0 is being run, frame 1 fills the queue, and frame 2 is skipped
+        trigger.on_frame(&frame(0))?;
+        let recv = || started_rx.recv_timeout(Duration::from_secs(5));
+        assert_eq!(recv().map_err(Error::from_std)?, 0);
+        trigger.on_frame(&frame(1))?;
+        trigger.on_frame(&frame(2))?;
+        release_tx.send(())?;
+        assert_eq!(recv().map_err(Error::from_std)?, 1);
+        release_tx.send(())?;
+        runner_thread.queue.send(TriggerCommand::Stop)?;
+        assert!(started_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_oldest_doesnt_block() -> Result<()> {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let release_rx = Mutex::new(release_rx);
+        let trigger = SimpleTrigger::new(move |frame| {
+            started_tx.lock().unwrap().send(frame.frame_num).unwrap();
+            release_rx.lock().unwrap().recv().unwrap();
+        });
+        let runner_thread = TriggerThread::spawn_with(1, BackpressurePolicy::DropOldest);
+        let trigger = AsyncTrigger::from_trigger(trigger, runner_thread.clone());
+        let frame = |frame_num| Frame {
+            image: DynamicImage::new_rgb8(1, 1),
+            frame_num,
+            timestamp: 0.0,
+            input_index: 0,
+        };
+
+        // While the worker is stuck on frame 0, new frames replace the queued
+        // one instead of waiting for room
+        trigger.on_frame(&frame(0))?;
+        let recv = || started_rx.recv_timeout(Duration::from_secs(5));
+        assert_eq!(recv().map_err(Error::from_std)?, 0);
+        let (done_tx, done_rx) = mpsc::channel();
+        let sender = thread::spawn(move || {
+            let result = (1..10).try_for_each(|frame_num| trigger.on_frame(&frame(frame_num)));
+            done_tx.send(()).unwrap();
+            result.map_err(|e| e.to_string())
+        });
+        let done = done_rx.recv_timeout(Duration::from_secs(5));
+        release_tx.send(())?;
+        assert!(done.is_ok(), "on_frame blocked on a full queue");
+        sender
+            .join()
+            .map_err(|_| Error::from_display("sender panicked"))?
+            .map_err(Error::from_display)?;
+
+        // Only the latest frame is left to run
+        assert_eq!(recv().map_err(Error::from_std)?, 9);
+        release_tx.send(())?;
+        runner_thread.queue.send(TriggerCommand::Stop)?;
+        assert!(started_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let trigger_a: Arc<dyn Trigger> = Arc::new(SimpleTrigger::new(|_| {}));
+        let trigger_b: Arc<dyn Trigger> = Arc::new(SimpleTrigger::new(|_| {}));
+        let packet = |trigger: &Arc<dyn Trigger>, frame_num| {
+            TriggerCommand::Packet(TriggerPacket {
+                frame: Frame {
+                    image: DynamicImage::new_rgb8(1, 1),
+                    frame_num,
+                    timestamp: 0.0,
+                    input_index: 0,
+                },
+                trigger: trigger.clone(),
+                trigger_id: None,
+                session_id: None,
+                events: None,
+            })
+        };
+
+        let commands = vec![
+            packet(&trigger_a, 0),
+            packet(&trigger_b, 0),
+            packet(&trigger_a, 1),
+            TriggerCommand::Stop,
+        ];
+        let kept: Vec<Option<u64>> = keep_latest_packets(commands, &BufferPool::default())
+            .iter()
+            .map(|command| match command {
+                TriggerCommand::Packet(packet) => Some(packet.frame.frame_num),
+                TriggerCommand::Stop => None,
+            })
+            .collect();
+        assert_eq!(kept, vec![Some(0), Some(1), None]);
+    }
 }
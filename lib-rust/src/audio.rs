@@ -0,0 +1,364 @@
+// Runs Triggers on the input's audio as well as its video, to catch cues that
+// never show up on screen (a buzzer, a spike in crowd noise).
+//
+// The video frames already take up ffmpeg's stdout, and that's the only pipe
+// we can portably hand it, so the audio is decoded by a second ffmpeg process
+// reading the same input alongside the first.
+
+use crate::concat::CONCAT_INPUT_ARGS;
+use crate::error::{Error, FfmpegError, NoneError, Result};
+use crate::log;
+use crate::pipeline::{read_frame, Hypetrigger, SeekAccuracy};
+use crate::trigger::Trigger;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, Scope, ScopedJoinHandle};
+use std::time::Duration;
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// A chunk of the input's audio, as mono signed 16-bit samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioChunk {
+    pub samples: Vec<i16>,
+
+    /// Number of samples per second, from `Hypetrigger::audio_sample_rate`.
+    pub sample_rate: u32,
+
+    /// Index of this chunk among all the chunks of the input, starting at 0.
+    pub chunk_num: u64,
+
+    /// Time at which this chunk starts in the input, in seconds.
+    pub timestamp: f64,
+}
+
+impl AudioChunk {
+    /// The timestamp as a `Duration` since the start of the input.
+    pub fn time(&self) -> Duration {
+        Duration::from_secs_f64(self.timestamp)
+    }
+
+    /// Root mean square loudness of the chunk, from 0 (silence) to 1.
+    pub fn rms(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .samples
+            .iter()
+            .map(|&sample| (sample as f64 / i16::MAX as f64).powi(2))
+            .sum();
+        (sum / self.samples.len() as f64).sqrt().min(1.0)
+    }
+
+    /// Loudness of the loudest sample in the chunk, from 0 to 1.
+    pub fn peak(&self) -> f64 {
+        let peak = self
+            .samples
+            .iter()
+            .map(|sample| sample.unsigned_abs())
+            .max();
+        (peak.unwrap_or(0) as f64 / i16::MAX as f64).min(1.0)
+    }
+}
+
+/// Handle to the thread started by `Hypetrigger::spawn_ffmpeg_audio_thread`,
+/// which returns the number of chunks read.
+pub type FfmpegAudioJoinHandle<'scope> =
+    ScopedJoinHandle<'scope, core::result::Result<u64, String>>;
+
+impl Hypetrigger {
+    /// Whether any of the Triggers run on the audio (see `Trigger::is_audio`).
+    pub fn has_audio_triggers(&self) -> bool {
+        self.triggers
+            .iter()
+            .any(|trigger| trigger.get_enabled() && trigger.is_audio())
+    }
+
+    /// Number of samples in each `AudioChunk`.
+    pub fn audio_chunk_samples(&self) -> usize {
+        (self.audio_sample_rate as f64 * self.audio_chunk_duration.as_secs_f64()) as usize
+    }
+
+    /// Build the command for the second ffmpeg process, which decodes the
+    /// audio of the main input to mono 16-bit PCM on its stdout. Uses the
+    /// same input options as the video (including its `concat_list`), apart
+    /// from hardware acceleration.
+    pub fn audio_ffmpeg_command(
+        &self,
+        concat_list: Option<&Path>,
+    ) -> core::result::Result<Command, FfmpegError> {
+        let concat_list = self.check_concat_list(concat_list)?;
+        let mut cmd = Command::new(self.resolve_ffmpeg_exe()?);
+        // Only errors are logged, so stderr can be read once ffmpeg is done
+        cmd.arg("-nostats").arg("-loglevel").arg("error");
+        if concat_list.is_some() {
+            cmd.args(CONCAT_INPUT_ARGS);
+        }
+        if let Some(input_format) = &self.input_format {
+            cmd.arg("-f").arg(input_format);
+        }
+        if self.seek_accuracy != SeekAccuracy::Decode {
+            cmd.args(self.seek_args());
+        }
+        cmd.args(&self.extra_input_args);
+        cmd.arg("-i").arg(concat_list.unwrap_or(&self.input));
+        if self.seek_accuracy == SeekAccuracy::Decode {
+            cmd.args(self.seek_args());
+        }
+        if let Some(duration) = self.duration {
+            cmd.arg("-t").arg(duration.as_secs_f64().to_string());
+        }
+        // Optional, so that an input without audio isn't an error
+        cmd.arg("-map")
+            .arg("0:a:0?")
+            .arg("-ac")
+            .arg("1")
+            .arg("-ar")
+            .arg(self.audio_sample_rate.to_string())
+            .arg("-f")
+            .arg("s16le")
+            .arg("pipe:1");
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        #[cfg(windows)]
+        cmd.creation_flags(self.windows_process_flags.bits());
+
+        Ok(cmd)
+    }
+
+    /// Spawn the ffmpeg process that decodes the audio.
+    pub fn spawn_ffmpeg_audio_child(
+        &self,
+        concat_list: Option<&Path>,
+    ) -> core::result::Result<Child, FfmpegError> {
+        self.audio_ffmpeg_command(concat_list)?
+            .spawn()
+            .map_err(FfmpegError::SpawnFailed)
+    }
+
+    /// If there are any audio Triggers, spawn the audio ffmpeg process, and a
+    /// thread that reads its output in chunks of `audio_chunk_duration` and
+    /// passes each one to the audio Triggers. Setting `stop` kills ffmpeg
+    /// before the next chunk. The thread exits after ffmpeg does, with an
+    /// error if ffmpeg failed. An input with no audio just has no chunks.
+    pub fn spawn_ffmpeg_audio_thread<'scope>(
+        &'scope self,
+        scope: &'scope Scope<'scope, '_>,
+        stop: &'scope AtomicBool,
+        concat_list: Option<&Path>,
+    ) -> Result<Option<FfmpegAudioJoinHandle<'scope>>> {
+        if !self.has_audio_triggers() {
+            return Ok(None);
+        }
+        let mut ffmpeg_child = self.spawn_ffmpeg_audio_child(concat_list)?;
+        let ffmpeg_stdout = ffmpeg_child.stdout.take().ok_or(NoneError)?;
+        let join_handle = thread::Builder::new()
+            .name(self.thread_name("ffmpeg_audio"))
+            .spawn_scoped(scope, move || {
+                let _session = log::enter_session(self.session_id.as_deref());
+                let result = self.read_audio(ffmpeg_stdout, stop);
+                if result.is_err() || stop.load(Ordering::Relaxed) {
+                    let _ = ffmpeg_child.kill();
+                }
+                let mut stderr = String::new();
+                if let Some(ffmpeg_stderr) = &mut ffmpeg_child.stderr {
+                    let _ = ffmpeg_stderr.read_to_string(&mut stderr);
+                }
+                let status = ffmpeg_child.wait().map_err(|e| e.to_string())?;
+                let chunks_read = result.map_err(|e| e.to_string())?;
+                log_debug!("[ffmpeg.audio] ffmpeg exited with status {}", status);
+                if !status.success() && chunks_read == 0 && has_no_streams(&stderr) {
+                    log_warn!("[ffmpeg.audio] The input has no audio for the audio Triggers");
+                    return Ok(0);
+                }
+                if !status.success() && !stop.load(Ordering::Relaxed) {
+                    return Err(format!(
+                        "audio ffmpeg exited with {}:\n{}",
+                        status,
+                        stderr.trim_end()
+                    ));
+                }
+                Ok(chunks_read)
+            })?;
+        Ok(Some(join_handle))
+    }
+
+    /// Read chunks of audio until ffmpeg closes its stdout, running the audio
+    /// Triggers on each one. A truncated last chunk is still passed on.
+    fn read_audio(&self, mut ffmpeg_stdout: ChildStdout, stop: &AtomicBool) -> Result<u64> {
+        let triggers: Vec<(usize, &Arc<dyn Trigger>)> = self
+            .triggers
+            .iter()
+            .enumerate()
+            .filter(|(_, trigger)| trigger.is_audio())
+            .collect();
+        let mut disabled = vec![false; triggers.len()];
+        let start_time = self.start_time.unwrap_or_default().as_secs_f64();
+        let mut buffer = vec![0_u8; self.audio_chunk_samples() * 2];
+        let mut chunk_num = 0;
+        while !stop.load(Ordering::Relaxed) {
+            let bytes_read = read_frame(&mut ffmpeg_stdout, &mut buffer)?;
+            if bytes_read < 2 {
+                break;
+            }
+            let chunk = AudioChunk {
+                samples: buffer[..bytes_read]
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                    .collect(),
+                sample_rate: self.audio_sample_rate,
+                chunk_num,
+                timestamp: start_time + chunk_num as f64 * self.audio_chunk_duration.as_secs_f64(),
+            };
+            for ((trigger_id, trigger), disabled) in triggers.iter().zip(&mut disabled) {
+                if *disabled || !trigger.get_enabled() {
+                    continue;
+                }
+                if let Err(e) = trigger.on_audio(&chunk) {
+                    self.handle_trigger_error(*trigger_id, e, disabled)?;
+                }
+            }
+            chunk_num += 1;
+            if bytes_read < buffer.len() {
+                break;
+            }
+        }
+        log_debug!("[ffmpeg.audio] Finished reading {} chunks", chunk_num);
+        Ok(chunk_num)
+    }
+}
+
+/// Whether ffmpeg gave up because none of its optional `-map`s matched a
+/// stream, which for the audio process means the input has no audio.
+fn has_no_streams(stderr: &str) -> bool {
+    stderr.contains("does not contain any stream")
+}
+
+/// Wait for the audio thread, and pass on any error it returned.
+pub fn join_audio_thread(join_handle: FfmpegAudioJoinHandle) -> Result<u64> {
+    join_handle
+        .join()
+        .map_err(|_| Error::from_display("ffmpeg audio thread panicked"))?
+        .map_err(Error::from_display)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AudioChunk;
+    #[cfg(unix)]
+    use crate::test_util::fake_ffmpeg;
+    use crate::{
+        error::Result,
+        pipeline::Hypetrigger,
+        simple_trigger::{SimpleAudioTrigger, SimpleTrigger},
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn loudness() {
+        let chunk = AudioChunk {
+            samples: vec![0, i16::MAX, 0, i16::MIN],
+            sample_rate: 4,
+            chunk_num: 0,
+            timestamp: 0.0,
+        };
+        assert_eq!(chunk.peak(), 1.0);
+        assert!((chunk.rms() - 0.5_f64.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn audio_ffmpeg_command() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input("input.mp4")
+            .set_audio_sample_rate(8000);
+        let command = hypetrigger.audio_ffmpeg_command(None)?;
+        let args: Vec<String> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            args[args.len() - 9..],
+            ["-map", "0:a:0?", "-ac", "1", "-ar", "8000", "-f", "s16le", "pipe:1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn audio_triggers() -> Result<()> {
+        let chunks = Arc::new(Mutex::new(vec![]));
+        let chunks_clone = chunks.clone();
+        let frames = Arc::new(Mutex::new(0));
+        let frames_clone = frames.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .set_audio_chunk_duration(Duration::from_millis(500))
+            .add_trigger(SimpleTrigger::new(move |_| {
+                *frames_clone.lock().unwrap() += 1;
+            }))
+            .add_trigger(SimpleAudioTrigger::new(move |chunk| {
+                let mut chunks = chunks_clone.lock().unwrap();
+                chunks.push((chunk.chunk_num, chunk.timestamp, chunk.samples.len()));
+            }))
+            .run()?;
+
+        // Audio Triggers don't get frames, and video Triggers don't get audio
+        assert_eq!(*frames.lock()?, 20);
+        let chunks = chunks.lock()?;
+        assert!(!chunks.is_empty());
+        for (i, &(chunk_num, timestamp, samples)) in chunks.iter().enumerate() {
+            assert_eq!(chunk_num, i as u64);
+            assert_eq!(timestamp, i as f64 * 0.5);
+            assert!(samples <= 8000);
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_audio() -> Result<()> {
+        // Stand-in for ffmpeg with an input that only has video
+        let ffmpeg = fake_ffmpeg(
+            "no-audio",
+            "#!/bin/sh\n\
+             case \"$*\" in\n\
+               *s16le*) echo 'Output file #0 does not contain any stream' >&2; exit 1;;\n\
+             esac\n\
+             echo \"Output #0, rawvideo, to 'pipe:1':\" >&2\n\
+             echo \"  Stream #0:0: Video: rawvideo, rgb24, 64x36, 2 fps\" >&2\n\
+             head -c 6912 /dev/zero\n",
+        )?;
+
+        let chunks = Arc::new(Mutex::new(0));
+        let chunks_clone = chunks.clone();
+        let frames = Arc::new(Mutex::new(0));
+        let frames_clone = frames.clone();
+        Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(move |_| {
+                *frames_clone.lock().unwrap() += 1;
+            }))
+            .add_trigger(SimpleAudioTrigger::new(move |_| {
+                *chunks_clone.lock().unwrap() += 1;
+            }))
+            .run()?;
+        assert_eq!(*frames.lock()?, 1);
+        assert_eq!(*chunks.lock()?, 0);
+        Ok(())
+    }
+}
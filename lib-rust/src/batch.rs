@@ -0,0 +1,146 @@
+// Runs the same Triggers over many inputs, e.g. a folder of clips, one ffmpeg
+// process per input. The Triggers themselves are shared between all of them,
+// so anything they load up front (like a Tesseract instance) is only loaded once.
+
+use crate::error::{Error, Result};
+use crate::pipeline::Hypetrigger;
+use crate::simple_trigger::TriggerEvent;
+use crate::trigger::Frame;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What `Hypetrigger::run_batch` found in one of the inputs.
+#[derive(Debug)]
+pub struct BatchResult<T> {
+    /// The input these results are for.
+    pub input: PathBuf,
+
+    /// Everything detected in this input, in order, or why processing it
+    /// failed. A failed input doesn't stop the rest of the batch.
+    pub events: Result<Vec<TriggerEvent<T>>>,
+}
+
+/// Passed to the `on_batch_progress` callback each time `run_batch` finishes
+/// one of its inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchProgress {
+    /// The input that just finished.
+    pub input: PathBuf,
+
+    /// Whether it was processed successfully.
+    pub succeeded: bool,
+
+    /// How many inputs have finished so far, including this one.
+    pub completed: usize,
+
+    /// How many inputs there are in the whole batch.
+    pub total: usize,
+}
+
+impl Hypetrigger {
+    /// Like `run_to_completion`, but for each of the given inputs in turn
+    /// (replacing `input`), with up to `concurrency` ffmpeg processes at
+    /// once. The other callbacks (e.g. `on_progress`) are called for every
+    /// input, and `on_batch_progress` after each one finishes. Returns the
+    /// results for each input, in the same order as the inputs.
+    pub fn run_batch<P, T, F>(
+        &self,
+        inputs: impl IntoIterator<Item = P>,
+        concurrency: usize,
+        detect: F,
+    ) -> Vec<BatchResult<T>>
+    where
+        P: Into<PathBuf>,
+        T: Send + 'static,
+        F: Fn(&Frame) -> Option<T> + Send + Sync + 'static,
+    {
+        let inputs: Vec<PathBuf> = inputs.into_iter().map(Into::into).collect();
+        let detect = Arc::new(detect);
+        // `Error` can't cross threads, so errors are kept as `String` until the end
+        type InputEvents<T> = core::result::Result<Vec<TriggerEvent<T>>, String>;
+        let results: Vec<Mutex<Option<InputEvents<T>>>> =
+            inputs.iter().map(|_| Mutex::new(None)).collect();
+        let next_input = AtomicUsize::new(0);
+        let completed = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..concurrency.clamp(1, inputs.len().max(1)) {
+                scope.spawn(|| loop {
+                    let i = next_input.fetch_add(1, Ordering::SeqCst);
+                    let Some(input) = inputs.get(i) else { break };
+                    log_debug!("[batch] Starting input {} of {}", i + 1, inputs.len());
+
+                    let detect = detect.clone();
+                    let events = self
+                        .clone()
+                        .set_input(input.clone())
+                        .run_to_completion(move |frame| detect(frame))
+                        .map_err(|e| e.to_string());
+                    let progress = BatchProgress {
+                        input: input.clone(),
+                        succeeded: events.is_ok(),
+                        completed: completed.fetch_add(1, Ordering::SeqCst) + 1,
+                        total: inputs.len(),
+                    };
+                    if let Ok(mut result) = results[i].lock() {
+                        *result = Some(events);
+                    }
+                    if let Some(callback) = &self.on_batch_progress_callback {
+                        callback(&progress);
+                    }
+                });
+            }
+        });
+
+        inputs
+            .into_iter()
+            .zip(results)
+            .map(|(input, result)| {
+                let events = match result.into_inner() {
+                    Ok(Some(events)) => events.map_err(Error::from_display),
+                    _ => Err(Error::from_display("input was never processed")),
+                };
+                BatchResult { input, events }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Result, pipeline::Hypetrigger, pipeline::FFMPEG_TEST_INPUT};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn run_batch() -> Result<()> {
+        let progress = Arc::new(Mutex::new(vec![]));
+        let progress_clone = progress.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_input_format("lavfi")
+            .set_scale(64, 36)
+            .on_batch_progress(move |progress| {
+                progress_clone.lock().unwrap().push(progress.completed);
+            });
+        let results = hypetrigger.run_batch(
+            [FFMPEG_TEST_INPUT, "missing.mp4", FFMPEG_TEST_INPUT],
+            2,
+            |frame| Some(frame.frame_num),
+        );
+
+        assert_eq!(results.len(), 3);
+        assert!(!results[0]
+            .events
+            .as_ref()
+            .map_err(|e| e.to_string())?
+            .is_empty());
+        assert!(results[1].events.is_err());
+        assert!(results[2].events.is_ok());
+        let mut progress = progress.lock()?.clone();
+        progress.sort();
+        assert_eq!(progress, vec![1, 2, 3]);
+        Ok(())
+    }
+}
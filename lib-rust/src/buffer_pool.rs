@@ -0,0 +1,177 @@
+// Recycles the byte buffers of frames that have to be copied to outlive the
+// pipeline's own buffer (e.g. to queue them for an `AsyncTrigger`), or to split
+// up multiple inputs, so that a high framerate doesn't turn into an allocation
+// per frame.
+
+use crate::pipeline::{into_raw_buffer, PixelFormat};
+use crate::trigger::Frame;
+use image::{DynamicImage, GenericImageView};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A pool of byte buffers for copies of frames. Buffers are taken from the
+/// pool to copy a frame into, and put back once the copy is done with, so
+/// that at most one buffer is allocated for each copy that's alive at once.
+///
+/// Only 8-bit frames (`Rgb24`, `Gray8` and `Rgba`) can be stored in a byte
+/// buffer; copies of 16-bit frames are allocated as usual.
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    /// Buffers that are free to be taken
+    buffers: Mutex<Vec<Vec<u8>>>,
+
+    /// Most buffers to keep around when they're put back; any more are freed
+    pub max_buffers: usize,
+
+    /// Number of times a buffer had to be allocated (or grown), rather than
+    /// reused
+    allocations: AtomicUsize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+            allocations: AtomicUsize::new(0),
+        }
+    }
+
+    /// A buffer of `len` bytes, reused from the pool if there is one. Its
+    /// contents are whatever was left in it.
+    pub fn take(&self, len: usize) -> Vec<u8> {
+        let mut buffer = self
+            .buffers
+            .lock()
+            .ok()
+            .and_then(|mut buffers| buffers.pop())
+            .unwrap_or_default();
+        if buffer.capacity() < len {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+        }
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    /// Return a buffer to the pool, to be reused by the next `take`.
+    pub fn put(&self, buffer: Vec<u8>) {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_buffers {
+                buffers.push(buffer);
+            }
+        }
+    }
+
+    /// Copy a frame into a buffer from the pool.
+    pub fn copy_frame(&self, frame: &Frame) -> Frame {
+        let format = PixelFormat::of(&frame.image).filter(|format| format.bit_depth() == 8);
+        let Some(format) = format else {
+            return frame.clone();
+        };
+        let bytes = frame.image.as_bytes();
+        let mut buffer = self.take(bytes.len());
+        buffer.copy_from_slice(bytes);
+        let (width, height) = (frame.image.width(), frame.image.height());
+        match format.image_from_raw(width, height, buffer) {
+            Some(image) => Frame { image, ..*frame },
+            None => frame.clone(),
+        }
+    }
+
+    /// Copy a region of an image, as `(x, y, width, height)` in pixels, into a
+    /// buffer from the pool. Like `crop_imm`, the region is clipped to the
+    /// image.
+    pub fn copy_region(
+        &self,
+        image: &DynamicImage,
+        (x, y, width, height): (u32, u32, u32, u32),
+    ) -> DynamicImage {
+        let x = x.min(image.width());
+        let y = y.min(image.height());
+        let width = width.min(image.width() - x);
+        let height = height.min(image.height() - y);
+        let format = PixelFormat::of(image).filter(|format| format.bit_depth() == 8);
+        let Some(format) = format.filter(|_| width > 0 && height > 0) else {
+            return image.crop_imm(x, y, width, height);
+        };
+        let stride = format.frame_size(image.width(), 1);
+        let offset = format.frame_size(x, 1);
+        let bytes = image.as_bytes();
+        let mut buffer = self.take(format.frame_size(width, height));
+        let rows = buffer.chunks_exact_mut(format.frame_size(width, 1));
+        for (row, dest) in (y as usize..).zip(rows) {
+            let start = row * stride + offset;
+            dest.copy_from_slice(&bytes[start..start + dest.len()]);
+        }
+        format
+            .image_from_raw(width, height, buffer)
+            .unwrap_or_else(|| image.crop_imm(x, y, width, height))
+    }
+
+    /// Put the buffer of a frame copied with `copy_frame` (or an image copied
+    /// with `copy_region`) back in the pool.
+    pub fn recycle(&self, frame: Frame) {
+        if PixelFormat::of(&frame.image).is_some_and(|format| format.bit_depth() == 8) {
+            self.put(into_raw_buffer(frame.image));
+        }
+    }
+
+    /// Number of buffers allocated so far, as opposed to reused.
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+    use crate::trigger::Frame;
+    use image::{DynamicImage, RgbImage};
+
+    #[test]
+    fn buffer_pool() {
+        let pool = BufferPool::new(2);
+        let frame = Frame {
+            image: DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 2, image::Rgb([1, 2, 3]))),
+            frame_num: 3,
+            timestamp: 1.5,
+            input_index: 0,
+        };
+
+        // Only as many buffers are allocated as there are copies at once
+        for _ in 0..100 {
+            let copies = [pool.copy_frame(&frame), pool.copy_frame(&frame)];
+            for copy in copies {
+                assert_eq!(copy, frame);
+                pool.recycle(copy);
+            }
+        }
+        assert_eq!(pool.allocations(), 2);
+
+        // Extra buffers beyond `max_buffers` are freed
+        let copies: Vec<Frame> = (0..3).map(|_| pool.copy_frame(&frame)).collect();
+        copies.into_iter().for_each(|copy| pool.recycle(copy));
+        assert_eq!(pool.allocations(), 3);
+        assert_eq!(pool.buffers.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn copy_region() {
+        let pool = BufferPool::new(1);
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(6, 4, |x, y| {
+            image::Rgb([x as u8, y as u8, 0])
+        }));
+        for region in [(0, 0, 6, 4), (3, 1, 2, 3), (4, 2, 8, 8), (6, 0, 1, 1)] {
+            let (x, y, width, height) = region;
+            let copy = pool.copy_region(&image, region);
+            assert_eq!(copy, image.crop_imm(x, y, width, height), "{:?}", region);
+            pool.recycle(Frame {
+                image: copy,
+                frame_num: 0,
+                timestamp: 0.0,
+                input_index: 0,
+            });
+        }
+        assert_eq!(pool.allocations(), 1);
+    }
+}
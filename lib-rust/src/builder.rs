@@ -0,0 +1,153 @@
+// Builds a `Hypetrigger` from just the settings that matter for it, with the
+// usual defaults for the rest, checking the result before it's used.
+
+use crate::error::FfmpegError;
+use crate::pipeline::Hypetrigger;
+use crate::trigger::Trigger;
+use crate::util::FfmpegLogLevel;
+use std::path::PathBuf;
+
+/// Fluent builder for a `Hypetrigger`, e.g.
+/// `Hypetrigger::builder().input("match.mp4").samples_per_second(2).add_trigger(trigger).build()?`.
+/// Anything left unset keeps its default from `Hypetrigger::new`, and
+/// `configure` reaches the rest of the settings. Unlike the setters on
+/// `Hypetrigger`, `build` checks the configuration up front.
+#[derive(Clone, Default)]
+pub struct HypetriggerConfigBuilder {
+    pub hypetrigger: Hypetrigger,
+}
+
+impl HypetriggerConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The input video file or URL (see `Hypetrigger::set_input`)
+    pub fn input(mut self, input: impl Into<PathBuf>) -> Self {
+        self.hypetrigger.set_input(input);
+        self
+    }
+
+    /// How many frames to sample from each second of the input (see
+    /// `Hypetrigger::fps`)
+    pub fn samples_per_second(mut self, samples_per_second: u64) -> Self {
+        self.hypetrigger.set_fps(samples_per_second);
+        self
+    }
+
+    /// Add a Trigger to be run on every sampled frame
+    pub fn add_trigger<T>(mut self, trigger: T) -> Self
+    where
+        T: Trigger + 'static,
+    {
+        self.hypetrigger.add_trigger(trigger);
+        self
+    }
+
+    /// How much ffmpeg logs (see `Hypetrigger::ffmpeg_loglevel`). Where the
+    /// log messages go is set for the whole process by `log::set_log_writer`.
+    pub fn logging(mut self, ffmpeg_loglevel: FfmpegLogLevel) -> Self {
+        self.hypetrigger.set_ffmpeg_loglevel(ffmpeg_loglevel);
+        self
+    }
+
+    /// Change any other setting, through its setter on `Hypetrigger`
+    pub fn configure(mut self, configure: impl FnOnce(&mut Hypetrigger)) -> Self {
+        configure(&mut self.hypetrigger);
+        self
+    }
+
+    /// Check the configuration and return the `Hypetrigger`: it needs an
+    /// input, at least one Trigger and a sample rate above 0, as well as
+    /// passing `Hypetrigger::validate`.
+    pub fn build(self) -> Result<Hypetrigger, FfmpegError> {
+        let hypetrigger = self.hypetrigger;
+        if hypetrigger.input.as_os_str().is_empty() && hypetrigger.concat_inputs.is_empty() {
+            return Err(FfmpegError::InvalidConfig("no input was set".to_string()));
+        }
+        if hypetrigger.triggers.is_empty() {
+            return Err(FfmpegError::InvalidConfig(
+                "at least one Trigger must be added".to_string(),
+            ));
+        }
+        if hypetrigger.fps == 0 {
+            return Err(FfmpegError::InvalidConfig(
+                "`samples_per_second` must be greater than 0".to_string(),
+            ));
+        }
+        hypetrigger.validate()?;
+        Ok(hypetrigger)
+    }
+}
+
+impl Hypetrigger {
+    /// Start building a `Hypetrigger` (see `HypetriggerConfigBuilder`)
+    pub fn builder() -> HypetriggerConfigBuilder {
+        HypetriggerConfigBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+    use crate::pipeline::OutputSink;
+    use crate::simple_trigger::SimpleTrigger;
+    use std::path::Path;
+
+    #[test]
+    fn build() -> Result<()> {
+        let hypetrigger = Hypetrigger::builder()
+            .input("input.mp4")
+            .samples_per_second(4)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .logging(FfmpegLogLevel::Warning)
+            .configure(|hypetrigger| {
+                hypetrigger.set_scale(64, 36);
+            })
+            .build()?;
+        assert_eq!(hypetrigger.input, Path::new("input.mp4"));
+        assert_eq!(hypetrigger.fps, 4);
+        assert_eq!(hypetrigger.triggers.len(), 1);
+        assert_eq!(hypetrigger.ffmpeg_loglevel, Some(FfmpegLogLevel::Warning));
+        assert_eq!(hypetrigger.scale, Some((64, 36)));
+
+        // Everything else keeps its default
+        let default = Hypetrigger::new();
+        assert_eq!(hypetrigger.is_live, default.is_live);
+        assert_eq!(hypetrigger.max_frames, default.max_frames);
+        Ok(())
+    }
+
+    #[test]
+    fn build_validates() {
+        let error = |builder: HypetriggerConfigBuilder| match builder.build() {
+            Err(FfmpegError::InvalidConfig(message)) => message,
+            _ => panic!("expected an invalid config"),
+        };
+        let with_trigger = || Hypetrigger::builder().add_trigger(SimpleTrigger::new(|_| {}));
+        assert_eq!(error(with_trigger()), "no input was set");
+        assert_eq!(
+            error(Hypetrigger::builder().input("input.mp4")),
+            "at least one Trigger must be added"
+        );
+
+        // Even where `validate` would do without Triggers
+        let to_file = Hypetrigger::builder()
+            .input("input.mp4")
+            .configure(|hypetrigger| {
+                hypetrigger.set_output_sink(OutputSink::NamedPipe("/tmp/frames".into()));
+            });
+        assert_eq!(error(to_file), "at least one Trigger must be added");
+        assert!(
+            error(with_trigger().input("input.mp4").samples_per_second(0))
+                .contains("`samples_per_second`")
+        );
+
+        // The rest of `validate` still applies
+        let zero_threads = with_trigger().input("input.mp4").configure(|hypetrigger| {
+            hypetrigger.set_ffmpeg_threads(0);
+        });
+        assert!(error(zero_threads).contains("thread counts"));
+    }
+}
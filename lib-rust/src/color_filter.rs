@@ -0,0 +1,90 @@
+use image::{DynamicImage, GrayImage, Luma};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Turns a frame into a black and white mask of the pixels close to a target
+/// color, e.g. to pick out a red kill feed before classifying it. A Trigger
+/// opts in with `Trigger::get_color_filter()`.
+///
+/// With `run` and `run_async`, all Triggers share the same video stream from
+/// ffmpeg, so the mask is made after reading each frame (once per distinct
+/// filter). With `Hypetrigger::spawn_ffmpeg_multi_pipe_session`, ffmpeg makes
+/// it instead, in the Trigger's own branch (see `ffmpeg_filter`). Either way,
+/// the masked frame has the same size and pixel format as the original.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ColorFilter {
+    /// The target color, as `[r, g, b]`.
+    pub color: [u8; 3],
+
+    /// How far each channel of a pixel can be from the target color (0-255)
+    /// for it to count as a match.
+    pub tolerance: u8,
+}
+
+impl ColorFilter {
+    pub fn new(color: [u8; 3], tolerance: u8) -> Self {
+        Self { color, tolerance }
+    }
+
+    /// Whether a pixel is close enough to the target color.
+    pub fn matches(&self, rgb: [u8; 3]) -> bool {
+        rgb.iter()
+            .zip(self.color)
+            .all(|(&channel, target)| channel.abs_diff(target) <= self.tolerance)
+    }
+
+    /// The ffmpeg filters that make the same mask as `mask`, as white or black
+    /// RGB pixels, for ffmpeg to convert to the Trigger's pixel format.
+    pub fn ffmpeg_filter(&self) -> String {
+        let [r, g, b] = self.color;
+        let matches = format!(
+            "255*lte(abs(r(X,Y)-{r}),{t})*lte(abs(g(X,Y)-{g}),{t})*lte(abs(b(X,Y)-{b}),{t})",
+            r = r,
+            g = g,
+            b = b,
+            t = self.tolerance
+        );
+        format!("format=rgb24,geq=r='{0}':g='{0}':b='{0}'", matches)
+    }
+
+    /// The mask of an image: white where it matches the target color, and
+    /// black everywhere else.
+    pub fn mask(&self, image: &DynamicImage) -> GrayImage {
+        let rgb = image.to_rgb8();
+        GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+            match self.matches(rgb.get_pixel(x, y).0) {
+                true => Luma([255]),
+                false => Luma([0]),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColorFilter;
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    #[test]
+    fn color_filter() {
+        let mut image = RgbImage::from_pixel(4, 1, Rgb([0, 0, 0]));
+        image.put_pixel(1, 0, Rgb([250, 10, 10]));
+        image.put_pixel(2, 0, Rgb([200, 10, 10]));
+        image.put_pixel(3, 0, Rgb([250, 250, 250]));
+
+        let filter = ColorFilter::new([255, 0, 0], 20);
+        let mask = filter.mask(&DynamicImage::ImageRgb8(image));
+        assert_eq!(mask.as_raw(), &vec![0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn ffmpeg_filter() {
+        let filter = ColorFilter::new([255, 0, 10], 20);
+        let matches = "255*lte(abs(r(X,Y)-255),20)*lte(abs(g(X,Y)-0),20)*lte(abs(b(X,Y)-10),20)";
+        assert_eq!(
+            filter.ffmpeg_filter(),
+            format!("format=rgb24,geq=r='{0}':g='{0}':b='{0}'", matches)
+        );
+    }
+}
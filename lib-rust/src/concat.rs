@@ -0,0 +1,159 @@
+// Plays several files back to back as one input (e.g. a match recorded in
+// parts), so that events spanning a file boundary aren't split in two.
+
+use crate::error::FfmpegError;
+use crate::pipeline::Hypetrigger;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Input args for a list of files read with ffmpeg's `concat` demuxer. Without
+/// `-safe 0`, it refuses absolute paths.
+pub const CONCAT_INPUT_ARGS: [&str; 4] = ["-f", "concat", "-safe", "0"];
+
+/// A list of files for ffmpeg's `concat` demuxer, written to a temporary file,
+/// which is deleted when this is dropped.
+#[derive(Debug)]
+pub struct ConcatList {
+    /// Where the list was written
+    pub path: PathBuf,
+
+    /// The files in the list, in order
+    pub files: Vec<PathBuf>,
+}
+
+impl ConcatList {
+    /// Write a list of the given files to a new temporary file.
+    pub fn write(files: &[PathBuf]) -> std::io::Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "hypetrigger-concat-{}-{}.txt",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, concat_list_contents(files)?)?;
+        Ok(Self {
+            path,
+            files: files.to_vec(),
+        })
+    }
+}
+
+impl Drop for ConcatList {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The contents of a `concat` list of the given files, one `file '...'` line
+/// each. Paths are made absolute, since the demuxer would otherwise look for
+/// them next to the list rather than in the current directory.
+pub fn concat_list_contents(files: &[PathBuf]) -> std::io::Result<String> {
+    let mut contents = String::new();
+    for file in files {
+        let file = std::path::absolute(file)?;
+        // Quotes can't be escaped inside quotes, so they're closed around it
+        let file = file.to_string_lossy().replace('\'', r"'\''");
+        contents.push_str(&format!("file '{}'\n", file));
+    }
+    Ok(contents)
+}
+
+impl Hypetrigger {
+    /// Setter for the files to play one after another as the input
+    pub fn set_concat_inputs<P: AsRef<Path>>(&mut self, files: &[P]) -> &mut Self {
+        self.concat_inputs = files
+            .iter()
+            .map(|file| file.as_ref().to_path_buf())
+            .collect();
+        self
+    }
+
+    /// Write the `concat` list of the `concat_inputs` for ffmpeg to read
+    /// instead of the `input`, if there are any. Each run writes its own, and
+    /// keeps it until ffmpeg is done with it.
+    pub fn write_concat_list(&self) -> Result<Option<ConcatList>, FfmpegError> {
+        if self.concat_inputs.is_empty() {
+            return Ok(None);
+        }
+        ConcatList::write(&self.concat_inputs)
+            .map(Some)
+            .map_err(FfmpegError::ConcatList)
+    }
+
+    /// The `concat_list` to pass to ffmpeg, which is needed if (and only used
+    /// if) there are `concat_inputs`.
+    pub(crate) fn check_concat_list<'a>(
+        &self,
+        concat_list: Option<&'a Path>,
+    ) -> Result<Option<&'a Path>, FfmpegError> {
+        if self.concat_inputs.is_empty() {
+            return Ok(None);
+        }
+        match concat_list {
+            Some(concat_list) => Ok(Some(concat_list)),
+            None => Err(FfmpegError::InvalidConfig(
+                "`concat_inputs` need the list from `write_concat_list`".to_string(),
+            )),
+        }
+    }
+}
+
+/// The path of a `concat` list, as passed to the functions that build ffmpeg
+/// commands.
+pub fn concat_list_path(concat_list: &Option<ConcatList>) -> Option<&Path> {
+    concat_list.as_ref().map(|list| list.path.as_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::concat_list_contents;
+    use crate::{
+        error::{NoneError, Result},
+        pipeline::{HwAccel, Hypetrigger},
+        simple_trigger::SimpleTrigger,
+    };
+    use std::path::PathBuf;
+
+    #[test]
+    fn concat_inputs() -> Result<()> {
+        let contents = concat_list_contents(&[
+            PathBuf::from("/videos/part 1.mp4"),
+            PathBuf::from("/videos/it's part 2.mp4"),
+        ])?;
+        assert_eq!(
+            contents,
+            "file '/videos/part 1.mp4'\nfile '/videos/it'\\''s part 2.mp4'\n"
+        );
+
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_hwaccel(HwAccel::None)
+            .set_concat_inputs(&["part1.mp4", "part2.mp4"])
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        hypetrigger.validate()?;
+        // Building the command doesn't write a list
+        assert!(hypetrigger.build_ffmpeg_command().is_err());
+
+        let list = hypetrigger.write_concat_list()?.ok_or(NoneError)?;
+        let command = hypetrigger.ffmpeg_command(Some(&list.path))?;
+        let command: Vec<_> = command.get_args().collect();
+        let i = command
+            .iter()
+            .position(|arg| *arg == "-i")
+            .ok_or(NoneError)?;
+        assert_eq!(command[i - 4..i], ["-f", "concat", "-safe", "0"]);
+        assert_eq!(command[i + 1], list.path.as_os_str());
+        let contents = std::fs::read_to_string(&list.path)?;
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.contains("part2.mp4'"));
+
+        // Each run gets its own list, deleted when it's done with
+        let other = hypetrigger.clone().write_concat_list()?.ok_or(NoneError)?;
+        assert_ne!(other.path, list.path);
+        let path = list.path.clone();
+        drop(list);
+        assert!(!path.exists());
+        assert!(other.path.exists());
+        Ok(())
+    }
+}
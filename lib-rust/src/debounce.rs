@@ -0,0 +1,91 @@
+use crate::trigger::{Trigger, TriggerPayload};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Suppresses repeats of the same result within a time window, e.g. so that
+/// an OCR Trigger reading the same kill feed on 30 consecutive frames only
+/// reports it once. A different result is always let through straight away.
+///
+/// The window is measured in the input's own time (`Frame::timestamp`)
+/// from when the result was last let through, so it behaves the same no
+/// matter how fast the input is processed.
+#[derive(Debug)]
+pub struct Debouncer<T> {
+    /// How long to suppress repeats of the same result for.
+    pub window: Duration,
+
+    /// The last result that was let through, and its timestamp
+    pub last: Mutex<Option<(T, f64)>>,
+}
+
+impl<T: PartialEq + Clone> Debouncer<T> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Whether to report this result, found at the given timestamp (in
+    /// seconds), or suppress it as a repeat.
+    pub fn should_emit(&self, value: &T, timestamp: f64) -> bool {
+        let Ok(mut last) = self.last.lock() else {
+            return true;
+        };
+        if let Some((last_value, last_timestamp)) = &*last {
+            let elapsed = timestamp - last_timestamp;
+            if last_value == value && elapsed >= 0.0 && elapsed < self.window.as_secs_f64() {
+                return false;
+            }
+        }
+        *last = Some((value.clone(), timestamp));
+        true
+    }
+}
+
+/// A `TriggerPayload` compared by `TriggerPayload::is_same_result`, to
+/// debounce the events of a Trigger.
+#[derive(Clone, Debug)]
+pub(crate) struct SameResult(pub TriggerPayload);
+
+impl PartialEq for SameResult {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.is_same_result(&other.0)
+    }
+}
+
+/// The state for debouncing the events of a Trigger, if it asked for it with
+/// `Trigger::get_debounce`. It's shared with the worker of an `AsyncTrigger`,
+/// which reports events of its own.
+pub(crate) fn event_debouncer(trigger: &dyn Trigger) -> Option<Arc<Debouncer<SameResult>>> {
+    trigger
+        .get_debounce()
+        .map(|window| Arc::new(Debouncer::new(window)))
+}
+
+/// The state for debouncing the events of each Trigger, by `trigger_id`.
+pub(crate) fn event_debouncers(
+    triggers: &[Arc<dyn Trigger>],
+) -> Vec<Option<Arc<Debouncer<SameResult>>>> {
+    triggers
+        .iter()
+        .map(|trigger| event_debouncer(trigger.as_ref()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debouncer;
+    use std::time::Duration;
+
+    #[test]
+    fn debounce() {
+        let debouncer = Debouncer::new(Duration::from_secs(2));
+        assert!(debouncer.should_emit(&"a", 0.0));
+        assert!(!debouncer.should_emit(&"a", 1.0));
+        assert!(debouncer.should_emit(&"b", 1.5)); // changed
+        assert!(debouncer.should_emit(&"a", 2.0));
+        assert!(!debouncer.should_emit(&"a", 3.5));
+        assert!(debouncer.should_emit(&"a", 4.0)); // window is over
+    }
+}
@@ -1,9 +1,40 @@
 use crate::error::NoneError;
-use crate::trigger::Frame;
+use crate::trigger::{current_trigger_id, Frame};
 use crate::{error::Result, util::format_seconds};
-use image::{DynamicImage, RgbImage};
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::env::current_exe;
-use std::io::stdin;
+use std::fs::File;
+use std::io::{stdin, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// How `save_screenshot` encodes images on disk.
+///
+/// WebP isn't offered, since the version of `image` used here (pinned to match
+/// `photon-rs`) can only decode it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScreenshotFormat {
+    /// Lossless, but large
+    #[default]
+    Png,
+
+    /// Lossy, with a quality from 1 to 100. Much smaller than PNG, which adds
+    /// up when saving every frame; quality 80 is usually still plenty to see
+    /// what a Trigger was looking at.
+    Jpeg { quality: u8 },
+}
+
+impl ScreenshotFormat {
+    /// The file extension for this format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "png",
+            ScreenshotFormat::Jpeg { .. } => "jpg",
+        }
+    }
+}
 
 /// Write image to disk and pause execution.
 pub fn debug_image(image: &DynamicImage) -> Result<()> {
@@ -13,20 +44,20 @@ pub fn debug_image(image: &DynamicImage) -> Result<()> {
         .join("debug-image.bmp");
     image.save(&preview_path)?;
 
-    println!("[debug] Preview image saved to {}", &preview_path.display());
-    println!("[debug] Press any key to continue...");
+    log_debug!("[debug] Preview image saved to {}", &preview_path.display());
+    log_debug!("[debug] Press any key to continue...");
     stdin().read_line(&mut String::new())?;
     Ok(())
 }
 
 /// Write current frame to disk and pause execution.
 pub fn debug_frame(frame: &Frame) -> Result<()> {
-    println!(
+    log_debug!(
         "[debug] Execution paused on frame {} ({})",
         frame.frame_num,
         format_seconds(frame.timestamp)
     );
-    debug_rgb(&frame.image)
+    debug_image(&frame.image)
 }
 
 /// Write image to disk and pause execution.
@@ -40,3 +71,86 @@ pub fn debug_photon_image(image: &photon_rs::PhotonImage) -> Result<()> {
     let dynamic_image = photon_rs::helpers::dyn_image_from_raw(image);
     debug_image(&dynamic_image)
 }
+
+/// Write an image to `dir` named after the Trigger and frame number, without
+/// pausing. Returns the path it was saved to. While a pipeline is running the
+/// Trigger, the name includes its index too (see `current_trigger_id`), e.g.
+/// `tesseract_1_42.png` for frame 42 of the second Trigger; otherwise it's
+/// just `tesseract_42.png`.
+pub fn save_screenshot(
+    dir: &Path,
+    trigger_name: &str,
+    frame_num: u64,
+    image: &DynamicImage,
+    format: ScreenshotFormat,
+) -> Result<PathBuf> {
+    let name = match current_trigger_id() {
+        Some(trigger_id) => format!("{}_{}_{}", trigger_name, trigger_id, frame_num),
+        None => format!("{}_{}", trigger_name, frame_num),
+    };
+    let path = dir.join(format!("{}.{}", name, format.extension()));
+    match format {
+        ScreenshotFormat::Png => image.save(&path)?,
+        ScreenshotFormat::Jpeg { quality } => {
+            // JPEG has no alpha channel or 16-bit depth
+            let mut file = BufWriter::new(File::create(&path)?);
+            DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_to(&mut file, ImageOutputFormat::Jpeg(quality))?;
+        }
+    }
+    Ok(path)
+}
+
+/// Write an image to `dir` named after the Trigger and frame number. See
+/// `save_screenshot`.
+#[cfg(feature = "photon")]
+pub fn save_photon_screenshot(
+    dir: &Path,
+    trigger_name: &str,
+    frame_num: u64,
+    image: &photon_rs::PhotonImage,
+    format: ScreenshotFormat,
+) -> Result<PathBuf> {
+    let dynamic_image = photon_rs::helpers::dyn_image_from_raw(image);
+    save_screenshot(dir, trigger_name, frame_num, &dynamic_image, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{save_screenshot, ScreenshotFormat};
+    use crate::error::Result;
+    use crate::trigger::enter_trigger;
+    use image::{DynamicImage, GenericImageView};
+
+    #[test]
+    fn screenshot() -> Result<()> {
+        let dir = std::env::temp_dir();
+        let image = DynamicImage::new_rgb8(4, 2);
+        for format in [
+            ScreenshotFormat::Png,
+            ScreenshotFormat::Jpeg { quality: 80 },
+        ] {
+            let path = save_screenshot(&dir, "test", std::process::id() as u64, &image, format)?;
+            let saved = image::open(&path)?;
+            std::fs::remove_file(&path)?;
+            assert_eq!(
+                path.extension().and_then(|e| e.to_str()),
+                Some(format.extension())
+            );
+            assert_eq!(saved.dimensions(), (4, 2));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn screenshot_trigger_id() -> Result<()> {
+        let dir = std::env::temp_dir();
+        let image = DynamicImage::new_rgb8(4, 2);
+        let frame_num = std::process::id() as u64;
+        let _trigger = enter_trigger(Some(3));
+        let path = save_screenshot(&dir, "test", frame_num, &image, ScreenshotFormat::Png)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(path, dir.join(format!("test_3_{}.png", frame_num)));
+        Ok(())
+    }
+}
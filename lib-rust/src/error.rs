@@ -1,8 +1,10 @@
 use std::error::Error as StdError;
 use std::fmt::{Display, Formatter};
 use std::io;
+use std::process::ExitStatus;
 use std::sync::mpsc::SendError;
 use std::sync::PoisonError;
+use std::time::Duration;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -64,6 +66,80 @@ impl Display for NoneError {
 }
 impl std::error::Error for NoneError {}
 
+/// Represents a failure to start ffmpeg.
+#[derive(Debug)]
+pub enum FfmpegError {
+    /// No ffmpeg binary was configured, and none could be found next to the
+    /// current executable or on the `PATH`.
+    BinaryNotFound,
+
+    /// The pipeline configuration can't be translated into a valid ffmpeg
+    /// command.
+    InvalidConfig(String),
+
+    /// The ffmpeg process couldn't be spawned.
+    SpawnFailed(io::Error),
+
+    /// The list of `concat_inputs` couldn't be written for ffmpeg to read.
+    ConcatList(io::Error),
+
+    /// ffmpeg exited with an error before producing any frames.
+    Exited(FfmpegFailure),
+
+    /// ffmpeg stopped sending data on stdout for longer than the
+    /// `stdout_read_timeout`, and was killed.
+    StdoutTimeout(Duration),
+}
+impl Display for FfmpegError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FfmpegError::BinaryNotFound => write!(
+                f,
+                "could not find ffmpeg next to the current executable or on the PATH"
+            ),
+            FfmpegError::InvalidConfig(message) => write!(f, "invalid config: {}", message),
+            FfmpegError::SpawnFailed(e) => write!(f, "failed to spawn ffmpeg: {}", e),
+            FfmpegError::ConcatList(e) => write!(f, "failed to write the concat list: {}", e),
+            FfmpegError::Exited(failure) => write!(
+                f,
+                "ffmpeg exited with {} before producing any frames:\n{}",
+                failure.status,
+                failure.stderr_tail.join("\n")
+            ),
+            FfmpegError::StdoutTimeout(timeout) => write!(
+                f,
+                "ffmpeg sent nothing on stdout for {:?}, so it was killed",
+                timeout
+            ),
+        }
+    }
+}
+/// Details of an ffmpeg process that exited unsuccessfully, e.g. because its
+/// input doesn't exist or uses an unsupported codec.
+#[derive(Debug, Clone)]
+pub struct FfmpegFailure {
+    /// Exit status of the ffmpeg process
+    pub status: ExitStatus,
+
+    /// The last lines ffmpeg wrote to stderr, which usually say what went wrong
+    pub stderr_tail: Vec<String>,
+}
+
+impl std::error::Error for FfmpegError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            FfmpegError::SpawnFailed(e) | FfmpegError::ConcatList(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<FfmpegError> for Error {
+    fn from(e: FfmpegError) -> Self {
+        Error::from_std(e)
+    }
+}
+
 impl From<NoneError> for Error {
     fn from(e: NoneError) -> Self {
         Error::from_std(e)
@@ -136,6 +212,13 @@ impl From<tesseract::plumbing::TessBaseApiGetUtf8TextError> for Error {
     }
 }
 
+#[cfg(feature = "tesseract")]
+impl From<tesseract::SetVariableError> for Error {
+    fn from(e: tesseract::SetVariableError) -> Self {
+        Error::from_std(e)
+    }
+}
+
 #[cfg(feature = "tensorflow")]
 impl From<tensorflow::Status> for Error {
     fn from(e: tensorflow::Status) -> Self {
@@ -0,0 +1,405 @@
+// Skips an expensive classifier on frames it has already seen, for mostly
+// static screens where the same few images come up over and over.
+
+use crate::error::Result;
+use crate::pipeline::PixelFormat;
+use crate::trigger::{Frame, Trigger, TriggerPayload};
+use image::{DynamicImage, GenericImageView};
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub type CachedTriggerClassifier<T> = Arc<dyn Fn(&Frame) -> Result<T> + Send + Sync>;
+pub type CachedTriggerCallback<T> = Arc<dyn Fn(&Frame, &T) + Send + Sync>;
+
+/// A Trigger that runs a classifier on each frame, and remembers its results
+/// for the most recently seen images, so that an identical image gets the
+/// cached result instead of running the classifier again. The callback gets
+/// the result for every frame either way, and it's reported as a
+/// `TriggerPayload::Custom` event holding a `CachedResult`, along with the
+/// hash of the image.
+///
+/// Images are identified by a hash of their pixels, so only exact duplicates
+/// are skipped; see `MotionGateTrigger` to also skip frames that only changed
+/// a little. Hash collisions are possible in theory, but vanishingly unlikely
+/// among the handful of images kept in the cache.
+pub struct CachedTrigger<T> {
+    pub classify: CachedTriggerClassifier<T>,
+    pub callback: CachedTriggerCallback<T>,
+
+    /// Optionally only hash this region of each frame, as
+    /// `(x, y, width, height)` in pixels, e.g. the part the classifier looks
+    /// at. The whole frame is still passed to the classifier, but this is the
+    /// region reported by `get_region`, e.g. to draw in previews.
+    pub region: Option<(u32, u32, u32, u32)>,
+
+    /// How many results to keep. The least recently used one is forgotten
+    /// first.
+    pub capacity: usize,
+
+    /// The pixel format to receive frames in, if not the pipeline's own.
+    pub pixel_format: Option<PixelFormat>,
+
+    /// The cached results, with the most recently used last
+    pub cache: Mutex<VecDeque<(u64, T)>>,
+
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+}
+
+/// The payload of each event from a `CachedTrigger`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResult<T> {
+    /// What the classifier said about the image, this time or when it was first
+    /// seen
+    pub result: T,
+
+    /// The `image_hash` of the image (or its `region`) the result is for,
+    /// e.g. to group the frames that showed the same image
+    pub hash: u64,
+}
+
+/// How often a `CachedTrigger` found a result in its cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of frames that were found in the cache, from 0 to 1.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl<T> Trigger for CachedTrigger<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn on_frame(&self, frame: &Frame) -> Result<()> {
+        self.on_frame_events(frame, &mut vec![]).map(|_| ())
+    }
+
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
+        let hash = match self.region {
+            Some(region) => region_hash(&frame.image, region),
+            None => image_hash(&frame.image),
+        };
+        let result = match self.lookup(hash)? {
+            Some(result) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                metric!(counter!("hypetrigger_cache_hits_total").increment(1));
+                result
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                metric!(counter!("hypetrigger_cache_misses_total").increment(1));
+                let result = (self.classify)(frame)?;
+                self.insert(hash, result.clone())?;
+                result
+            }
+        };
+        (self.callback)(frame, &result);
+        payloads.push(TriggerPayload::custom(CachedResult { result, hash }));
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.pixel_format
+    }
+
+    fn get_region(&self, _width: u32, _height: u32) -> Option<(u32, u32, u32, u32)> {
+        self.region
+            .map(|(x, y, width, height)| (x, y, x + width, y + height))
+    }
+}
+
+impl<T: Clone> CachedTrigger<T> {
+    pub fn new<C, F>(classify: C, callback: F) -> Self
+    where
+        C: Fn(&Frame) -> Result<T> + Send + Sync + 'static,
+        F: Fn(&Frame, &T) + Send + Sync + 'static,
+    {
+        Self {
+            classify: Arc::new(classify),
+            callback: Arc::new(callback),
+            region: None,
+            capacity: 16,
+            pixel_format: None,
+            cache: Mutex::new(VecDeque::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Setter for the region of each frame to hash
+    pub fn set_region(mut self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.region = Some((x, y, width, height));
+        self
+    }
+
+    /// Setter for the number of results to keep
+    pub fn set_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Setter for the pixel format to receive frames in
+    pub fn set_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = Some(pixel_format);
+        self
+    }
+
+    /// How many frames were found in the cache so far.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Find a cached result, marking it as the most recently used.
+    fn lookup(&self, hash: u64) -> Result<Option<T>> {
+        let mut cache = self.cache.lock()?;
+        let Some(index) = cache.iter().position(|(cached, _)| *cached == hash) else {
+            return Ok(None);
+        };
+        let entry = cache.remove(index).expect("index is in bounds");
+        let result = entry.1.clone();
+        cache.push_back(entry);
+        Ok(Some(result))
+    }
+
+    fn insert(&self, hash: u64, result: T) -> Result<()> {
+        let mut cache = self.cache.lock()?;
+        cache.push_back((hash, result));
+        while cache.len() > self.capacity {
+            cache.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// A fast, non-cryptographic hash of an image's size, color type and pixels.
+/// The pixels are mixed in 8 bytes at a time (as with `FxHasher`), with a
+/// final scramble of the bits (from MurmurHash3) so that the whole hash is
+/// worth comparing, not just its high bits.
+pub fn image_hash(image: &DynamicImage) -> u64 {
+    let mut hasher = PixelHasher::new(image, image.width(), image.height());
+    hasher.write(image.as_bytes());
+    hasher.finish()
+}
+
+/// The `image_hash` of a region of an image, as `(x, y, width, height)` in
+/// pixels, without copying it out first: its rows are hashed where they are.
+/// Like `crop_imm`, the region is clipped to the image.
+pub fn region_hash(image: &DynamicImage, (x, y, width, height): (u32, u32, u32, u32)) -> u64 {
+    let x = x.min(image.width());
+    let y = y.min(image.height());
+    let width = width.min(image.width() - x);
+    let height = height.min(image.height() - y);
+    let bytes_per_pixel = image.color().bytes_per_pixel() as usize;
+    let stride = image.width() as usize * bytes_per_pixel;
+    let bytes = image.as_bytes();
+    let mut hasher = PixelHasher::new(image, width, height);
+    for row in y..y + height {
+        let start = row as usize * stride + x as usize * bytes_per_pixel;
+        hasher.write(&bytes[start..start + width as usize * bytes_per_pixel]);
+    }
+    hasher.finish()
+}
+
+/// The state of an `image_hash` partway through the pixels, which can be
+/// written in pieces (e.g. row by row) without changing the result.
+struct PixelHasher {
+    hash: u64,
+
+    /// The start of a word that the next piece finishes
+    pending: [u8; 8],
+    pending_len: usize,
+
+    /// Number of bytes written so far
+    len: u64,
+}
+
+impl PixelHasher {
+    const SEED: u64 = 0x517c_c1b7_2722_0a95;
+
+    /// Start hashing the pixels of an image of the given size, with the
+    /// color type of `image`
+    fn new(image: &DynamicImage, width: u32, height: u32) -> Self {
+        let color = image.color();
+        let mut hasher = Self {
+            hash: 0,
+            pending: [0; 8],
+            pending_len: 0,
+            len: 0,
+        };
+        hasher.mix((width as u64) << 32 | height as u64);
+        hasher.mix((color.bytes_per_pixel() as u64) << 8 | color.channel_count() as u64);
+        hasher
+    }
+
+    fn mix(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(Self::SEED);
+    }
+
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if self.pending_len > 0 {
+            let n = bytes.len().min(8 - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + n].copy_from_slice(&bytes[..n]);
+            self.pending_len += n;
+            bytes = &bytes[n..];
+            if self.pending_len < 8 {
+                return;
+            }
+            self.mix(u64::from_le_bytes(self.pending));
+            self.pending_len = 0;
+        }
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            self.mix(u64::from_le_bytes(
+                word.try_into().expect("chunks are 8 bytes"),
+            ));
+        }
+        let rest = words.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    fn finish(mut self) -> u64 {
+        let mut rest = [0; 8];
+        rest[..self.pending_len].copy_from_slice(&self.pending[..self.pending_len]);
+        self.mix(u64::from_le_bytes(rest));
+        self.mix(self.len);
+
+        let mut hash = self.hash;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ hash >> 33
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{image_hash, region_hash, CachedResult, CachedTrigger};
+    use crate::{
+        error::Result,
+        trigger::{Frame, Trigger, TriggerPayload},
+    };
+    use image::{DynamicImage, GrayImage, Luma};
+    use std::sync::{Arc, Mutex};
+
+    fn frame(frame_num: u64, brightness: u8) -> Frame {
+        let image = GrayImage::from_pixel(8, 8, Luma([brightness]));
+        Frame {
+            image: DynamicImage::ImageLuma8(image),
+            frame_num,
+            timestamp: frame_num as f64,
+            input_index: 0,
+        }
+    }
+
+    #[test]
+    fn cached_trigger() -> Result<()> {
+        let classified = Arc::new(Mutex::new(vec![]));
+        let classified_clone = classified.clone();
+        let results = Arc::new(Mutex::new(vec![]));
+        let results_clone = results.clone();
+        let trigger = CachedTrigger::new(
+            move |frame| {
+                classified_clone.lock()?.push(frame.frame_num);
+                Ok(frame.image.as_bytes()[0] > 128)
+            },
+            move |frame, &bright| {
+                results_clone
+                    .lock()
+                    .unwrap()
+                    .push((frame.frame_num, bright))
+            },
+        )
+        .set_capacity(2);
+
+        for (frame_num, brightness) in [0, 0, 255, 0, 100, 200, 0].into_iter().enumerate() {
+            trigger.on_frame(&frame(frame_num as u64, brightness))?;
+        }
+
+        // 0 was pushed out of the cache by 100 and 200
+        assert_eq!(*classified.lock()?, vec![0, 2, 4, 5, 6]);
+        assert_eq!(results.lock()?.len(), 7);
+        assert_eq!(results.lock()?[3], (3, false));
+        let stats = trigger.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 5));
+
+        // Events carry the hash of the image along with the result
+        let mut payloads = vec![];
+        let bright = frame(7, 255);
+        assert!(trigger
+            .on_frame_events(&bright, &mut payloads)?
+            .is_continue());
+        let payload = payloads.first().and_then(TriggerPayload::downcast_ref);
+        assert_eq!(
+            payload,
+            Some(&CachedResult {
+                result: true,
+                hash: image_hash(&bright.image),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn image_hashes() {
+        let hash = |image: GrayImage| image_hash(&DynamicImage::ImageLuma8(image));
+        let black = hash(GrayImage::new(8, 8));
+        assert_eq!(black, hash(GrayImage::new(8, 8)));
+        assert_ne!(black, hash(GrayImage::new(4, 16)));
+        assert_ne!(black, hash(GrayImage::new(9, 8)));
+        assert_ne!(
+            black,
+            image_hash(&DynamicImage::ImageLuma16(image::ImageBuffer::new(8, 8)))
+        );
+        let mut image = GrayImage::new(8, 8);
+        image.put_pixel(7, 7, Luma([1]));
+        assert_ne!(black, hash(image));
+    }
+
+    #[test]
+    fn region_hashes() {
+        // Rows that don't line up with the 8 bytes hashed at a time
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(13, 7, |x, y| {
+            image::Rgb([x as u8, y as u8, (x * y) as u8])
+        }));
+        for region in [(0, 0, 13, 7), (2, 1, 5, 4), (3, 6, 1, 1), (10, 4, 8, 8)] {
+            let (x, y, width, height) = region;
+            assert_eq!(
+                region_hash(&image, region),
+                image_hash(&image.crop_imm(x, y, width, height)),
+                "{:?}",
+                region
+            );
+        }
+        assert_ne!(
+            region_hash(&image, (0, 0, 5, 4)),
+            region_hash(&image, (1, 0, 5, 4))
+        );
+    }
+
+    #[test]
+    fn region() {
+        let trigger = CachedTrigger::new(|_| Ok(()), |_, _| {}).set_region(2, 1, 4, 3);
+        assert_eq!(trigger.get_region(8, 8), Some((2, 1, 6, 4)));
+    }
+}
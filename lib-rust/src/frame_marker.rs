@@ -0,0 +1,181 @@
+// Marks each frame ffmpeg sends with its own number, so that the pipeline can
+// tell if it ever loses its place in ffmpeg's output (e.g. after a short read)
+// and get back in step, instead of quietly running the Triggers on the wrong
+// frames for the rest of the input.
+
+use crate::pipeline::{Hypetrigger, PixelFormat};
+
+/// Most bits of the frame number in each marker, one pixel each. Frames
+/// narrower than this get as many as they have pixels.
+pub const FRAME_MARKER_BITS: u32 = 32;
+
+impl Hypetrigger {
+    /// Setter for whether to mark each frame with its number
+    pub fn set_frame_markers(&mut self, frame_markers: bool) -> &mut Self {
+        self.frame_markers = frame_markers;
+        self
+    }
+
+    /// The filters that add the marker row to the bottom of each frame: the
+    /// bits of ffmpeg's frame number `N`, low bit first, as black and white
+    /// pixels. The frame is converted to the output pixel format first, so
+    /// that chroma subsampling can't swallow the extra row.
+    pub fn frame_marker_filter(&self) -> String {
+        let bit = "255*mod(floor(N/pow(2,X)),2)";
+        format!(
+            "format={},split[unmarked][marker_bits];\
+             [marker_bits]crop=min(iw\\,{}):1:0:0,geq=r='{2}':g='{2}':b='{2}'[marker];\
+             [unmarked]pad=iw:ih+1[padded];[padded][marker]overlay=0:main_h-1",
+            self.output_pixel_format().as_ffmpeg_str(),
+            FRAME_MARKER_BITS,
+            bit
+        )
+    }
+}
+
+/// Number of bits in the marker of a frame of the given width.
+pub fn frame_marker_bits(width: u32) -> u32 {
+    width.min(FRAME_MARKER_BITS)
+}
+
+/// Read the frame number from the marker row of a frame, i.e. its last row of
+/// pixels. `None` if any of its pixels isn't clearly black or white, as when
+/// the row is really part of some other frame's pixels.
+pub fn read_frame_marker(row: &[u8], format: PixelFormat, width: u32) -> Option<u64> {
+    let bytes_per_pixel = format.bytes_per_pixel() as usize;
+    // The high byte of the first sample of each pixel, little-endian
+    let high_byte = (format.bit_depth() / 8 - 1) as usize;
+    let mut marker = 0;
+    for bit in 0..frame_marker_bits(width) as usize {
+        match row.get(bit * bytes_per_pixel + high_byte)? {
+            0..=63 => {}
+            192.. => marker |= 1 << bit,
+            _ => return None,
+        }
+    }
+    Some(marker)
+}
+
+/// The frame number closest to `frame_num` that has the given marker, for a
+/// marker of `bits` bits that wraps around on long inputs.
+pub fn resync_frame_num(frame_num: u64, marker: u64, bits: u32) -> u64 {
+    let modulus = 1_u64 << bits;
+    let candidate = frame_num - frame_num % modulus + marker;
+    if candidate > frame_num + modulus / 2 && candidate >= modulus {
+        candidate - modulus
+    } else if candidate + modulus / 2 < frame_num {
+        candidate + modulus
+    } else {
+        candidate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_frame_marker, resync_frame_num};
+    use crate::{
+        error::Result,
+        pipeline::{HwAccel, Hypetrigger, PixelFormat, VsyncMode},
+        simple_trigger::SimpleTrigger,
+        util::FfmpegLogLevel,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// A marker row for the given frame number, as ffmpeg would draw it.
+    fn marker_row(frame_num: u64, format: PixelFormat, width: u32) -> Vec<u8> {
+        let bytes_per_pixel = format.bytes_per_pixel() as usize;
+        let mut row = vec![0; width as usize * bytes_per_pixel];
+        for (x, pixel) in row.chunks_mut(bytes_per_pixel).enumerate() {
+            if x < 32 && frame_num >> x & 1 == 1 {
+                pixel.fill(255);
+            }
+        }
+        row
+    }
+
+    #[test]
+    fn frame_markers() {
+        for format in [PixelFormat::Rgb24, PixelFormat::Gray8, PixelFormat::Rgb48] {
+            let row = marker_row(12345, format, 40);
+            assert_eq!(read_frame_marker(&row, format, 40), Some(12345));
+        }
+        // Narrow frames only hold the low bits
+        let row = marker_row(0b1011, PixelFormat::Gray8, 3);
+        assert_eq!(read_frame_marker(&row, PixelFormat::Gray8, 3), Some(0b011));
+
+        // Mid-grey isn't a marker
+        let mut row = marker_row(7, PixelFormat::Gray8, 32);
+        row[4] = 128;
+        assert_eq!(read_frame_marker(&row, PixelFormat::Gray8, 32), None);
+
+        assert_eq!(resync_frame_num(10, 12, 32), 12);
+        assert_eq!(resync_frame_num(10, 7, 32), 7);
+        assert_eq!(resync_frame_num(17, 1, 4), 17);
+        assert_eq!(resync_frame_num(15, 1, 4), 17);
+        assert_eq!(resync_frame_num(17, 15, 4), 15);
+    }
+
+    #[test]
+    fn frame_marker_filter() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_input("test.mp4")
+            .set_hwaccel(HwAccel::None)
+            .set_frame_markers(true)
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        let command = hypetrigger.build_ffmpeg_command()?;
+        let filter = &command[command.iter().position(|arg| arg == "-filter:v").unwrap() + 1];
+        assert!(filter.starts_with("fps=2,format=rgb24,split[unmarked][marker_bits];"));
+        assert!(filter.ends_with("[padded][marker]overlay=0:main_h-1"));
+
+        // ffmpeg's duplicates would carry the marker of the frame they copy
+        hypetrigger.set_vsync(VsyncMode::Cfr);
+        assert!(hypetrigger.validate().is_err());
+        Ok(())
+    }
+
+    /// A fake ffmpeg that sends 32x2 gray frames with marker rows, but loses
+    /// frame 2, and garbles the marker of frame 5.
+    #[cfg(unix)]
+    #[test]
+    fn lost_frame_markers() -> Result<()> {
+        let mut script =
+            String::from("#!/usr/bin/env python3\nimport sys\nout = sys.stdout.buffer\n");
+        for frame_num in [0_u64, 1, 3, 4, 5, 6] {
+            let mut row = marker_row(frame_num, PixelFormat::Gray8, 32);
+            if frame_num == 5 {
+                row[0] = 128;
+            }
+            script += &format!(
+                "out.write(bytes([{}]) * 64 + bytes({:?}))\n",
+                frame_num, row
+            );
+        }
+        let ffmpeg = crate::test_util::fake_ffmpeg("frame-markers", &script)?;
+
+        let frames = Arc::new(Mutex::new(vec![]));
+        let frames_clone = frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .set_input("test.mp4")
+            .set_hwaccel(HwAccel::None)
+            .set_scale(32, 2)
+            .set_pixel_format(PixelFormat::Gray8)
+            .set_ffmpeg_loglevel(FfmpegLogLevel::Warning)
+            .set_frame_markers(true)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                let bytes = frame.image.as_bytes();
+                assert_eq!(bytes.len(), 64);
+                if let Ok(mut frames) = frames_clone.lock() {
+                    frames.push((frame.frame_num, bytes[0] as u64));
+                }
+            }));
+        hypetrigger.run()?;
+
+        // Back in step after the lost frame, and the garbled one is skipped
+        let frames = frames.lock()?;
+        assert_eq!(*frames, [(0, 0), (1, 1), (3, 3), (4, 4), (6, 6)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+// Decouples reading and dispatching frames from the ffmpeg subprocess, so the
+// routing of frames to Triggers can be exercised with scripted frames, without
+// ffmpeg or a real video.
+
+use crate::pipeline::PixelFormat;
+use std::io::Read;
+
+/// Somewhere raw frames can be read from, the way the pipeline reads them from
+/// ffmpeg's stdout: one frame after another, each `width * height` pixels in
+/// the pipeline's `output_pixel_format()`, with no padding. Used with
+/// `Hypetrigger::attach_source`.
+pub trait FrameSource: Read {
+    /// The width and height of each frame, which ffmpeg would otherwise report
+    /// on stderr.
+    fn output_size(&self) -> (u32, u32);
+}
+
+/// A `FrameSource` that plays back a scripted sequence of frames, for testing
+/// Triggers and the pipeline itself.
+#[derive(Debug, Clone)]
+pub struct MockFrameSource {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+
+    /// All of the scripted output, as it would come from ffmpeg
+    pub data: Vec<u8>,
+
+    /// How much of `data` has been read so far
+    pub position: usize,
+}
+
+impl MockFrameSource {
+    pub fn new(width: u32, height: u32, pixel_format: PixelFormat) -> Self {
+        Self {
+            width,
+            height,
+            pixel_format,
+            data: vec![],
+            position: 0,
+        }
+    }
+
+    /// Size of a single frame, in bytes.
+    pub fn frame_size(&self) -> usize {
+        self.pixel_format.frame_size(self.width, self.height)
+    }
+
+    /// Add a frame with every byte set to `value`, like the frames of the
+    /// stand-in ffmpeg used by the tests.
+    pub fn push_fill(&mut self, value: u8) -> &mut Self {
+        let frame = vec![value; self.frame_size()];
+        self.push_bytes(&frame)
+    }
+
+    /// Add a frame where each pixel is given by `pixel(x, y)`, which must
+    /// return one value per byte of the pixel format.
+    pub fn push_pattern<F>(&mut self, pixel: F) -> &mut Self
+    where
+        F: Fn(u32, u32) -> Vec<u8>,
+    {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.data.extend(pixel(x, y));
+            }
+        }
+        self
+    }
+
+    /// Add raw bytes to the output, which don't have to line up with the
+    /// frames (e.g. to script a truncated last frame).
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+}
+
+impl Read for MockFrameSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = &self.data[self.position..];
+        let len = remaining.len().min(buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl FrameSource for MockFrameSource {
+    fn output_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockFrameSource;
+    use crate::{
+        error::Result,
+        frame_cache::{CachedResult, CachedTrigger},
+        pipeline::{Hypetrigger, PixelFormat},
+        simple_trigger::SimpleTrigger,
+        trigger::{Frame, Trigger, TriggerPayload},
+    };
+    use std::ops::ControlFlow;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn routing() -> Result<()> {
+        struct Recorder(Arc<Mutex<Vec<(u64, u8)>>>, Option<f64>);
+        impl Trigger for Recorder {
+            fn on_frame(&self, frame: &Frame) -> Result<()> {
+                let first_byte = frame.image.as_bytes()[0];
+                self.0.lock()?.push((frame.frame_num, first_byte));
+                Ok(())
+            }
+            fn get_fps(&self) -> Option<f64> {
+                self.1
+            }
+        }
+
+        let mut source = MockFrameSource::new(4, 2, PixelFormat::Gray8);
+        for value in [10, 20, 30, 40] {
+            source.push_fill(value);
+        }
+        let every_frame = Arc::new(Mutex::new(vec![]));
+        let half_rate = Arc::new(Mutex::new(vec![]));
+        let summary = Hypetrigger::new()
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(Recorder(every_frame.clone(), None))
+            .add_trigger(Recorder(half_rate.clone(), Some(1.0)))
+            .attach_source(source)?;
+
+        assert_eq!(summary.frames_read, 4);
+        assert_eq!(summary.trigger_frames, vec![4, 2]);
+        assert_eq!(
+            *every_frame.lock()?,
+            vec![(0, 10), (1, 20), (2, 30), (3, 40)]
+        );
+        assert_eq!(*half_rate.lock()?, vec![(0, 10), (2, 30)]);
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_trigger() -> Result<()> {
+        struct Toggle(Arc<Mutex<Vec<u64>>>, bool);
+        impl Trigger for Toggle {
+            fn on_frame(&self, frame: &Frame) -> Result<()> {
+                self.0.lock()?.push(frame.frame_num);
+                Ok(())
+            }
+            fn get_enabled(&self) -> bool {
+                self.1
+            }
+        }
+
+        let mut source = MockFrameSource::new(4, 2, PixelFormat::Gray8);
+        source.push_fill(0).push_fill(1);
+        let frames = [(); 3].map(|_| Arc::new(Mutex::new(vec![])));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(Toggle(frames[0].clone(), true))
+            .add_trigger(Toggle(frames[1].clone(), false))
+            .add_trigger(Toggle(frames[2].clone(), true));
+        let summary = hypetrigger.attach_source(source)?;
+
+        // The Triggers after the disabled one keep their ids
+        assert_eq!(summary.trigger_frames, vec![2, 0, 2]);
+        assert!(frames[1].lock()?.is_empty());
+        assert_eq!(*frames[2].lock()?, vec![0, 1]);
+
+        let mut all_disabled = Hypetrigger::new();
+        all_disabled
+            .test_input()
+            .add_trigger(Toggle(frames[0].clone(), false));
+        assert!(all_disabled.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn trigger_events() -> Result<()> {
+        struct Reader;
+        impl Trigger for Reader {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn on_frame_events(
+                &self,
+                frame: &Frame,
+                payloads: &mut Vec<TriggerPayload>,
+            ) -> Result<ControlFlow<()>> {
+                let text = format!("value {}", frame.image.as_bytes()[0]);
+                payloads.push(TriggerPayload::Text(text));
+                Ok(ControlFlow::Continue(()))
+            }
+        }
+
+        let mut source = MockFrameSource::new(4, 2, PixelFormat::Gray8);
+        source.push_fill(10).push_fill(20);
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_clone = events.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .add_trigger(Reader)
+            .add_trigger(CachedTrigger::new(
+                |frame| Ok(frame.image.as_bytes()[0] > 15),
+                |_, _| {},
+            ))
+            .on_trigger_event(move |event| events_clone.lock().unwrap().push(event.clone()));
+        hypetrigger.attach_source(source)?;
+
+        let events = events.lock()?;
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| (event.trigger_id, event.frame_num))
+            .collect();
+        assert_eq!(summary, vec![(1, 0), (2, 0), (1, 1), (2, 1)]);
+        assert!(matches!(&events[2].payload, TriggerPayload::Text(text) if text == "value 20"));
+        let cached = events[3].payload.downcast_ref::<CachedResult<bool>>();
+        assert_eq!(cached.map(|cached| cached.result), Some(true));
+        Ok(())
+    }
+
+    #[test]
+    fn debounce() -> Result<()> {
+        // A custom Trigger that reads the first byte on every frame
+        struct Reader;
+        impl Trigger for Reader {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn on_frame_events(
+                &self,
+                frame: &Frame,
+                payloads: &mut Vec<TriggerPayload>,
+            ) -> Result<ControlFlow<()>> {
+                let text = format!("value {}", frame.image.as_bytes()[0]);
+                payloads.push(TriggerPayload::Text(text));
+                Ok(ControlFlow::Continue(()))
+            }
+            fn get_debounce(&self) -> Option<Duration> {
+                Some(Duration::from_secs(2))
+            }
+        }
+
+        // One frame a second
+        let mut source = MockFrameSource::new(4, 2, PixelFormat::Gray8);
+        for value in [10, 10, 10, 20, 10, 10, 10] {
+            source.push_fill(value);
+        }
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_clone = events.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_fps(1)
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(Reader)
+            .on_trigger_event(move |event| events_clone.lock().unwrap().push(event.frame_num));
+        hypetrigger.attach_source(source)?;
+
+        // Repeats are suppressed for 2 seconds, but a change is let through
+        assert_eq!(*events.lock()?, vec![0, 2, 3, 4, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn pattern_and_truncation() -> Result<()> {
+        let mut source = MockFrameSource::new(2, 2, PixelFormat::Rgb24);
+        source
+            .push_pattern(|x, y| vec![x as u8, y as u8, 7])
+            .push_bytes(&[1, 2, 3]); // not a whole frame
+        let pixels = Arc::new(Mutex::new(vec![]));
+        let pixels_clone = pixels.clone();
+        let summary = Hypetrigger::new()
+            .add_trigger(SimpleTrigger::new_rgb(move |image, _| {
+                let pixel = image.get_pixel(1, 1).0;
+                pixels_clone.lock().unwrap().push(pixel);
+            }))
+            .attach_source(source)?;
+
+        assert_eq!(summary.frames_read, 1);
+        assert_eq!(*pixels.lock()?, vec![[1, 1, 7]]);
+        Ok(())
+    }
+}
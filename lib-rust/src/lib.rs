@@ -1,7 +1,12 @@
+#![allow(clippy::four_forward_slashes)] // used for section headers
+
 #[cfg(not(target_arch = "wasm32"))]
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
+pub mod log;
+
 //// Image processing modules
 // Required for tesseract/tensorflow, but can be skipped for simple or custom
 // triggers. Also required for wasm builds and provides a rich image library.
@@ -23,23 +28,89 @@ pub mod tensorflow;
 
 //// Core functionality
 // Not WASM-safe; intended for Rust usage only. Involves spawning and attaching to ffmpeg processes.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod aligned;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod async_trigger;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod audio;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod batch;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod builder;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod buffer_pool;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod color_filter;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod concat;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod debounce;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod debug;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod error;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod frame_cache;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod frame_marker;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod frame_source;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod motion_gate;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod multi_pipe;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod pipeline;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pixel_probe;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod preview;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod probe;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod session;
+
+#[cfg(all(feature = "bytes", not(target_arch = "wasm32")))]
+pub mod shared_frame;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod simple_trigger;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod supervisor;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod trigger;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod trigger_config;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test_util;
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod tokio_pipeline;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod util;
@@ -0,0 +1,178 @@
+// Internal logging macros. By default these print to stdout/stderr (or the
+// writer from `set_log_writer`), but with the `tracing` feature enabled they
+// emit `tracing` events instead, so the host application can filter, format
+// and collect them.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Somewhere to write log messages instead of stdout and stderr, e.g. a file.
+pub type LogWriter = Arc<Mutex<dyn Write + Send>>;
+
+static LOG_WRITER: Mutex<Option<LogWriter>> = Mutex::new(None);
+
+thread_local! {
+    static SESSION_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Send all log messages to the given writer, one line per message, instead
+/// of printing them to stdout and stderr, e.g. for a daemon with no terminal.
+/// `None` goes back to printing them. This is global to the process, since
+/// not all messages come from a particular `Hypetrigger`. Has no effect with
+/// the `tracing` feature, where the tracing subscriber decides instead.
+pub fn set_log_writer(writer: Option<LogWriter>) {
+    if let Ok(mut log_writer) = LOG_WRITER.lock() {
+        *log_writer = writer;
+    }
+}
+
+/// The `session_id` of the pipeline running on the current thread, if it has
+/// one (see `Hypetrigger::set_session_id`). This is set on every thread of
+/// `run`, `run_async` and `run_supervised`, in the tasks of `run_tokio`, and
+/// on a `TriggerThread` worker while it runs an `AsyncTrigger`, so callbacks
+/// like `on_progress` and `on_ffmpeg_stderr` can use it to tell concurrent
+/// sessions apart.
+pub fn current_session_id() -> Option<String> {
+    SESSION_ID.with(|session_id| session_id.borrow().clone())
+}
+
+/// Tags log messages from the current thread with a session id until it's
+/// dropped, when the previous one (if any) is restored.
+pub(crate) struct SessionGuard {
+    previous: Option<String>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        SESSION_ID.with(|session_id| *session_id.borrow_mut() = previous);
+    }
+}
+
+/// Tag log messages from the current thread with the given session id, or
+/// leave them as they are if it's `None`.
+pub(crate) fn enter_session(session_id: Option<&str>) -> SessionGuard {
+    let previous = match session_id {
+        Some(new) => SESSION_ID.with(|session_id| session_id.replace(Some(new.to_string()))),
+        None => current_session_id(),
+    };
+    SessionGuard { previous }
+}
+
+/// The name for one of a pipeline's threads, with its session id added to it,
+/// if there is one.
+pub(crate) fn thread_name(name: &str, session_id: Option<&str>) -> String {
+    match session_id {
+        Some(session_id) => format!("{}-{}", name, session_id),
+        None => name.to_string(),
+    }
+}
+
+/// Write a log message to the `LogWriter`, if there is one, or else to stdout
+/// (or stderr for warnings), prefixed with the `current_session_id`. Used by
+/// the logging macros.
+#[doc(hidden)]
+pub fn write_log(message: fmt::Arguments, is_warning: bool) {
+    let message = match current_session_id() {
+        Some(session_id) => format!("[{}] {}", session_id, message),
+        None => message.to_string(),
+    };
+    let writer = LOG_WRITER.lock().ok().and_then(|writer| writer.clone());
+    match (writer, is_warning) {
+        (Some(writer), _) => {
+            if let Ok(mut writer) = writer.lock() {
+                let _ = writeln!(writer, "{}", message);
+            }
+        }
+        (None, false) => println!("{}", message),
+        (None, true) => eprintln!("{}", message),
+    }
+}
+
+/// Routine debug output about the pipeline.
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        $crate::log::write_log(format_args!($($arg)*), false);
+    }};
+}
+
+/// Very verbose output, such as every line of ffmpeg stderr.
+macro_rules! log_trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        $crate::log::write_log(format_args!($($arg)*), false);
+    }};
+}
+
+/// Record a metric through the `metrics` crate facade, e.g.
+/// `metric!(counter!("hypetrigger_frames_read_total").increment(1))`. Without
+/// the `metrics` feature, this (including its arguments) compiles to nothing.
+///
+/// All metrics are labelled with the `input` of the pipeline:
+/// - `hypetrigger_frames_read_total`: frames read from ffmpeg
+/// - `hypetrigger_trigger_frames_total`: frames passed to each Trigger
+///   (labelled with its index as `trigger`)
+/// - `hypetrigger_dispatch_seconds`: time to run all the Triggers on a frame
+/// - `hypetrigger_frames_dropped_total`: frames dropped to keep up in
+///   `realtime` mode
+/// - `hypetrigger_ffmpeg_restarts_total`: restarts by `run_supervised`
+///
+/// As well as `hypetrigger_trigger_thread_queue_depth`, the number of commands
+/// waiting for any `TriggerThread`, and `hypetrigger_cache_hits_total` and
+/// `hypetrigger_cache_misses_total` for every `CachedTrigger`.
+macro_rules! metric {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "metrics")]
+        metrics::$($arg)*;
+    }};
+}
+
+/// Recoverable errors.
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        $crate::log::write_log(format_args!($($arg)*), true);
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{current_session_id, enter_session, set_log_writer, write_log, LogWriter};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn log_writer() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        set_log_writer(Some(buffer.clone() as LogWriter));
+        write_log(format_args!("[test] hello {}", 1), false);
+        write_log(format_args!("[test] careful"), true);
+        {
+            let _session = enter_session(Some("stream-2"));
+            write_log(format_args!("[test] tagged"), false);
+            assert_eq!(current_session_id().as_deref(), Some("stream-2"));
+        }
+        assert_eq!(current_session_id(), None);
+        set_log_writer(None);
+
+        // Other tests may log at the same time, so only look for these lines
+        let logged = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logged.lines().filter(|l| l.contains("[test] ")).collect();
+        assert_eq!(
+            lines,
+            [
+                "[test] hello 1",
+                "[test] careful",
+                "[stream-2] [test] tagged"
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::audio::AudioChunk;
+use crate::color_filter::ColorFilter;
+use crate::error::Result;
+#[cfg(feature = "photon")]
+use crate::photon::CropRegion;
+use crate::pipeline::{PixelFormat, Transpose};
+use crate::trigger::{Frame, Trigger, TriggerPayload};
+use crate::trigger_config::TriggerConfig;
+use image::{DynamicImage, GrayImage};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A Trigger that wraps another one, and only passes frames along to it when
+/// they differ enough from the previous frame. Put it in front of expensive
+/// Triggers (e.g. OCR) that watch mostly static regions like a HUD.
+///
+/// Frames are compared in grayscale, by the mean absolute difference between
+/// pixels. The first frame is always passed along. The previous frame is kept
+/// separately for each input.
+pub struct MotionGateTrigger {
+    /// The Trigger to run on frames that changed.
+    pub trigger: Arc<dyn Trigger>,
+
+    /// How much a frame needs to differ from the previous one to be passed
+    /// along, as a percentage: 0 means any change, 100 means black to white.
+    pub threshold_percent: f64,
+
+    /// Optionally only compare this region of each frame, as
+    /// `(x, y, width, height)` in pixels. The whole frame is still passed
+    /// along.
+    pub region: Option<(u32, u32, u32, u32)>,
+
+    /// The previously seen frame of each input, in grayscale
+    pub previous: Mutex<HashMap<usize, GrayImage>>,
+}
+
+impl Trigger for MotionGateTrigger {
+    fn on_frame(&self, frame: &Frame) -> Result<()> {
+        self.on_frame_control(frame).map(|_| ())
+    }
+
+    fn on_frame_control(&self, frame: &Frame) -> Result<ControlFlow<()>> {
+        self.on_frame_events(frame, &mut vec![])
+    }
+
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
+        let current = self.compared_image(&frame.image);
+        let changed = {
+            let mut previous = self.previous.lock()?;
+            let changed = match previous.get(&frame.input_index) {
+                Some(previous) => mean_difference_percent(previous, &current)
+                    .map_or(true, |difference| difference > self.threshold_percent),
+                None => true,
+            };
+            previous.insert(frame.input_index, current);
+            changed
+        };
+
+        match changed {
+            true => self.trigger.on_frame_events(frame, payloads),
+            false => Ok(ControlFlow::Continue(())),
+        }
+    }
+
+    fn get_fps(&self) -> Option<f64> {
+        self.trigger.get_fps()
+    }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.trigger.get_pixel_format()
+    }
+
+    fn get_color_filter(&self) -> Option<ColorFilter> {
+        self.trigger.get_color_filter()
+    }
+
+    fn get_invert(&self) -> bool {
+        self.trigger.get_invert()
+    }
+
+    fn get_debounce(&self) -> Option<Duration> {
+        self.trigger.get_debounce()
+    }
+
+    fn get_scale(&self) -> Option<(u32, u32)> {
+        self.trigger.get_scale()
+    }
+
+    fn get_transpose(&self) -> Option<Transpose> {
+        self.trigger.get_transpose()
+    }
+
+    fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        self.trigger.get_region(width, height)
+    }
+
+    fn get_enabled(&self) -> bool {
+        self.trigger.get_enabled()
+    }
+
+    fn get_output_dirs(&self) -> Vec<PathBuf> {
+        self.trigger.get_output_dirs()
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.trigger.validate()
+    }
+
+    fn is_audio(&self) -> bool {
+        self.trigger.is_audio()
+    }
+
+    /// Audio has no frames to compare, so it's always passed along.
+    fn on_audio(&self, chunk: &AudioChunk) -> Result<()> {
+        self.trigger.on_audio(chunk)
+    }
+
+    /// Saved as the Trigger it wraps, without the gate in front of it.
+    fn to_config(&self) -> Option<TriggerConfig> {
+        self.trigger.to_config()
+    }
+
+    /// The copy starts over comparing frames from the next one.
+    #[cfg(feature = "photon")]
+    fn with_crop(&self, crop: CropRegion) -> Option<Arc<dyn Trigger>> {
+        Some(Arc::new(MotionGateTrigger {
+            trigger: self.trigger.with_crop(crop)?,
+            threshold_percent: self.threshold_percent,
+            region: self.region,
+            previous: Mutex::new(HashMap::new()),
+        }))
+    }
+}
+
+impl MotionGateTrigger {
+    pub fn new<T>(trigger: T, threshold_percent: f64) -> Self
+    where
+        T: Trigger + 'static,
+    {
+        Self {
+            trigger: Arc::new(trigger),
+            threshold_percent,
+            region: None,
+            previous: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Setter for the region of each frame to compare
+    pub fn set_region(mut self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.region = Some((x, y, width, height));
+        self
+    }
+
+    /// The part of the image that's compared between frames, in grayscale
+    fn compared_image(&self, image: &DynamicImage) -> GrayImage {
+        match self.region {
+            Some((x, y, width, height)) => image.crop_imm(x, y, width, height).to_luma8(),
+            None => image.to_luma8(),
+        }
+    }
+}
+
+/// Mean absolute difference between the pixels of two grayscale images, as a
+/// percentage of the largest possible difference. Returns `None` if they
+/// aren't the same size.
+pub fn mean_difference_percent(a: &GrayImage, b: &GrayImage) -> Option<f64> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let num_pixels = a.as_raw().len();
+    if num_pixels == 0 {
+        return Some(0.0);
+    }
+    let total: u64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| a.abs_diff(b) as u64)
+        .sum();
+    Some(total as f64 / (num_pixels as f64 * 255.0) * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MotionGateTrigger;
+    use crate::{
+        error::Result,
+        simple_trigger::SimpleTrigger,
+        trigger::{Frame, Trigger},
+    };
+    use image::{DynamicImage, GrayImage, Luma};
+    use std::sync::{Arc, Mutex};
+
+    fn frame(frame_num: u64, brightness: u8) -> Frame {
+        let image = GrayImage::from_pixel(8, 8, Luma([brightness]));
+        Frame {
+            image: DynamicImage::ImageLuma8(image),
+            frame_num,
+            timestamp: frame_num as f64,
+            input_index: 0,
+        }
+    }
+
+    #[test]
+    fn motion_gate() -> Result<()> {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let trigger = MotionGateTrigger::new(
+            SimpleTrigger::new(move |frame| {
+                received_clone.lock().unwrap().push(frame.frame_num);
+            }),
+            10.0,
+        );
+
+        trigger.on_frame(&frame(0, 0))?; // first frame
+        trigger.on_frame(&frame(1, 0))?; // no change
+        trigger.on_frame(&frame(2, 10))?; // ~4% change
+        trigger.on_frame(&frame(3, 255))?; // ~96% change
+        assert_eq!(*received.lock()?, vec![0, 3]);
+        Ok(())
+    }
+}
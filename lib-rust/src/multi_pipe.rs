@@ -0,0 +1,765 @@
+// Gives each Trigger an ffmpeg output (and pipe) of its own, instead of the one
+// stream of frames on stdout that all of them share: ffmpeg splits the sampled
+// frames into a branch per Trigger, converts each branch to that Trigger's
+// pixel format, and writes it to a pipe that a thread of its own reads. Only
+// on unix, where ffmpeg can be handed the pipes as file descriptors 3 and up
+// (`pipe:3`, `pipe:4`, ...).
+
+use crate::concat::concat_list_path;
+use crate::error::{Error, FfmpegError, NoneError, Result};
+use crate::log;
+use crate::pipeline::{
+    check_ffmpeg_exit, join_stderr_thread, trigger_frame_size, Hypetrigger,
+    HypetriggerOnFfmpegErrorCallback, OutputSink, PipelineSummary, PixelFormat, SeekAccuracy,
+    VsyncMode,
+};
+use crate::session::{join, PipelineJoinHandle};
+use crate::trigger::{Frame, Trigger, TriggerPayload};
+use crate::util::command_to_string;
+use std::{
+    fs::File,
+    ops::ControlFlow,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The file descriptor of the first pipe, right after stdin, stdout and stderr
+pub const FIRST_PIPE_FD: u32 = 3;
+
+/// One of the outputs of `Hypetrigger::spawn_ffmpeg_multi_pipe_session`: the
+/// frames of a single Trigger, written by ffmpeg to the pipe `pipe:{fd}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipeOutput {
+    /// Index of the Trigger that reads this pipe, among all the Triggers
+    pub trigger_id: usize,
+
+    /// The file descriptor ffmpeg writes to
+    pub fd: u32,
+
+    /// The format of the frames ffmpeg writes, i.e. the Trigger's own
+    pub pixel_format: PixelFormat,
+
+    /// Width of the frames ffmpeg writes
+    pub width: u32,
+
+    /// Height of the frames ffmpeg writes
+    pub height: u32,
+}
+
+impl PipeOutput {
+    /// The `pipe:N` output that ffmpeg writes to.
+    pub fn as_ffmpeg_arg(&self) -> String {
+        format!("pipe:{}", self.fd)
+    }
+}
+
+impl Hypetrigger {
+    /// The pipe each Trigger is read from by
+    /// `spawn_ffmpeg_multi_pipe_session`, in order: one for every enabled
+    /// video Trigger, starting with `pipe:3`, sized to its `get_scale()` and
+    /// `get_transpose()` (see `trigger_frame_size`).
+    /// Empty unless the size of the frames is known up front (see
+    /// `frame_size`).
+    pub fn pipe_outputs(&self) -> Vec<PipeOutput> {
+        let Some((width, height)) = self.frame_size() else {
+            return vec![];
+        };
+        self.triggers
+            .iter()
+            .enumerate()
+            .filter(|(_, trigger)| trigger.get_enabled() && !trigger.is_audio())
+            .zip(FIRST_PIPE_FD..)
+            .map(|((trigger_id, trigger), fd)| {
+                let (width, height) = trigger_frame_size(trigger.as_ref(), width, height);
+                PipeOutput {
+                    trigger_id,
+                    fd,
+                    pixel_format: trigger.get_pixel_format().unwrap_or(self.pixel_format),
+                    width,
+                    height,
+                }
+            })
+            .collect()
+    }
+
+    /// The filters of a Trigger's own branch of the `multi_pipe_filter`,
+    /// which leave its frames in the size, orientation and format it asked
+    /// for, masked by its color filter and inverted, so the pipeline doesn't
+    /// have to convert them. Unlike the pipeline, ffmpeg inverts the whole frame.
+    pub fn pipe_branch_filter(&self, pipe: &PipeOutput) -> String {
+        let trigger = &self.triggers[pipe.trigger_id];
+        let mut filters = vec![];
+        if let Some((width, height)) = trigger.get_scale() {
+            filters.push(format!("scale={}:{}", width, height));
+        }
+        if let Some(color_filter) = trigger.get_color_filter() {
+            filters.push(color_filter.ffmpeg_filter());
+        }
+        if trigger.get_invert() {
+            filters.push("negate".to_string());
+        }
+        if let Some(transpose) = trigger.get_transpose() {
+            filters.push(transpose.as_ffmpeg_filter().to_string());
+        }
+        filters.push(format!("format={}", pipe.pixel_format.as_ffmpeg_str()));
+        filters.join(",")
+    }
+
+    /// The filter graph for `spawn_ffmpeg_multi_pipe_session`: the
+    /// `video_filter()` of the input, split into a branch for each of the
+    /// `pipe_outputs`, labelled `[pipe3]`, `[pipe4]` and so on.
+    pub fn multi_pipe_filter(&self) -> String {
+        let pipes = self.pipe_outputs();
+        let mut graph = format!("[0:v]{},split={}", self.input_filter(), pipes.len());
+        for pipe in &pipes {
+            graph += &format!("[branch{}]", pipe.fd);
+        }
+        for pipe in &pipes {
+            graph += &format!(
+                ";[branch{0}]{1}[pipe{0}]",
+                pipe.fd,
+                self.pipe_branch_filter(pipe)
+            );
+        }
+        graph
+    }
+
+    /// Make sure the pipeline can be run with an output per Trigger. This
+    /// rules out anything that depends on the single stream of frames on
+    /// stdout, on top of everything `validate` checks.
+    pub fn validate_multi_pipe(&self) -> core::result::Result<(), FfmpegError> {
+        self.validate()?;
+        let unsupported = |what: &str| {
+            Err(FfmpegError::InvalidConfig(format!(
+                "{} isn't supported with an output per Trigger",
+                what
+            )))
+        };
+        if !cfg!(unix) {
+            return unsupported("this platform");
+        }
+        if self.frame_size().is_none() {
+            return Err(FfmpegError::InvalidConfig(
+                "an output per Trigger requires `scale` to be set, so the size of each Trigger's frames is known up front"
+                    .to_string(),
+            ));
+        }
+        if self.pipe_outputs().is_empty() {
+            return Err(FfmpegError::InvalidConfig(
+                "an output per Trigger requires at least one enabled video Trigger".to_string(),
+            ));
+        }
+        if self.has_audio_triggers() {
+            return unsupported("an audio Trigger");
+        }
+        if !self.extra_inputs.is_empty() {
+            return unsupported("more than one input");
+        }
+        if self.is_stdin_input() {
+            return unsupported("reading the input from stdin");
+        }
+        if self.frame_markers {
+            return unsupported("`frame_markers`");
+        }
+        if self.vsync == VsyncMode::SourcePts {
+            return unsupported("`VsyncMode::SourcePts`");
+        }
+        if self.output_sink != OutputSink::Stdout {
+            return unsupported("an `output_sink`");
+        }
+        if self.dump_frames_to.is_some() || self.dump_crops_to.is_some() {
+            return unsupported("dumping frames or crops to files");
+        }
+        Ok(())
+    }
+
+    /// Build the ffmpeg command for `spawn_ffmpeg_multi_pipe_session`,
+    /// without spawning it or opening the pipes. The `concat_list` is as for
+    /// `ffmpeg_command`.
+    pub fn multi_pipe_command(
+        &self,
+        concat_list: Option<&std::path::Path>,
+    ) -> core::result::Result<Command, FfmpegError> {
+        self.validate_multi_pipe()?;
+        let mut cmd = self.ffmpeg_input_command(concat_list)?;
+        cmd.arg("-filter_complex").arg(self.multi_pipe_filter());
+        cmd.arg(self.overwrite_arg());
+        for (i, pipe) in self.pipe_outputs().iter().enumerate() {
+            // The seek and duration were already given for the first output
+            if i > 0 {
+                if self.seek_accuracy == SeekAccuracy::Decode {
+                    cmd.args(self.seek_args());
+                }
+                if let Some(duration) = self.duration {
+                    cmd.arg("-t").arg(duration.as_secs_f64().to_string());
+                }
+            }
+            cmd.arg("-map")
+                .arg(format!("[pipe{}]", pipe.fd))
+                .arg("-vsync")
+                .arg(self.vsync.as_ffmpeg_str())
+                .arg("-f")
+                .arg("rawvideo")
+                .arg("-pix_fmt")
+                .arg(pipe.pixel_format.as_ffmpeg_str())
+                .arg("-an");
+            cmd.args(&self.extra_output_args);
+            cmd.arg(pipe.as_ffmpeg_arg());
+        }
+
+        // Nothing is written to stdout; the frames all go to the pipes
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+
+        #[cfg(windows)]
+        std::os::windows::process::CommandExt::creation_flags(
+            &mut cmd,
+            self.windows_process_flags.bits(),
+        );
+
+        Ok(cmd)
+    }
+
+    /// Like `run_async`, but with an ffmpeg output of its own for each
+    /// Trigger (see `pipe_outputs`), instead of the one stream of frames on
+    /// stdout that all the Triggers share. ffmpeg converts each Trigger's
+    /// frames to its own pixel format, so a `Gray8` Trigger is never sent
+    /// `Rgb24` frames to convert, and each output is read by a thread of its
+    /// own, so a slow Trigger only holds up its own pipe (until that pipe is
+    /// full, when ffmpeg waits on it). Each frame still has the same
+    /// `frame_num` and timestamp on every pipe.
+    ///
+    /// Only on unix, and only for a single input of a known size; see
+    /// `validate_multi_pipe`. Triggers that are disabled when it starts get no
+    /// pipe, so they never run.
+    pub fn spawn_ffmpeg_multi_pipe_session(self) -> Result<MultiPipeSession> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        log_debug!("[hypetrigger] spawn_ffmpeg_multi_pipe_session()");
+        self.validate_multi_pipe()?;
+        self.check_ffmpeg_support()?;
+
+        let concat_list = self.write_concat_list()?;
+        let pipes = self.pipe_outputs();
+        let cmd = self.multi_pipe_command(concat_list_path(&concat_list))?;
+        log_debug!("[debug] ffmpeg command appears below:");
+        log_debug!("{}", command_to_string(&cmd));
+        let (mut child, readers) = spawn_with_pipes(cmd, pipes.len())?;
+        let ffmpeg_stderr = child.stderr.take().ok_or(NoneError)?;
+
+        let on_ffmpeg_error_callback = self.on_ffmpeg_error_callback.clone();
+        let session_id = self.session_id.clone();
+        let thread_pipes = pipes.clone();
+        let pipeline_thread = thread::Builder::new()
+            .name(self.thread_name("hypetrigger_pipeline"))
+            .spawn(move || {
+                // The list is kept until ffmpeg closes its pipes
+                let _concat_list = concat_list;
+                self.read_pipes(ffmpeg_stderr, readers, &thread_pipes)
+                    .map_err(|e| e.to_string())
+            })?;
+
+        Ok(MultiPipeSession {
+            child,
+            pipeline_thread,
+            pipes,
+            on_ffmpeg_error_callback,
+            session_id,
+        })
+    }
+
+    /// Read each pipe on a thread of its own until ffmpeg closes them all,
+    /// along with ffmpeg's stderr, and add up what was read from each.
+    fn read_pipes(
+        &self,
+        mut ffmpeg_stderr: std::process::ChildStderr,
+        readers: Vec<File>,
+        pipes: &[PipeOutput],
+    ) -> Result<PipelineSummary> {
+        let started_at = Instant::now();
+        thread::scope(|scope| {
+            // The size is already known, but the channel has to stay open, or
+            // the stderr thread would stop reading once it's logged
+            let (_output_size_rx, _frame_time_rx, ffmpeg_stderr_join_handle) =
+                self.spawn_ffmpeg_stderr_thread(&mut ffmpeg_stderr, scope)?;
+
+            let mut pipe_threads = vec![];
+            for (pipe, reader) in pipes.iter().zip(readers) {
+                let pipeline = self.pipe_pipeline(pipe);
+                let size = (pipe.width, pipe.height);
+                let pipe_thread = thread::Builder::new()
+                    .name(self.thread_name(&format!("ffmpeg_pipe{}", pipe.fd)))
+                    .spawn_scoped(scope, move || {
+                        let _session = log::enter_session(pipeline.session_id.as_deref());
+                        // Dropping the pipe when a Trigger stops the pipeline
+                        // makes ffmpeg exit, which closes all the others
+                        pipeline.read_frames(reader, size, None, false, 0, None)
+                    })?;
+                pipe_threads.push(pipe_thread);
+            }
+
+            let mut summary = PipelineSummary {
+                trigger_frames: vec![0; self.triggers.len()],
+                ..Default::default()
+            };
+            for pipe_thread in pipe_threads {
+                let pipe_summary = pipe_thread
+                    .join()
+                    .map_err(|_| Error::from_display("ffmpeg pipe thread panicked"))?
+                    .map_err(Error::from_display)?;
+                summary.frames_read = summary.frames_read.max(pipe_summary.frames_read);
+                summary.frames_dropped += pipe_summary.frames_dropped;
+                summary.stopped_early |= pipe_summary.stopped_early;
+                // Only the pipe's own Trigger ever runs on it
+                for (total, frames) in summary
+                    .trigger_frames
+                    .iter_mut()
+                    .zip(pipe_summary.trigger_frames)
+                {
+                    *total += frames;
+                }
+            }
+            let summary = summary
+                .with_stderr(join_stderr_thread(ffmpeg_stderr_join_handle).map_err(Error::from)?);
+            self.report_completion(&summary, started_at);
+            Ok(summary)
+        })
+    }
+
+    /// The pipeline a single pipe is read by: this one, but with only the
+    /// pipe's own Trigger enabled, behind a `PipeTrigger`. The others keep
+    /// their places, so the Trigger's id (and the ids of its events and
+    /// errors) stays the same.
+    fn pipe_pipeline(&self, pipe: &PipeOutput) -> Hypetrigger {
+        let mut pipeline = self.clone();
+        pipeline.pixel_format = pipe.pixel_format;
+        pipeline.triggers = self
+            .triggers
+            .iter()
+            .enumerate()
+            .map(
+                |(trigger_id, trigger)| match trigger_id == pipe.trigger_id {
+                    true => Arc::new(PipeTrigger {
+                        trigger: trigger.clone(),
+                    }) as Arc<dyn Trigger>,
+                    false => Arc::new(OtherPipe) as Arc<dyn Trigger>,
+                },
+            )
+            .collect();
+        pipeline
+    }
+}
+
+/// A Trigger read from a pipe of its own, whose frames ffmpeg has already
+/// scaled, masked, inverted and transposed: everything is passed through to the Trigger except what was done
+/// in its branch, so that the pipeline doesn't do it again.
+struct PipeTrigger {
+    trigger: Arc<dyn Trigger>,
+}
+
+impl Trigger for PipeTrigger {
+    fn on_frame(&self, frame: &Frame) -> Result<()> {
+        self.trigger.on_frame(frame)
+    }
+
+    fn on_frame_control(&self, frame: &Frame) -> Result<ControlFlow<()>> {
+        self.trigger.on_frame_control(frame)
+    }
+
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
+        self.trigger.on_frame_events(frame, payloads)
+    }
+
+    fn get_fps(&self) -> Option<f64> {
+        self.trigger.get_fps()
+    }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.trigger.get_pixel_format()
+    }
+
+    fn get_debounce(&self) -> Option<Duration> {
+        self.trigger.get_debounce()
+    }
+
+    fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        self.trigger.get_region(width, height)
+    }
+
+    fn get_enabled(&self) -> bool {
+        self.trigger.get_enabled()
+    }
+}
+
+/// Stands in for a Trigger that's read from a different pipe
+struct OtherPipe;
+
+impl Trigger for OtherPipe {
+    fn on_frame(&self, _frame: &Frame) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_enabled(&self) -> bool {
+        false
+    }
+}
+
+/// Spawn ffmpeg with the write end of a new pipe as each of the file
+/// descriptors `FIRST_PIPE_FD` and up, returning the read ends in the same
+/// order.
+#[cfg(unix)]
+fn spawn_with_pipes(mut cmd: Command, count: usize) -> Result<(Child, Vec<File>)> {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::process::CommandExt;
+
+    let last_fd = FIRST_PIPE_FD as RawFd + count as RawFd;
+    let mut readers = vec![];
+    let mut writers = vec![];
+    for _ in 0..count {
+        let (reader, writer) = cloexec_pipe()?;
+        // Kept clear of the descriptors the pipes are moved to in the child,
+        // so that moving one never overwrites another that's still to move.
+        // SAFETY: `writer` is open, and the new descriptor is owned from here
+        let writer = unsafe {
+            let fd = libc::fcntl(writer.as_raw_fd(), libc::F_DUPFD_CLOEXEC, last_fd);
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        readers.push(File::from(reader));
+        writers.push(writer);
+    }
+
+    let raw_writers: Vec<RawFd> = writers.iter().map(AsRawFd::as_raw_fd).collect();
+    // SAFETY: only calls `dup2`, which is async-signal-safe, between fork and
+    // exec. The copies it makes aren't close-on-exec, unlike everything else.
+    unsafe {
+        cmd.pre_exec(move || {
+            for (fd, &writer) in (FIRST_PIPE_FD as RawFd..).zip(&raw_writers) {
+                if libc::dup2(writer, fd) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn().map_err(FfmpegError::SpawnFailed)?;
+
+    // ffmpeg has its own copies now, so each reader sees the end of its pipe
+    // once ffmpeg closes it
+    drop(writers);
+    Ok((child, readers))
+}
+
+#[cfg(not(unix))]
+fn spawn_with_pipes(_cmd: Command, _count: usize) -> Result<(Child, Vec<File>)> {
+    Err(
+        FfmpegError::InvalidConfig("an output per Trigger is only supported on unix".to_string())
+            .into(),
+    )
+}
+
+/// A new pipe, as `(reader, writer)`, which isn't inherited by child
+/// processes until it's explicitly handed to one.
+#[cfg(unix)]
+fn cloexec_pipe() -> std::io::Result<(std::os::unix::io::OwnedFd, std::os::unix::io::OwnedFd)> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    // Set atomically where possible, so a process spawned by another thread in
+    // the meantime can't inherit the pipe and hold it open
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    // SAFETY: `fds` has room for both ends of the pipe
+    let result = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    // SAFETY: `fds` has room for both ends of the pipe
+    let result = unsafe {
+        match libc::pipe(fds.as_mut_ptr()) {
+            0 => {
+                libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+                0
+            }
+            error => error,
+        }
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: both ends were just opened, and are owned from here
+    unsafe {
+        Ok((
+            std::os::unix::io::OwnedFd::from_raw_fd(fds[0]),
+            std::os::unix::io::OwnedFd::from_raw_fd(fds[1]),
+        ))
+    }
+}
+
+/// A running ffmpeg process writing to a pipe per Trigger, as returned from
+/// `Hypetrigger::spawn_ffmpeg_multi_pipe_session()`.
+///
+/// The stderr thread and the thread for each pipe are scoped to the pipeline
+/// thread, so they're joined along with it.
+pub struct MultiPipeSession {
+    /// The ffmpeg process itself. Its stderr has already been taken.
+    pub child: Child,
+
+    /// Reads each pipe and runs its Trigger, then adds up the summaries.
+    pub pipeline_thread: PipelineJoinHandle,
+
+    /// The pipe each Trigger is read from.
+    pub pipes: Vec<PipeOutput>,
+
+    /// Called from `wait` if ffmpeg failed before producing any frames.
+    pub on_ffmpeg_error_callback: Option<HypetriggerOnFfmpegErrorCallback>,
+
+    /// The `session_id` of the `Hypetrigger` that started this session.
+    pub session_id: Option<String>,
+}
+
+impl MultiPipeSession {
+    /// Block until ffmpeg has finished the input (or a Trigger stopped it)
+    /// and every pipe has been read, returning what was read. `frames_read`
+    /// counts the frames of the pipe that got the most, and `frames_dropped`
+    /// adds up the drops of every pipe.
+    ///
+    /// Returns an error if ffmpeg exited unsuccessfully without producing any
+    /// frames, e.g. because the input doesn't exist.
+    pub fn wait(mut self) -> Result<PipelineSummary> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        drop(self.child.stdin.take());
+        let exit_status: ExitStatus = self.child.wait()?;
+        let summary = join(self.pipeline_thread)?.map_err(Error::from)?;
+        check_ffmpeg_exit(
+            exit_status,
+            summary.clone(),
+            self.on_ffmpeg_error_callback.as_ref(),
+        )?;
+        Ok(summary)
+    }
+
+    /// Tell ffmpeg to exit gracefully, then `wait` for it.
+    pub fn stop(mut self) -> Result<PipelineSummary> {
+        if let Some(stdin) = &mut self.child.stdin {
+            // It may have already exited and closed its stdin
+            if let Err(e) = crate::pipeline::stop_ffmpeg(stdin) {
+                log_warn!("[ffmpeg.in] Failed to stop ffmpeg: {}", e);
+            }
+        }
+        self.wait()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::PipeOutput;
+    use crate::{
+        color_filter::ColorFilter,
+        error::Result,
+        pipeline::{Hypetrigger, PixelFormat, Transpose},
+        pixel_probe::PixelProbeTrigger,
+        test_util::fake_ffmpeg,
+        trigger::{Frame, Trigger},
+    };
+    use image::GenericImageView;
+    use std::sync::{Arc, Mutex};
+
+    /// Writes three frames to each `pipe:N` output, of the size of the last
+    /// `scale` before its branch's output and its `-pix_fmt`, filled with the
+    /// pipe's number.
+    const MULTI_PIPE_FFMPEG: &str = r#"#!/usr/bin/env python3
+import re, sys
+args = sys.argv[1:]
+graph = args[args.index("-filter_complex") + 1]
+def frame_size(fd):
+    shared = graph.split(";")[0]
+    branch = graph.split("[pipe%d]" % fd)[0].rsplit(";", 1)[1]
+    width, height = re.findall(r"scale=(\d+):(\d+)", shared + branch)[-1]
+    return int(width) * int(height)
+bytes_per_pixel = {"rgb24": 3, "gray": 1, "rgba": 4}
+outputs = []
+for i, arg in enumerate(args):
+    if arg == "-pix_fmt":
+        pixel_format = args[i + 1]
+    match = re.fullmatch(r"pipe:(\d+)", arg)
+    if match:
+        outputs.append((int(match.group(1)), pixel_format))
+pipes = [(open(fd, "wb", closefd=False), fd, pixel_format) for fd, pixel_format in outputs]
+for _ in range(3):
+    for pipe, fd, pixel_format in pipes:
+        pipe.write(bytes([fd]) * (frame_size(fd) * bytes_per_pixel[pixel_format]))
+        pipe.flush()
+"#;
+
+    struct Disabled;
+
+    impl Trigger for Disabled {
+        fn on_frame(&self, _frame: &Frame) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_enabled(&self) -> bool {
+            false
+        }
+    }
+
+    /// The frame_num, size, format and first byte of a frame
+    type Received = (u64, (u32, u32), Option<PixelFormat>, u8);
+
+    #[derive(Default)]
+    struct Recorder {
+        format: Option<PixelFormat>,
+        scale: Option<(u32, u32)>,
+        color_filter: Option<ColorFilter>,
+        invert: bool,
+        transpose: Option<Transpose>,
+        frames: Arc<Mutex<Vec<Received>>>,
+    }
+
+    impl Trigger for Recorder {
+        fn on_frame(&self, frame: &Frame) -> Result<()> {
+            let first_byte = frame.image.as_bytes()[0];
+            let format = PixelFormat::of(&frame.image);
+            self.frames.lock()?.push((
+                frame.frame_num,
+                frame.image.dimensions(),
+                format,
+                first_byte,
+            ));
+            Ok(())
+        }
+
+        fn get_pixel_format(&self) -> Option<PixelFormat> {
+            self.format
+        }
+
+        fn get_scale(&self) -> Option<(u32, u32)> {
+            self.scale
+        }
+
+        fn get_color_filter(&self) -> Option<ColorFilter> {
+            self.color_filter
+        }
+
+        fn get_invert(&self) -> bool {
+            self.invert
+        }
+
+        fn get_transpose(&self) -> Option<Transpose> {
+            self.transpose
+        }
+    }
+
+    #[test]
+    fn multi_pipe_command() {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_input("input.mp4")
+            .set_ffmpeg_exe("ffmpeg")
+            .set_fps(2)
+            .set_scale(64, 36)
+            .add_trigger(Disabled)
+            .add_trigger(PixelProbeTrigger::new(0, 0, |_, _| {}))
+            .add_trigger(Recorder {
+                format: Some(PixelFormat::Gray8),
+                scale: Some((32, 18)),
+                color_filter: Some(ColorFilter::new([255, 0, 0], 20)),
+                invert: true,
+                transpose: Some(Transpose::Rotate90),
+                ..Default::default()
+            });
+
+        assert_eq!(
+            hypetrigger.pipe_outputs(),
+            vec![
+                PipeOutput {
+                    trigger_id: 1,
+                    fd: 3,
+                    pixel_format: PixelFormat::Rgb24,
+                    width: 64,
+                    height: 36,
+                },
+                PipeOutput {
+                    trigger_id: 2,
+                    fd: 4,
+                    pixel_format: PixelFormat::Gray8,
+                    width: 18,
+                    height: 32,
+                },
+            ]
+        );
+        let color_filter = ColorFilter::new([255, 0, 0], 20).ffmpeg_filter();
+        assert_eq!(
+            hypetrigger.multi_pipe_filter(),
+            format!(
+                "[0:v]fps=2,scale=64:36,split=2[branch3][branch4];[branch3]format=rgb24[pipe3];[branch4]scale=32:18,{},negate,transpose=clock,format=gray[pipe4]",
+                color_filter
+            )
+        );
+        let cmd = hypetrigger.multi_pipe_command(None).unwrap();
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_string_lossy()).collect();
+        let outputs: Vec<_> = args.iter().filter(|arg| arg.starts_with("pipe:")).collect();
+        assert_eq!(outputs, ["pipe:3", "pipe:4"]);
+        assert!(args.windows(2).any(|args| args == ["-pix_fmt", "gray"]));
+
+        // Anything that needs the single stream on stdout is rejected
+        hypetrigger.set_frame_markers(true);
+        assert!(hypetrigger.validate_multi_pipe().is_err());
+        hypetrigger.set_frame_markers(false);
+        hypetrigger.scale = None;
+        assert!(hypetrigger.validate_multi_pipe().is_err());
+    }
+
+    #[test]
+    fn multi_pipe_session() {
+        let ffmpeg = fake_ffmpeg("multi_pipe_ffmpeg", MULTI_PIPE_FFMPEG).unwrap();
+        let rgb = Recorder::default();
+        let rgb_frames = rgb.frames.clone();
+        let gray = Recorder {
+            format: Some(PixelFormat::Gray8),
+            scale: Some((4, 2)),
+            transpose: Some(Transpose::Rotate90),
+            ..Default::default()
+        };
+        let gray_frames = gray.frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_input("input.mp4")
+            .set_ffmpeg_exe(ffmpeg.exe.clone())
+            .set_fps(2)
+            .set_scale(8, 4)
+            .add_trigger(Disabled)
+            .add_trigger(rgb)
+            .add_trigger(gray);
+
+        let summary = hypetrigger
+            .spawn_ffmpeg_multi_pipe_session()
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(summary.frames_read, 3);
+        assert_eq!(summary.trigger_frames, vec![0, 3, 3]);
+
+        // Each Trigger got the frames of its own pipe, in its own size,
+        // orientation and format, as ffmpeg wrote them
+        assert_eq!(
+            *rgb_frames.lock().unwrap(),
+            (0..3)
+                .map(|n| (n, (8, 4), Some(PixelFormat::Rgb24), 3))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            *gray_frames.lock().unwrap(),
+            (0..3)
+                .map(|n| (n, (2, 4), Some(PixelFormat::Gray8), 4))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use image::DynamicImage::ImageRgba8;
 use image::GenericImageView;
-use image::{ImageBuffer, RgbImage, RgbaImage};
+use image::{DynamicImage, ImageBuffer, RgbImage, RgbaImage};
 use photon_rs::{
     helpers,
     transform::{resize, SamplingFilter},
@@ -9,12 +9,17 @@ use photon_rs::{
 use std::cmp::min;
 use wasm_bindgen::prelude::wasm_bindgen;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::iter::ImageIterator;
 use crate::threshold::threshold_color_distance_rgba;
+use crate::util::eval_expression;
 
 /// A threshold function based on perceptual color distance
 #[wasm_bindgen]
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ThresholdFilter {
     pub r: u8,
     pub g: u8,
@@ -32,22 +37,129 @@ impl ThresholdFilter {
     }
 }
 
+/// A crop region in percentages of the frame size, so it works across
+/// resolutions. By default `left_percent` and `top_percent` are measured from
+/// the top left corner, but for UI elements anchored to another edge (or the
+/// center), `anchor_x` and `anchor_y` measure them from there instead.
 #[wasm_bindgen]
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Crop {
     pub left_percent: f64,
     pub top_percent: f64,
     pub width_percent: f64,
     pub height_percent: f64,
+
+    /// Which edge `left_percent` is measured from
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub anchor_x: AnchorX,
+
+    /// Which edge `top_percent` is measured from
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub anchor_y: AnchorY,
+}
+
+/// Where a `Crop` is positioned horizontally.
+#[wasm_bindgen]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnchorX {
+    /// `left_percent` is the distance from the left edge to the crop
+    #[default]
+    Left,
+    /// `left_percent` is the distance from the right edge to the crop
+    Right,
+    /// `left_percent` is an offset from horizontally centered (negative to go
+    /// left)
+    Center,
+}
+
+/// Where a `Crop` is positioned vertically.
+#[wasm_bindgen]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnchorY {
+    /// `top_percent` is the distance from the top edge to the crop
+    #[default]
+    Top,
+    /// `top_percent` is the distance from the bottom edge to the crop
+    Bottom,
+    /// `top_percent` is an offset from vertically centered (negative to go
+    /// up)
+    Center,
+}
+
+/// An anchor of either axis, measured from the start (left or top) edge, the
+/// end edge, or the center.
+#[derive(Debug, Clone, Copy)]
+enum Anchor {
+    Start,
+    End,
+    Center,
+}
+
+impl From<AnchorX> for Anchor {
+    fn from(anchor: AnchorX) -> Self {
+        match anchor {
+            AnchorX::Left => Anchor::Start,
+            AnchorX::Right => Anchor::End,
+            AnchorX::Center => Anchor::Center,
+        }
+    }
+}
+
+impl From<AnchorY> for Anchor {
+    fn from(anchor: AnchorY) -> Self {
+        match anchor {
+            AnchorY::Top => Anchor::Start,
+            AnchorY::Bottom => Anchor::End,
+            AnchorY::Center => Anchor::Center,
+        }
+    }
+}
+
+impl Anchor {
+    /// Where a crop of `size` percent starts, as a percentage from the start
+    /// edge, given its `offset` percent from the anchor.
+    fn start_percent(self, offset: f64, size: f64) -> f64 {
+        match self {
+            Anchor::Start => offset,
+            Anchor::End => 100.0 - size - offset,
+            Anchor::Center => (100.0 - size) / 2.0 + offset,
+        }
+    }
+
+    /// The same as an expression of the frame (`input`) and crop (`output`)
+    /// sizes, e.g. `in_w-out_w-in_w*10/100`. See `ExpressionCrop`.
+    fn start_expression(self, input: &str, output: &str, offset: f64) -> String {
+        let term = |sign: f64| match sign * offset < 0.0 {
+            true => format!("-{}*{}/100", input, offset.abs()),
+            false => format!("+{}*{}/100", input, offset.abs()),
+        };
+        match self {
+            Anchor::Start => term(1.0).trim_start_matches('+').to_string(),
+            Anchor::End => format!("{}-{}{}", input, output, term(-1.0)),
+            Anchor::Center => format!("({}-{})/2{}", input, output, term(1.0)),
+        }
+    }
 }
 
 #[wasm_bindgen]
 impl Crop {
     pub fn apply(&self, mut image: PhotonImage) -> PhotonImage {
-        let width = image.get_width() as f64;
-        let height = image.get_height() as f64;
-        let x1 = (width * (self.left_percent / 100.0)) as u32;
-        let y1 = (height * (self.top_percent / 100.0)) as u32;
+        let (x1, y1, x2, y2) = self.bounds(image.get_width(), image.get_height());
+        crop(&mut image, x1, y1, x2, y2)
+    }
+}
+
+impl Crop {
+    /// The corners `(x1, y1, x2, y2)` of the region in an image of the given
+    /// size, in pixels.
+    pub fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (left_percent, top_percent) = self.start_percents();
+        let (width, height) = (width as f64, height as f64);
+        let x1 = (width * (left_percent / 100.0)) as u32;
+        let y1 = (height * (top_percent / 100.0)) as u32;
         let x2 = min(
             (x1 as f64 + (self.width_percent * width / 100.0)) as u32,
             width as u32 - 1,
@@ -56,8 +168,226 @@ impl Crop {
             (y1 as f64 + (self.height_percent * height / 100.0)) as u32,
             height as u32 - 1,
         );
+        (x1, y1, x2, y2)
+    }
+
+    /// Check that the region is inside the image (between 0 and 100 percent)
+    /// and isn't empty, describing the problem if not.
+    pub fn validate(&self) -> Result<(), String> {
+        let (left_percent, top_percent) = self.start_percents();
+        let Crop {
+            width_percent,
+            height_percent,
+            ..
+        } = *self;
+        let in_bounds = |percent: f64| (0.0..=100.0).contains(&percent);
+        if !(width_percent > 0.0 && height_percent > 0.0) {
+            return Err(format!(
+                "crop width and height must be positive, got {}% x {}%",
+                width_percent, height_percent
+            ));
+        }
+        if !in_bounds(left_percent) || !in_bounds(left_percent + width_percent) {
+            return Err(format!(
+                "crop from {}% to {}% horizontally is outside the frame (0-100%)",
+                left_percent,
+                left_percent + width_percent
+            ));
+        }
+        if !in_bounds(top_percent) || !in_bounds(top_percent + height_percent) {
+            return Err(format!(
+                "crop from {}% to {}% vertically is outside the frame (0-100%)",
+                top_percent,
+                top_percent + height_percent
+            ));
+        }
+        Ok(())
+    }
+
+    /// Where the crop starts, as percentages from the left and top edges,
+    /// whatever it's anchored to.
+    pub fn start_percents(&self) -> (f64, f64) {
+        let left = Anchor::from(self.anchor_x).start_percent(self.left_percent, self.width_percent);
+        let top = Anchor::from(self.anchor_y).start_percent(self.top_percent, self.height_percent);
+        (left, top)
+    }
+
+    /// The same region as an `ExpressionCrop`, e.g. a crop anchored 10% from
+    /// the right edge starts at `in_w-out_w-in_w*10/100`.
+    pub fn to_expression_crop(&self) -> ExpressionCrop {
+        ExpressionCrop {
+            w: format!("in_w*{}/100", self.width_percent),
+            h: format!("in_h*{}/100", self.height_percent),
+            x: Anchor::from(self.anchor_x).start_expression("in_w", "out_w", self.left_percent),
+            y: Anchor::from(self.anchor_y).start_expression("in_h", "out_h", self.top_percent),
+        }
+    }
+}
+
+/// A crop region in absolute pixels, for when percentages aren't precise
+/// enough (e.g. for small UI elements at a known resolution).
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PixelCrop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[wasm_bindgen]
+impl PixelCrop {
+    /// Crops to the region, clamped to the bounds of the image.
+    pub fn apply(&self, mut image: PhotonImage) -> PhotonImage {
+        let (x1, y1, x2, y2) = self.bounds(image.get_width(), image.get_height());
+        crop(&mut image, x1, y1, x2, y2)
+    }
+}
+
+impl PixelCrop {
+    /// The corners `(x1, y1, x2, y2)` of the region, clamped to an image of
+    /// the given size.
+    pub fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x1 = min(self.x, width);
+        let y1 = min(self.y, height);
+        let x2 = min(self.x.saturating_add(self.width), width);
+        let y2 = min(self.y.saturating_add(self.height), height);
+        (x1, y1, x2, y2)
+    }
+}
+
+/// A crop region given by expressions of the frame size, in the style of the
+/// ffmpeg `crop` filter, for UI elements that are anchored or centered rather
+/// than at a fixed percentage, e.g. `w: "400"`, `x: "(in_w-out_w)/2"`.
+///
+/// The width and height can use `in_w` and `in_h` (or `iw` and `ih`), the size
+/// of the frame; `x` and `y` can also use `out_w` and `out_h` (or `ow` and
+/// `oh`), the size of the crop. The expressions are evaluated for the size of
+/// each frame the Trigger receives (after any `scale`), since the crop is
+/// applied to the frame rather than by ffmpeg. See `util::eval_expression`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExpressionCrop {
+    pub w: String,
+    pub h: String,
+    pub x: String,
+    pub y: String,
+}
+
+impl ExpressionCrop {
+    pub fn new(w: &str, h: &str, x: &str, y: &str) -> Self {
+        Self {
+            w: w.to_string(),
+            h: h.to_string(),
+            x: x.to_string(),
+            y: y.to_string(),
+        }
+    }
+
+    /// Evaluate the expressions for an image of the given size, returning
+    /// `(x, y, width, height)` before clamping to the image.
+    pub fn eval(&self, width: u32, height: u32) -> Result<(f64, f64, f64, f64), String> {
+        let (in_w, in_h) = (width as f64, height as f64);
+        let input = [("in_w", in_w), ("iw", in_w), ("in_h", in_h), ("ih", in_h)];
+        let w = eval_expression(&self.w, &input)?;
+        let h = eval_expression(&self.h, &input)?;
+        let output = [("out_w", w), ("ow", w), ("out_h", h), ("oh", h)];
+        let variables: Vec<(&str, f64)> = input.into_iter().chain(output).collect();
+        let x = eval_expression(&self.x, &variables)?;
+        let y = eval_expression(&self.y, &variables)?;
+        Ok((x, y, w, h))
+    }
+
+    /// Crops to the region, clamped to the bounds of the image.
+    pub fn apply(&self, mut image: PhotonImage) -> PhotonImage {
+        let (x1, y1, x2, y2) = self.bounds(image.get_width(), image.get_height());
         crop(&mut image, x1, y1, x2, y2)
     }
+
+    /// The corners `(x1, y1, x2, y2)` of the region, clamped to an image of
+    /// the given size. Expressions that fail to evaluate give an empty region,
+    /// but `validate` catches those up front.
+    pub fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (x, y, w, h) = self.eval(width, height).unwrap_or_default();
+        // Float to int casts saturate, so anything negative (or NaN) is 0
+        let x1 = min(x as u32, width);
+        let y1 = min(y as u32, height);
+        let x2 = min((x + w) as u32, width).max(x1);
+        let y2 = min((y + h) as u32, height).max(y1);
+        (x1, y1, x2, y2)
+    }
+
+    /// Check that the expressions are well formed, by evaluating them for a
+    /// 1920x1080 frame.
+    pub fn validate(&self) -> Result<(), String> {
+        self.eval(1920, 1080)
+            .map(|_| ())
+            .map_err(|e| format!("invalid crop expression: {}", e))
+    }
+}
+
+/// A region to crop to, in percentages, absolute pixels, or expressions of
+/// the frame size.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CropRegion {
+    Percent(Crop),
+    Pixels(PixelCrop),
+    Expression(ExpressionCrop),
+}
+
+impl CropRegion {
+    pub fn apply(&self, image: PhotonImage) -> PhotonImage {
+        match self {
+            CropRegion::Percent(crop) => crop.apply(image),
+            CropRegion::Pixels(crop) => crop.apply(image),
+            CropRegion::Expression(crop) => crop.apply(image),
+        }
+    }
+
+    /// The corners `(x1, y1, x2, y2)` of the region in an image of the given
+    /// size, in pixels, exactly as `apply` would crop it.
+    pub fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        match self {
+            CropRegion::Percent(crop) => crop.bounds(width, height),
+            CropRegion::Pixels(crop) => crop.bounds(width, height),
+            CropRegion::Expression(crop) => crop.bounds(width, height),
+        }
+    }
+
+    /// Check that the region makes sense, describing the problem if not. Pixel
+    /// crops are clamped to the frame when applied, so they only need to be
+    /// non-empty.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            CropRegion::Percent(crop) => crop.validate(),
+            CropRegion::Pixels(crop) if crop.width == 0 || crop.height == 0 => Err(format!(
+                "crop width and height must be positive, got {} x {} px",
+                crop.width, crop.height
+            )),
+            CropRegion::Pixels(_) => Ok(()),
+            CropRegion::Expression(crop) => crop.validate(),
+        }
+    }
+}
+
+impl From<Crop> for CropRegion {
+    fn from(crop: Crop) -> Self {
+        CropRegion::Percent(crop)
+    }
+}
+
+impl From<PixelCrop> for CropRegion {
+    fn from(crop: PixelCrop) -> Self {
+        CropRegion::Pixels(crop)
+    }
+}
+
+impl From<ExpressionCrop> for CropRegion {
+    fn from(crop: ExpressionCrop) -> Self {
+        CropRegion::Expression(crop)
+    }
 }
 
 /// Fixed version of `crop` from `photon-rs@0.3.1`.
@@ -167,3 +497,139 @@ pub fn rgb_to_photon(rgb: &RgbImage) -> PhotonImage {
 
     PhotonImage::new(rgb32, rgb.width(), rgb.height())
 }
+
+/// Convert a `DynamicImage` of any pixel format (`image` crate) to a
+/// `PhotonImage` (`photon-rs` crate)
+pub fn dynamic_to_photon(image: &DynamicImage) -> PhotonImage {
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    PhotonImage::new(rgba.into_raw(), width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnchorX, AnchorY, Crop, CropRegion, ExpressionCrop, PixelCrop};
+    use photon_rs::PhotonImage;
+
+    #[test]
+    fn validate_crop() {
+        let crop = Crop {
+            left_percent: 25.0,
+            top_percent: 25.0,
+            width_percent: 10.0,
+            height_percent: 10.0,
+            anchor_x: AnchorX::Left,
+            anchor_y: AnchorY::Top,
+        };
+        assert!(crop.validate().is_ok());
+        let too_wide = Crop {
+            width_percent: 80.0,
+            ..crop
+        };
+        assert!(too_wide.validate().is_err());
+        let negative = Crop {
+            left_percent: -5.0,
+            ..crop
+        };
+        assert!(negative.validate().is_err());
+        let empty = Crop {
+            height_percent: 0.0,
+            ..crop
+        };
+        assert!(empty.validate().is_err());
+        let empty = CropRegion::from(PixelCrop {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 10,
+        });
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn anchored_crop() {
+        // 10% from the right edge, and centered vertically
+        let crop = Crop {
+            left_percent: 10.0,
+            top_percent: 0.0,
+            width_percent: 20.0,
+            height_percent: 50.0,
+            anchor_x: AnchorX::Right,
+            anchor_y: AnchorY::Center,
+        };
+        assert!(crop.validate().is_ok());
+        assert_eq!(crop.start_percents(), (70.0, 25.0));
+        assert_eq!(crop.bounds(200, 100), (140, 25, 180, 75));
+        let expression = crop.to_expression_crop();
+        assert_eq!(expression.x, "in_w-out_w-in_w*10/100");
+        assert_eq!(expression.bounds(200, 100), (140, 25, 180, 75));
+
+        // Outside the frame once measured from the right edge
+        let too_far = Crop {
+            left_percent: 85.0,
+            ..crop
+        };
+        assert!(too_far.validate().is_err());
+        let above_center = Crop {
+            top_percent: -10.0,
+            ..crop
+        };
+        assert_eq!(above_center.bounds(200, 100).1, 15);
+        assert_eq!(
+            above_center.to_expression_crop().y,
+            "(in_h-out_h)/2-in_h*10/100"
+        );
+    }
+
+    #[test]
+    fn pixel_crop() {
+        let image = PhotonImage::new(vec![255; 100 * 50 * 4], 100, 50);
+        let crop = CropRegion::from(PixelCrop {
+            x: 10,
+            y: 20,
+            width: 7,
+            height: 3,
+        });
+        let cropped = crop.apply(image.clone());
+        assert_eq!((cropped.get_width(), cropped.get_height()), (7, 3));
+
+        // Clamped to the bounds of the image
+        let crop = PixelCrop {
+            x: 90,
+            y: 40,
+            width: 20,
+            height: 20,
+        };
+        let cropped = crop.apply(image);
+        assert_eq!((cropped.get_width(), cropped.get_height()), (10, 10));
+    }
+
+    #[test]
+    fn expression_crop() {
+        // Centered horizontally, 10px from the bottom
+        let crop = CropRegion::from(ExpressionCrop::new(
+            "in_w/2",
+            "20",
+            "(in_w-out_w)/2",
+            "in_h-oh-10",
+        ));
+        assert!(crop.validate().is_ok());
+        assert_eq!(crop.bounds(100, 50), (25, 20, 75, 40));
+        assert_eq!(crop.bounds(200, 100), (50, 70, 150, 90));
+        let cropped = crop.apply(PhotonImage::new(vec![255; 100 * 50 * 4], 100, 50));
+        assert_eq!((cropped.get_width(), cropped.get_height()), (50, 20));
+
+        // Clamped to the bounds of the image
+        let crop = ExpressionCrop::new("in_w", "in_h", "in_w-10", "-5");
+        assert_eq!(crop.bounds(100, 50), (90, 0, 100, 45));
+
+        // Only x and y can refer to the size of the crop
+        assert!(ExpressionCrop::new("out_w", "10", "0", "0")
+            .validate()
+            .is_err());
+        assert!(ExpressionCrop::new("10", "10", "(ow", "0")
+            .validate()
+            .is_err());
+    }
+}
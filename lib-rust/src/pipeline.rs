@@ -1,36 +1,98 @@
+pub use crate::util::{FfmpegLogLevel, StreamInfo};
 use crate::{
-    error::{Error, NoneError, Result},
-    trigger::{Frame, Trigger},
-    util::{command_to_string, parse_ffmpeg_output_size},
+    audio::join_audio_thread,
+    batch::BatchProgress,
+    buffer_pool::BufferPool,
+    color_filter::ColorFilter,
+    concat::{concat_list_path, CONCAT_INPUT_ARGS},
+    debounce::{event_debouncer, event_debouncers, Debouncer, SameResult},
+    error::{Error, FfmpegError, FfmpegFailure, NoneError, Result},
+    frame_marker::{frame_marker_bits, read_frame_marker, resync_frame_num},
+    frame_source::FrameSource,
+    log,
+    session::{
+        spawn_ffmpeg_stdin_thread, FfmpegSession, PauseSignal, PipelineControls, TriggerHealth,
+        TriggerUpdate,
+    },
+    supervisor::FfmpegRestart,
+    trigger::{enter_trigger, Frame, PipelineEvent, Trigger, TriggerPayload},
+    util::{
+        command_to_string, find_on_path, is_http_url, parse_ffmpeg_drop_dup, parse_ffmpeg_duration,
+        parse_ffmpeg_log_level, parse_ffmpeg_output_size, parse_ffmpeg_progress_time,
+        parse_ffmpeg_showinfo_pts_time, parse_ffmpeg_stream_info, split_lines_cr_lf,
+    },
 };
-use image::RgbImage;
+use image::imageops::FilterType;
+use image::{
+    DynamicImage, GenericImage, GenericImageView, GrayImage, ImageBuffer, RgbImage, RgbaImage,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
 use std::io::Write;
+use std::ops::ControlFlow;
+#[cfg(windows)]
 use std::os::windows::process::CommandExt;
-use std::{
-    io::BufRead,
-    process::{ChildStdin, ChildStdout, Command, Stdio},
-    thread::JoinHandle,
-};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdin, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 use std::{
     io::BufReader,
     process::ChildStderr,
     sync::{
-        mpsc::{channel, Receiver},
-        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
     },
     thread::{self, Scope, ScopedJoinHandle},
 };
 use std::{io::Read, process::Child};
 
-pub type HypetriggerOnCompleteCallback = Arc<dyn Fn() + Send + Sync>;
+pub type HypetriggerOnCompleteCallback = Arc<dyn Fn(CompletionStats) + Send + Sync>;
+pub type HypetriggerOnProgressCallback = Arc<dyn Fn(FfmpegProgress) + Send + Sync>;
+pub type HypetriggerOnFfmpegErrorCallback = Arc<dyn Fn(&FfmpegFailure) + Send + Sync>;
+pub type HypetriggerOnRestartCallback = Arc<dyn Fn(&FfmpegRestart) + Send + Sync>;
+pub type HypetriggerOnTriggerErrorCallback = Arc<dyn Fn(usize, &Error) + Send + Sync>;
+pub type HypetriggerOnBatchProgressCallback = Arc<dyn Fn(&BatchProgress) + Send + Sync>;
+pub type HypetriggerOnStreamInfoCallback = Arc<dyn Fn(StreamInfo) + Send + Sync>;
+pub type HypetriggerOnFfmpegStderrCallback = Arc<dyn Fn(FfmpegLogLevel, &str) + Send + Sync>;
+pub type HypetriggerOnOversamplingCallback = Arc<dyn Fn(u64, f64) + Send + Sync>;
+pub type HypetriggerOnTriggerEventCallback = Arc<dyn Fn(&PipelineEvent) + Send + Sync>;
 
+/// When the `serde` feature is enabled, the configuration can be saved and
+/// loaded. The built-in Triggers are saved as a `TriggerConfig` each, and
+/// built again (without their callbacks) when it's loaded; other Triggers and
+/// all the callbacks are skipped, since they hold closures. Add them again
+/// after deserializing.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Hypetrigger {
-    // Path the the ffmpeg binary or command to use
-    pub ffmpeg_exe: String,
+    /// Path to the ffmpeg binary to use. If `None`, looks for an ffmpeg binary
+    /// next to the current executable, and then falls back to `ffmpeg` on the
+    /// `PATH`.
+    pub ffmpeg_exe: Option<PathBuf>,
 
     /// Path to input video (or image) for ffmpeg. Corresponds to ffmpeg `-i` arg.
-    pub input: String,
+    ///
+    /// Use `pipe:0` to write the input video to ffmpeg's stdin yourself, e.g.
+    /// from another process. Only works with `run_async`: take the stdin from
+    /// `FfmpegSession::child`, and close it to signal the end of the input.
+    ///
+    /// It's passed to ffmpeg as a single arg, exactly as is, so paths with
+    /// spaces or that aren't valid UTF-8 work too. URLs and the like are
+    /// stored as a (non-existent) path.
+    pub input: PathBuf,
+
+    /// Any more inputs to process in lockstep with `input`, e.g. multiple
+    /// camera angles of the same event. Each one gets its own `-i` arg.
+    ///
+    /// The inputs are scaled to the same size (which requires `scale` to be
+    /// set) and stacked side by side by ffmpeg, then split apart again, so that
+    /// the Triggers receive one `Frame` per input for each sample, in order.
+    /// `Frame::input_index` tells them apart.
+    pub extra_inputs: Vec<PathBuf>,
 
     /// Less commonly used, indicates the video format of the input, if it can't
     /// be inferred from the file extension. Corresponds to ffmpeg `-f` arg.
@@ -40,17 +102,323 @@ pub struct Hypetrigger {
     /// - certain methods of screen capture (`-f gdigrab`).
     pub input_format: Option<String>,
 
+    /// Describes the input as raw frames with no container, e.g. screen
+    /// capture buffers written to ffmpeg's stdin (with `pipe:0` as the
+    /// input), since ffmpeg has no way to tell their size or format. Takes
+    /// the place of `input_format`. See `RawInput`.
+    pub raw_input: Option<RawInput>,
+
+    /// Files to play one after another as a single input, e.g. a recording
+    /// split into parts, so that frame numbers and timestamps carry on across
+    /// them. Takes the place of `input` (and `input_format`). They're read
+    /// with ffmpeg's `concat` demuxer from a temporary list, which each run
+    /// writes when it starts and deletes once ffmpeg exits. The files should
+    /// all have the same codecs; see `ConcatList`.
+    pub concat_inputs: Vec<PathBuf>,
+
+    /// Indicates that the input is a live stream (e.g. RTMP or HLS), which
+    /// has no end to read up to:
+    /// - `run` starts ffmpeg again each time it exits after sending frames
+    ///   (e.g. once the stream drops for longer than `-reconnect` rides out),
+    ///   and keeps reading until a Trigger stops the pipeline (see
+    ///   `Trigger::on_frame_control`) or `max_frames` is reached. Frame
+    ///   numbers carry on across the restarts, and `on_complete` is called
+    ///   once at the end. It only returns an error once ffmpeg exits without
+    ///   having sent any frames. Audio Triggers aren't restarted.
+    /// - ffmpeg is passed `-n` instead of `-y`, so an existing output file is
+    ///   never overwritten.
+    /// - `realtime` doesn't throttle ffmpeg with `-re`, since a live stream
+    ///   already arrives in real time.
+    pub is_live: bool,
+
+    /// Keep up with the input in real time, for reacting to live events:
+    /// files are read at their native rate (the ffmpeg `-re` arg), and frames
+    /// that arrive more than `realtime_max_latency` behind where the input
+    /// should be are dropped without running the Triggers, so a slow Trigger
+    /// can't make the latency grow without bound. Dropped frames are counted
+    /// in `CompletionStats::frames_dropped`. Applies to `run` and `run_async`.
+    pub realtime: bool,
+
+    /// How far behind real time a frame can be before it's dropped, when
+    /// `realtime` is set.
+    pub realtime_max_latency: Duration,
+
+    /// Where to start processing the input, instead of from the beginning.
+    /// Corresponds to the ffmpeg `-ss` arg. See `seek_accuracy` for how
+    /// exactly it's found.
+    ///
+    /// Frame numbers still start at 0, but timestamps (and progress) are
+    /// relative to the beginning of the input, not to `start_time`.
+    pub start_time: Option<Duration>,
+
+    /// How ffmpeg seeks to the `start_time`, trading speed for landing on
+    /// exactly the right frame. See `SeekAccuracy`.
+    pub seek_accuracy: SeekAccuracy,
+
+    /// Maximum duration of the input to process, starting from `start_time`.
+    /// Corresponds to the ffmpeg `-t` arg.
+    pub duration: Option<Duration>,
+
+    /// Optionally stop after this many frames have been sampled from the
+    /// input (counting any dropped to keep up in `realtime` mode), e.g. to
+    /// only look at a bounded part of an endless stream. ffmpeg is stopped
+    /// the same way as when a Trigger stops the pipeline early (see
+    /// `Trigger::on_frame_control`). With multiple inputs side by side, each
+    /// frame holds one sample of every input.
+    pub max_frames: Option<u64>,
+
+    /// If set, ffmpeg is killed when it sends nothing on stdout for this long,
+    /// e.g. because a network input stalled, instead of waiting on it forever.
+    /// This includes the time it takes to open the input and send the first
+    /// frame. Only time spent waiting on ffmpeg counts, so slow Triggers or a
+    /// pause (which leave ffmpeg blocked on a full pipe) don't time it out.
+    /// Applies to `run` and `run_supervised`, which own the ffmpeg process;
+    /// with `run_async`, use `FfmpegSession::shutdown` instead.
+    pub stdout_read_timeout: Option<Duration>,
+
+    /// Optionally read ffmpeg's stdout through a buffer of this many bytes,
+    /// so that small frames (e.g. a tiny `scale` at a high framerate) are read
+    /// many at a time, with fewer syscalls. Frames at least this big are read
+    /// directly, so it only helps when it's a few times the frame size. By
+    /// default, each frame is read straight into the frame buffer.
+    pub stdout_buffer_size: Option<usize>,
+
+    /// Whether to still run the Triggers on the last frame when ffmpeg's
+    /// output ends partway through it, with the missing bytes filled in with
+    /// zeros (black). By default the truncated frame is dropped with a
+    /// warning, but for short clips the event may be in that very frame.
+    pub dispatch_partial_frame: bool,
+
+    /// Have ffmpeg add a row of pixels to the bottom of each frame, with the
+    /// frame's number in it, which is checked (and removed) as each frame is
+    /// read. If the pipeline ever gets out of step with ffmpeg's output, it
+    /// logs a warning and carries on from the frame number in the marker,
+    /// skipping any frame whose marker can't be read. Costs a little time in
+    /// ffmpeg per frame. Can't be combined with `VsyncMode::Cfr`, and isn't
+    /// supported by `run_tokio`. See `frame_marker_filter`.
+    pub frame_markers: bool,
+
+    /// Sample rate of the audio passed to audio Triggers (see
+    /// `Trigger::is_audio`), in Hz. 16 kHz is plenty for most sound cues.
+    pub audio_sample_rate: u32,
+
+    /// Length of each `AudioChunk` passed to the audio Triggers.
+    pub audio_chunk_duration: Duration,
+
+    /// How much ffmpeg logs to stderr. Corresponds to the ffmpeg `-loglevel`
+    /// arg, and also tags each line with its level so that it can be passed to
+    /// `on_ffmpeg_stderr` with the right severity. If `None`, ffmpeg logs at
+    /// its default `Info` level.
+    ///
+    /// Below `Info`, ffmpeg no longer describes the input and output streams,
+    /// so `scale` has to be set to know the size of the frames, and the
+    /// `on_stream_info` callback isn't called. ffmpeg would also stop logging
+    /// its progress, so it's passed `-stats` to keep it, for `on_progress`
+    /// and the drop and dup counts.
+    pub ffmpeg_loglevel: Option<FfmpegLogLevel>,
+
+    /// Only pass lines of ffmpeg stderr at least this severe to the
+    /// `on_ffmpeg_stderr` callback, e.g. `Warning` for warnings and errors.
+    /// Defaults to `Trace`, which passes everything.
+    pub ffmpeg_stderr_level: FfmpegLogLevel,
+
+    /// Optionally a name for this pipeline, to tell it apart from others
+    /// running at the same time. It's added to the names of its threads, and
+    /// to its log messages (as a `session_id` field on the tracing span, with
+    /// the `tracing` feature). Callbacks can get it with
+    /// `log::current_session_id`.
+    pub session_id: Option<String>,
+
+    /// Limit how many threads ffmpeg uses to decode each input, e.g. to keep a
+    /// background job from taking over every core. Corresponds to the ffmpeg
+    /// `-threads` input arg. If `None`, ffmpeg decides (usually one per core).
+    pub ffmpeg_threads: Option<u32>,
+
+    /// Limit how many threads ffmpeg uses for the filter graph (fps, scale,
+    /// and stacking multiple inputs). Corresponds to the ffmpeg
+    /// `-filter_threads` and `-filter_complex_threads` args.
+    pub filter_threads: Option<u32>,
+
+    /// On Windows, the process creation flags for ffmpeg, e.g. to hide its
+    /// console window (the default) or lower its priority. Has no effect on
+    /// other platforms.
+    pub windows_process_flags: WindowsProcessFlags,
+
+    /// Hardware acceleration method to use for decoding the input.
+    /// Corresponds to the ffmpeg `-hwaccel` arg. A specific method is checked
+    /// against what the ffmpeg binary supports before it's started (see
+    /// `check_ffmpeg_support`).
+    pub hwaccel: HwAccel,
+
+    /// Which device to decode on, for systems with more than one GPU, e.g.
+    /// `"1"` for the second CUDA device. Corresponds to the ffmpeg
+    /// `-hwaccel_device` arg. Only supported by some `hwaccel` methods (see
+    /// `HwAccel::supports_device`).
+    pub hwaccel_device: Option<String>,
+
+    /// Optionally decode the video with a specific decoder, e.g. `av1_cuvid`
+    /// or `vp9_qsv` for codecs that `hwaccel` doesn't pick a hardware decoder
+    /// for by itself. Corresponds to the ffmpeg `-c:v` input arg, for the main
+    /// `input` only, since `extra_inputs` may well be in other codecs. It's
+    /// checked against the decoders the ffmpeg binary has before
+    /// it's started (see `check_ffmpeg_support`).
+    pub video_decoder: Option<String>,
+
     /// Framerate to sample the input video at. This can (an should) by much
     /// lower than the input video's native framerate. 2-4 frames per second is
     /// more than sufficient to capture most events.
     pub fps: u64,
 
+    /// How ffmpeg syncs the sampled frames to their timestamps. Corresponds to
+    /// the ffmpeg `-vsync` arg. See `VsyncMode` for how each mode affects the
+    /// `Frame` timestamps.
+    pub vsync: VsyncMode,
+
+    /// Deinterlace the input before sampling it, for interlaced sources (e.g.
+    /// broadcast or capture card footage) whose comb artifacts would otherwise
+    /// confuse OCR and classification. Applied as the first filter, before
+    /// `fps` and `scale`.
+    ///
+    /// Works with `hwaccel`, since the decoded frames are downloaded from the
+    /// GPU before any filters run.
+    pub deinterlace: Option<DeinterlaceMode>,
+
+    /// Optionally run the whole input through a chain of ffmpeg filters of
+    /// your own before it's sampled, e.g. `hqdn3d` to denoise low-light
+    /// footage or `eq=contrast=1.2`, which then applies to every Trigger at
+    /// once. Goes after `deinterlace`, and before `fps` and `scale`. It has to
+    /// be a plain chain of filters, without labels or `split`.
+    pub global_filter: Option<String>,
+
+    /// Optionally scale every frame to a fixed `(width, height)` before it is
+    /// sent to the Triggers. Corresponds to the ffmpeg `scale` filter.
+    ///
+    /// Useful for downscaling high resolution input that the Triggers don't
+    /// need in full detail, or for feeding fixed-size models. Crops expressed
+    /// in percentages are unaffected.
+    ///
+    /// This is the size ffmpeg outputs, shared by every Trigger. A Trigger
+    /// that needs its own size (like a 224x224 classifier next to OCR at full
+    /// resolution) asks for it with `Trigger::get_scale`, which resizes the
+    /// frames at this size again just for that Trigger. So this should be the
+    /// largest size any of the Triggers needs, or `None` to leave the frames
+    /// at the input's size.
+    pub scale: Option<(u32, u32)>,
+
+    /// Pixel format of the raw frames that ffmpeg sends to the Triggers.
+    /// Corresponds to the ffmpeg `-pix_fmt` arg.
+    ///
+    /// Use `Gray8` if the Triggers only need luminance (e.g. for OCR), which
+    /// cuts the amount of data read from ffmpeg to a third.
+    ///
+    /// Triggers can ask for a different format with `get_pixel_format()`, so
+    /// mixing formats is supported. ffmpeg still sends a single stream, in
+    /// whichever format has the most channels (see `output_pixel_format`), and
+    /// each frame is converted for the Triggers that asked for something else.
+    pub pixel_format: PixelFormat,
+
+    /// Any more ffmpeg args to add before each `-i`, for options that aren't
+    /// modelled here (e.g. `-probesize`). Can't include another `-i`.
+    pub extra_input_args: Vec<String>,
+
+    /// Any more ffmpeg args to add right before the output, for options that
+    /// aren't modelled here (e.g. `-threads`). Can't change the output format
+    /// (`-f`) or destination (`pipe:`), which the pipeline relies on.
+    pub extra_output_args: Vec<String>,
+
+    /// What to do when a Trigger returns an error (e.g. an `AsyncTrigger`
+    /// whose thread has died). By default the whole pipeline stops; the
+    /// other policies keep the rest of the Triggers running.
+    pub trigger_error_policy: TriggerErrorPolicy,
+
+    /// Where ffmpeg writes the raw frames. By default they go to its stdout,
+    /// where they're read and passed to the Triggers. Any other sink hands
+    /// them to another process instead: in that case no frames are read and
+    /// no Triggers are run, and `run` just waits for ffmpeg to finish (still
+    /// reporting progress).
+    pub output_sink: OutputSink,
+
+    /// If set, ffmpeg also writes the sampled frames to this video file, e.g.
+    /// `frames.mp4`, as a second output alongside the one read by the
+    /// Triggers. They're exactly the frames the Triggers receive (after `fps`
+    /// and `scale`, with multiple inputs side by side), before any cropping
+    /// done by the Triggers themselves, which is handy for tuning crops. See
+    /// also the `screenshots_dir` of the Tesseract and Tensorflow Triggers.
+    pub dump_frames_to: Option<PathBuf>,
+
+    /// If set, ffmpeg also writes a video for each Trigger to this directory,
+    /// as `trigger_<id>.mp4`, of just the region of the frames it reads (see
+    /// `Trigger::get_region`), which is handy for tuning crops. Triggers that
+    /// read the whole frame get the whole frame. The crops are made before
+    /// the Trigger's own `get_scale` or `get_transpose`, and with multiple
+    /// inputs, they're of the main input.
+    ///
+    /// The size of the frames has to be known to place the crops before
+    /// ffmpeg starts, so this requires the `scale` (or a `raw_input`).
+    pub dump_crops_to: Option<PathBuf>,
+
+    /// Whether to create the directories that files are written to (the
+    /// `dump_frames_to` file's, the `dump_crops_to` directory, and each
+    /// Trigger's `get_output_dirs()`) when
+    /// the pipeline starts, if they don't exist yet. If not, a missing
+    /// directory is reported as an error up front, instead of as every frame
+    /// failing to save. `true` by default.
+    pub create_output_dirs: bool,
+
     /// List of all callback functions to run on each frame of the video
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::trigger_config::serde_triggers")
+    )]
     pub triggers: Vec<Arc<dyn Trigger>>,
 
-    /// Callback when the video is finished processing. Particularly useful in
-    /// combination with `run_async`.
+    /// Callback when the video is finished processing, with some stats about
+    /// the run. Particularly useful in combination with `run_async`, to know
+    /// for sure when the input is done.
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub on_complete_callback: Option<HypetriggerOnCompleteCallback>,
+
+    /// Callback each time ffmpeg reports its progress through the input.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_progress_callback: Option<HypetriggerOnProgressCallback>,
+
+    /// Callback on each line of ffmpeg stderr that's at least as severe as
+    /// `ffmpeg_stderr_level`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_ffmpeg_stderr_callback: Option<HypetriggerOnFfmpegStderrCallback>,
+
+    /// Callback once ffmpeg has described the (main) input video stream.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_stream_info_callback: Option<HypetriggerOnStreamInfoCallback>,
+
+    /// Callback when the `fps` is higher than the input's native framerate,
+    /// so ffmpeg has to duplicate frames to keep up, with the `fps` and the
+    /// native framerate.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_oversampling_callback: Option<HypetriggerOnOversamplingCallback>,
+
+    /// Callback when ffmpeg exits with an error before producing any frames,
+    /// with the last lines of its stderr output explaining why.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_ffmpeg_error_callback: Option<HypetriggerOnFfmpegErrorCallback>,
+
+    /// Callback when `run_supervised` restarts ffmpeg after it crashed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_restart_callback: Option<HypetriggerOnRestartCallback>,
+
+    /// Callback when a Trigger returns an error, with its index among the
+    /// Triggers. What happens next depends on the `trigger_error_policy`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_trigger_error_callback: Option<HypetriggerOnTriggerErrorCallback>,
+
+    /// Callback with everything the Triggers report finding, e.g. the text
+    /// read by a `TesseractTrigger` (see `Trigger::on_frame_events`).
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_trigger_event_callback: Option<HypetriggerOnTriggerEventCallback>,
+
+    /// Callback when `run_batch` finishes one of its inputs.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_batch_progress_callback: Option<HypetriggerOnBatchProgressCallback>,
 }
 
 impl Default for Hypetrigger {
@@ -63,25 +431,78 @@ impl Hypetrigger {
     // --- Constructor ---
     pub fn new() -> Self {
         Self {
-            ffmpeg_exe: "ffmpeg".to_string(),
-            input: "".to_string(),
+            ffmpeg_exe: None,
+            input: PathBuf::new(),
+            extra_inputs: vec![],
             input_format: None,
+            raw_input: None,
+            concat_inputs: vec![],
+            is_live: false,
+            realtime: false,
+            realtime_max_latency: Duration::from_secs(1),
+            start_time: None,
+            duration: None,
+            max_frames: None,
+            stdout_read_timeout: None,
+            stdout_buffer_size: None,
+            dispatch_partial_frame: false,
+            frame_markers: false,
+            audio_sample_rate: 16000,
+            audio_chunk_duration: Duration::from_millis(100),
+            trigger_error_policy: TriggerErrorPolicy::default(),
+            output_sink: OutputSink::default(),
+            ffmpeg_loglevel: None,
+            ffmpeg_stderr_level: FfmpegLogLevel::Trace,
+            session_id: None,
+            ffmpeg_threads: None,
+            filter_threads: None,
+            windows_process_flags: WindowsProcessFlags::default(),
+            hwaccel: HwAccel::default(),
+            hwaccel_device: None,
+            video_decoder: None,
             fps: 2,
+            vsync: VsyncMode::default(),
+            seek_accuracy: SeekAccuracy::default(),
+            deinterlace: None,
+            global_filter: None,
+            scale: None,
+            pixel_format: PixelFormat::default(),
+            extra_input_args: vec![],
+            extra_output_args: vec![],
+            dump_frames_to: None,
+            dump_crops_to: None,
+            create_output_dirs: true,
             triggers: vec![],
             on_complete_callback: None,
+            on_progress_callback: None,
+            on_ffmpeg_stderr_callback: None,
+            on_stream_info_callback: None,
+            on_oversampling_callback: None,
+            on_ffmpeg_error_callback: None,
+            on_restart_callback: None,
+            on_trigger_error_callback: None,
+            on_trigger_event_callback: None,
+            on_batch_progress_callback: None,
         }
     }
 
     // --- Getters and setters ---
     /// Setter for the ffmpeg binary or command to use
-    pub fn set_ffmpeg_exe(&mut self, ffmpeg_exe: String) -> &mut Self {
-        self.ffmpeg_exe = ffmpeg_exe;
+    pub fn set_ffmpeg_exe(&mut self, ffmpeg_exe: impl Into<PathBuf>) -> &mut Self {
+        self.ffmpeg_exe = Some(ffmpeg_exe.into());
+        self
+    }
+
+    /// Setter for the input video (or image) for ffmpeg: a path, or any other
+    /// input ffmpeg understands, like a URL
+    pub fn set_input(&mut self, input: impl Into<PathBuf>) -> &mut Self {
+        self.input = input.into();
         self
     }
 
-    /// Setter for the input video (or image) for ffmpeg
-    pub fn set_input(&mut self, input: String) -> &mut Self {
-        self.input = input;
+    /// Add another input to be processed alongside the main one
+    pub fn add_input(&mut self, input: impl Into<PathBuf>) -> &mut Self {
+        self.extra_inputs.push(input.into());
         self
     }
 
@@ -91,18 +512,245 @@ impl Hypetrigger {
         self
     }
 
+    /// Read the input as raw frames of the given size, format and framerate
+    pub fn set_raw_input(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel_format: PixelFormat,
+        fps: f64,
+    ) -> &mut Self {
+        self.raw_input = Some(RawInput {
+            width,
+            height,
+            pixel_format,
+            fps,
+        });
+        self
+    }
+
     /// Alias for `set_input_format("lavfi")` and `set_input(FFMPEG_TEST_INPUT)`
     pub fn test_input(&mut self) -> &mut Self {
         self.set_input_format("lavfi")
             .set_input(FFMPEG_TEST_INPUT.to_string())
     }
 
+    /// Setter for whether the input is a live stream
+    pub fn set_live(&mut self, is_live: bool) -> &mut Self {
+        self.is_live = is_live;
+        self
+    }
+
+    /// Setter for keeping up with the input in real time, dropping frames
+    /// that fall more than `max_latency` behind.
+    pub fn set_realtime(&mut self, max_latency: Duration) -> &mut Self {
+        self.realtime = true;
+        self.realtime_max_latency = max_latency;
+        self
+    }
+
+    /// Setter for where to start processing the input
+    pub fn set_start_time(&mut self, start_time: Duration) -> &mut Self {
+        self.start_time = Some(start_time);
+        self
+    }
+
+    /// Setter for the maximum duration of the input to process
+    pub fn set_duration(&mut self, duration: Duration) -> &mut Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Setter for the number of frames to stop after
+    pub fn set_max_frames(&mut self, max_frames: u64) -> &mut Self {
+        self.max_frames = Some(max_frames);
+        self
+    }
+
+    /// Setter for how long to wait for data from ffmpeg before killing it
+    pub fn set_stdout_read_timeout(&mut self, stdout_read_timeout: Duration) -> &mut Self {
+        self.stdout_read_timeout = Some(stdout_read_timeout);
+        self
+    }
+
+    /// Setter for the size of the buffer to read ffmpeg's stdout through
+    pub fn set_stdout_buffer_size(&mut self, stdout_buffer_size: usize) -> &mut Self {
+        self.stdout_buffer_size = Some(stdout_buffer_size);
+        self
+    }
+
+    /// Setter for the sample rate of the audio passed to audio Triggers.
+    pub fn set_audio_sample_rate(&mut self, audio_sample_rate: u32) -> &mut Self {
+        self.audio_sample_rate = audio_sample_rate;
+        self
+    }
+
+    /// Setter for the length of each chunk of audio passed to audio Triggers.
+    pub fn set_audio_chunk_duration(&mut self, audio_chunk_duration: Duration) -> &mut Self {
+        self.audio_chunk_duration = audio_chunk_duration;
+        self
+    }
+
+    /// Setter for whether to run the Triggers on a truncated last frame.
+    pub fn set_dispatch_partial_frame(&mut self, dispatch_partial_frame: bool) -> &mut Self {
+        self.dispatch_partial_frame = dispatch_partial_frame;
+        self
+    }
+
+    /// Setter for how much ffmpeg logs to stderr
+    pub fn set_ffmpeg_loglevel(&mut self, ffmpeg_loglevel: FfmpegLogLevel) -> &mut Self {
+        self.ffmpeg_loglevel = Some(ffmpeg_loglevel);
+        self
+    }
+
+    /// Setter for the least severe lines of ffmpeg stderr passed to the
+    /// `on_ffmpeg_stderr` callback
+    pub fn set_ffmpeg_stderr_level(&mut self, ffmpeg_stderr_level: FfmpegLogLevel) -> &mut Self {
+        self.ffmpeg_stderr_level = ffmpeg_stderr_level;
+        self
+    }
+
+    /// Setter for the name to tell this pipeline apart from others by
+    pub fn set_session_id(&mut self, session_id: impl Into<String>) -> &mut Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    /// The name for one of this pipeline's threads, with the `session_id`
+    /// added to it, if there is one.
+    pub(crate) fn thread_name(&self, name: &str) -> String {
+        log::thread_name(name, self.session_id.as_deref())
+    }
+
+    /// Setter for whether to hide the ffmpeg console window on Windows
+    pub fn set_hide_ffmpeg_window(&mut self, hide_ffmpeg_window: bool) -> &mut Self {
+        self.windows_process_flags.hide_window = hide_ffmpeg_window;
+        self
+    }
+
+    /// Setter for the number of threads ffmpeg uses to decode each input
+    pub fn set_ffmpeg_threads(&mut self, ffmpeg_threads: u32) -> &mut Self {
+        self.ffmpeg_threads = Some(ffmpeg_threads);
+        self
+    }
+
+    /// Setter for the number of threads ffmpeg uses for the filter graph
+    pub fn set_filter_threads(&mut self, filter_threads: u32) -> &mut Self {
+        self.filter_threads = Some(filter_threads);
+        self
+    }
+
+    /// Setter for the process creation flags for ffmpeg on Windows
+    pub fn set_windows_process_flags(&mut self, flags: WindowsProcessFlags) -> &mut Self {
+        self.windows_process_flags = flags;
+        self
+    }
+
+    /// Setter for the hardware acceleration method
+    pub fn set_hwaccel(&mut self, hwaccel: HwAccel) -> &mut Self {
+        self.hwaccel = hwaccel;
+        self
+    }
+
+    /// Setter for the device to use for hardware accelerated decoding
+    pub fn set_hwaccel_device(&mut self, hwaccel_device: &str) -> &mut Self {
+        self.hwaccel_device = Some(hwaccel_device.to_string());
+        self
+    }
+
+    /// Setter for the decoder to decode the video with
+    pub fn set_video_decoder(&mut self, video_decoder: impl Into<String>) -> &mut Self {
+        self.video_decoder = Some(video_decoder.into());
+        self
+    }
+
+    /// Setter for how ffmpeg seeks to the `start_time`.
+    pub fn set_seek_accuracy(&mut self, seek_accuracy: SeekAccuracy) -> &mut Self {
+        self.seek_accuracy = seek_accuracy;
+        self
+    }
+
     /// Setter for the framerate to sample the input video at.
     pub fn set_fps(&mut self, fps: u64) -> &mut Self {
         self.fps = fps;
         self
     }
 
+    /// Setter for how ffmpeg syncs the sampled frames to their timestamps.
+    pub fn set_vsync(&mut self, vsync: VsyncMode) -> &mut Self {
+        self.vsync = vsync;
+        self
+    }
+
+    /// Setter for the filter used to deinterlace the input.
+    pub fn set_deinterlace(&mut self, deinterlace: DeinterlaceMode) -> &mut Self {
+        self.deinterlace = Some(deinterlace);
+        self
+    }
+
+    /// Setter for the filters to run the whole input through before it's
+    /// sampled, e.g. `"hqdn3d,unsharp"`.
+    pub fn set_global_filter(&mut self, global_filter: impl Into<String>) -> &mut Self {
+        self.global_filter = Some(global_filter.into());
+        self
+    }
+
+    /// Setter for a video file to also write the sampled frames to
+    pub fn set_dump_frames_to(&mut self, path: PathBuf) -> &mut Self {
+        self.dump_frames_to = Some(path);
+        self
+    }
+
+    /// Setter for a directory to write a video of each Trigger's crop to
+    pub fn set_dump_crops_to(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.dump_crops_to = Some(dir.into());
+        self
+    }
+
+    /// Setter for whether to create missing output directories
+    pub fn set_create_output_dirs(&mut self, create_output_dirs: bool) -> &mut Self {
+        self.create_output_dirs = create_output_dirs;
+        self
+    }
+
+    /// Setter for what to do when a Trigger returns an error
+    pub fn set_trigger_error_policy(&mut self, policy: TriggerErrorPolicy) -> &mut Self {
+        self.trigger_error_policy = policy;
+        self
+    }
+
+    /// Setter for where ffmpeg writes the raw frames
+    pub fn set_output_sink(&mut self, output_sink: OutputSink) -> &mut Self {
+        self.output_sink = output_sink;
+        self
+    }
+
+    /// Setter for the size to scale each frame to before running Triggers.
+    pub fn set_scale(&mut self, width: u32, height: u32) -> &mut Self {
+        self.scale = Some((width, height));
+        self
+    }
+
+    /// Setter for the pixel format of the frames sent to the Triggers.
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) -> &mut Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    /// Add extra ffmpeg args to go before each input
+    pub fn add_input_args(&mut self, args: &[&str]) -> &mut Self {
+        self.extra_input_args
+            .extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    /// Add extra ffmpeg args to go before the output
+    pub fn add_output_args(&mut self, args: &[&str]) -> &mut Self {
+        self.extra_output_args
+            .extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
     /// Add a Trigger to be run on every frame of the input
     pub fn add_trigger<T>(&mut self, trigger: T) -> &mut Self
     where
@@ -123,220 +771,4491 @@ impl Hypetrigger {
     /// Call the given function when the input finishes processing
     pub fn on_complete<T>(&mut self, callback: T) -> &mut Self
     where
-        T: Fn() + Send + Sync + 'static,
+        T: Fn(CompletionStats) + Send + Sync + 'static,
     {
         self.on_complete_callback = Some(Arc::new(callback));
         self
     }
 
-    // --- Behavior ---
-    /// Spawn ffmpeg, call callbacks on each frame, and block until completion.
-    pub fn run(&mut self) -> Result<()> {
-        println!("[hypetrigger] run()");
-
-        // Spawn FFMPEG command
-        let mut ffmpeg_child = self.spawn_ffmpeg_child()?;
-        let ffmpeg_stderr = ffmpeg_child.stderr.take().ok_or(NoneError)?;
-        let ffmpeg_stdout = ffmpeg_child.stdout.take().ok_or(NoneError)?;
-
-        // Attach to ffmpeg
-        self.attach(ffmpeg_stderr, ffmpeg_stdout)?;
-
-        // Block until ffmpeg finishes
-        let ffmpeg_exit_status = ffmpeg_child.wait()?;
-        println!(
-            "[ffmpeg] ffmpeg command exited with status {}",
-            ffmpeg_exit_status
-        );
+    /// Call the given function each time ffmpeg reports its progress
+    pub fn on_progress<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(FfmpegProgress) + Send + Sync + 'static,
+    {
+        self.on_progress_callback = Some(Arc::new(callback));
+        self
+    }
 
-        Ok(())
+    /// Call the given function with the size, framerate and codec of the
+    /// input, as detected by ffmpeg
+    pub fn on_stream_info<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(StreamInfo) + Send + Sync + 'static,
+    {
+        self.on_stream_info_callback = Some(Arc::new(callback));
+        self
     }
 
-    pub fn run_async(self) -> Result<(JoinHandle<()>, ChildStdin)> {
-        println!("[hypetrigger] run_async()");
+    /// Call the given function if the `fps` turns out to be higher than the
+    /// input's native framerate. A warning is logged either way.
+    pub fn on_oversampling<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(u64, f64) + Send + Sync + 'static,
+    {
+        self.on_oversampling_callback = Some(Arc::new(callback));
+        self
+    }
 
-        // Spawn FFMPEG command
-        let mut ffmpeg_child = self.spawn_ffmpeg_child()?;
+    /// Call the given function if ffmpeg fails before producing any frames
+    pub fn on_ffmpeg_error<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(&FfmpegFailure) + Send + Sync + 'static,
+    {
+        self.on_ffmpeg_error_callback = Some(Arc::new(callback));
+        self
+    }
 
-        // Separate each stdio channel to use in different places
-        let ffmpeg_stderr = ffmpeg_child.stderr.take().ok_or(NoneError)?;
-        let ffmpeg_stdout = ffmpeg_child.stdout.take().ok_or(NoneError)?;
-        let ffmpeg_stdin = ffmpeg_child.stdin.take().ok_or(NoneError)?;
+    /// Call the given function each time `run_supervised` restarts ffmpeg
+    pub fn on_restart<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(&FfmpegRestart) + Send + Sync + 'static,
+    {
+        self.on_restart_callback = Some(Arc::new(callback));
+        self
+    }
 
-        // Attach to ffmpeg
-        let join_handle = thread::spawn(move || {
-            // this blocks (on the inner thread) until the pipeline is done:
-            self.attach(ffmpeg_stderr, ffmpeg_stdout)
-                .expect("pipeline should complete");
-        });
+    /// Call the given function when a Trigger returns an error, with the
+    /// index of the Trigger
+    pub fn on_trigger_error<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(usize, &Error) + Send + Sync + 'static,
+    {
+        self.on_trigger_error_callback = Some(Arc::new(callback));
+        self
+    }
 
-        Ok((join_handle, ffmpeg_stdin))
+    /// Call the given function with each result any of the Triggers reports,
+    /// e.g. to handle the results of all of them in one place, rather than
+    /// with the callback of each one. Triggers on an `AsyncTrigger` report
+    /// theirs from the worker of its `TriggerThread`, once they've run, so
+    /// the callback is called on that thread instead.
+    pub fn on_trigger_event<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(&PipelineEvent) + Send + Sync + 'static,
+    {
+        self.on_trigger_event_callback = Some(Arc::new(callback));
+        self
     }
 
-    /// Set up the watchers for the ffmpeg process on stdout and stderr. Stderr
-    /// will run in a separate scoped thread, while stdout will run on the
-    /// current thread, and then block until completion.
-    pub fn attach(
-        &self,
-        mut ffmpeg_stderr: ChildStderr,
-        mut ffmpeg_stdout: ChildStdout,
-    ) -> Result<()> {
-        // Enter a new scope that will block until ffmpeg_stderr_thread is done
-        thread::scope(|scope| {
-            // Spawn a thread to read stderr from ffmpeg
-            let (output_size_rx, _ffmpeg_stderr_join_handle) =
-                match self.spawn_ffmpeg_stderr_thread(&mut ffmpeg_stderr, scope) {
-                    Ok(ffmpeg_stderr_thread) => ffmpeg_stderr_thread,
-                    Err(e) => {
-                        // TODO lost scope:
-                        // ffmpeg_child
-                        //     .kill()
-                        //     .expect("able to stop ffmpeg process if something goes wrong");
-                        return Err(e.to_string());
-                    }
-                };
+    /// Call the given function each time `run_batch` finishes an input
+    pub fn on_batch_progress<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(&BatchProgress) + Send + Sync + 'static,
+    {
+        self.on_batch_progress_callback = Some(Arc::new(callback));
+        self
+    }
 
-            // Block on each line of ffmpeg stderr until receiving the output size
-            let (output_width, output_height) = output_size_rx.recv().map_err(|_| {
-              "ffmpeg exited before sending output size. This is likely due to an invalid input file.".to_string()
-            })?;
-            println!(
-                "[ffmpeg] Parsed output size from logs: {}x{}",
-                output_width, output_height
-            );
+    /// Call the given function on each line of ffmpeg stderr, along with how
+    /// severe it is (see `ffmpeg_stderr_level`)
+    pub fn on_ffmpeg_stderr<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(FfmpegLogLevel, &str) + Send + Sync + 'static,
+    {
+        self.on_ffmpeg_stderr_callback = Some(Arc::new(callback));
+        self
+    }
 
-            // Initialize a buffer
-            const CHANNELS: u32 = 3; // matches in the `-f rgb24` flag to ffmpeg
-            let buf_size = (output_width * output_height * CHANNELS) as usize;
-            let mut buffer = vec![0_u8; buf_size];
-            println!("[ffmpeg.stdout] Allocated buffer of size {}", buf_size);
-
-            // Read from stdout on the current thread, invoking Triggers each frame
-            let mut frame_num = 0;
-            while ffmpeg_stdout.read_exact(&mut buffer).is_ok() {
-                let image = match RgbImage::from_vec(output_width, output_height, buffer.clone()) {
-                    Some(image) => image,
-                    None => {
-                        return Err(
-                            "unable to convert vec to imagebuffer (size mismatch)".to_string()
-                        )
-                    }
-                };
-                let frame = Frame {
-                    image,
-                    frame_num,
-                    timestamp: frame_num as f64 / self.fps as f64,
-                };
-                for trigger in &self.triggers {
-                    trigger.on_frame(&frame).map_err(|e| e.to_string())?;
-                }
-                frame_num += 1;
-            }
-            
-            println!("[ffmpeg.out] Finished reading from stdout");
-            if let Some(callback) = &self.on_complete_callback {
-                callback();
-            }
-            Ok(())
-        }).map_err(Error::from)
+    // --- Behavior ---
+    /// Check that the configuration makes sense before spawning ffmpeg, so
+    /// that mistakes show up as a clear error rather than deep in ffmpeg's
+    /// output. Called automatically by `run`, `run_async` and
+    /// `run_supervised`, and by `run_tokio` and `frame_stream`, which don't
+    /// need any Triggers.
+    pub fn validate(&self) -> core::result::Result<(), FfmpegError> {
+        self.validate_settings(true)
     }
 
-    pub fn spawn_ffmpeg_child(&self) -> Result<Child> {
-        let mut cmd = Command::new(self.ffmpeg_exe.as_str());
-        cmd.arg("-hwaccel").arg("auto");
-        if let Some(input_format) = &self.input_format {
-            cmd.arg("-f").arg(input_format);
+    /// `validate`, but with `needs_triggers` off, for when the frames are
+    /// handed over to the caller instead of being run through the Triggers.
+    pub(crate) fn validate_settings(
+        &self,
+        needs_triggers: bool,
+    ) -> core::result::Result<(), FfmpegError> {
+        if self.input.as_os_str().is_empty() && self.concat_inputs.is_empty() {
+            return Err(FfmpegError::InvalidConfig("no input was set".to_string()));
         }
-        cmd.arg("-i")
-            .arg(self.input.as_str())
-            .arg("-filter:v")
-            .arg(format!("fps={}", self.fps))
-            .arg("-vsync")
-            .arg("drop")
-            .arg("-f")
-            .arg("rawvideo")
-            .arg("-pix_fmt")
-            .arg("rgb24")
-            .arg("-an")
-            .arg("-y")
-            .arg("pipe:1")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .creation_flags(0x08000000); // this seems Windows-only. Is there a cross-platform solution?
+        if !self.concat_inputs.is_empty()
+            && (self.input_format.is_some() || self.raw_input.is_some())
+        {
+            return Err(FfmpegError::InvalidConfig(
+                "`concat_inputs` can't be combined with `input_format` or `raw_input`".to_string(),
+            ));
+        }
+        if self.fps == 0 {
+            return Err(FfmpegError::InvalidConfig(
+                "fps must be greater than 0".to_string(),
+            ));
+        }
+        if self.ffmpeg_threads == Some(0) || self.filter_threads == Some(0) {
+            return Err(FfmpegError::InvalidConfig(
+                "thread counts must be greater than 0".to_string(),
+            ));
+        }
+        if self.output_sink != OutputSink::Stdout && self.stdout_read_timeout.is_some() {
+            return Err(FfmpegError::InvalidConfig(
+                "`stdout_read_timeout` only works when the frames are sent to stdout".to_string(),
+            ));
+        }
+        if self.dump_crops_to.is_some() && self.frame_size().is_none() {
+            return Err(FfmpegError::InvalidConfig(
+                "`dump_crops_to` requires `scale` to be set, to know where the crops are"
+                    .to_string(),
+            ));
+        }
+        if needs_triggers && self.triggers.is_empty() && self.output_sink == OutputSink::Stdout {
+            return Err(FfmpegError::InvalidConfig(
+                "no Triggers were added, so there's nothing to run on the frames".to_string(),
+            ));
+        }
+        if !self.triggers.is_empty() && !self.triggers.iter().any(|t| t.get_enabled()) {
+            return Err(FfmpegError::InvalidConfig(
+                "all of the Triggers are disabled, so there's nothing to run on the frames"
+                    .to_string(),
+            ));
+        }
+        if self.known_output_size().is_none()
+            && self
+                .ffmpeg_loglevel
+                .is_some_and(|level| level < FfmpegLogLevel::Info)
+        {
+            return Err(FfmpegError::InvalidConfig(
+                "below the `Info` loglevel, ffmpeg doesn't log the output size, so `scale` (or `raw_input`) must be set"
+                    .to_string(),
+            ));
+        }
+        if let Some(global_filter) = &self.global_filter {
+            validate_global_filter(global_filter)?;
+        }
+        if self.max_frames == Some(0) {
+            return Err(FfmpegError::InvalidConfig(
+                "`max_frames` must be at least 1".to_string(),
+            ));
+        }
+        if self.frame_markers && self.vsync == VsyncMode::Cfr {
+            return Err(FfmpegError::InvalidConfig(
+                "`frame_markers` can't be used with `VsyncMode::Cfr`, whose duplicate frames would repeat the markers"
+                    .to_string(),
+            ));
+        }
+        // ffmpeg's `scale` filter would take a 0 to mean "keep the input size"
+        if self
+            .scale
+            .is_some_and(|(width, height)| width == 0 || height == 0)
+        {
+            return Err(FfmpegError::InvalidConfig(
+                "`scale` needs a nonzero width and height".to_string(),
+            ));
+        }
+        if self.vsync == VsyncMode::SourcePts
+            && self.seek_accuracy == SeekAccuracy::Decode
+            && self.start_time.is_some()
+        {
+            return Err(FfmpegError::InvalidConfig(
+                "`VsyncMode::SourcePts` can't be used with `SeekAccuracy::Decode`, which logs the frames before the `start_time` too"
+                    .to_string(),
+            ));
+        }
+        if self.vsync == VsyncMode::SourcePts {
+            if !self.extra_inputs.is_empty() {
+                return Err(FfmpegError::InvalidConfig(
+                    "`VsyncMode::SourcePts` only supports a single input".to_string(),
+                ));
+            }
+            if self
+                .ffmpeg_loglevel
+                .is_some_and(|level| level < FfmpegLogLevel::Info)
+            {
+                return Err(FfmpegError::InvalidConfig(
+                    "`VsyncMode::SourcePts` reads the frame timestamps from ffmpeg's logs, so needs at least the `Info` loglevel"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(raw_input) = &self.raw_input {
+            if self.input_format.is_some() {
+                return Err(FfmpegError::InvalidConfig(
+                    "`raw_input` and `input_format` can't both be set".to_string(),
+                ));
+            }
+            if raw_input.width == 0 || raw_input.height == 0 || raw_input.fps <= 0.0 {
+                return Err(FfmpegError::InvalidConfig(
+                    "`raw_input` needs a nonzero size and framerate".to_string(),
+                ));
+            }
+        }
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            trigger.validate().map_err(|e| {
+                FfmpegError::InvalidConfig(format!("Trigger {} is misconfigured: {}", i, e))
+            })?;
+            // When the frame size is known up front, crops can be checked against it
+            if let Some((width, height)) = self.frame_size() {
+                if let Some((scaled_width, scaled_height)) = trigger
+                    .get_scale()
+                    .filter(|&(w, h)| is_upscaled((w, h), (width, height)))
+                {
+                    return Err(FfmpegError::InvalidConfig(format!(
+                        "Trigger {} asks for {}x{} frames, which is bigger than the {}x{} frames from ffmpeg; raise the `scale` instead",
+                        i, scaled_width, scaled_height, width, height
+                    )));
+                }
+                let (width, height) = trigger_frame_size(trigger.as_ref(), width, height);
+                if let Some((x1, y1, x2, y2)) = trigger.get_region(width, height) {
+                    if x1 >= x2 || y1 >= y2 {
+                        return Err(FfmpegError::InvalidConfig(format!(
+                            "the region of Trigger {} is empty in a {}x{} frame",
+                            i, width, height
+                        )));
+                    }
+                }
+            }
+        }
+        if self.has_audio_triggers() {
+            if self.is_stdin_input() {
+                return Err(FfmpegError::InvalidConfig(
+                    "audio Triggers can't read the input from stdin, since it's decoded twice"
+                        .to_string(),
+                ));
+            }
+            if self.audio_chunk_samples() == 0 {
+                return Err(FfmpegError::InvalidConfig(
+                    "`audio_chunk_duration` must be at least one sample long".to_string(),
+                ));
+            }
+        }
+        if self.video_decoder.as_deref() == Some("") {
+            return Err(FfmpegError::InvalidConfig(
+                "`video_decoder` can't be empty".to_string(),
+            ));
+        }
+        if self.hwaccel_device.is_some() && !self.hwaccel.supports_device() {
+            return Err(FfmpegError::InvalidConfig(format!(
+                "hwaccel {:?} doesn't support choosing a device",
+                self.hwaccel
+            )));
+        }
+        Ok(())
+    }
 
-        // Debug command
-        println!("[debug] ffmpeg command appears below:");
-        println!("{}", command_to_string(&cmd));
+    /// Create the directories that files are written to, or with
+    /// `create_output_dirs` turned off, check that they exist. Called
+    /// automatically by `run`, `run_async`, `run_supervised` and `run_tokio`.
+    pub fn prepare_output_dirs(&self) -> core::result::Result<(), FfmpegError> {
+        let dump_frames_dir = self
+            .dump_frames_to
+            .as_deref()
+            .and_then(Path::parent)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf);
+        let trigger_dirs = self
+            .triggers
+            .iter()
+            .filter(|trigger| trigger.get_enabled())
+            .flat_map(|trigger| trigger.get_output_dirs());
+        let dirs = dump_frames_dir
+            .into_iter()
+            .chain(self.dump_crops_to.clone())
+            .chain(trigger_dirs);
+        for dir in dirs {
+            if self.create_output_dirs {
+                std::fs::create_dir_all(&dir).map_err(|e| {
+                    FfmpegError::InvalidConfig(format!(
+                        "couldn't create the output directory {}: {}",
+                        dir.display(),
+                        e
+                    ))
+                })?;
+            } else if !dir.is_dir() {
+                return Err(FfmpegError::InvalidConfig(format!(
+                    "the output directory {} doesn't exist",
+                    dir.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Spawn ffmpeg, call callbacks on each frame, and block until completion.
+    pub fn run(&mut self) -> Result<()> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        log_debug!("[hypetrigger] run()");
+        self.validate()?;
+        self.check_ffmpeg_support()?;
+        self.prepare_output_dirs()?;
+        if self.is_stdin_input() {
+            return Err(FfmpegError::InvalidConfig(
+                "reading the input from stdin requires `run_async`, to write to ffmpeg's stdin"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        // Spawn FFMPEG command
+        let concat_list = self.write_concat_list()?;
+        let ffmpeg_child = self.spawn_ffmpeg_child(concat_list_path(&concat_list))?;
 
-        cmd.spawn().map_err(Error::from)
+        // Attach to ffmpeg, with any audio Triggers running alongside, and
+        // block until ffmpeg finishes
+        let this = &*self;
+        let stop_audio = AtomicBool::new(false);
+        let (summary, ffmpeg_exit_status) = thread::scope(|scope| {
+            let audio_thread =
+                this.spawn_ffmpeg_audio_thread(scope, &stop_audio, concat_list_path(&concat_list))?;
+            let summary = this.attach_until_stopped(ffmpeg_child, concat_list_path(&concat_list));
+            if summary
+                .as_ref()
+                .map_or(true, |(summary, _)| summary.stopped_early)
+            {
+                stop_audio.store(true, Ordering::Relaxed);
+            }
+            if let Some(audio_thread) = audio_thread {
+                join_audio_thread(audio_thread)?;
+            }
+            summary
+        })?;
+
+        if summary.timed_out {
+            return Err(
+                FfmpegError::StdoutTimeout(self.stdout_read_timeout.unwrap_or_default()).into(),
+            );
+        }
+
+        check_ffmpeg_exit(
+            ffmpeg_exit_status,
+            summary,
+            self.on_ffmpeg_error_callback.as_ref(),
+        )?;
+        Ok(())
     }
 
-    /// Spawns a thread to handle reading the stderr channel from ffmpeg.
+    /// Attach to ffmpeg and block until it exits. For a live input, ffmpeg
+    /// is spawned again each time it exits after sending frames, carrying on
+    /// the frame numbers, until the pipeline is stopped (see `is_live`).
+    fn attach_until_stopped(
+        &self,
+        mut ffmpeg_child: Child,
+        concat_list: Option<&Path>,
+    ) -> Result<(PipelineSummary, ExitStatus)> {
+        if !self.is_live {
+            let summary = self.attach_child(&mut ffmpeg_child, 0)?;
+            let ffmpeg_exit_status = ffmpeg_child.wait()?;
+            log_debug!(
+                "[ffmpeg] ffmpeg command exited with status {}",
+                ffmpeg_exit_status
+            );
+            return Ok((summary, ffmpeg_exit_status));
+        }
+
+        // `on_complete` is only called once, for all the ffmpeg processes
+        let started_at = Instant::now();
+        let mut attempt = self.clone();
+        attempt.on_complete_callback = None;
+        let mut total = PipelineSummary {
+            trigger_frames: vec![0; self.triggers.len()],
+            ..Default::default()
+        };
+        loop {
+            let summary = attempt.attach_child(&mut ffmpeg_child, total.frames_read)?;
+            let ffmpeg_exit_status = ffmpeg_child.wait()?;
+            log_debug!(
+                "[ffmpeg] ffmpeg command exited with status {}",
+                ffmpeg_exit_status
+            );
+
+            total.frames_read += summary.frames_read;
+            total.frames_dropped += summary.frames_dropped;
+            total.ffmpeg_dropped += summary.ffmpeg_dropped;
+            total.ffmpeg_duplicated += summary.ffmpeg_duplicated;
+            for (total, count) in total.trigger_frames.iter_mut().zip(&summary.trigger_frames) {
+                *total += count;
+            }
+            if summary.stopped_early || summary.timed_out || summary.frames_read == 0 {
+                total.stopped_early = summary.stopped_early;
+                total.timed_out = summary.timed_out;
+                total.stderr_tail = summary.stderr_tail;
+                self.report_completion(&total, started_at);
+                return Ok((total, ffmpeg_exit_status));
+            }
+            log_warn!(
+                "[ffmpeg] live input ended with {} after {} frames; reconnecting",
+                ffmpeg_exit_status,
+                total.frames_read
+            );
+            ffmpeg_child = attempt.spawn_ffmpeg_child(concat_list)?;
+        }
+    }
+
+    /// The arg for what ffmpeg does about existing output files: overwrite
+    /// them (`-y`), or for a live input, never overwrite them (`-n`). Either
+    /// way, ffmpeg doesn't wait on stdin for an answer to its prompt.
+    pub(crate) fn overwrite_arg(&self) -> &'static str {
+        if self.is_live {
+            "-n"
+        } else {
+            "-y"
+        }
+    }
+
+    /// Spawn ffmpeg and process it on background threads, returning
+    /// immediately with a handle to the running session.
+    pub fn run_async(mut self) -> Result<FfmpegSession> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        log_debug!("[hypetrigger] run_async()");
+        self.validate()?;
+        self.check_ffmpeg_support()?;
+        self.prepare_output_dirs()?;
+        if self.has_audio_triggers() {
+            return Err(FfmpegError::InvalidConfig(
+                "audio Triggers are only supported by `run`".to_string(),
+            )
+            .into());
+        }
+
+        // Spawn FFMPEG command
+        let concat_list = self.write_concat_list()?;
+        let mut ffmpeg_child = self.spawn_ffmpeg_child(concat_list_path(&concat_list))?;
+
+        // Separate each stdio channel to use in different places
+        let ffmpeg_stderr = ffmpeg_child.stderr.take().ok_or(NoneError)?;
+        let ffmpeg_stdout = ffmpeg_child.stdout.take().ok_or(NoneError)?;
+        // With stdin as the input, it's left on the child for the caller to write to
+        let ffmpeg_stdin = match self.is_stdin_input() {
+            true => None,
+            false => Some(ffmpeg_child.stdin.take().ok_or(NoneError)?),
+        };
+
+        // Keep the stream info on the session as well as passing it to the callback
+        let stream_info = Arc::new(Mutex::new(None));
+        let stream_info_clone = stream_info.clone();
+        let on_stream_info_callback = self.on_stream_info_callback.take();
+        self.on_stream_info(move |info| {
+            if let Ok(mut stream_info) = stream_info_clone.lock() {
+                *stream_info = Some(info.clone());
+            }
+            if let Some(callback) = &on_stream_info_callback {
+                callback(info);
+            }
+        });
+
+        // Attach to ffmpeg
+        let on_ffmpeg_error_callback = self.on_ffmpeg_error_callback.clone();
+        let pixel_format = self.output_pixel_format();
+        let (trigger_tx, trigger_rx) = channel::<TriggerUpdate>();
+        let pause = Arc::new(PauseSignal::default());
+        let health = Arc::new(TriggerHealth::default());
+        let controls = PipelineControls {
+            trigger_rx,
+            pause: pause.clone(),
+            health: health.clone(),
+        };
+        let session_id = self.session_id.clone();
+        let pipeline_thread = thread::Builder::new()
+            .name(self.thread_name("hypetrigger_pipeline"))
+            .spawn(move || {
+                // The list is kept until ffmpeg closes its stdout
+                let _concat_list = concat_list;
+                // this blocks (on the inner thread) until the pipeline is done:
+                self.attach_with_controls(ffmpeg_stderr, ffmpeg_stdout, 0, Some(controls))
+                    .map_err(|e| e.to_string())
+            })?;
+
+        // Listen for commands to forward to ffmpeg
+        let (stdin_tx, stdin_thread) =
+            spawn_ffmpeg_stdin_thread(ffmpeg_stdin, pause.clone(), session_id.clone())?;
+
+        Ok(FfmpegSession {
+            child: ffmpeg_child,
+            pipeline_thread,
+            stdin_thread,
+            stdin_tx,
+            on_ffmpeg_error_callback,
+            stream_info,
+            trigger_tx,
+            pixel_format,
+            pause,
+            health,
+            session_id,
+        })
+    }
+
+    /// Set up the watchers for the ffmpeg process on stdout and stderr. Stderr
+    /// will run in a separate scoped thread, while stdout will run on the
+    /// current thread, and then block until completion.
     ///
-    /// After first spawning, we read the metadata/prelude of the ffmpeg job in
-    /// order to determine the width and height of the output frames. That's
-    /// sent back to the main thread via a channel. After the metadata is
-    /// received, the channel closes, while the stderr handler thread continues
-    /// to run in the background. It automatically stops after ffmpeg exits.
-    pub fn spawn_ffmpeg_stderr_thread<'scope>(
-        &'scope self,
-        ffmpeg_stderr: &'scope mut ChildStderr,
-        scope: &'scope Scope<'scope, '_>, // scope scope scope scope wheeee
-    ) -> Result<(Receiver<(u32, u32)>, FfmpegStderrJoinHandle<'scope>)> {
-        let (output_size_tx, output_size_rx) = channel::<(u32, u32)>();
-        let thread_body = move || {
-            let reader = BufReader::new(ffmpeg_stderr);
-            let mut current_section = "";
-            let mut output_size: Option<(u32, u32)> = None;
-            for line in reader.lines() {
-                let text = match line {
+    /// Frames are delivered in a fixed order: every Trigger receives each
+    /// frame, in the order the Triggers were added, before any Trigger receives
+    /// the next frame. With multiple inputs, the frames of each input are
+    /// delivered in input order for every sample. ffmpeg only ever sends a
+    /// single stream of whole frames, so a slow Trigger can delay the sequence
+    /// but never reorder it. Wrap a Trigger in an `AsyncTrigger` to stop it
+    /// from holding up the others.
+    ///
+    /// Returns once ffmpeg closes its stdout, with a summary of what was read
+    /// that can be passed to `check_ffmpeg_exit` along with ffmpeg's exit
+    /// status.
+    pub fn attach(
+        &self,
+        ffmpeg_stderr: ChildStderr,
+        ffmpeg_stdout: impl Read,
+    ) -> Result<PipelineSummary> {
+        self.attach_from(ffmpeg_stderr, ffmpeg_stdout, 0)
+    }
+
+    /// Take the stdio handles of a spawned ffmpeg process and `attach_from`
+    /// them. If `stdout_read_timeout` is set, a watchdog thread kills ffmpeg
+    /// when the pipeline has been waiting on its stdout for that long, which
+    /// ends the pipeline with `timed_out` set in the summary.
+    pub fn attach_child(
+        &self,
+        ffmpeg_child: &mut Child,
+        first_frame_num: u64,
+    ) -> Result<PipelineSummary> {
+        let ffmpeg_stderr = ffmpeg_child.stderr.take().ok_or(NoneError)?;
+        let ffmpeg_stdout = ffmpeg_child.stdout.take().ok_or(NoneError)?;
+        let timeout = match self.stdout_read_timeout {
+            Some(timeout) => timeout,
+            None => return self.attach_from(ffmpeg_stderr, ffmpeg_stdout, first_frame_num),
+        };
+
+        thread::scope(|scope| {
+            let (heartbeat_tx, heartbeat_rx) = channel::<StdoutActivity>();
+            let watchdog = thread::Builder::new()
+                .name(self.thread_name("ffmpeg_watchdog"))
+                .spawn_scoped(scope, move || {
+                    let _session = log::enter_session(self.session_id.as_deref());
+                    watch_ffmpeg_stdout(ffmpeg_child, timeout, heartbeat_rx)
+                })?;
+
+            // The watchdog exits when the reader is dropped at the end of the pipeline
+            let ffmpeg_stdout = WatchedReader {
+                inner: ffmpeg_stdout,
+                heartbeat_tx,
+            };
+            let summary = self.attach_from(ffmpeg_stderr, ffmpeg_stdout, first_frame_num);
+            let timed_out = watchdog
+                .join()
+                .map_err(|_| Error::from_display("ffmpeg watchdog thread panicked"))?;
+            Ok(PipelineSummary {
+                timed_out,
+                ..summary?
+            })
+        })
+    }
+
+    /// Same as `attach`, but numbers the frames starting from
+    /// `first_frame_num` instead of 0, to carry on counting from a previous
+    /// ffmpeg process. Timestamps are unaffected, and still come from the
+    /// position in the input.
+    pub fn attach_from(
+        &self,
+        ffmpeg_stderr: ChildStderr,
+        ffmpeg_stdout: impl Read,
+        first_frame_num: u64,
+    ) -> Result<PipelineSummary> {
+        self.attach_with_controls(ffmpeg_stderr, ffmpeg_stdout, first_frame_num, None)
+    }
+
+    /// Like `attach`, but reads frames from any `FrameSource` instead of an
+    /// ffmpeg process (e.g. a `MockFrameSource` in tests), running the
+    /// Triggers on each one until it runs out.
+    pub fn attach_source(&self, source: impl FrameSource) -> Result<PipelineSummary> {
+        let started_at = Instant::now();
+        let _session = log::enter_session(self.session_id.as_deref());
+        let output_size = source.output_size();
+        let summary = self
+            .read_frames(source, output_size, None, false, 0, None)
+            .map_err(Error::from_display)?;
+        self.report_completion(&summary, started_at);
+        Ok(summary)
+    }
+
+    /// Same as `attach_from`, but controlled by an `FfmpegSession`: before
+    /// each frame, it waits while the session is paused, and swaps in any new
+    /// Triggers from `FfmpegSession::set_triggers` or `replace_trigger`.
+    fn attach_with_controls(
+        &self,
+        mut ffmpeg_stderr: ChildStderr,
+        ffmpeg_stdout: impl Read,
+        first_frame_num: u64,
+        controls: Option<PipelineControls>,
+    ) -> Result<PipelineSummary> {
+        let started_at = Instant::now();
+        let _session = log::enter_session(self.session_id.as_deref());
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "ffmpeg_session",
+            input = %self.input.display(),
+            session_id = self.session_id.as_deref()
+        )
+        .entered();
+
+        // Enter a new scope that will block until ffmpeg_stderr_thread is done
+        thread::scope(|scope| {
+            // Spawn a thread to read stderr from ffmpeg
+            let (output_size_rx, frame_time_rx, ffmpeg_stderr_join_handle) =
+                match self.spawn_ffmpeg_stderr_thread(&mut ffmpeg_stderr, scope) {
+                    Ok(ffmpeg_stderr_thread) => ffmpeg_stderr_thread,
                     Err(e) => {
-                        eprintln!("[ffmpeg.err] Error reading ffmpeg stderr: {}", e);
-                        eprintln!("[ffmpeg.err] Attempting to continue reading next line.");
-                        continue;
+                        // TODO lost scope:
+                        // ffmpeg_child
+                        //     .kill()
+                        //     .expect("able to stop ffmpeg process if something goes wrong");
+                        return Err(e.to_string());
                     }
-                    Ok(text) => text,
                 };
 
-                // Parse for output size if not already found
-                if output_size.is_none() {
-                    if text.starts_with("Output #") {
-                        current_section = "Output"; // stringly-typed rather than enum for convenience
-                    } else if current_section == "Output" {
-                        if let Some(size) = parse_ffmpeg_output_size(text.as_str()) {
-                            output_size = Some(size); // remember this, so we don't check for it anymore
-                            output_size_tx.send(size).map_err(|e| e.to_string())?;
+            // With the frames going elsewhere, just wait for ffmpeg to finish
+            if self.output_sink != OutputSink::Stdout {
+                log_debug!(
+                    "[ffmpeg.out] Frames are sent to {:?}; not reading stdout",
+                    self.output_sink
+                );
+                let summary = PipelineSummary {
+                    trigger_frames: vec![0; self.triggers.len()],
+                    ..Default::default()
+                };
+                return Ok(summary.with_stderr(join_stderr_thread(ffmpeg_stderr_join_handle)?));
+            }
+
+            // Block on each line of ffmpeg stderr until receiving the output size
+            let (output_width, output_height) = match output_size_rx.recv() {
+                Ok(output_size) => output_size,
+                Err(_) => {
+                    // ffmpeg exited before it got as far as the output, which
+                    // is likely due to an invalid input file
+                    let summary = PipelineSummary {
+                        trigger_frames: vec![0; self.triggers.len()],
+                        ..Default::default()
+                    };
+                    return Ok(summary.with_stderr(join_stderr_thread(ffmpeg_stderr_join_handle)?));
+                }
+            };
+            log_debug!(
+                "[ffmpeg] Parsed output size from logs: {}x{}",
+                output_width,
+                output_height
+            );
+
+            // Moved into `read_frames`, so that stdout is closed as soon as the
+            // pipeline stops, e.g. on an error from a Trigger. Otherwise ffmpeg
+            // would block writing the next frame, and never close the stderr
+            // that the scope waits on.
+            let frame_times = (self.vsync == VsyncMode::SourcePts).then_some(&frame_time_rx);
+            let summary = self.read_frames(
+                ffmpeg_stdout,
+                (output_width, output_height),
+                frame_times,
+                self.frame_markers,
+                first_frame_num,
+                controls.as_ref(),
+            )?;
+            // Wait for ffmpeg's last progress line, with the final drop and dup counts
+            let summary = summary.with_stderr(join_stderr_thread(ffmpeg_stderr_join_handle)?);
+            self.report_completion(&summary, started_at);
+            Ok(summary)
+        })
+        .map_err(Error::from)
+    }
+
+    /// Call the `on_complete` callback at the end of the output.
+    pub(crate) fn report_completion(&self, summary: &PipelineSummary, started_at: Instant) {
+        if let Some(callback) = &self.on_complete_callback {
+            callback(CompletionStats {
+                frames_read: summary.frames_read,
+                trigger_frames: summary.trigger_frames.clone(),
+                frames_dropped: summary.frames_dropped,
+                ffmpeg_dropped: summary.ffmpeg_dropped,
+                ffmpeg_duplicated: summary.ffmpeg_duplicated,
+                elapsed: started_at.elapsed(),
+            });
+        }
+    }
+
+    /// Read raw frames of the given size until the end of the output, passing
+    /// each one to the Triggers. Everything `attach` does, minus ffmpeg's
+    /// stderr, which is left for the caller (`stderr_tail` is empty). With
+    /// `frame_times`, each frame gets its timestamp from it instead of
+    /// computing one (see `VsyncMode::SourcePts`). With `frame_markers`, the
+    /// last row of each frame is its marker (see `Hypetrigger::frame_markers`).
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn read_frames(
+        &self,
+        ffmpeg_stdout: impl Read,
+        (output_width, output_height): (u32, u32),
+        frame_times: Option<&Receiver<(u64, f64)>>,
+        frame_markers: bool,
+        first_frame_num: u64,
+        controls: Option<&PipelineControls>,
+    ) -> core::result::Result<PipelineSummary, String> {
+        // With no capacity, every read goes straight to ffmpeg's stdout
+        let buffer_size = self.stdout_buffer_size.unwrap_or(0);
+        let mut ffmpeg_stdout = BufReader::with_capacity(buffer_size, ffmpeg_stdout);
+
+        // Initialize a buffer
+        let pixel_format = self.output_pixel_format(); // matches the `-pix_fmt` flag to ffmpeg
+        let buf_size = pixel_format.frame_size(output_width, output_height);
+        // The marker row is read off the end of each frame's bytes
+        let output_height = output_height.saturating_sub(frame_markers as u32);
+        let image_size = pixel_format.frame_size(output_width, output_height);
+        let mut buffer = RawFrameBuffer {
+            bytes: vec![0_u8; buf_size],
+            samples: vec![],
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes = buf_size, "[ffmpeg.stdout] Allocated frame buffer");
+        #[cfg(not(feature = "tracing"))]
+        log_debug!("[ffmpeg.stdout] Allocated buffer of size {}", buf_size);
+
+        // With multiple inputs, each frame from ffmpeg holds all of them side
+        // by side, and is split into a copy of each one
+        let num_inputs = self.inputs().count();
+        let input_frame_size = (output_width / num_inputs as u32, output_height);
+        let input_pool = BufferPool::new(num_inputs);
+
+        // Read from stdout on the current thread, invoking Triggers each frame.
+        // The same buffers are moved into each frame and recovered afterwards
+        // (and the copies of each input are recycled through `input_pool`), so
+        // there's no allocation per frame.
+        //
+        // Every Trigger sees every frame, so they can't get out of step with
+        // each other; the only way to lose alignment would be a partial
+        // frame, which can only happen at the very end of the output.
+        let mut frame_num = 0;
+        let mut triggers = self.triggers.clone();
+        let mut trigger_frames = vec![0; triggers.len()];
+        let mut disabled = vec![false; triggers.len()];
+        let mut debouncers = event_debouncers(&triggers);
+        let mut partial_frame = false;
+        let mut frames_dropped = 0;
+        let mut first_frame_read_at = None;
+        let mut stopped_early = false;
+        let mut next_frame_time = None;
+        let mut run_metrics = RunMetrics::new(self);
+        warn_upscaled_triggers(&triggers, input_frame_size);
+        loop {
+            if let Some(controls) = &controls {
+                // While paused, ffmpeg blocks once the stdout pipe is full
+                controls.pause.wait_while_paused();
+
+                for update in controls.trigger_rx.try_iter() {
+                    let (trigger_id, trigger) = match update {
+                        TriggerUpdate::ReplaceAll(new_triggers) => {
+                            log_debug!(
+                                "[hypetrigger] Switching to {} Triggers",
+                                new_triggers.len()
+                            );
+                            trigger_frames = vec![0; new_triggers.len()];
+                            disabled = vec![false; new_triggers.len()];
+                            debouncers = event_debouncers(&new_triggers);
+                            warn_upscaled_triggers(&new_triggers, input_frame_size);
+                            triggers = new_triggers;
+                            controls.health.reset(None);
+                            continue;
+                        }
+                        TriggerUpdate::Replace(trigger_id, trigger) => (trigger_id, trigger),
+                        #[cfg(feature = "photon")]
+                        TriggerUpdate::SetCrop(trigger_id, crop) => {
+                            let trigger = triggers.get(trigger_id).map(|t| t.with_crop(crop));
+                            match trigger {
+                                Some(Some(trigger)) => (trigger_id, trigger),
+                                Some(None) => {
+                                    log_warn!(
+                                        "[hypetrigger] Can't set the crop of Trigger {}; it doesn't crop",
+                                        trigger_id
+                                    );
+                                    continue;
+                                }
+                                None => {
+                                    log_warn!(
+                                        "[hypetrigger] Can't set the crop of Trigger {}; there are only {}",
+                                        trigger_id,
+                                        triggers.len()
+                                    );
+                                    continue;
+                                }
+                            }
                         }
+                    };
+                    if trigger_id >= triggers.len() {
+                        log_warn!(
+                            "[hypetrigger] Can't replace Trigger {}; there are only {}",
+                            trigger_id,
+                            triggers.len()
+                        );
+                        continue;
+                    }
+                    log_debug!("[hypetrigger] Replacing Trigger {}", trigger_id);
+                    trigger_frames[trigger_id] = 0;
+                    disabled[trigger_id] = false;
+                    debouncers[trigger_id] = event_debouncer(trigger.as_ref());
+                    warn_if_upscaled(trigger_id, trigger.as_ref(), input_frame_size);
+                    triggers[trigger_id] = trigger;
+                    controls.health.reset(Some(trigger_id));
+                }
+            }
+            buffer.bytes.resize(buf_size, 0);
+            match read_frame(&mut ffmpeg_stdout, &mut buffer.bytes) {
+                Ok(0) => break,
+                Ok(bytes_read) if bytes_read < buffer.bytes.len() => {
+                    if !self.dispatch_partial_frame {
+                        log_warn!(
+                            "[ffmpeg.out] Output ended partway through a frame; dropping the last {} bytes",
+                            bytes_read
+                        );
+                        break;
+                    }
+                    log_warn!(
+                        "[ffmpeg.out] Output ended partway through a frame; padding the last {} bytes with zeros",
+                        bytes_read
+                    );
+                    buffer.bytes[bytes_read..].fill(0);
+                    partial_frame = true;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log_warn!("[ffmpeg.out] Error reading from stdout: {}", e);
+                    break;
+                }
+            }
+            if frame_markers && !partial_frame {
+                let row = &buffer.bytes[image_size..];
+                match read_frame_marker(row, pixel_format, output_width) {
+                    Some(marker)
+                        if marker == frame_num % (1 << frame_marker_bits(output_width)) => {}
+                    Some(marker) => {
+                        let marked_frame_num =
+                            resync_frame_num(frame_num, marker, frame_marker_bits(output_width));
+                        log_warn!(
+                            "[ffmpeg.out] Expected frame {} but the marker says {}; resynchronizing",
+                            frame_num,
+                            marked_frame_num
+                        );
+                        frame_num = marked_frame_num;
+                    }
+                    None => {
+                        log_warn!(
+                            "[ffmpeg.out] The marker of frame {} can't be read; skipping it",
+                            frame_num
+                        );
+                        frame_num += 1;
+                        continue;
+                    }
+                }
+            }
+            buffer.bytes.truncate(image_size);
+            // The timestamp is logged as the frame leaves the filters, just
+            // before ffmpeg writes it to stdout
+            let pts_time =
+                frame_times.and_then(|rx| recv_frame_time(rx, frame_num, &mut next_frame_time));
+            let first_frame_read_at = *first_frame_read_at.get_or_insert_with(Instant::now);
+            if self.is_behind_realtime(frame_num, first_frame_read_at.elapsed()) {
+                log_trace!("[hypetrigger] Dropping frame {} to catch up", frame_num);
+                run_metrics.frame_dropped();
+                frames_dropped += 1;
+                frame_num += 1;
+                if partial_frame {
+                    break;
+                }
+                continue;
+            }
+            let image = match buffer.take_image(pixel_format, output_width, output_height) {
+                Some(image) => image,
+                None => {
+                    return Err("unable to convert vec to imagebuffer (size mismatch)".to_string())
+                }
+            };
+            run_metrics.frame_read();
+            let dispatch_started = Instant::now();
+            let mut flow = ControlFlow::Continue(());
+            if num_inputs == 1 {
+                let mut frame = self.frame(image, frame_num, 0);
+                frame.frame_num += first_frame_num;
+                if let Some(pts_time) = pts_time {
+                    frame.timestamp = self.start_time.unwrap_or_default().as_secs_f64() + pts_time;
+                }
+                self.count_trigger_frames(
+                    &triggers,
+                    &disabled,
+                    &mut trigger_frames,
+                    &mut run_metrics,
+                    frame.frame_num,
+                );
+                flow = self
+                    .dispatch_frame_to_enabled(&triggers, &frame, &mut disabled, &debouncers)
+                    .map_err(|e| e.to_string())?;
+                buffer.put_back(frame.image);
+            } else {
+                for mut frame in self.split_inputs_into(&image, frame_num, &input_pool) {
+                    frame.frame_num += first_frame_num;
+                    self.count_trigger_frames(
+                        &triggers,
+                        &disabled,
+                        &mut trigger_frames,
+                        &mut run_metrics,
+                        frame.frame_num,
+                    );
+                    let frame_flow = self
+                        .dispatch_frame_to_enabled(&triggers, &frame, &mut disabled, &debouncers)
+                        .map_err(|e| e.to_string())?;
+                    if frame_flow.is_break() {
+                        flow = frame_flow;
                     }
+                    input_pool.recycle(frame);
+                }
+                buffer.put_back(image);
+            }
+            run_metrics.dispatched(dispatch_started);
+            if let Some(controls) = &controls {
+                controls.health.record(&trigger_frames);
+            }
+            frame_num += 1;
+            let reached_max_frames = self
+                .max_frames
+                .is_some_and(|max_frames| first_frame_num + frame_num >= max_frames);
+            if flow.is_break() || reached_max_frames {
+                // Closing stdout on the way out makes ffmpeg exit too
+                log_debug!("[ffmpeg.out] Stopping early at frame {}", frame_num);
+                stopped_early = true;
+                break;
+            }
+            if partial_frame {
+                break;
+            }
+        }
+
+        log_debug!("[ffmpeg.out] Finished reading from stdout");
+        Ok(PipelineSummary {
+            frames_read: frame_num,
+            trigger_frames,
+            frames_dropped,
+            stopped_early,
+            ..Default::default()
+        })
+    }
+
+    /// Wrap an image read from ffmpeg into a `Frame`, computing its timestamp.
+    pub fn frame(&self, image: DynamicImage, frame_num: u64, input_index: usize) -> Frame {
+        Frame {
+            image,
+            frame_num,
+            timestamp: self.frame_timestamp(frame_num),
+            input_index,
+        }
+    }
+
+    /// When frame `frame_num` appears in the input, in seconds (see
+    /// `Frame::timestamp`)
+    pub fn frame_timestamp(&self, frame_num: u64) -> f64 {
+        // Timestamps are relative to the start of the input, not where we seeked to
+        let start_time = self.start_time.unwrap_or_default().as_secs_f64();
+        start_time + frame_num as f64 / self.fps as f64
+    }
+
+    /// With multiple inputs, ffmpeg stacks them side by side into a single
+    /// image. Split it back up into one `Frame` per input.
+    pub fn split_inputs(&self, image: &DynamicImage, frame_num: u64) -> Vec<Frame> {
+        self.split_inputs_into(image, frame_num, &BufferPool::default())
+    }
+
+    /// Like `split_inputs`, but each input is copied into a buffer from
+    /// `pool`, which can be given back with `BufferPool::recycle` once the
+    /// frame is done with.
+    pub fn split_inputs_into(
+        &self,
+        image: &DynamicImage,
+        frame_num: u64,
+        pool: &BufferPool,
+    ) -> Vec<Frame> {
+        let num_inputs = self.inputs().count() as u32;
+        let input_width = image.width() / num_inputs;
+        (0..num_inputs)
+            .map(|input_index| {
+                let x = input_index * input_width;
+                let input_image = pool.copy_region(image, (x, 0, input_width, image.height()));
+                self.frame(input_image, frame_num, input_index as usize)
+            })
+            .collect()
+    }
+
+    /// Run the Triggers on a raw frame from some source other than ffmpeg
+    /// (e.g. a screen capture library), in the format given by
+    /// `pixel_format`. The `fps` and `start_time` are still used to compute the
+    /// timestamp of the frame.
+    pub fn push_frame(&self, buf: Vec<u8>, width: u32, height: u32, frame_num: u64) -> Result<()> {
+        let expected_size = self.pixel_format.frame_size(width, height);
+        if buf.len() != expected_size {
+            return Err(Error::from_display(format!(
+                "expected a buffer of {} bytes for a {}x{} {:?} frame, but got {}",
+                expected_size,
+                width,
+                height,
+                self.pixel_format,
+                buf.len()
+            )));
+        }
+        let image = self
+            .pixel_format
+            .image_from_raw(width, height, buf)
+            .ok_or(NoneError)?;
+        self.dispatch_frame(&self.frame(image, frame_num, 0))
+    }
+
+    /// The pixel format to read from ffmpeg: `pixel_format`, unless one of the
+    /// Triggers asks for more channels or a higher bit depth, in which case
+    /// it's the format that holds everything any of them asks for (see
+    /// `PixelFormat::merge`), which can then be converted down for the others.
+    pub fn output_pixel_format(&self) -> PixelFormat {
+        self.triggers
+            .iter()
+            .filter(|trigger| trigger.get_enabled())
+            .filter_map(|trigger| trigger.get_pixel_format())
+            .fold(self.pixel_format, |widest, format| widest.merge(format))
+    }
+
+    /// Run every Trigger on a single frame, in order, skipping the ones that
+    /// sample at a lower framerate. Triggers that asked for a different pixel
+    /// format, a color filter, a scale or a transpose get a converted copy of
+    /// the frame, made once per combination of them.
+    pub fn dispatch_frame(&self, frame: &Frame) -> Result<()> {
+        self.dispatch_frame_to(&self.triggers, frame)
+    }
+
+    /// Same as `dispatch_frame`, but runs the given Triggers instead of the
+    /// `triggers` of this `Hypetrigger`.
+    ///
+    /// A Trigger that returns an error is handled according to the
+    /// `trigger_error_policy`. Outside of a running pipeline, there's nothing
+    /// to remember a disabled Trigger by, so `Disable` only skips it, and
+    /// events are only debounced within the frame.
+    pub fn dispatch_frame_to(&self, triggers: &[Arc<dyn Trigger>], frame: &Frame) -> Result<()> {
+        self.dispatch_frame_to_enabled(
+            triggers,
+            frame,
+            &mut vec![false; triggers.len()],
+            &event_debouncers(triggers),
+        )
+        .map(|_| ())
+    }
+
+    /// Same as `dispatch_frame_to`, but skips the Triggers marked in
+    /// `disabled`, and marks the ones disabled by the `trigger_error_policy`.
+    /// Events are debounced with the state in `debouncers`, by `trigger_id`.
+    /// Returns `ControlFlow::Break` if any of the Triggers asked to stop.
+    fn dispatch_frame_to_enabled(
+        &self,
+        triggers: &[Arc<dyn Trigger>],
+        frame: &Frame,
+        disabled: &mut [bool],
+        debouncers: &[Option<Arc<Debouncer<SameResult>>>],
+    ) -> Result<ControlFlow<()>> {
+        let mut flow = ControlFlow::Continue(());
+        let mut converted_frames: HashMap<FrameConversion, Frame> = HashMap::new();
+        let mut payloads = vec![];
+        for (trigger_id, trigger) in triggers.iter().enumerate() {
+            if disabled[trigger_id]
+                || !trigger.get_enabled()
+                || trigger.is_audio()
+                || !self.is_trigger_sampled(trigger.as_ref(), frame.frame_num)
+            {
+                continue;
+            }
+            #[cfg(feature = "tracing")]
+            let _span =
+                tracing::trace_span!("trigger", trigger_id, frame_num = frame.frame_num).entered();
+            // Triggers that don't ask for a format get the pipeline's own
+            let format = trigger.get_pixel_format().unwrap_or(self.pixel_format);
+            let color_filter = trigger.get_color_filter();
+            let scale = trigger
+                .get_scale()
+                .filter(|&size| size != frame.image.dimensions());
+            let transpose = trigger.get_transpose();
+            // Only the part the Trigger looks at is inverted, if it says
+            let invert = trigger.get_invert().then(|| {
+                let (width, height) = frame.image.dimensions();
+                let (width, height) = trigger_frame_size(trigger.as_ref(), width, height);
+                trigger.get_region(width, height)
+            });
+            let _trigger = enter_trigger(Some(trigger_id));
+            let events = self
+                .on_trigger_event_callback
+                .clone()
+                .map(|callback| EventSink {
+                    trigger_id,
+                    callback,
+                    debouncer: debouncers[trigger_id].clone(),
+                });
+            let _events = enter_event_sink(events.clone());
+            let result = if color_filter.is_none()
+                && invert.is_none()
+                && scale.is_none()
+                && transpose.is_none()
+                && PixelFormat::of(&frame.image) == Some(format)
+            {
+                trigger.on_frame_events(frame, &mut payloads)
+            } else {
+                let converted_frame = converted_frames
+                    .entry((format, color_filter, invert, scale, transpose))
+                    .or_insert_with(|| {
+                        let scaled;
+                        let image = match scale {
+                            Some((width, height)) => {
+                                scaled =
+                                    frame
+                                        .image
+                                        .resize_exact(width, height, FilterType::CatmullRom);
+                                &scaled
+                            }
+                            None => &frame.image,
+                        };
+                        let image = match color_filter {
+                            Some(color_filter) => {
+                                let mask = color_filter.mask(image);
+                                format.convert(&DynamicImage::ImageLuma8(mask))
+                            }
+                            None => format.convert(image),
+                        };
+                        let mut image = match transpose {
+                            Some(transpose) => transpose.apply(&image),
+                            None => image,
+                        };
+                        match invert {
+                            Some(Some(region)) => invert_region(&mut image, region),
+                            Some(None) => image.invert(),
+                            None => {}
+                        }
+                        Frame { image, ..*frame }
+                    });
+                trigger.on_frame_events(converted_frame, &mut payloads)
+            };
+            match &events {
+                Some(events) => events.report(frame, &mut payloads),
+                None => payloads.clear(),
+            }
+            match result {
+                Ok(ControlFlow::Break(())) => {
+                    log_debug!("[hypetrigger] Trigger {} asked to stop", trigger_id);
+                    flow = ControlFlow::Break(());
                 }
+                Ok(ControlFlow::Continue(())) => {}
+                Err(e) => self.handle_trigger_error(trigger_id, e, &mut disabled[trigger_id])?,
+            }
+        }
+        Ok(flow)
+    }
 
-                // Regular callback on every line of stderr
-                println!("[ffmpeg.err] {}", text.trim_end());
+    /// Report an error from a Trigger, and either pass it on to stop the
+    /// pipeline, or carry on without the Trigger, depending on the
+    /// `trigger_error_policy`.
+    pub fn handle_trigger_error(
+        &self,
+        trigger_id: usize,
+        error: Error,
+        disabled: &mut bool,
+    ) -> Result<()> {
+        log_warn!("[hypetrigger] Trigger {} failed: {}", trigger_id, error);
+        if let Some(callback) = &self.on_trigger_error_callback {
+            callback(trigger_id, &error);
+        }
+        match self.trigger_error_policy {
+            TriggerErrorPolicy::Abort => Err(error),
+            TriggerErrorPolicy::Skip => Ok(()),
+            TriggerErrorPolicy::Disable => {
+                log_warn!("[hypetrigger] Disabling Trigger {}", trigger_id);
+                *disabled = true;
+                Ok(())
             }
+        }
+    }
 
-            println!("[ffmpeg.err] ffmpeg stderr thread exiting");
-            Ok(())
-        };
+    /// Whether a frame read this long after the first one is too far behind
+    /// the input to still be worth running the Triggers on, in `realtime`
+    /// mode.
+    pub fn is_behind_realtime(&self, frame_num: u64, since_first_frame: Duration) -> bool {
+        let frame_offset = Duration::from_secs_f64(frame_num as f64 / self.fps as f64);
+        self.realtime && since_first_frame.saturating_sub(frame_offset) > self.realtime_max_latency
+    }
 
-        let join_handle = thread::Builder::new()
-            .name("ffmpeg_stderr".to_string())
-            .spawn_scoped(scope, thread_body)?;
+    /// Whether a Trigger runs on the given frame, or skips it to run at its
+    /// own lower framerate.
+    pub fn is_trigger_sampled(&self, trigger: &dyn Trigger, frame_num: u64) -> bool {
+        match trigger.get_fps() {
+            Some(trigger_fps) => is_sampled(frame_num, self.fps as f64, trigger_fps),
+            None => true,
+        }
+    }
 
-        Ok((output_size_rx, join_handle))
+    /// Add one to the count of each Trigger that ran on the given frame.
+    fn count_trigger_frames(
+        &self,
+        triggers: &[Arc<dyn Trigger>],
+        disabled: &[bool],
+        trigger_frames: &mut [u64],
+        run_metrics: &mut RunMetrics,
+        frame_num: u64,
+    ) {
+        let counts = trigger_frames.iter_mut().zip(triggers).zip(disabled);
+        for (trigger_id, ((count, trigger), disabled)) in counts.enumerate() {
+            if !disabled
+                && trigger.get_enabled()
+                && !trigger.is_audio()
+                && self.is_trigger_sampled(trigger.as_ref(), frame_num)
+            {
+                *count += 1;
+                run_metrics.trigger_frame(trigger_id);
+            }
+        }
     }
-}
 
-pub type FfmpegStderrJoinHandle<'scope> =
-    ScopedJoinHandle<'scope, core::result::Result<(), String>>;
+    /// Determine which ffmpeg binary to run, in order of preference:
+    /// 1. The `ffmpeg_exe` field, if set (used verbatim)
+    /// 2. An ffmpeg binary in the same directory as the current executable
+    /// 3. An ffmpeg binary on the `PATH`
+    pub fn resolve_ffmpeg_exe(&self) -> core::result::Result<PathBuf, FfmpegError> {
+        if let Some(ffmpeg_exe) = &self.ffmpeg_exe {
+            return Ok(ffmpeg_exe.clone());
+        }
 
-/// Used with the ffmpeg `-i` argument, or with `.input()` in the Hypetrigger API.
-/// <https://www.bogotobogo.com/FFMpeg/ffmpeg_video_test_patterns_src.php>
-pub const FFMPEG_TEST_INPUT: &str = "testsrc=duration=10:size=1280x720:rate=30";
+        let sibling = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(FFMPEG_BINARY)));
+        if let Some(sibling) = sibling {
+            if sibling.is_file() {
+                return Ok(sibling);
+            }
+        }
 
-/// Sends a `q` to the ffmpeg process over stdin, which tells it gracefully exit.
-/// You could also call `kill()` on the `Child` process instance of ffmpeg to stop it
-/// more abruptly. You can obtain the `stdin` handle from the return value of `run_async()`.
-pub fn stop_ffmpeg(stdin: &mut ChildStdin) -> Result<()> {
-    stdin.write_all(b"q\n").map_err(Error::from)
+        find_on_path(FFMPEG_BINARY).ok_or(FfmpegError::BinaryNotFound)
+    }
+
+    /// Spawn the ffmpeg process, with all stdio channels piped. The
+    /// `concat_list` is as for `ffmpeg_command`.
+    pub fn spawn_ffmpeg_child(
+        &self,
+        concat_list: Option<&Path>,
+    ) -> core::result::Result<Child, FfmpegError> {
+        let mut cmd = self.ffmpeg_command(concat_list)?;
+
+        // Debug command
+        log_debug!("[debug] ffmpeg command appears below:");
+        log_debug!("{}", command_to_string(&cmd));
+
+        cmd.spawn().map_err(FfmpegError::SpawnFailed)
+    }
+
+    /// The full ffmpeg command line, starting with the ffmpeg binary, exactly
+    /// as it would be spawned by `run`. Useful for logging, or for running
+    /// ffmpeg some other way. With `concat_inputs`, which need a list to be
+    /// written first, use `ffmpeg_command` instead.
+    pub fn build_ffmpeg_command(&self) -> core::result::Result<Vec<String>, FfmpegError> {
+        let cmd = self.ffmpeg_command(None)?;
+        let program = cmd.get_program().to_string_lossy().to_string();
+        let args = cmd.get_args().map(|arg| arg.to_string_lossy().to_string());
+        Ok(std::iter::once(program).chain(args).collect())
+    }
+
+    /// Build the ffmpeg command, with all stdio channels piped, without
+    /// spawning it. With `concat_inputs`, the `concat_list` is the path of
+    /// the list from `write_concat_list`, which has to be kept until ffmpeg
+    /// exits.
+    pub fn ffmpeg_command(
+        &self,
+        concat_list: Option<&Path>,
+    ) -> core::result::Result<Command, FfmpegError> {
+        let mut cmd = self.ffmpeg_input_command(concat_list)?;
+        if self.extra_inputs.is_empty() {
+            cmd.arg("-filter:v").arg(self.video_filter());
+        } else {
+            cmd.arg("-filter_complex")
+                .arg(self.filter_complex()?)
+                .arg("-map")
+                .arg("[out]");
+        }
+        cmd.arg("-vsync")
+            .arg(self.vsync.as_ffmpeg_str())
+            .arg("-f")
+            .arg("rawvideo")
+            .arg("-pix_fmt")
+            .arg(self.output_pixel_format().as_ffmpeg_str())
+            .arg("-an");
+        cmd.arg(self.overwrite_arg());
+        cmd.args(&self.extra_output_args);
+        cmd.arg(self.output_sink.as_ffmpeg_arg());
+
+        // Optionally write the same frames (or each Trigger's crop) to files as well
+        if let Some(dump_frames_to) = &self.dump_frames_to {
+            cmd.args(self.file_output_args("dump", None));
+            cmd.arg(dump_frames_to);
+        }
+        for (trigger_id, path, crop) in self.crop_dumps() {
+            let label = format!("trigger{}", trigger_id);
+            cmd.args(self.file_output_args(&label, crop.as_deref()));
+            cmd.arg(path);
+        }
+
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        #[cfg(windows)]
+        cmd.creation_flags(self.windows_process_flags.bits());
+
+        Ok(cmd)
+    }
+
+    /// The start of the ffmpeg command, up to the outputs: the global options
+    /// and each of the inputs, followed by the `seek_args` and `duration` for
+    /// the first output.
+    pub(crate) fn ffmpeg_input_command(
+        &self,
+        concat_list: Option<&Path>,
+    ) -> core::result::Result<Command, FfmpegError> {
+        self.validate_extra_args()?;
+        let concat_list = self.check_concat_list(concat_list)?;
+        let mut cmd = Command::new(self.resolve_ffmpeg_exe()?);
+        if let Some(ffmpeg_loglevel) = self.ffmpeg_loglevel {
+            cmd.arg("-loglevel")
+                .arg(format!("level+{}", ffmpeg_loglevel.as_ffmpeg_str()));
+            if ffmpeg_loglevel < FfmpegLogLevel::Info {
+                cmd.arg("-stats");
+            }
+        }
+        if let Some(filter_threads) = self.filter_threads {
+            cmd.arg("-filter_threads").arg(filter_threads.to_string());
+            if !self.extra_inputs.is_empty() {
+                cmd.arg("-filter_complex_threads")
+                    .arg(filter_threads.to_string());
+            }
+        }
+        for (i, input) in self.inputs().enumerate() {
+            let concat_list = concat_list.filter(|_| i == 0);
+            if let Some(hwaccel) = self.hwaccel.as_ffmpeg_str() {
+                cmd.arg("-hwaccel").arg(hwaccel);
+            }
+            if let Some(hwaccel_device) = &self.hwaccel_device {
+                if self.hwaccel.supports_device() {
+                    cmd.arg("-hwaccel_device").arg(hwaccel_device);
+                }
+            }
+            if concat_list.is_some() {
+                cmd.args(CONCAT_INPUT_ARGS);
+            }
+            if let Some(input_format) = &self.input_format {
+                cmd.arg("-f").arg(input_format);
+            }
+            if let Some(raw_input) = &self.raw_input {
+                cmd.args(raw_input.ffmpeg_args());
+            }
+            if self.seek_accuracy != SeekAccuracy::Decode {
+                cmd.args(self.seek_args());
+            }
+            if input.to_str().is_some_and(is_http_url) {
+                // Recover from dropped connections, especially for live streams
+                cmd.arg("-reconnect")
+                    .arg("1")
+                    .arg("-reconnect_streamed")
+                    .arg("1")
+                    .arg("-reconnect_delay_max")
+                    .arg("5");
+            }
+            if let Some(ffmpeg_threads) = self.ffmpeg_threads {
+                cmd.arg("-threads").arg(ffmpeg_threads.to_string());
+            }
+            // Live inputs already arrive in real time
+            if self.realtime && !self.is_live {
+                cmd.arg("-re");
+            }
+            if let Some(video_decoder) = self.video_decoder.as_ref().filter(|_| i == 0) {
+                cmd.arg("-c:v").arg(video_decoder);
+            }
+            cmd.args(&self.extra_input_args);
+            cmd.arg("-i").arg(concat_list.unwrap_or(input));
+        }
+        if self.seek_accuracy == SeekAccuracy::Decode {
+            cmd.args(self.seek_args());
+        }
+        if let Some(duration) = self.duration {
+            cmd.arg("-t").arg(duration.as_secs_f64().to_string());
+        }
+        Ok(cmd)
+    }
+
+    /// The args for an extra output file, written alongside the frames sent to
+    /// the Triggers: the `sampling_filter` followed by `extra_filter`, or with
+    /// multiple inputs, the given output of the `filter_complex`.
+    fn file_output_args(&self, label: &str, extra_filter: Option<&str>) -> Vec<String> {
+        let mut args = vec![];
+        if self.seek_accuracy == SeekAccuracy::Decode {
+            args.extend(self.seek_args());
+        }
+        if let Some(duration) = self.duration {
+            args.push("-t".to_string());
+            args.push(duration.as_secs_f64().to_string());
+        }
+        if self.extra_inputs.is_empty() {
+            let mut filters = self.sampling_filter();
+            filters.extend(extra_filter.map(str::to_string));
+            args.push("-filter:v".to_string());
+            args.push(filters.join(","));
+        } else {
+            args.push("-map".to_string());
+            args.push(format!("[{}]", label));
+        }
+        args.push("-vsync".to_string());
+        args.push(self.vsync.as_ffmpeg_str().to_string());
+        args.push("-an".to_string());
+        args
+    }
+
+    /// The `dump_crops_to` file for each enabled video Trigger, by id, along
+    /// with the `crop` filter for its region, if it has one.
+    fn crop_dumps(&self) -> Vec<(usize, PathBuf, Option<String>)> {
+        let (Some(dir), Some((width, height))) = (&self.dump_crops_to, self.frame_size()) else {
+            return vec![];
+        };
+        self.triggers
+            .iter()
+            .enumerate()
+            .filter(|(_, trigger)| trigger.get_enabled() && !trigger.is_audio())
+            .map(|(trigger_id, trigger)| {
+                let path = dir.join(format!("trigger_{}.mp4", trigger_id));
+                let crop = trigger
+                    .get_region(width, height)
+                    .map(|(x0, y0, x1, y1)| format!("crop={}:{}:{}:{}", x1 - x0, y1 - y0, x0, y0));
+                (trigger_id, path, crop)
+            })
+            .collect()
+    }
+
+    /// The args to seek to the `start_time`, if there is one. They go before
+    /// each `-i`, except with `SeekAccuracy::Decode`, where they go before each
+    /// output instead.
+    pub fn seek_args(&self) -> Vec<String> {
+        let Some(start_time) = self.start_time else {
+            return vec![];
+        };
+        let mut args = vec![];
+        if self.seek_accuracy == SeekAccuracy::Fast {
+            args.push("-noaccurate_seek".to_string());
+        }
+        args.push("-ss".to_string());
+        args.push(start_time.as_secs_f64().to_string());
+        args
+    }
+
+    /// Make sure the `extra_input_args` and `extra_output_args` won't conflict
+    /// with the args that the pipeline depends on.
+    pub fn validate_extra_args(&self) -> core::result::Result<(), FfmpegError> {
+        if let Some(arg) = self.extra_input_args.iter().find(|arg| *arg == "-i") {
+            return Err(FfmpegError::InvalidConfig(format!(
+                "extra input args can't contain `{}`; use `add_input` instead",
+                arg
+            )));
+        }
+        let conflicting = |arg: &&String| *arg == "-f" || arg.starts_with("pipe:");
+        if let Some(arg) = self.extra_output_args.iter().find(conflicting) {
+            return Err(FfmpegError::InvalidConfig(format!(
+                "extra output args can't contain `{}`, which would conflict with the rawvideo output",
+                arg
+            )));
+        }
+        Ok(())
+    }
+
+    /// The size of the frames ffmpeg sends, if it's known without parsing
+    /// ffmpeg's logs, i.e. because the `frame_size()` is known and ffmpeg is
+    /// logging so little that the size would never be logged anyway.
+    pub fn known_output_size(&self) -> Option<(u32, u32)> {
+        let (width, height) = self.frame_size()?;
+        match self.ffmpeg_loglevel {
+            Some(level) if level < FfmpegLogLevel::Info => Some((
+                width * self.inputs().count() as u32,
+                height + self.frame_markers as u32,
+            )),
+            _ => None,
+        }
+    }
+
+    /// The size of each input's frames, if it's known before ffmpeg starts:
+    /// the `scale`, or else the size of a `raw_input`.
+    pub fn frame_size(&self) -> Option<(u32, u32)> {
+        self.scale.or_else(|| {
+            self.raw_input
+                .as_ref()
+                .map(|raw_input| (raw_input.width, raw_input.height))
+        })
+    }
+
+    /// The `input` label for metrics from this pipeline.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_input(&self) -> String {
+        self.input.to_string_lossy().to_string()
+    }
+
+    /// Whether the input video is read from ffmpeg's stdin, rather than from a
+    /// file or URL.
+    pub fn is_stdin_input(&self) -> bool {
+        matches!(self.input.to_str(), Some("pipe:0" | "pipe:" | "-"))
+    }
+
+    /// Builds the chain of filters passed to ffmpeg with `-filter:v`.
+    pub fn video_filter(&self) -> String {
+        let mut filters = self.input_filter();
+        if self.frame_markers {
+            filters.push(',');
+            filters.push_str(&self.frame_marker_filter());
+        }
+        filters
+    }
+
+    /// The filters applied to each input before they're stacked together.
+    pub(crate) fn input_filter(&self) -> String {
+        let mut filters = self.sampling_filter();
+        if self.vsync == VsyncMode::SourcePts {
+            // Logs the timestamp of each frame as it's output
+            filters.push("showinfo".to_string());
+        }
+        filters.join(",")
+    }
+
+    /// The filters that pick out and resize the sampled frames, shared by the
+    /// frames sent to the Triggers and any `dump_frames_to`.
+    fn sampling_filter(&self) -> Vec<String> {
+        let mut filters = vec![];
+        if let Some(deinterlace) = self.deinterlace {
+            filters.push(deinterlace.as_ffmpeg_filter().to_string());
+        }
+        if let Some(global_filter) = &self.global_filter {
+            filters.push(global_filter.clone());
+        }
+        match self.vsync {
+            // Keeps the source frames and their timestamps, unlike `fps`,
+            // which retimes them onto an even grid
+            VsyncMode::SourcePts => filters.push(format!(
+                "select='isnan(prev_selected_t)+gte(t-prev_selected_t,{})'",
+                1.0 / self.fps as f64
+            )),
+            _ => filters.push(format!("fps={}", self.fps)),
+        }
+        if let Some((width, height)) = self.scale {
+            filters.push(format!("scale={}:{}", width, height));
+        }
+        filters
+    }
+
+    /// All of the inputs, starting with the main one.
+    pub fn inputs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.input.as_path()).chain(self.extra_inputs.iter().map(PathBuf::as_path))
+    }
+
+    /// Builds the filter graph used when there are multiple inputs. Applies
+    /// `video_filter()` to each input and then stacks them horizontally,
+    /// before marking the stacked frames with any `frame_markers`.
+    pub fn filter_complex(&self) -> core::result::Result<String, FfmpegError> {
+        if self.scale.is_none() {
+            return Err(FfmpegError::InvalidConfig(
+                "multiple inputs require `scale` to be set, so they can be stacked".to_string(),
+            ));
+        }
+
+        let num_inputs = self.inputs().count();
+        let video_filter = self.input_filter();
+        let mut graph = String::new();
+        for i in 0..num_inputs {
+            graph += &format!("[{}:v]{}[in{}];", i, video_filter, i);
+        }
+        for i in 0..num_inputs {
+            graph += &format!("[in{}]", i);
+        }
+        graph += &format!("hstack=inputs={}", num_inputs);
+
+        // Also feed `dump_frames_to` and `dump_crops_to`, if they're set
+        let crop_dumps = self.crop_dumps();
+        let frames_output = match self.frame_markers {
+            true => "[unmarked_out]",
+            false => "[out]",
+        };
+        let mut outputs = vec![frames_output.to_string()];
+        if self.dump_frames_to.is_some() {
+            outputs.push("[dump]".to_string());
+        }
+        for (trigger_id, _, crop) in &crop_dumps {
+            match crop {
+                Some(_) => outputs.push(format!("[uncropped{}]", trigger_id)),
+                None => outputs.push(format!("[trigger{}]", trigger_id)),
+            }
+        }
+        match outputs.len() {
+            1 => graph += frames_output,
+            2 => graph += &format!(",split{}", outputs.concat()),
+            n => graph += &format!(",split={}{}", n, outputs.concat()),
+        }
+        for (trigger_id, _, crop) in &crop_dumps {
+            if let Some(crop) = crop {
+                graph += &format!(";[uncropped{0}]{1}[trigger{0}]", trigger_id, crop);
+            }
+        }
+        if self.frame_markers {
+            graph += &format!(";[unmarked_out]{}[out]", self.frame_marker_filter());
+        }
+        Ok(graph)
+    }
+
+    /// Spawns a thread to handle reading the stderr channel from ffmpeg.
+    ///
+    /// After first spawning, we read the metadata/prelude of the ffmpeg job in
+    /// order to determine the width and height of the output frames. That's
+    /// sent back to the main thread via a channel. After the metadata is
+    /// received, the channel closes, while the stderr handler thread continues
+    /// to run in the background. It automatically stops after ffmpeg exits.
+    ///
+    /// With `VsyncMode::SourcePts`, the index and timestamp of each frame are
+    /// sent over a second channel as they're logged.
+    #[allow(clippy::type_complexity)]
+    pub fn spawn_ffmpeg_stderr_thread<'scope>(
+        &'scope self,
+        ffmpeg_stderr: &'scope mut ChildStderr,
+        scope: &'scope Scope<'scope, '_>, // scope scope scope scope wheeee
+    ) -> Result<(
+        Receiver<(u32, u32)>,
+        Receiver<(u64, f64)>,
+        FfmpegStderrJoinHandle<'scope>,
+    )> {
+        let (output_size_tx, output_size_rx) = channel::<(u32, u32)>();
+        let (frame_time_tx, frame_time_rx) = channel::<(u64, f64)>();
+        #[cfg(feature = "tracing")]
+        let span = tracing::Span::current();
+        let thread_body = move || {
+            let _session = log::enter_session(self.session_id.as_deref());
+            #[cfg(feature = "tracing")]
+            let _span = span.entered();
+            let reader = BufReader::new(ffmpeg_stderr);
+            let mut parser = FfmpegStderrParser::default();
+            if let Some(size) = self.known_output_size() {
+                parser.output_size = Some(size);
+                output_size_tx.send(size).map_err(|e| e.to_string())?;
+            }
+            // Progress lines are terminated with `\r` rather than `\n`
+            for line in split_lines_cr_lf(reader) {
+                let text = match line {
+                    Err(e) => {
+                        log_warn!("[ffmpeg.err] Error reading ffmpeg stderr: {}", e);
+                        log_warn!("[ffmpeg.err] Attempting to continue reading next line.");
+                        continue;
+                    }
+                    Ok(text) if text.trim().is_empty() => continue,
+                    Ok(text) => text,
+                };
+
+                if let Some(size) = parser.parse_line(self, text.as_str()) {
+                    output_size_tx.send(size).map_err(|e| e.to_string())?;
+                }
+                for frame_time in parser.frame_times.drain(..) {
+                    // The stdout thread may already have stopped reading
+                    let _ = frame_time_tx.send(frame_time);
+                }
+
+                log_trace!("[ffmpeg.err] {}", text.trim_end());
+            }
+
+            log_debug!("[ffmpeg.err] ffmpeg stderr thread exiting");
+            Ok(parser)
+        };
+
+        let join_handle = thread::Builder::new()
+            .name(self.thread_name("ffmpeg_stderr"))
+            .spawn_scoped(scope, thread_body)?;
+
+        Ok((output_size_rx, frame_time_rx, join_handle))
+    }
+}
+
+/// Handles to the metrics of a run, labelled once when it starts rather than
+/// for every frame. Without the `metrics` feature, there's nothing to record.
+pub(crate) struct RunMetrics {
+    #[cfg(feature = "metrics")]
+    input: String,
+    #[cfg(feature = "metrics")]
+    frames_read: metrics::Counter,
+    #[cfg(feature = "metrics")]
+    frames_dropped: metrics::Counter,
+    #[cfg(feature = "metrics")]
+    dispatch_seconds: metrics::Histogram,
+    /// By `trigger_id`, labelled the first time each one runs
+    #[cfg(feature = "metrics")]
+    trigger_frames: Vec<metrics::Counter>,
+}
+
+impl RunMetrics {
+    pub fn new(hypetrigger: &Hypetrigger) -> Self {
+        #[cfg(feature = "metrics")]
+        let input = hypetrigger.metrics_input();
+        #[cfg(not(feature = "metrics"))]
+        let _ = hypetrigger;
+        Self {
+            #[cfg(feature = "metrics")]
+            frames_read: metrics::counter!("hypetrigger_frames_read_total", "input" => input.clone()),
+            #[cfg(feature = "metrics")]
+            frames_dropped: metrics::counter!(
+                "hypetrigger_frames_dropped_total",
+                "input" => input.clone()
+            ),
+            #[cfg(feature = "metrics")]
+            dispatch_seconds: metrics::histogram!(
+                "hypetrigger_dispatch_seconds",
+                "input" => input.clone()
+            ),
+            #[cfg(feature = "metrics")]
+            trigger_frames: vec![],
+            #[cfg(feature = "metrics")]
+            input,
+        }
+    }
+
+    pub fn frame_read(&self) {
+        #[cfg(feature = "metrics")]
+        self.frames_read.increment(1);
+    }
+
+    pub fn frame_dropped(&self) {
+        #[cfg(feature = "metrics")]
+        self.frames_dropped.increment(1);
+    }
+
+    /// Record how long it took to run the Triggers on a frame.
+    pub fn dispatched(&self, started: Instant) {
+        #[cfg(feature = "metrics")]
+        self.dispatch_seconds.record(started.elapsed());
+        #[cfg(not(feature = "metrics"))]
+        let _ = started;
+    }
+
+    pub fn trigger_frame(&mut self, trigger_id: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            while self.trigger_frames.len() <= trigger_id {
+                let trigger = self.trigger_frames.len().to_string();
+                self.trigger_frames.push(metrics::counter!(
+                    "hypetrigger_trigger_frames_total",
+                    "input" => self.input.clone(),
+                    "trigger" => trigger
+                ));
+            }
+            self.trigger_frames[trigger_id].increment(1);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = trigger_id;
+    }
+}
+
+/// Where the events of one of the pipeline's Triggers go: the
+/// `on_trigger_event` callback, through the Trigger's debouncer.
+#[derive(Clone)]
+pub(crate) struct EventSink {
+    pub trigger_id: usize,
+    pub callback: HypetriggerOnTriggerEventCallback,
+    pub debouncer: Option<Arc<Debouncer<SameResult>>>,
+}
+
+impl EventSink {
+    /// Pass on what the Trigger reported finding in a frame, except for
+    /// repeats suppressed by the debouncer.
+    pub fn report(&self, frame: &Frame, payloads: &mut Vec<TriggerPayload>) {
+        for payload in payloads.drain(..) {
+            if let Some(debouncer) = &self.debouncer {
+                if !debouncer.should_emit(&SameResult(payload.clone()), frame.timestamp) {
+                    continue;
+                }
+            }
+            (self.callback)(&PipelineEvent {
+                trigger_id: self.trigger_id,
+                input_index: frame.input_index,
+                frame_num: frame.frame_num,
+                timestamp: frame.timestamp,
+                payload,
+            });
+        }
+    }
+}
+
+thread_local! {
+    static EVENT_SINK: RefCell<Option<EventSink>> = const { RefCell::new(None) };
+}
+
+/// Where the Trigger running on the current thread reports its events, while
+/// the pipeline is running it, for an `AsyncTrigger` to take along to its
+/// worker.
+pub(crate) fn current_event_sink() -> Option<EventSink> {
+    EVENT_SINK.with(|sink| sink.borrow().clone())
+}
+
+/// Restores the previous `current_event_sink` when it's dropped.
+pub(crate) struct EventSinkGuard {
+    previous: Option<EventSink>,
+}
+
+impl Drop for EventSinkGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        EVENT_SINK.with(|sink| *sink.borrow_mut() = previous);
+    }
+}
+
+/// Set where the Trigger running on the current thread reports its events.
+pub(crate) fn enter_event_sink(events: Option<EventSink>) -> EventSinkGuard {
+    let previous = EVENT_SINK.with(|sink| sink.replace(events));
+    EventSinkGuard { previous }
+}
+
+/// What to do when a Trigger returns an error from `on_frame`. The error is
+/// logged and passed to the `on_trigger_error` callback either way.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TriggerErrorPolicy {
+    /// Stop the pipeline, and return the error from `run`
+    #[default]
+    Abort,
+
+    /// Carry on, including running the Trigger again on the next frame
+    Skip,
+
+    /// Carry on without the Trigger, for the rest of the input
+    Disable,
+}
+
+/// Where ffmpeg writes the raw frames
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OutputSink {
+    /// ffmpeg's stdout (`pipe:1`), which is read to run the Triggers
+    #[default]
+    Stdout,
+
+    /// Another file descriptor of the ffmpeg process (`pipe:N`), e.g. the
+    /// write end of a pipe shared with another process. On Unix, it has to be
+    /// open in the current process without `FD_CLOEXEC`, so that ffmpeg
+    /// inherits it.
+    Fd(i32),
+
+    /// A named pipe (FIFO), or any other path, that another process reads from
+    NamedPipe(PathBuf),
+}
+
+impl OutputSink {
+    /// The output arg passed to ffmpeg
+    pub fn as_ffmpeg_arg(&self) -> OsString {
+        match self {
+            OutputSink::Stdout => OsString::from("pipe:1"),
+            OutputSink::Fd(fd) => OsString::from(format!("pipe:{}", fd)),
+            OutputSink::NamedPipe(path) => path.clone().into_os_string(),
+        }
+    }
+}
+
+/// The options for how ffmpeg is started on Windows, which are combined into
+/// its process creation flags. The default just hides its console window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct WindowsProcessFlags {
+    /// Don't open a console window for ffmpeg (`CREATE_NO_WINDOW`). Turn it
+    /// off to watch ffmpeg while debugging.
+    pub hide_window: bool,
+
+    /// Run ffmpeg at a different priority, e.g. `BelowNormal` so that it
+    /// doesn't starve the UI on a busy machine.
+    pub priority: Option<WindowsPriorityClass>,
+
+    /// Start ffmpeg in its own process group (`CREATE_NEW_PROCESS_GROUP`), so
+    /// that Ctrl+C in the console isn't passed on to it.
+    pub new_process_group: bool,
+}
+
+impl Default for WindowsProcessFlags {
+    fn default() -> Self {
+        Self {
+            hide_window: true,
+            priority: None,
+            new_process_group: false,
+        }
+    }
+}
+
+impl WindowsProcessFlags {
+    /// The value to pass to `CommandExt::creation_flags`
+    pub fn bits(&self) -> u32 {
+        let mut bits = 0;
+        if self.hide_window {
+            bits |= 0x08000000; // CREATE_NO_WINDOW
+        }
+        if let Some(priority) = self.priority {
+            bits |= priority.bits();
+        }
+        if self.new_process_group {
+            bits |= 0x00000200; // CREATE_NEW_PROCESS_GROUP
+        }
+        bits
+    }
+}
+
+/// Windows process priority classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum WindowsPriorityClass {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+}
+
+impl WindowsPriorityClass {
+    /// The corresponding `*_PRIORITY_CLASS` process creation flag
+    pub fn bits(&self) -> u32 {
+        match self {
+            WindowsPriorityClass::Idle => 0x00000040,
+            WindowsPriorityClass::BelowNormal => 0x00004000,
+            WindowsPriorityClass::Normal => 0x00000020,
+            WindowsPriorityClass::AboveNormal => 0x00008000,
+            WindowsPriorityClass::High => 0x00000080,
+        }
+    }
+}
+
+/// Hardware acceleration methods for decoding the input video
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HwAccel {
+    /// Let ffmpeg pick a method (`-hwaccel auto`)
+    #[default]
+    Auto,
+
+    /// Decode in software, omitting the `-hwaccel` arg entirely
+    None,
+
+    /// NVIDIA CUDA (`-hwaccel cuda`)
+    Cuda,
+
+    /// Intel Quick Sync Video (`-hwaccel qsv`)
+    Qsv,
+
+    /// Apple VideoToolbox (`-hwaccel videotoolbox`)
+    VideoToolbox,
+
+    /// Any other method supported by ffmpeg, passed verbatim
+    Custom(String),
+}
+
+impl HwAccel {
+    /// The corresponding value for the ffmpeg `-hwaccel` arg, or `None` if the
+    /// arg should be omitted.
+    pub fn as_ffmpeg_str(&self) -> Option<&str> {
+        match self {
+            HwAccel::Auto => Some("auto"),
+            HwAccel::None => None,
+            HwAccel::Cuda => Some("cuda"),
+            HwAccel::Qsv => Some("qsv"),
+            HwAccel::VideoToolbox => Some("videotoolbox"),
+            HwAccel::Custom(hwaccel) => Some(hwaccel.as_str()),
+        }
+    }
+
+    /// Whether a specific device can be chosen with `-hwaccel_device`. With
+    /// `Auto`, ffmpeg picks both the method and the device itself.
+    pub fn supports_device(&self) -> bool {
+        matches!(self, HwAccel::Cuda | HwAccel::Qsv | HwAccel::Custom(_))
+    }
+}
+
+/// How ffmpeg syncs the output frames to their timestamps (the `-vsync` arg).
+///
+/// In every mode but `SourcePts`, each `Frame` gets the timestamp
+/// `frame_num / fps`, which assumes the sampled frames are evenly spaced. That
+/// holds for `Drop` and `Cfr`. With `Passthrough` or `Vfr`, frames may be
+/// missing (e.g. from a variable framerate phone recording), so the timestamps
+/// drift after each gap. Even with `Drop`, each sample is whichever source
+/// frame is nearest to its spot on the even grid, so its timestamp can be off
+/// from the frame's real one by up to half a source frame.
+///
+/// `SourcePts` is exact instead, for when a detection needs to be traced back
+/// to the very frame it was found in, at the cost of unevenly spaced samples
+/// and a line of ffmpeg logs per frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VsyncMode {
+    /// Drop the input timestamps, so ffmpeg never duplicates or reorders
+    /// frames to match them (`-vsync drop`)
+    #[default]
+    Drop,
+
+    /// Pass each frame through with its timestamp, without duplicating or
+    /// dropping any (`-vsync passthrough`)
+    Passthrough,
+
+    /// Duplicate and drop frames to keep a constant framerate, so samples stay
+    /// evenly spaced even for variable framerate input (`-vsync cfr`)
+    Cfr,
+
+    /// Drop frames that share a timestamp, but never duplicate any
+    /// (`-vsync vfr`)
+    Vfr,
+
+    /// Sample real frames of the input, at most `fps` per second, and give
+    /// each `Frame` the presentation timestamp that ffmpeg logs for it
+    /// (relative to the start of the input, like the computed ones). Uses
+    /// `-vsync passthrough`, with the `select` filter instead of `fps` and
+    /// `showinfo` to log the timestamps. Samples follow the input's own frame
+    /// spacing, so with a variable framerate they can be further apart than
+    /// `1 / fps`. Needs a single input and at least the `Info` loglevel, and
+    /// isn't supported by `run_tokio`.
+    SourcePts,
+}
+
+impl VsyncMode {
+    /// The corresponding value for the ffmpeg `-vsync` arg
+    pub fn as_ffmpeg_str(&self) -> &'static str {
+        match self {
+            VsyncMode::Drop => "drop",
+            VsyncMode::Passthrough => "passthrough",
+            VsyncMode::Cfr => "cfr",
+            VsyncMode::Vfr => "vfr",
+            VsyncMode::SourcePts => "passthrough",
+        }
+    }
+}
+
+/// How ffmpeg seeks to the `start_time` of the input, which is a tradeoff
+/// between speed and starting on exactly the right frame.
+///
+/// Video can only be decoded starting from a keyframe, which may be several
+/// seconds apart. Seeking jumps straight to the keyframe before the
+/// `start_time`, so the only question is what happens to the frames between
+/// the keyframe and the `start_time`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SeekAccuracy {
+    /// Start from the keyframe itself, without decoding anything before it
+    /// (`-noaccurate_seek -ss` before `-i`). The fastest, but the first frame
+    /// can be up to a keyframe interval before the `start_time`. The
+    /// timestamps of the `Frame`s are still relative to the beginning of the
+    /// input, but they're off by as much, since the first frame is taken to
+    /// be at the `start_time`. Fine for roughly skipping ahead.
+    Fast,
+
+    /// Decode from the keyframe, but throw away the frames before the
+    /// `start_time` (`-ss` before `-i`), so the first frame is exact. Only
+    /// the frames since the last keyframe are decoded for nothing, so this is
+    /// nearly as fast as `Fast`.
+    #[default]
+    Accurate,
+
+    /// Decode the whole input from the beginning, throwing away everything
+    /// before the `start_time` (`-ss` after `-i`). Exact, but slower the
+    /// further in the `start_time` is. Only worth it for inputs that can't
+    /// seek reliably, e.g. files with a broken index or some live streams.
+    Decode,
+}
+
+/// Which ffmpeg filter to deinterlace the input with (see
+/// `Hypetrigger::deinterlace`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DeinterlaceMode {
+    /// "Yet Another DeInterlacing Filter", the usual choice (`yadif`)
+    Yadif,
+
+    /// Bob Weaver Deinterlacing Filter, slower but with fewer artifacts on
+    /// fast motion (`bwdif`)
+    Bwdif,
+}
+
+impl DeinterlaceMode {
+    /// The name of the corresponding ffmpeg filter
+    pub fn as_ffmpeg_filter(&self) -> &'static str {
+        match self {
+            DeinterlaceMode::Yadif => "yadif",
+            DeinterlaceMode::Bwdif => "bwdif",
+        }
+    }
+}
+
+/// The layout of raw input frames (see `Hypetrigger::raw_input`): packed
+/// pixels with no padding, one frame after another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RawInput {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+
+    /// Framerate the frames are captured at, which gives them their
+    /// timestamps.
+    pub fps: f64,
+}
+
+impl RawInput {
+    /// The ffmpeg args that go before `-i` to describe the input
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        vec![
+            "-f".to_string(),
+            "rawvideo".to_string(),
+            "-pix_fmt".to_string(),
+            self.pixel_format.as_ffmpeg_str().to_string(),
+            "-video_size".to_string(),
+            format!("{}x{}", self.width, self.height),
+            "-framerate".to_string(),
+            self.fps.to_string(),
+        ]
+    }
+
+    /// Size of a single input frame, in bytes.
+    pub fn frame_size(&self) -> usize {
+        self.pixel_format.frame_size(self.width, self.height)
+    }
+}
+
+/// A rotation or flip of the frames a Trigger receives (see
+/// `Trigger::get_transpose`), e.g. for a capture source that's mounted
+/// sideways. Rotations are clockwise.
+///
+/// Like pixel formats and color filters, this is applied to each decoded frame
+/// of the shared stream (once per distinct transpose), so the frames read from
+/// ffmpeg keep their size, and the other Triggers aren't affected. Triggers
+/// that crop can use `apply_to_region` instead, to cut out their region first
+/// and only transpose that. With `Hypetrigger::spawn_ffmpeg_multi_pipe_session`,
+/// ffmpeg transposes each Trigger's frames itself (see `as_ffmpeg_filter`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Transpose {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+
+    /// Mirror left to right
+    FlipH,
+
+    /// Mirror top to bottom
+    FlipV,
+}
+
+impl Transpose {
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Transpose::Rotate90 => image.rotate90(),
+            Transpose::Rotate180 => image.rotate180(),
+            Transpose::Rotate270 => image.rotate270(),
+            Transpose::FlipH => image.fliph(),
+            Transpose::FlipV => image.flipv(),
+        }
+    }
+
+    /// The ffmpeg filters that do the same transpose.
+    pub fn as_ffmpeg_filter(&self) -> &'static str {
+        match self {
+            Transpose::Rotate90 => "transpose=clock",
+            Transpose::Rotate180 => "hflip,vflip",
+            Transpose::Rotate270 => "transpose=cclock",
+            Transpose::FlipH => "hflip",
+            Transpose::FlipV => "vflip",
+        }
+    }
+
+    /// The size of a `width` by `height` frame after the transpose, which has
+    /// the width and height swapped for 90° and 270° rotations.
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Transpose::Rotate90 | Transpose::Rotate270 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    /// The `region` of the transposed image, made by cropping `image` and
+    /// then transposing just the crop, which is much cheaper than transposing
+    /// the whole frame when the region is small.
+    pub fn apply_to_region(
+        &self,
+        image: &DynamicImage,
+        region: (u32, u32, u32, u32),
+    ) -> DynamicImage {
+        let (x1, y1, x2, y2) = self.unmap_region(region, image.width(), image.height());
+        self.apply(&image.crop_imm(x1, y1, x2 - x1, y2 - y1))
+    }
+
+    /// Map a region `(x1, y1, x2, y2)` of the transposed frame back onto the
+    /// original `width` by `height` frame. The region is clamped to the
+    /// transposed frame first, so it can't end up outside the original.
+    pub fn unmap_region(
+        &self,
+        region: (u32, u32, u32, u32),
+        width: u32,
+        height: u32,
+    ) -> (u32, u32, u32, u32) {
+        let (transposed_width, transposed_height) = self.output_size(width, height);
+        let (x1, y1, x2, y2) = region;
+        let (x2, y2) = (x2.min(transposed_width), y2.min(transposed_height));
+        let (x1, y1) = (x1.min(x2), y1.min(y2));
+        match self {
+            Transpose::Rotate90 => (y1, height - x2, y2, height - x1),
+            Transpose::Rotate180 => (width - x2, height - y2, width - x1, height - y1),
+            Transpose::Rotate270 => (width - y2, x1, width - y1, x2),
+            Transpose::FlipH => (width - x2, y1, width - x1, y2),
+            Transpose::FlipV => (x1, height - y2, x2, height - y1),
+        }
+    }
+}
+
+/// The pixel formats of raw video that can be read from ffmpeg
+///
+/// These are all packed formats without chroma subsampling, so ffmpeg writes
+/// each frame as exactly `width * height * bytes_per_pixel()` bytes, with no
+/// padding at the end of each row, even for odd sizes. Crops are applied to
+/// the decoded frames rather than by ffmpeg, so they can't change its stride
+/// either.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PixelFormat {
+    /// 8-bit RGB (`-pix_fmt rgb24`)
+    #[default]
+    Rgb24,
+
+    /// 8-bit grayscale (`-pix_fmt gray`)
+    Gray8,
+
+    /// 8-bit RGB with alpha (`-pix_fmt rgba`)
+    Rgba,
+
+    /// 16-bit RGB (`-pix_fmt rgb48le`), for high bit depth sources like HDR
+    /// or scientific video, where 8 bits would lose precision. Frames are
+    /// `ImageRgb16` images.
+    Rgb48,
+
+    /// 16-bit grayscale (`-pix_fmt gray16le`). Frames are `ImageLuma16`
+    /// images.
+    Gray16,
+
+    /// 16-bit RGB with alpha (`-pix_fmt rgba64le`). Frames are `ImageRgba16`
+    /// images.
+    Rgba64,
+}
+
+impl PixelFormat {
+    /// The corresponding value for the ffmpeg `-pix_fmt` arg
+    pub fn as_ffmpeg_str(&self) -> &'static str {
+        match self {
+            PixelFormat::Rgb24 => "rgb24",
+            PixelFormat::Gray8 => "gray",
+            PixelFormat::Rgba => "rgba",
+            PixelFormat::Rgb48 => "rgb48le",
+            PixelFormat::Gray16 => "gray16le",
+            PixelFormat::Rgba64 => "rgba64le",
+        }
+    }
+
+    /// Number of color channels per pixel
+    pub fn channels(&self) -> u32 {
+        match self {
+            PixelFormat::Rgb24 => 3,
+            PixelFormat::Gray8 => 1,
+            PixelFormat::Rgba => 4,
+            PixelFormat::Rgb48 => 3,
+            PixelFormat::Gray16 => 1,
+            PixelFormat::Rgba64 => 4,
+        }
+    }
+
+    /// Number of bits per channel
+    pub fn bit_depth(&self) -> u32 {
+        match self {
+            PixelFormat::Rgb24 | PixelFormat::Gray8 | PixelFormat::Rgba => 8,
+            PixelFormat::Rgb48 | PixelFormat::Gray16 | PixelFormat::Rgba64 => 16,
+        }
+    }
+
+    /// Number of bytes per pixel in the raw video from ffmpeg
+    pub fn bytes_per_pixel(&self) -> u32 {
+        self.channels() * self.bit_depth() / 8
+    }
+
+    /// Number of bytes in a raw `width` by `height` frame. This is computed
+    /// in `usize`, since for very large frames it can be more than fits in a
+    /// `u32`.
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        width as usize * height as usize * self.bytes_per_pixel() as usize
+    }
+
+    /// Whether frames in this format have everything frames in `other` do:
+    /// at least as many channels, at least as many bits each.
+    pub fn holds(&self, other: PixelFormat) -> bool {
+        self.channels() >= other.channels() && self.bit_depth() >= other.bit_depth()
+    }
+
+    /// The smallest format that holds both this one and `other`, with the
+    /// most channels and the highest bit depth of either, e.g. `Gray16` and
+    /// `Rgb24` make `Rgb48`.
+    pub fn merge(&self, other: PixelFormat) -> PixelFormat {
+        let channels = self.channels().max(other.channels());
+        let bit_depth = self.bit_depth().max(other.bit_depth());
+        match (channels, bit_depth) {
+            (1, 8) => PixelFormat::Gray8,
+            (3, 8) => PixelFormat::Rgb24,
+            (4, 8) => PixelFormat::Rgba,
+            (1, _) => PixelFormat::Gray16,
+            (3, _) => PixelFormat::Rgb48,
+            _ => PixelFormat::Rgba64,
+        }
+    }
+
+    /// The format of an image, if it's one of the supported formats.
+    pub fn of(image: &DynamicImage) -> Option<PixelFormat> {
+        match image {
+            DynamicImage::ImageRgb8(_) => Some(PixelFormat::Rgb24),
+            DynamicImage::ImageLuma8(_) => Some(PixelFormat::Gray8),
+            DynamicImage::ImageRgba8(_) => Some(PixelFormat::Rgba),
+            DynamicImage::ImageRgb16(_) => Some(PixelFormat::Rgb48),
+            DynamicImage::ImageLuma16(_) => Some(PixelFormat::Gray16),
+            DynamicImage::ImageRgba16(_) => Some(PixelFormat::Rgba64),
+            _ => None,
+        }
+    }
+
+    /// Convert an image into this format. Converting to `Rgba` from a format
+    /// without alpha makes every pixel opaque.
+    pub fn convert(&self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            PixelFormat::Rgb24 => DynamicImage::ImageRgb8(image.to_rgb8()),
+            PixelFormat::Gray8 => DynamicImage::ImageLuma8(image.to_luma8()),
+            PixelFormat::Rgba => DynamicImage::ImageRgba8(image.to_rgba8()),
+            PixelFormat::Rgb48 => DynamicImage::ImageRgb16(image.to_rgb16()),
+            PixelFormat::Gray16 => DynamicImage::ImageLuma16(image.to_luma16()),
+            PixelFormat::Rgba64 => DynamicImage::ImageRgba16(image.to_rgba16()),
+        }
+    }
+
+    /// Wrap a raw buffer read from ffmpeg into an image of the matching type.
+    /// 16-bit samples are little-endian. Returns `None` if the buffer doesn't
+    /// match the given dimensions.
+    pub fn image_from_raw(&self, width: u32, height: u32, buf: Vec<u8>) -> Option<DynamicImage> {
+        match self {
+            PixelFormat::Rgb24 => {
+                RgbImage::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)
+            }
+            PixelFormat::Gray8 => {
+                GrayImage::from_raw(width, height, buf).map(DynamicImage::ImageLuma8)
+            }
+            PixelFormat::Rgba => {
+                RgbaImage::from_raw(width, height, buf).map(DynamicImage::ImageRgba8)
+            }
+            _ => {
+                let mut samples = Vec::with_capacity(buf.len() / 2);
+                samples_from_le(&buf, &mut samples);
+                self.image_from_samples(width, height, samples)
+            }
+        }
+    }
+
+    /// Wrap 16-bit samples into an image of the matching type. Returns `None`
+    /// for 8-bit formats, or if there are too few samples.
+    pub fn image_from_samples(
+        &self,
+        width: u32,
+        height: u32,
+        samples: Vec<u16>,
+    ) -> Option<DynamicImage> {
+        match self {
+            PixelFormat::Rgb48 => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16)
+            }
+            PixelFormat::Gray16 => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma16)
+            }
+            PixelFormat::Rgba64 => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The buffers raw frames are read into from ffmpeg, reused from one frame to
+/// the next. 8-bit frames are wrapped straight into images, while 16-bit
+/// frames are converted from their little-endian `bytes` into `samples`.
+#[derive(Debug, Default)]
+pub struct RawFrameBuffer {
+    pub bytes: Vec<u8>,
+    pub samples: Vec<u16>,
+}
+
+impl RawFrameBuffer {
+    /// Wrap the frame in `bytes` into an image, which takes over whichever
+    /// buffer holds its pixels until it's given back with `put_back`.
+    pub fn take_image(
+        &mut self,
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+    ) -> Option<DynamicImage> {
+        if format.bit_depth() == 8 {
+            return format.image_from_raw(width, height, std::mem::take(&mut self.bytes));
+        }
+        let mut samples = std::mem::take(&mut self.samples);
+        samples_from_le(&self.bytes, &mut samples);
+        format.image_from_samples(width, height, samples)
+    }
+
+    /// Take back the buffer of an image from `take_image`, for the next frame.
+    pub fn put_back(&mut self, image: DynamicImage) {
+        match image {
+            DynamicImage::ImageRgb16(image) => self.samples = image.into_raw(),
+            DynamicImage::ImageLuma16(image) => self.samples = image.into_raw(),
+            DynamicImage::ImageRgba16(image) => self.samples = image.into_raw(),
+            image => self.bytes = into_raw_buffer(image),
+        }
+    }
+}
+
+/// Keeps track of what has been parsed from ffmpeg stderr so far, one line at a
+/// time.
+#[derive(Debug, Default, Clone)]
+pub struct FfmpegStderrParser {
+    pub current_section: &'static str, // stringly-typed rather than enum for convenience
+    pub output_size: Option<(u32, u32)>,
+    pub total_duration: Option<Duration>,
+    pub stream_info: Option<StreamInfo>,
+
+    /// The last `FFMPEG_STDERR_TAIL_LINES` lines of stderr, to report if
+    /// ffmpeg fails.
+    pub stderr_tail: VecDeque<String>,
+
+    /// Indices and timestamps (in seconds) of frames logged by
+    /// `VsyncMode::SourcePts` that haven't been taken yet
+    pub frame_times: VecDeque<(u64, f64)>,
+
+    /// How many frames ffmpeg has dropped so far, from its progress lines
+    pub dropped_frames: u64,
+
+    /// How many frames ffmpeg has duplicated so far, from its progress lines
+    pub duplicated_frames: u64,
+}
+
+impl FfmpegStderrParser {
+    /// Parse a single line of ffmpeg stderr, invoking any callbacks of the
+    /// `Hypetrigger` that it triggers. Returns the output size the first time
+    /// it's found.
+    pub fn parse_line(&mut self, hypetrigger: &Hypetrigger, text: &str) -> Option<(u32, u32)> {
+        let (level, text) = parse_ffmpeg_log_level(text);
+        let text = text.as_str();
+
+        // A line per frame would drown out everything else in the tail
+        if hypetrigger.vsync == VsyncMode::SourcePts {
+            if let Some(frame_time) = parse_ffmpeg_showinfo_pts_time(text) {
+                self.frame_times.push_back(frame_time);
+                return None;
+            }
+        }
+
+        if level <= hypetrigger.ffmpeg_stderr_level {
+            if let Some(callback) = &hypetrigger.on_ffmpeg_stderr_callback {
+                callback(level, text.trim_end());
+            }
+        }
+
+        if self.stderr_tail.len() == FFMPEG_STDERR_TAIL_LINES {
+            self.stderr_tail.pop_front();
+        }
+        self.stderr_tail.push_back(text.trim_end().to_string());
+
+        if text.starts_with("Input #") {
+            self.current_section = "Input";
+        }
+
+        // Parse for total duration of the input, if it's known
+        if self.total_duration.is_none() && self.current_section != "Output" {
+            self.total_duration = parse_ffmpeg_duration(text);
+        }
+
+        // Parse for info about the input video, if not already found
+        if self.stream_info.is_none() && self.current_section == "Input" {
+            if let Some(stream_info) = parse_ffmpeg_stream_info(text) {
+                self.stream_info = Some(stream_info.clone());
+                if let Some(source_fps) = stream_info.fps {
+                    check_oversampling(hypetrigger, source_fps);
+                }
+                if let Some(callback) = &hypetrigger.on_stream_info_callback {
+                    callback(stream_info);
+                }
+            }
+        }
+
+        // Parse for frames dropped or duplicated by ffmpeg, which are totals so far
+        if let Some((dropped, duplicated)) = parse_ffmpeg_drop_dup(text) {
+            self.dropped_frames = dropped;
+            self.duplicated_frames = duplicated;
+        }
+
+        // Parse for progress through the input
+        if let Some(callback) = &hypetrigger.on_progress_callback {
+            if let Some(current) = parse_ffmpeg_progress_time(text) {
+                callback(FfmpegProgress {
+                    total: self.total_duration,
+                    current: hypetrigger.start_time.unwrap_or_default() + current,
+                    dropped_frames: self.dropped_frames,
+                    duplicated_frames: self.duplicated_frames,
+                });
+            }
+        }
+
+        // Parse for output size if not already found
+        if self.output_size.is_none() {
+            if text.starts_with("Output #") {
+                self.current_section = "Output";
+            } else if self.current_section == "Output" {
+                if let Some(size) = parse_ffmpeg_output_size(text) {
+                    self.output_size = Some(size); // remember this, so we don't check for it anymore
+                    return Some(size);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Check that a `global_filter` is a plain chain of filters, which can go in
+/// the middle of the pipeline's own chain without upsetting it.
+fn validate_global_filter(global_filter: &str) -> core::result::Result<(), FfmpegError> {
+    if global_filter.trim().is_empty() {
+        return Err(FfmpegError::InvalidConfig(
+            "`global_filter` can't be empty".to_string(),
+        ));
+    }
+    if global_filter.contains(['[', ']', ';']) {
+        return Err(FfmpegError::InvalidConfig(format!(
+            "`global_filter` must be a plain chain of filters, without labels or `;`, but got `{}`",
+            global_filter
+        )));
+    }
+    let filter_names = global_filter
+        .split(',')
+        .map(|filter| filter.split('=').next().unwrap_or_default().trim());
+    for name in filter_names {
+        if matches!(name, "split" | "fps" | "select") {
+            return Err(FfmpegError::InvalidConfig(format!(
+                "`global_filter` can't contain `{}`, since the pipeline does its own sampling",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Warn if the pipeline samples faster than the input's native framerate,
+/// where every extra frame is a duplicate that the Triggers run on for nothing.
+/// Rounds the native framerate up, so that e.g. 30 fps on a 29.97 fps input
+/// doesn't count.
+fn check_oversampling(hypetrigger: &Hypetrigger, source_fps: f64) {
+    if hypetrigger.fps as f64 <= source_fps.ceil() {
+        return;
+    }
+    log_warn!(
+        "[hypetrigger] Sampling at {} fps, but the input is only {} fps, so ffmpeg will duplicate frames; consider lowering `fps`",
+        hypetrigger.fps,
+        source_fps
+    );
+    if let Some(callback) = &hypetrigger.on_oversampling_callback {
+        callback(hypetrigger.fps, source_fps);
+    }
+}
+
+/// Progress through the input, as reported by ffmpeg on stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FfmpegProgress {
+    /// Total duration of the input, if known (e.g. not for live streams)
+    pub total: Option<Duration>,
+
+    /// How far into the input ffmpeg has read so far
+    pub current: Duration,
+
+    /// How many frames ffmpeg has dropped so far to sync the output to its
+    /// timestamps (`drop=` in its progress), e.g. with `VsyncMode::Cfr` on
+    /// variable framerate input. These never reach the pipeline, unlike the
+    /// `frames_dropped` to keep up in `realtime` mode.
+    pub dropped_frames: u64,
+
+    /// How many frames ffmpeg has duplicated so far to sync the output to its
+    /// timestamps (`dup=` in its progress), e.g. when sampling faster than the
+    /// input's framerate
+    pub duplicated_frames: u64,
+}
+
+/// Everything a Trigger can ask to have done to the frames it receives, which
+/// identifies each converted copy of a frame in `dispatch_frame`.
+type FrameConversion = (
+    PixelFormat,
+    Option<ColorFilter>,
+    Option<Option<(u32, u32, u32, u32)>>,
+    Option<(u32, u32)>,
+    Option<Transpose>,
+);
+
+/// Invert the colors of just the region `(x1, y1, x2, y2)` of an image,
+/// clamped to its bounds, e.g. a Trigger's crop.
+pub fn invert_region(image: &mut DynamicImage, region: (u32, u32, u32, u32)) {
+    fn invert<I: GenericImage>(image: &mut I, (x1, y1, x2, y2): (u32, u32, u32, u32)) {
+        let (x2, y2) = (x2.min(image.width()), y2.min(image.height()));
+        let (x1, y1) = (x1.min(x2), y1.min(y2));
+        image::imageops::invert(&mut image.sub_image(x1, y1, x2 - x1, y2 - y1));
+    }
+    match image {
+        DynamicImage::ImageLuma8(image) => invert(image, region),
+        DynamicImage::ImageLumaA8(image) => invert(image, region),
+        DynamicImage::ImageRgb8(image) => invert(image, region),
+        DynamicImage::ImageRgba8(image) => invert(image, region),
+        DynamicImage::ImageBgr8(image) => invert(image, region),
+        DynamicImage::ImageBgra8(image) => invert(image, region),
+        DynamicImage::ImageLuma16(image) => invert(image, region),
+        DynamicImage::ImageLumaA16(image) => invert(image, region),
+        DynamicImage::ImageRgb16(image) => invert(image, region),
+        DynamicImage::ImageRgba16(image) => invert(image, region),
+    }
+}
+
+/// Whether resizing frames of size `from` to `to` would make them bigger in
+/// either direction.
+fn is_upscaled(to: (u32, u32), from: (u32, u32)) -> bool {
+    to.0 > from.0 || to.1 > from.1
+}
+
+/// Log a warning if the Trigger's `get_scale()` is bigger than the frames it's
+/// made from, which can't add any detail.
+fn warn_if_upscaled(trigger_id: usize, trigger: &dyn Trigger, frame_size: (u32, u32)) {
+    if let Some(scale) = trigger
+        .get_scale()
+        .filter(|&scale| is_upscaled(scale, frame_size))
+    {
+        log_warn!(
+            "[hypetrigger] Trigger {} asks for {}x{} frames, so the {}x{} frames from ffmpeg are upscaled",
+            trigger_id,
+            scale.0,
+            scale.1,
+            frame_size.0,
+            frame_size.1
+        );
+    }
+}
+
+/// `warn_if_upscaled` for all of the Triggers.
+fn warn_upscaled_triggers(triggers: &[Arc<dyn Trigger>], frame_size: (u32, u32)) {
+    for (trigger_id, trigger) in triggers.iter().enumerate() {
+        warn_if_upscaled(trigger_id, trigger.as_ref(), frame_size);
+    }
+}
+
+/// The size of the frames a Trigger receives from a pipeline whose frames are
+/// `width` by `height`, after its `get_scale()` and `get_transpose()`.
+pub fn trigger_frame_size(trigger: &dyn Trigger, width: u32, height: u32) -> (u32, u32) {
+    let (width, height) = trigger.get_scale().unwrap_or((width, height));
+    match trigger.get_transpose() {
+        Some(transpose) => transpose.output_size(width, height),
+        None => (width, height),
+    }
+}
+
+/// Whether a Trigger running at `trigger_fps` should receive the given frame
+/// from a pipeline running at `pipeline_fps`.
+pub fn is_sampled(frame_num: u64, pipeline_fps: f64, trigger_fps: f64) -> bool {
+    let sample_index = |n: u64| (n as f64 * trigger_fps / pipeline_fps).floor() as u64;
+    frame_num == 0 || sample_index(frame_num) != sample_index(frame_num - 1)
+}
+
+/// Take back ownership of the raw buffer of an image created with
+/// `PixelFormat::image_from_raw`, so that it can be reused for the next frame.
+/// For any other kind of image, the buffer is copied.
+pub fn into_raw_buffer(image: DynamicImage) -> Vec<u8> {
+    match image {
+        DynamicImage::ImageRgb8(image) => image.into_raw(),
+        DynamicImage::ImageLuma8(image) => image.into_raw(),
+        DynamicImage::ImageRgba8(image) => image.into_raw(),
+        image => image.to_bytes(),
+    }
+}
+
+/// Read 16-bit little-endian samples from raw bytes, replacing the contents
+/// of `samples` (without allocating, if it's big enough already).
+fn samples_from_le(buf: &[u8], samples: &mut Vec<u16>) {
+    samples.clear();
+    samples.extend(
+        buf.chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])),
+    );
+}
+
+/// Joins to everything parsed from ffmpeg stderr, once ffmpeg has exited
+pub type FfmpegStderrJoinHandle<'scope> =
+    ScopedJoinHandle<'scope, core::result::Result<FfmpegStderrParser, String>>;
+
+pub(crate) fn join_stderr_thread(
+    join_handle: FfmpegStderrJoinHandle,
+) -> core::result::Result<FfmpegStderrParser, String> {
+    join_handle
+        .join()
+        .map_err(|_| "ffmpeg stderr thread panicked".to_string())?
+}
+
+/// How many lines of ffmpeg stderr to keep around for error reporting
+pub const FFMPEG_STDERR_TAIL_LINES: usize = 20;
+
+/// How long to wait for ffmpeg to log the timestamp of a frame that's already
+/// been read, with `VsyncMode::SourcePts`
+const FRAME_TIME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Wait for the timestamp of frame `frame_num` from the stderr thread. Any
+/// left over from earlier frames, which were logged too late to be used, are
+/// skipped; one for a later frame is kept in `next_frame_time` for its turn.
+fn recv_frame_time(
+    frame_times: &Receiver<(u64, f64)>,
+    frame_num: u64,
+    next_frame_time: &mut Option<(u64, f64)>,
+) -> Option<f64> {
+    let deadline = Instant::now() + FRAME_TIME_TIMEOUT;
+    loop {
+        let (n, pts_time) = match next_frame_time.take() {
+            Some(frame_time) => frame_time,
+            None => {
+                match frame_times.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(frame_time) => frame_time,
+                    Err(_) => break,
+                }
+            }
+        };
+        match n.cmp(&frame_num) {
+            std::cmp::Ordering::Less => continue,
+            std::cmp::Ordering::Equal => return Some(pts_time),
+            std::cmp::Ordering::Greater => {
+                *next_frame_time = Some((n, pts_time));
+                break;
+            }
+        }
+    }
+    log_warn!(
+        "[ffmpeg.err] No timestamp was logged for frame {}; computing it from the framerate",
+        frame_num
+    );
+    None
+}
+
+/// What the pipeline read from ffmpeg over the course of a run
+#[derive(Debug, Clone, Default)]
+pub struct PipelineSummary {
+    /// Number of frames read from ffmpeg stdout
+    pub frames_read: u64,
+
+    /// Number of frames each Trigger ran on, in the order they were added
+    pub trigger_frames: Vec<u64>,
+
+    /// Number of frames dropped to keep up in `realtime` mode
+    pub frames_dropped: u64,
+
+    /// Whether a Trigger stopped the pipeline before the end of the input
+    /// (see `Trigger::on_frame_control`), so ffmpeg was cut off on purpose
+    pub stopped_early: bool,
+
+    /// The last lines ffmpeg wrote to stderr
+    pub stderr_tail: Vec<String>,
+
+    /// Whether ffmpeg was killed for exceeding the `stdout_read_timeout`
+    pub timed_out: bool,
+
+    /// Number of frames ffmpeg dropped itself (see
+    /// `FfmpegProgress::dropped_frames`)
+    pub ffmpeg_dropped: u64,
+
+    /// Number of frames ffmpeg duplicated itself (see
+    /// `FfmpegProgress::duplicated_frames`)
+    pub ffmpeg_duplicated: u64,
+}
+
+impl PipelineSummary {
+    /// Add what was parsed from ffmpeg stderr, once it's finished.
+    pub(crate) fn with_stderr(self, parser: FfmpegStderrParser) -> Self {
+        Self {
+            stderr_tail: Vec::from(parser.stderr_tail),
+            ffmpeg_dropped: parser.dropped_frames,
+            ffmpeg_duplicated: parser.duplicated_frames,
+            ..self
+        }
+    }
+}
+
+/// Read a single frame into the buffer, like `read_exact`, but return how many
+/// bytes were read before the end of the output: 0 if it ended cleanly
+/// between frames, or less than the buffer size for a truncated frame.
+pub fn read_frame(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut bytes_read = 0;
+    while bytes_read < buffer.len() {
+        match reader.read(&mut buffer[bytes_read..]) {
+            Ok(0) => break,
+            Ok(n) => bytes_read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// Passed to the `on_complete` callback once the input is finished.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionStats {
+    /// Total number of frames read from ffmpeg
+    pub frames_read: u64,
+
+    /// Number of frames each Trigger ran on, in the order they were added.
+    /// Triggers with a lower `get_fps()` run on fewer frames. After
+    /// `FfmpegSession::set_triggers` (or `replace_trigger`), only the new
+    /// Triggers are counted, from when they were swapped in. Empty when the
+    /// pipeline doesn't run the Triggers itself (`run_tokio`).
+    pub trigger_frames: Vec<u64>,
+
+    /// Number of frames dropped without running the Triggers, to keep up in
+    /// `realtime` mode. Included in `frames_read`.
+    pub frames_dropped: u64,
+
+    /// Number of frames ffmpeg itself dropped before they were ever sent,
+    /// e.g. to keep a constant framerate (see `FfmpegProgress`). A gap in
+    /// the detections with a rising count here points at the input rather
+    /// than the Triggers. Always 0 with `run_tokio`, which doesn't wait for
+    /// ffmpeg's last progress line.
+    pub ffmpeg_dropped: u64,
+
+    /// Number of frames ffmpeg itself duplicated, which the Triggers see as
+    /// repeats of the previous frame. Always 0 with `run_tokio`.
+    pub ffmpeg_duplicated: u64,
+
+    /// Wall clock time from starting to read from ffmpeg until the end of
+    /// the input
+    pub elapsed: Duration,
+}
+
+/// What the pipeline is doing with ffmpeg stdout, as told to the watchdog
+enum StdoutActivity {
+    /// Waiting for ffmpeg to send more data
+    Waiting,
+
+    /// Got some data, and is busy with it (e.g. running the Triggers, or
+    /// paused) until it next waits
+    Received,
+}
+
+/// Passes reads through to ffmpeg stdout, letting the watchdog know when each
+/// one starts waiting on ffmpeg, and when it gets some data.
+struct WatchedReader<R> {
+    inner: R,
+    heartbeat_tx: Sender<StdoutActivity>,
+}
+
+impl<R: Read> Read for WatchedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let _ = self.heartbeat_tx.send(StdoutActivity::Waiting);
+        let result = self.inner.read(buf);
+        let _ = self.heartbeat_tx.send(StdoutActivity::Received);
+        result
+    }
+}
+
+/// Kill ffmpeg if the pipeline has been waiting on its stdout for longer than
+/// the `timeout`. The clock only runs while waiting, from the start until the
+/// first data arrives, and then from each read until it gets its data, so
+/// the time spent between reads doesn't count. Returns whether ffmpeg was
+/// killed, or returns `false` once the heartbeat sender is dropped.
+fn watch_ffmpeg_stdout(
+    ffmpeg_child: &mut Child,
+    timeout: Duration,
+    heartbeat_rx: Receiver<StdoutActivity>,
+) -> bool {
+    let mut waiting = true;
+    loop {
+        let activity = if waiting {
+            heartbeat_rx.recv_timeout(timeout)
+        } else {
+            heartbeat_rx
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match activity {
+            Ok(StdoutActivity::Waiting) => waiting = true,
+            Ok(StdoutActivity::Received) => waiting = false,
+            Err(RecvTimeoutError::Disconnected) => return false,
+            Err(RecvTimeoutError::Timeout) => {
+                log_warn!("[ffmpeg] No data from ffmpeg for {:?}; killing it", timeout);
+                if let Err(e) = ffmpeg_child.kill() {
+                    log_warn!("[ffmpeg] Unable to kill ffmpeg: {}", e);
+                }
+                return true;
+            }
+        }
+    }
+}
+
+/// If ffmpeg exits with an error before sending a single frame, it was most
+/// likely given a bad input (e.g. a typo'd path or a missing codec). Report it
+/// through the callback and as an error, rather than treating it like a
+/// successful run over an empty input.
+pub fn check_ffmpeg_exit(
+    status: ExitStatus,
+    summary: PipelineSummary,
+    callback: Option<&HypetriggerOnFfmpegErrorCallback>,
+) -> core::result::Result<(), FfmpegError> {
+    if summary.frames_read > 0 || status.success() {
+        return Ok(());
+    }
+    let failure = FfmpegFailure {
+        status,
+        stderr_tail: summary.stderr_tail,
+    };
+    if let Some(callback) = callback {
+        callback(&failure);
+    }
+    Err(FfmpegError::Exited(failure))
+}
+
+/// Filename of the ffmpeg binary on the current platform
+#[cfg(windows)]
+pub const FFMPEG_BINARY: &str = "ffmpeg.exe";
+
+/// Filename of the ffmpeg binary on the current platform
+#[cfg(not(windows))]
+pub const FFMPEG_BINARY: &str = "ffmpeg";
+
+/// Used with the ffmpeg `-i` argument, or with `.input()` in the Hypetrigger API.
+/// <https://www.bogotobogo.com/FFMpeg/ffmpeg_video_test_patterns_src.php>
+pub const FFMPEG_TEST_INPUT: &str = "testsrc=duration=10:size=1280x720:rate=30";
+
+/// Sends a `q` to the ffmpeg process over stdin, which tells it gracefully exit.
+/// You could also call `kill()` on the `Child` process instance of ffmpeg to stop it
+/// more abruptly. When using `run_async()`, prefer `FfmpegSession::stop()`.
+pub fn stop_ffmpeg(stdin: &mut ChildStdin) -> Result<()> {
+    stdin.write_all(b"q\n").map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_sampled, trigger_frame_size, DeinterlaceMode, FfmpegLogLevel, HwAccel, Hypetrigger,
+        OutputSink, PixelFormat, RawFrameBuffer, SeekAccuracy, Transpose, TriggerErrorPolicy,
+        VsyncMode, WindowsPriorityClass, WindowsProcessFlags,
+    };
+    #[cfg(unix)]
+    use crate::test_util::fake_ffmpeg;
+    use crate::{
+        color_filter::ColorFilter,
+        error::{Error, NoneError, Result},
+        pixel_probe::PixelProbeTrigger,
+        simple_trigger::SimpleTrigger,
+        test_util::TempDir,
+        trigger::{Frame, Trigger},
+    };
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn video_filter() {
+        let mut hypetrigger = Hypetrigger::new();
+        assert_eq!(hypetrigger.video_filter(), "fps=2");
+
+        hypetrigger.set_fps(4).set_scale(224, 224);
+        assert_eq!(hypetrigger.video_filter(), "fps=4,scale=224:224");
+
+        hypetrigger.set_deinterlace(DeinterlaceMode::Bwdif);
+        assert_eq!(hypetrigger.video_filter(), "bwdif,fps=4,scale=224:224");
+
+        hypetrigger.set_global_filter("hqdn3d,eq=contrast=1.2");
+        assert_eq!(
+            hypetrigger.video_filter(),
+            "bwdif,hqdn3d,eq=contrast=1.2,fps=4,scale=224:224"
+        );
+        hypetrigger
+            .set_input("input.mp4")
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(hypetrigger.validate().is_ok());
+        for invalid in ["", "split[a][b]", "hqdn3d,fps=30", "[0:v]hqdn3d"] {
+            hypetrigger.set_global_filter(invalid);
+            assert!(hypetrigger.validate().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn build_ffmpeg_command() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_hwaccel(HwAccel::None);
+        assert_eq!(
+            hypetrigger.build_ffmpeg_command()?,
+            vec![
+                "ffmpeg",
+                "-i",
+                "input.mp4",
+                "-filter:v",
+                "fps=2",
+                "-vsync",
+                "drop",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgb24",
+                "-an",
+                "-y",
+                "pipe:1"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn seek_accuracy() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_hwaccel(HwAccel::None)
+            .set_start_time(Duration::from_secs(90));
+        let input_args = |hypetrigger: &Hypetrigger| -> Result<Vec<String>> {
+            let command = hypetrigger.build_ffmpeg_command()?;
+            let input = command
+                .iter()
+                .position(|arg| arg == "-i")
+                .ok_or(NoneError)?;
+            Ok(command[1..input].to_vec())
+        };
+        assert_eq!(input_args(&hypetrigger)?, ["-ss", "90"]);
+
+        hypetrigger.set_seek_accuracy(SeekAccuracy::Fast);
+        assert_eq!(input_args(&hypetrigger)?, ["-noaccurate_seek", "-ss", "90"]);
+
+        hypetrigger.set_seek_accuracy(SeekAccuracy::Decode);
+        assert!(input_args(&hypetrigger)?.is_empty());
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(command[3..5], ["-ss", "90"]);
+        Ok(())
+    }
+
+    #[test]
+    fn vsync() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_vsync(VsyncMode::Cfr);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        let vsync = command
+            .iter()
+            .position(|arg| arg == "-vsync")
+            .ok_or(NoneError)?;
+        assert_eq!(command[vsync + 1], "cfr");
+
+        hypetrigger.set_vsync(VsyncMode::SourcePts);
+        assert_eq!(
+            hypetrigger.video_filter(),
+            "select='isnan(prev_selected_t)+gte(t-prev_selected_t,0.5)',showinfo"
+        );
+        hypetrigger.add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(hypetrigger.validate().is_ok());
+        hypetrigger.set_ffmpeg_loglevel(FfmpegLogLevel::Warning);
+        assert!(hypetrigger.validate().is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn source_pts() -> Result<()> {
+        // Stand-in for ffmpeg that logs a variable framerate's worth of frames
+        let ffmpeg = fake_ffmpeg(
+            "pts",
+            "#!/bin/sh\n\
+             echo \"Output #0, rawvideo, to 'pipe:1':\" >&2\n\
+             echo \"  Stream #0:0: Video: rawvideo, rgb24, 64x36, 2 fps\" >&2\n\
+             n=0\n\
+             for pts_time in 0 0.5 1.25; do\n\
+               echo \"[Parsed_showinfo_2 @ 0x55d5] n: $n pts:      0 pts_time:$pts_time\" >&2\n\
+               head -c 6912 /dev/zero\n\
+               n=$((n + 1))\n\
+             done\n",
+        )?;
+
+        let timestamps = Arc::new(Mutex::new(vec![]));
+        let timestamps_clone = timestamps.clone();
+        Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .set_start_time(Duration::from_secs(10))
+            .set_vsync(VsyncMode::SourcePts)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                timestamps_clone.lock().unwrap().push(frame.timestamp);
+            }))
+            .run()?;
+        assert_eq!(*timestamps.lock()?, vec![10.0, 10.5, 11.25]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn late_source_pts() -> Result<()> {
+        // The timestamp of the first frame is logged after the pipeline has
+        // given up waiting for it
+        let ffmpeg = fake_ffmpeg(
+            "latepts",
+            "#!/bin/sh
+             echo \"Output #0, rawvideo, to 'pipe:1':\" >&2
+             echo \"  Stream #0:0: Video: rawvideo, rgb24, 64x36, 2 fps\" >&2
+             head -c 6912 /dev/zero
+             sleep 1.5
+             echo \"[Parsed_showinfo_2 @ 0x55d5] n: 0 pts:      0 pts_time:0.1\" >&2
+             echo \"[Parsed_showinfo_2 @ 0x55d5] n: 1 pts:      0 pts_time:0.75\" >&2
+             head -c 6912 /dev/zero
+             echo \"[Parsed_showinfo_2 @ 0x55d5] n: 2 pts:      0 pts_time:1.25\" >&2
+             head -c 6912 /dev/zero
+",
+        )?;
+
+        let timestamps = Arc::new(Mutex::new(vec![]));
+        let timestamps_clone = timestamps.clone();
+        Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .set_vsync(VsyncMode::SourcePts)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                timestamps_clone.lock().unwrap().push(frame.timestamp);
+            }))
+            .run()?;
+
+        // The late timestamp isn't given to the next frame
+        assert_eq!(*timestamps.lock()?, vec![0.0, 0.75, 1.25]);
+        Ok(())
+    }
+
+    #[test]
+    fn dump_frames_to() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_hwaccel(HwAccel::None)
+            .set_dump_frames_to(PathBuf::from("frames.mp4"));
+        let command = hypetrigger.build_ffmpeg_command()?;
+        let pipe = command
+            .iter()
+            .position(|arg| arg == "pipe:1")
+            .ok_or(NoneError)?;
+        assert_eq!(
+            command[pipe + 1..],
+            ["-filter:v", "fps=2", "-vsync", "drop", "-an", "frames.mp4"].map(String::from)
+        );
+
+        hypetrigger
+            .add_input("input2.mp4".to_string())
+            .set_scale(64, 36);
+        assert!(hypetrigger
+            .filter_complex()?
+            .ends_with("hstack=inputs=2,split[out][dump]"));
+        Ok(())
+    }
+
+    #[test]
+    fn dump_crops_to() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input("input.mp4")
+            .set_hwaccel(HwAccel::None)
+            .set_live(true)
+            .set_dump_crops_to("crops")
+            .add_trigger(PixelProbeTrigger::new(10, 5, |_, _| {}).set_size(4, 2))
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(hypetrigger.validate().is_err()); // the crops can't be placed
+
+        hypetrigger.set_scale(64, 36);
+        hypetrigger.validate()?;
+        let command = hypetrigger.build_ffmpeg_command()?;
+        let output = |name: &str| {
+            let path = PathBuf::from("crops").join(name);
+            let i = command
+                .iter()
+                .position(|arg| path == Path::new(arg))
+                .ok_or(NoneError)?;
+            Ok::<_, Error>(command[i - 5..i].to_vec())
+        };
+        let crop = [
+            "-filter:v",
+            "fps=2,scale=64:36,crop=4:2:10:5",
+            "-vsync",
+            "drop",
+            "-an",
+        ];
+        assert_eq!(output("trigger_0.mp4")?, crop.map(String::from));
+        assert_eq!(output("trigger_1.mp4")?[1], "fps=2,scale=64:36");
+
+        // Being live, existing files are kept rather than overwritten (or
+        // prompting)
+        assert!(command.contains(&"-n".to_string()));
+
+        hypetrigger.add_input("input2.mp4");
+        assert!(hypetrigger.filter_complex()?.ends_with(
+            "hstack=inputs=2,split=3[out][uncropped0][trigger1];[uncropped0]crop=4:2:10:5[trigger0]"
+        ));
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert!(command
+            .windows(2)
+            .any(|args| args == ["-map", "[trigger0]"]));
+        Ok(())
+    }
+
+    #[test]
+    fn validate() {
+        let mut hypetrigger = Hypetrigger::new();
+        assert!(hypetrigger.validate().is_err()); // no input
+        hypetrigger.test_input();
+        assert!(hypetrigger.validate().is_err()); // no triggers
+        hypetrigger.add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(hypetrigger.validate().is_ok());
+        hypetrigger.set_fps(0);
+        assert!(hypetrigger.validate().is_err());
+        hypetrigger.set_fps(2).set_hwaccel_device("1");
+        assert!(hypetrigger.validate().is_err()); // `auto` can't pick a device
+        hypetrigger.set_hwaccel(HwAccel::Cuda);
+        assert!(hypetrigger.validate().is_ok());
+        hypetrigger.set_ffmpeg_loglevel(FfmpegLogLevel::Warning);
+        assert!(hypetrigger.validate().is_err()); // the output size isn't logged
+        hypetrigger.set_scale(64, 36);
+        assert!(hypetrigger.validate().is_ok());
+    }
+
+    #[test]
+    fn output_sink() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input("input.mp4")
+            .set_output_sink(OutputSink::Fd(3));
+        assert!(hypetrigger.validate().is_ok()); // no Triggers needed
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(command.last().ok_or(NoneError)?, "pipe:3");
+
+        hypetrigger.set_output_sink(OutputSink::NamedPipe(PathBuf::from("/tmp/frames")));
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(command.last().ok_or(NoneError)?, "/tmp/frames");
+        Ok(())
+    }
+
+    #[test]
+    fn threads() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input("input.mp4")
+            .set_hwaccel(HwAccel::None)
+            .set_ffmpeg_threads(2)
+            .set_filter_threads(1);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(command[1..3], ["-filter_threads", "1"].map(String::from));
+        let i = command
+            .iter()
+            .position(|arg| arg == "-i")
+            .ok_or(NoneError)?;
+        assert_eq!(command[i - 2..i], ["-threads", "2"].map(String::from));
+
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(hypetrigger.validate().is_ok());
+        hypetrigger.set_ffmpeg_threads(0);
+        assert!(hypetrigger.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn windows_process_flags() {
+        assert_eq!(WindowsProcessFlags::default().bits(), 0x08000000);
+        let flags = WindowsProcessFlags {
+            hide_window: false,
+            priority: Some(WindowsPriorityClass::BelowNormal),
+            new_process_group: true,
+        };
+        assert_eq!(flags.bits(), 0x00004200);
+    }
+
+    #[test]
+    fn ffmpeg_loglevel() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_ffmpeg_loglevel(FfmpegLogLevel::Warning);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(
+            command[1..4],
+            ["-loglevel", "level+warning", "-stats"].map(String::from)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn quiet_ffmpeg_progress() -> Result<()> {
+        let progress = Arc::new(Mutex::new(vec![]));
+        let progress_clone = progress.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .set_ffmpeg_loglevel(FfmpegLogLevel::Error)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .on_progress(move |p| progress_clone.lock().unwrap().push(p))
+            .run()?;
+        let progress = progress.lock()?;
+        let last = progress.last().ok_or(NoneError)?;
+        assert_eq!(last.current, Duration::from_secs(10));
+        assert!(last.dropped_frames > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn quiet_ffmpeg() -> Result<()> {
+        let frames = Arc::new(Mutex::new(0));
+        let frames_clone = frames.clone();
+        let stderr_levels = Arc::new(Mutex::new(vec![]));
+        let stderr_levels_clone = stderr_levels.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .set_ffmpeg_loglevel(FfmpegLogLevel::Warning)
+            .set_ffmpeg_stderr_level(FfmpegLogLevel::Warning)
+            .on_ffmpeg_stderr(move |level, _| stderr_levels_clone.lock().unwrap().push(level))
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                assert_eq!(frame.image.dimensions(), (64, 36));
+                *frames_clone.lock().unwrap() += 1;
+            }))
+            .run()?;
+        assert!(*frames.lock()? > 0);
+        assert!(stderr_levels
+            .lock()?
+            .iter()
+            .all(|&level| level <= FfmpegLogLevel::Warning));
+        Ok(())
+    }
+
+    #[test]
+    fn hwaccel_device() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_hwaccel(HwAccel::Cuda)
+            .set_hwaccel_device("1");
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(
+            command[1..5],
+            ["-hwaccel", "cuda", "-hwaccel_device", "1"].map(String::from)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn video_decoder() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_hwaccel(HwAccel::Cuda)
+            .set_video_decoder("av1_cuvid");
+        let command = hypetrigger.build_ffmpeg_command()?;
+        let i = command
+            .iter()
+            .position(|arg| arg == "-i")
+            .ok_or(NoneError)?;
+        assert_eq!(command[i - 2..i], ["-c:v", "av1_cuvid"]);
+
+        // Only the main input is decoded with it
+        hypetrigger.add_input("angle2.mp4").set_scale(64, 36);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(command.iter().filter(|arg| *arg == "-c:v").count(), 1);
+
+        hypetrigger
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .set_video_decoder("");
+        assert!(hypetrigger.validate().is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_input() -> Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let input = PathBuf::from(OsStr::from_bytes(b"my videos/caf\xe9.mp4"));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input(input.clone());
+        let command = hypetrigger.ffmpeg_command(None)?;
+        let args: Vec<&OsStr> = command.get_args().collect();
+        let i = args.iter().position(|&arg| arg == "-i").ok_or(NoneError)?;
+        assert_eq!(args[i + 1], input.as_os_str());
+        Ok(())
+    }
+
+    #[test]
+    fn realtime() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input("input.mp4")
+            .set_realtime(Duration::from_millis(500));
+        let command = hypetrigger.build_ffmpeg_command()?;
+        let re = command
+            .iter()
+            .position(|arg| arg == "-re")
+            .ok_or(NoneError)?;
+        assert!(
+            re < command
+                .iter()
+                .position(|arg| arg == "-i")
+                .ok_or(NoneError)?
+        );
+        assert!(command.contains(&"-y".to_string()));
+        hypetrigger.set_live(true);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert!(!command.contains(&"-re".to_string()));
+
+        // A live input never overwrites an existing output
+        assert!(command.contains(&"-n".to_string()));
+        assert!(!command.contains(&"-y".to_string()));
+
+        // At 2 fps, frame 4 should be read 2s after the first
+        let behind =
+            |elapsed_ms| hypetrigger.is_behind_realtime(4, Duration::from_millis(elapsed_ms));
+        assert!(!behind(1000));
+        assert!(!behind(2500));
+        assert!(behind(2501));
+        hypetrigger.realtime = false;
+        assert!(!hypetrigger.is_behind_realtime(4, Duration::from_secs(10)));
+        Ok(())
+    }
+
+    #[test]
+    fn extra_args() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg".to_string())
+            .set_input("input.mp4".to_string())
+            .set_hwaccel(HwAccel::None)
+            .add_input_args(&["-probesize", "32"])
+            .add_output_args(&["-threads", "2"]);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(command[1..4], ["-probesize", "32", "-i"]);
+        assert_eq!(command[command.len() - 3..], ["-threads", "2", "pipe:1"]);
+
+        hypetrigger.add_output_args(&["-f", "mp4"]);
+        assert!(hypetrigger.build_ffmpeg_command().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stream_info() -> Result<()> {
+        let session = Hypetrigger::new()
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .clone()
+            .run_async()?;
+        let stream_info = session.stream_info.clone();
+        session.stop()?;
+
+        let stream_info = stream_info.lock()?.clone().ok_or(NoneError)?;
+        assert_eq!((stream_info.width, stream_info.height), (1280, 720));
+        assert_eq!(stream_info.fps, Some(30.0));
+        Ok(())
+    }
+
+    #[test]
+    fn raw_input() -> Result<()> {
+        struct CornerTrigger;
+        impl Trigger for CornerTrigger {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+                Some((100.min(width), 100.min(height), width, height))
+            }
+        }
+
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe("ffmpeg")
+            .set_input("pipe:0")
+            .set_hwaccel(HwAccel::None)
+            .set_raw_input(320, 240, PixelFormat::Rgba, 30.0)
+            .add_trigger(CornerTrigger);
+        let command = hypetrigger.build_ffmpeg_command()?;
+        assert_eq!(
+            command[1..11],
+            [
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+                "-video_size",
+                "320x240",
+                "-framerate",
+                "30",
+                "-i",
+                "pipe:0"
+            ]
+        );
+        assert!(hypetrigger.validate().is_ok());
+
+        // A region that doesn't fit inside the raw frames
+        hypetrigger.set_raw_input(100, 50, PixelFormat::Rgba, 30.0);
+        assert!(hypetrigger.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn oversampling() -> Result<()> {
+        let oversampled = Arc::new(Mutex::new(vec![]));
+        let oversampled_clone = oversampled.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .on_oversampling(move |fps, source_fps| {
+                oversampled_clone.lock().unwrap().push((fps, source_fps));
+            });
+
+        // The stand-in ffmpeg reports a 30 fps input
+        hypetrigger.set_fps(30).run().map_err(Error::from_display)?;
+        assert!(oversampled.lock()?.is_empty());
+        hypetrigger.set_fps(60).run().map_err(Error::from_display)?;
+        assert_eq!(*oversampled.lock()?, vec![(60, 30.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn grayscale() -> Result<()> {
+        Hypetrigger::new()
+            .test_input()
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(SimpleTrigger::new(|frame| {
+                assert!(frame.image.as_luma8().is_some());
+            }))
+            .run()
+            .map_err(Error::from_display)
+    }
+
+    #[test]
+    fn high_bit_depth() -> Result<()> {
+        let image = PixelFormat::Gray16
+            .image_from_raw(1, 1, vec![0x34, 0x12])
+            .ok_or(NoneError)?;
+        assert_eq!(image.as_luma16().ok_or(NoneError)?.as_raw(), &vec![0x1234]);
+
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .set_pixel_format(PixelFormat::Rgb48)
+            .add_trigger(SimpleTrigger::new(|frame| {
+                assert!(frame.image.as_rgb16().is_some());
+                assert_eq!(frame.bit_depth(), 16);
+            }))
+            .run()
+            .map_err(Error::from_display)
+    }
+
+    #[test]
+    fn frame_size() {
+        assert_eq!(PixelFormat::Rgb24.frame_size(1280, 720), 1280 * 720 * 3);
+        assert_eq!(PixelFormat::Gray16.frame_size(3, 2), 12);
+
+        // More bytes than fit in a u32
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(PixelFormat::Rgb48.frame_size(30_000, 30_000), 5_400_000_000);
+    }
+
+    #[test]
+    fn odd_size() -> Result<()> {
+        for pixel_format in [PixelFormat::Rgb24, PixelFormat::Gray8, PixelFormat::Rgb48] {
+            let frames = Hypetrigger::new()
+                .test_input()
+                .set_scale(63, 35)
+                .set_pixel_format(pixel_format)
+                .run_to_completion(|frame| Some(frame.image.dimensions()))?;
+            assert!(!frames.is_empty());
+            assert!(frames.iter().all(|frame| frame.value == (63, 35)));
+        }
+        Ok(())
+    }
+
+    /// Fails on every frame after the first
+    struct FailingTrigger;
+    impl Trigger for FailingTrigger {
+        fn on_frame(&self, frame: &Frame) -> Result<()> {
+            match frame.frame_num {
+                0 => Ok(()),
+                _ => Err(Error::from_display("runner thread died")),
+            }
+        }
+    }
+
+    #[test]
+    fn trigger_error_policy() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(FailingTrigger);
+        assert!(hypetrigger.run().is_err()); // aborts by default
+
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_clone = errors.clone();
+        let stats = Arc::new(Mutex::new(None));
+        let stats_clone = stats.clone();
+        let events = hypetrigger
+            .set_trigger_error_policy(TriggerErrorPolicy::Disable)
+            .on_trigger_error(move |trigger_id, _| errors_clone.lock().unwrap().push(trigger_id))
+            .on_complete(move |completion| *stats_clone.lock().unwrap() = Some(completion))
+            .run_to_completion(|frame| Some(frame.frame_num))?;
+        assert_eq!(*errors.lock()?, vec![0]);
+        assert!(events.len() > 2);
+        let stats = stats.lock()?.clone().ok_or(NoneError)?;
+        assert_eq!(stats.trigger_frames[0], 2);
+        Ok(())
+    }
+
+    /// Asserts that every frame it receives is RGBA
+    struct RgbaTrigger;
+    impl Trigger for RgbaTrigger {
+        fn on_frame(&self, frame: &Frame) -> Result<()> {
+            assert!(frame.image.as_rgba8().is_some());
+            Ok(())
+        }
+
+        fn get_pixel_format(&self) -> Option<PixelFormat> {
+            Some(PixelFormat::Rgba)
+        }
+    }
+
+    #[test]
+    fn mixed_pixel_formats() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(SimpleTrigger::new(|frame| {
+                assert!(frame.image.as_luma8().is_some());
+            }))
+            .add_trigger(RgbaTrigger);
+        assert_eq!(hypetrigger.output_pixel_format(), PixelFormat::Rgba);
+        hypetrigger.run().map_err(Error::from_display)
+    }
+
+    #[test]
+    fn merge_pixel_formats() {
+        // Neither the bit depth nor the channels of either format are lost
+        assert_eq!(
+            PixelFormat::Gray16.merge(PixelFormat::Rgb24),
+            PixelFormat::Rgb48
+        );
+        assert_eq!(
+            PixelFormat::Rgba.merge(PixelFormat::Rgb48),
+            PixelFormat::Rgba64
+        );
+        assert_eq!(
+            PixelFormat::Rgb24.merge(PixelFormat::Gray8),
+            PixelFormat::Rgb24
+        );
+        assert!(PixelFormat::Rgb48.holds(PixelFormat::Gray16));
+        assert!(!PixelFormat::Rgba.holds(PixelFormat::Rgb48));
+
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_pixel_format(PixelFormat::Gray16)
+            .add_trigger(RgbaTrigger);
+        assert_eq!(hypetrigger.output_pixel_format(), PixelFormat::Rgba64);
+    }
+
+    #[test]
+    fn reused_sample_buffer() -> Result<()> {
+        let mut buffer = RawFrameBuffer {
+            bytes: vec![0x34, 0x12, 0x78, 0x56],
+            samples: vec![],
+        };
+        let image = buffer
+            .take_image(PixelFormat::Gray16, 2, 1)
+            .ok_or(NoneError)?;
+        assert_eq!(
+            image.as_luma16().ok_or(NoneError)?.as_raw(),
+            &vec![0x1234, 0x5678]
+        );
+        let samples = image.as_luma16().ok_or(NoneError)?.as_ptr();
+        buffer.put_back(image);
+
+        // The next frame is converted into the same samples, and the bytes
+        // were never given up
+        assert_eq!(buffer.bytes.len(), 4);
+        let image = buffer
+            .take_image(PixelFormat::Gray16, 2, 1)
+            .ok_or(NoneError)?;
+        assert_eq!(image.as_luma16().ok_or(NoneError)?.as_ptr(), samples);
+        Ok(())
+    }
+
+    /// Records the frames it receives, through a color filter for red
+    struct RedTrigger(Arc<Mutex<Vec<DynamicImage>>>);
+    impl Trigger for RedTrigger {
+        fn on_frame(&self, frame: &Frame) -> Result<()> {
+            self.0.lock()?.push(frame.image.clone());
+            Ok(())
+        }
+
+        fn get_color_filter(&self) -> Option<ColorFilter> {
+            Some(ColorFilter::new([255, 0, 0], 20))
+        }
+    }
+
+    #[test]
+    fn color_filter() -> Result<()> {
+        let received = Arc::new(Mutex::new(vec![]));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.add_trigger(RedTrigger(received.clone()));
+
+        let mut image = RgbImage::from_pixel(2, 1, Rgb([0, 0, 255]));
+        image.put_pixel(0, 0, Rgb([255, 0, 0]));
+        hypetrigger.dispatch_frame(&hypetrigger.frame(DynamicImage::ImageRgb8(image), 0, 0))?;
+
+        // Still an RGB frame of the same size, but masked
+        let received = received.lock()?;
+        let mask = received[0].as_rgb8().ok_or(NoneError)?;
+        assert_eq!(mask.as_raw(), &vec![255, 255, 255, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn invert() -> Result<()> {
+        struct InvertedTrigger(
+            Arc<Mutex<Vec<DynamicImage>>>,
+            Option<ColorFilter>,
+            Option<(u32, u32, u32, u32)>,
+        );
+        impl Trigger for InvertedTrigger {
+            fn on_frame(&self, frame: &Frame) -> Result<()> {
+                self.0.lock()?.push(frame.image.clone());
+                Ok(())
+            }
+            fn get_color_filter(&self) -> Option<ColorFilter> {
+                self.1
+            }
+            fn get_invert(&self) -> bool {
+                true
+            }
+            fn get_region(&self, _width: u32, _height: u32) -> Option<(u32, u32, u32, u32)> {
+                self.2
+            }
+        }
+
+        let inverted = Arc::new(Mutex::new(vec![]));
+        let masked = Arc::new(Mutex::new(vec![]));
+        let cropped = Arc::new(Mutex::new(vec![]));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .add_trigger(InvertedTrigger(inverted.clone(), None, None))
+            .add_trigger(InvertedTrigger(
+                masked.clone(),
+                Some(ColorFilter::new([255, 0, 0], 20)),
+                None,
+            ))
+            .add_trigger(InvertedTrigger(cropped.clone(), None, Some((1, 0, 2, 1))));
+
+        let mut image = RgbImage::from_pixel(2, 1, Rgb([0, 0, 255]));
+        image.put_pixel(0, 0, Rgb([255, 0, 0]));
+        hypetrigger.dispatch_frame(&hypetrigger.frame(DynamicImage::ImageRgb8(image), 0, 0))?;
+
+        let inverted = inverted.lock()?;
+        let inverted = inverted[0].as_rgb8().ok_or(NoneError)?;
+        assert_eq!(inverted.as_raw(), &vec![0, 255, 255, 255, 255, 0]);
+
+        // The mask is flipped: the red pixel is black and the rest is white
+        let masked = masked.lock()?;
+        let masked = masked[0].as_rgb8().ok_or(NoneError)?;
+        assert_eq!(masked.as_raw(), &vec![0, 0, 0, 255, 255, 255]);
+
+        // Only the region is inverted
+        let cropped = cropped.lock()?;
+        let cropped = cropped[0].as_rgb8().ok_or(NoneError)?;
+        assert_eq!(cropped.as_raw(), &vec![255, 0, 0, 255, 255, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn transpose() -> Result<()> {
+        struct SidewaysTrigger(Arc<Mutex<Vec<DynamicImage>>>, Transpose);
+        impl Trigger for SidewaysTrigger {
+            fn on_frame(&self, frame: &Frame) -> Result<()> {
+                self.0.lock()?.push(frame.image.clone());
+                Ok(())
+            }
+            fn get_transpose(&self) -> Option<Transpose> {
+                Some(self.1)
+            }
+        }
+
+        let rotated = Arc::new(Mutex::new(vec![]));
+        let flipped = Arc::new(Mutex::new(vec![]));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .add_trigger(SidewaysTrigger(rotated.clone(), Transpose::Rotate90))
+            .add_trigger(SidewaysTrigger(flipped.clone(), Transpose::FlipH));
+
+        // 3x2, with the top left pixel marked
+        let mut image = RgbImage::from_pixel(3, 2, Rgb([0, 0, 0]));
+        image.put_pixel(0, 0, Rgb([255, 0, 0]));
+        hypetrigger.dispatch_frame(&hypetrigger.frame(DynamicImage::ImageRgb8(image), 0, 0))?;
+
+        // Rotated clockwise, the top left corner ends up at the top right
+        let rotated = rotated.lock()?;
+        let rotated = rotated[0].as_rgb8().ok_or(NoneError)?;
+        assert_eq!(rotated.dimensions(), (2, 3));
+        assert_eq!(rotated.get_pixel(1, 0).0, [255, 0, 0]);
+        let flipped = flipped.lock()?;
+        let flipped = flipped[0].as_rgb8().ok_or(NoneError)?;
+        assert_eq!(flipped.dimensions(), (3, 2));
+        assert_eq!(flipped.get_pixel(2, 0).0, [255, 0, 0]);
+
+        // The marked pixel maps back to the top left of the original
+        assert_eq!(
+            Transpose::Rotate90.unmap_region((1, 0, 2, 1), 3, 2),
+            (0, 0, 1, 1)
+        );
+        assert_eq!(
+            Transpose::Rotate270.unmap_region((0, 2, 1, 3), 3, 2),
+            (0, 0, 1, 1)
+        );
+        assert_eq!(
+            Transpose::Rotate180.unmap_region((2, 1, 3, 2), 3, 2),
+            (0, 0, 1, 1)
+        );
+
+        // A region hanging off the edge is clamped rather than overflowing
+        assert_eq!(
+            Transpose::Rotate90.unmap_region((1, 0, 10, 10), 3, 2),
+            (0, 0, 3, 1)
+        );
+        assert_eq!(
+            Transpose::FlipV.unmap_region((4, 5, 6, 7), 3, 2),
+            (3, 0, 3, 0)
+        );
+
+        // Cropping first gives the same region as transposing the whole frame
+        let image =
+            DynamicImage::ImageRgb8(RgbImage::from_fn(5, 3, |x, y| Rgb([x as u8, y as u8, 0])));
+        for transpose in [
+            Transpose::Rotate90,
+            Transpose::Rotate180,
+            Transpose::Rotate270,
+            Transpose::FlipH,
+            Transpose::FlipV,
+        ] {
+            let region = (1, 1, 3, 2);
+            let expected = transpose.apply(&image).crop_imm(1, 1, 2, 1);
+            assert_eq!(transpose.apply_to_region(&image, region), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn trigger_scale() -> Result<()> {
+        struct ThumbnailTrigger(Arc<Mutex<Vec<(u32, u32)>>>);
+        impl Trigger for ThumbnailTrigger {
+            fn on_frame(&self, frame: &Frame) -> Result<()> {
+                self.0.lock()?.push(frame.image.dimensions());
+                Ok(())
+            }
+            fn get_scale(&self) -> Option<(u32, u32)> {
+                Some((16, 9))
+            }
+            fn get_transpose(&self) -> Option<Transpose> {
+                Some(Transpose::Rotate90)
+            }
+        }
+
+        let sizes = Arc::new(Mutex::new(vec![]));
+        let full_size = Arc::new(Mutex::new(vec![]));
+        let full_size_clone = full_size.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(ThumbnailTrigger(sizes.clone()))
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                full_size_clone
+                    .lock()
+                    .unwrap()
+                    .push(frame.image.dimensions());
+            }))
+            .run()
+            .map_err(Error::from_display)?;
+
+        // Resized, then rotated, without affecting the other Trigger
+        assert_eq!(sizes.lock()?[0], (9, 16));
+        assert_eq!(full_size.lock()?[0], (64, 36));
+        let thumbnail = ThumbnailTrigger(sizes.clone());
+        assert_eq!(trigger_frame_size(&thumbnail, 64, 36), (9, 16));
+
+        // Frames aren't upscaled past the pipeline's own `scale`
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_scale(12, 9)
+            .add_trigger(thumbnail);
+        assert!(hypetrigger.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn on_complete() -> Result<()> {
+        struct HalfRateTrigger;
+        impl Trigger for HalfRateTrigger {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn get_fps(&self) -> Option<f64> {
+                Some(1.0)
+            }
+        }
+
+        let stats = Arc::new(Mutex::new(None));
+        let stats_clone = stats.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .add_trigger(HalfRateTrigger)
+            .on_complete(move |stats| {
+                *stats_clone.lock().unwrap() = Some(stats);
+            })
+            .run()
+            .map_err(Error::from_display)?;
+
+        let stats = stats.lock()?.take().ok_or(NoneError)?;
+        assert!(stats.frames_read > 0);
+        assert_eq!(stats.trigger_frames[0], stats.frames_read);
+        assert_eq!(stats.trigger_frames[1], stats.frames_read.div_ceil(2));
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<()> {
+        let last_progress = Arc::new(Mutex::new(None));
+        let last_progress_clone = last_progress.clone();
+        Hypetrigger::new()
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .on_progress(move |progress| {
+                *last_progress_clone.lock().unwrap() = Some(progress);
+            })
+            .run()
+            .map_err(Error::from_display)?;
+
+        let last_progress = last_progress.lock()?.ok_or(NoneError)?;
+        assert_eq!(last_progress.total, Some(Duration::from_secs(10)));
+        assert!(last_progress.current > Duration::ZERO);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_fps(4)
+            .set_pixel_format(PixelFormat::Gray8)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .add_trigger(PixelProbeTrigger::new(3, 4, |_, _| {}).set_fps(1.0));
+
+        let json = serde_json::to_string(&hypetrigger).unwrap();
+        let loaded: Hypetrigger = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.input, hypetrigger.input);
+        assert_eq!(loaded.fps, 4);
+        assert_eq!(loaded.pixel_format, PixelFormat::Gray8);
+
+        // Only the built-in Trigger is saved, and it's built again
+        assert_eq!(loaded.triggers.len(), 1);
+        assert_eq!(
+            loaded.triggers[0].to_config(),
+            hypetrigger.triggers[1].to_config()
+        );
+        assert!(loaded.validate().is_ok());
+
+        // Missing fields fall back to their defaults
+        let loaded: Hypetrigger = serde_json::from_str(r#"{ "input": "in.mp4" }"#).unwrap();
+        assert_eq!(loaded.input, PathBuf::from("in.mp4"));
+        assert_eq!(loaded.fps, 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_wrapped() {
+        use crate::{async_trigger::TriggerThread, motion_gate::MotionGateTrigger};
+
+        // Wrapped Triggers are saved as the Trigger they wrap
+        let probe = || PixelProbeTrigger::new(3, 4, |_, _| {}).set_fps(1.0);
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(probe().into_async(TriggerThread::spawn()))
+            .add_trigger(MotionGateTrigger::new(probe(), 5.0));
+
+        let json = serde_json::to_string(&hypetrigger).unwrap();
+        let loaded: Hypetrigger = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.triggers.len(), 2);
+        for trigger in &loaded.triggers {
+            assert_eq!(trigger.to_config(), probe().to_config());
+        }
+    }
+
+    #[test]
+    fn multiple_inputs() -> Result<()> {
+        let frames = Arc::new(Mutex::new(vec![]));
+        let frames_clone = frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_input(super::FFMPEG_TEST_INPUT.to_string())
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                assert_eq!(frame.image.dimensions(), (64, 36));
+                let frame_id = (frame.frame_num, frame.input_index);
+                frames_clone.lock().unwrap().push(frame_id);
+            }));
+        assert_eq!(
+            hypetrigger.filter_complex()?,
+            "[0:v]fps=2,scale=64:36[in0];[1:v]fps=2,scale=64:36[in1];[in0][in1]hstack=inputs=2[out]"
+        );
+        hypetrigger.run().map_err(Error::from_display)?;
+
+        let frames = frames.lock()?;
+        assert!(!frames.is_empty());
+        assert_eq!(frames[0], (0, 0));
+        assert_eq!(frames[1], (0, 1));
+        assert_eq!(frames[2], (1, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_inputs_require_scale() {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.test_input().add_input("other.mp4".to_string());
+        assert!(hypetrigger.filter_complex().is_err());
+    }
+
+    #[test]
+    fn start_time() -> Result<()> {
+        let first_timestamp = Arc::new(Mutex::new(None));
+        let first_timestamp_clone = first_timestamp.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_start_time(Duration::from_secs(5))
+            .set_duration(Duration::from_secs(2))
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                let mut first_timestamp = first_timestamp_clone.lock().unwrap();
+                if frame.frame_num == 0 {
+                    *first_timestamp = Some(frame.timestamp);
+                }
+            }))
+            .run()
+            .map_err(Error::from_display)?;
+
+        assert_eq!(*first_timestamp.lock()?, Some(5.0));
+        Ok(())
+    }
+
+    #[test]
+    fn frame_order() -> Result<()> {
+        let received = Arc::new(Mutex::new(vec![]));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.test_input().set_scale(64, 36);
+        for trigger_id in 0..3 {
+            let received = received.clone();
+            hypetrigger.add_trigger(SimpleTrigger::new(move |frame| {
+                received.lock().unwrap().push((frame.frame_num, trigger_id));
+            }));
+        }
+        hypetrigger.run().map_err(Error::from_display)?;
+
+        let received = received.lock()?;
+        let expected: Vec<(u64, i32)> = (0..received.len() as u64 / 3)
+            .flat_map(|frame_num| (0..3).map(move |trigger_id| (frame_num, trigger_id)))
+            .collect();
+        assert!(!received.is_empty());
+        assert_eq!(*received, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn read_frame() -> Result<()> {
+        let mut output = std::io::Cursor::new(vec![1_u8; 10]);
+        let mut buffer = vec![0_u8; 4];
+        assert_eq!(super::read_frame(&mut output, &mut buffer)?, 4);
+        assert_eq!(super::read_frame(&mut output, &mut buffer)?, 4);
+        assert_eq!(super::read_frame(&mut output, &mut buffer)?, 2); // truncated
+        assert_eq!(super::read_frame(&mut output, &mut buffer)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn skipped_frames_keep_frame_num() -> Result<()> {
+        struct HalfRateTrigger(Arc<Mutex<Vec<(u64, Duration)>>>);
+        impl Trigger for HalfRateTrigger {
+            fn on_frame(&self, frame: &Frame) -> Result<()> {
+                self.0.lock()?.push((frame.frame_num, frame.time()));
+                Ok(())
+            }
+            fn get_fps(&self) -> Option<f64> {
+                Some(1.0)
+            }
+        }
+
+        let received = Arc::new(Mutex::new(vec![]));
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(HalfRateTrigger(received.clone()))
+            .run()
+            .map_err(Error::from_display)?;
+
+        let received = received.lock()?;
+        assert_eq!(received[0], (0, Duration::ZERO));
+        assert_eq!(received[1], (2, Duration::from_secs(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn push_frame() -> Result<()> {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.add_trigger(SimpleTrigger::new(move |frame| {
+            let frame_id = (frame.frame_num, frame.image.dimensions());
+            received_clone.lock().unwrap().push(frame_id);
+        }));
+
+        hypetrigger.push_frame(vec![0; 4 * 2 * 3], 4, 2, 0)?;
+        assert!(hypetrigger.push_frame(vec![0; 4 * 2], 4, 2, 1).is_err());
+        assert_eq!(*received.lock()?, vec![(0, (4, 2))]);
+        Ok(())
+    }
+
+    #[test]
+    fn trigger_fps() {
+        let sampled: Vec<u64> = (0..10).filter(|&n| is_sampled(n, 4.0, 1.0)).collect();
+        assert_eq!(sampled, vec![0, 4, 8]);
+
+        let sampled: Vec<u64> = (0..5).filter(|&n| is_sampled(n, 4.0, 4.0)).collect();
+        assert_eq!(sampled, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn scale() -> Result<()> {
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(224, 224)
+            .add_trigger(SimpleTrigger::new(|frame| {
+                assert_eq!(frame.image.dimensions(), (224, 224));
+            }))
+            .run()
+            .map_err(Error::from_display)?;
+
+        // Down to a single pixel, but no smaller
+        let frames = Arc::new(Mutex::new(vec![]));
+        let frames_clone = frames.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(1, 1)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                let bytes = frame.image.as_bytes().to_vec();
+                frames_clone.lock().unwrap().push(bytes);
+            }))
+            .run()
+            .map_err(Error::from_display)?;
+        let frames = frames.lock()?;
+        assert_eq!(frames.len(), 20);
+        assert_eq!(frames[19], [19, 19, 19]);
+        let mut empty = Hypetrigger::new();
+        empty
+            .test_input()
+            .set_scale(0, 36)
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(empty.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn create_output_dirs() -> Result<()> {
+        struct Screenshots(PathBuf);
+        impl Trigger for Screenshots {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn get_output_dirs(&self) -> Vec<PathBuf> {
+                vec![self.0.clone()]
+            }
+        }
+
+        let dir = TempDir::new("dirs")?;
+        std::fs::remove_dir(&dir.path)?;
+        let screenshots_dir = dir.path.join("screenshots").join("ocr");
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_max_frames(1)
+            .set_create_output_dirs(false)
+            .add_trigger(Screenshots(screenshots_dir.clone()));
+        let error = hypetrigger.run().expect_err("the directory is missing");
+        assert!(error.to_string().contains("doesn't exist"));
+
+        hypetrigger.set_create_output_dirs(true);
+        hypetrigger.run().map_err(Error::from_display)?;
+        assert!(screenshots_dir.is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn max_frames() -> Result<()> {
+        let frames = Arc::new(Mutex::new(vec![]));
+        let frames_clone = frames.clone();
+        let stats = Arc::new(Mutex::new(None));
+        let stats_clone = stats.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_max_frames(5)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                frames_clone.lock().unwrap().push(frame.frame_num);
+            }))
+            .on_complete(move |completion| *stats_clone.lock().unwrap() = Some(completion))
+            .run()
+            .map_err(Error::from_display)?;
+        assert_eq!(*frames.lock()?, vec![0, 1, 2, 3, 4]);
+        assert_eq!(stats.lock()?.as_ref().map(|s| s.frames_read), Some(5));
+
+        let mut none = Hypetrigger::new();
+        none.test_input()
+            .set_max_frames(0)
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        assert!(none.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stdout_buffer_size() -> Result<()> {
+        let frames = Arc::new(Mutex::new(vec![]));
+        let frames_clone = frames.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(4, 2)
+            .set_stdout_buffer_size(64 * 1024)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                let first_byte = frame.image.as_bytes()[0];
+                frames_clone.lock().unwrap().push(first_byte);
+            }))
+            .run()
+            .map_err(Error::from_display)?;
+        assert_eq!(*frames.lock()?, (0..20).collect::<Vec<u8>>());
+        Ok(())
+    }
+
+    #[test]
+    fn ffmpeg_error() {
+        let reported = Arc::new(Mutex::new(None));
+        let reported_clone = reported.clone();
+        let result = Hypetrigger::new()
+            .set_input("does/not/exist.mp4".to_string())
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .on_ffmpeg_error(move |failure| {
+                *reported_clone.lock().unwrap() = Some(failure.stderr_tail.clone());
+            })
+            .run();
+
+        let error = result.expect_err("missing input should fail");
+        assert!(error.to_string().contains("No such file or directory"));
+        let stderr_tail = reported
+            .lock()
+            .unwrap()
+            .take()
+            .expect("callback was called");
+        assert!(stderr_tail
+            .iter()
+            .any(|line| line.contains("does/not/exist.mp4")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stdout_read_timeout() -> Result<()> {
+        use std::time::Instant;
+
+        // Stand-in for ffmpeg that describes its output, but then stalls
+        let ffmpeg = fake_ffmpeg(
+            "stall",
+            "#!/bin/sh\n\
+             echo \"Output #0, rawvideo, to 'pipe:1':\" >&2\n\
+             echo \"  Stream #0:0: Video: rawvideo, rgb24, 64x36, 2 fps\" >&2\n\
+             exec sleep 30\n",
+        )?;
+
+        let started = Instant::now();
+        let result = Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .set_stdout_read_timeout(Duration::from_millis(500))
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .run();
+        let error = result.expect_err("stalled ffmpeg should time out");
+        assert!(error.to_string().contains("killed"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn slow_trigger_read_timeout() -> Result<()> {
+        // Each frame takes longer to run than the timeout, while ffmpeg has
+        // the next one ready
+        let frames = Arc::new(Mutex::new(0));
+        let frames_clone = frames.clone();
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .set_max_frames(3)
+            .set_stdout_read_timeout(Duration::from_millis(250))
+            .add_trigger(SimpleTrigger::new(move |_| {
+                std::thread::sleep(Duration::from_millis(500));
+                *frames_clone.lock().unwrap() += 1;
+            }))
+            .run()?;
+        assert_eq!(*frames.lock()?, 3);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ffmpeg_drop_dup() -> Result<()> {
+        // Wrap ffmpeg with a script that reports some dropped frames at the end
+        let ffmpeg = fake_ffmpeg(
+            "dropdup",
+            "#!/bin/sh
+ffmpeg \"$@\"
+echo \"frame=   20 fps=0.0 q=-0.0 size=   54000kB time=00:00:10.00 bitrate=44236.8kbits/s dup=1 drop=4 speed=29.6x\" >&2
+",
+        )?;
+
+        let stats = Arc::new(Mutex::new(None));
+        let stats_clone = stats.clone();
+        let progress = Arc::new(Mutex::new(None));
+        let progress_clone = progress.clone();
+        Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .on_progress(move |p| *progress_clone.lock().unwrap() = Some(p))
+            .on_complete(move |s| *stats_clone.lock().unwrap() = Some(s))
+            .run()?;
+        let stats = stats.lock()?.take().ok_or(NoneError)?;
+        assert_eq!((stats.ffmpeg_dropped, stats.ffmpeg_duplicated), (4, 1));
+        let progress = progress.lock()?.take().ok_or(NoneError)?;
+        assert_eq!(
+            (progress.dropped_frames, progress.duplicated_frames),
+            (4, 1)
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn partial_frame() -> Result<()> {
+        // Wrap ffmpeg with a script that flushes a few extra bytes at the end
+        let ffmpeg = fake_ffmpeg(
+            "partial",
+            "#!/bin/sh
+ffmpeg \"$@\"
+printf abc
+",
+        )?;
+
+        let run = |dispatch_partial_frame| {
+            let frames = Arc::new(Mutex::new(vec![]));
+            let frames_clone = frames.clone();
+            Hypetrigger::new()
+                .set_ffmpeg_exe(&ffmpeg.exe)
+                .test_input()
+                .set_scale(64, 36)
+                .set_dispatch_partial_frame(dispatch_partial_frame)
+                .add_trigger(SimpleTrigger::new(move |frame| {
+                    let pixels = frame.rgb();
+                    let first = pixels.get_pixel(0, 0).0;
+                    let last = pixels.get_pixel(63, 35).0;
+                    frames_clone.lock().unwrap().push((first, last));
+                }))
+                .run()
+                .map(|_| frames.lock().unwrap().clone())
+        };
+        let (dropped, dispatched) = (run(false)?, run(true)?);
+        assert_eq!(dispatched.len(), dropped.len() + 1);
+        assert_eq!(dispatched[..dropped.len()], dropped[..]);
+        assert_eq!(*dispatched.last().ok_or(NoneError)?, (*b"abc", [0, 0, 0]));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn live_reconnect() -> Result<()> {
+        // Wrap ffmpeg with a script that counts how many times it's started,
+        // each one sending the 20 frames of the test input before exiting
+        let ffmpeg = fake_ffmpeg(
+            "live",
+            "#!/bin/sh
+echo >> \"$(dirname \"$0\")/spawns\"
+exec ffmpeg \"$@\"
+",
+        )?;
+
+        let frame_nums = Arc::new(Mutex::new(vec![]));
+        let frame_nums_clone = frame_nums.clone();
+        let stats = Arc::new(Mutex::new(vec![]));
+        let stats_clone = stats.clone();
+        Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .set_scale(64, 36)
+            .set_live(true)
+            .set_max_frames(50)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                frame_nums_clone.lock().unwrap().push(frame.frame_num);
+            }))
+            .on_complete(move |s| stats_clone.lock().unwrap().push(s.frames_read))
+            .run()?;
+
+        // Reading carries on across the restarts, until `max_frames`
+        assert_eq!(*frame_nums.lock()?, (0..50).collect::<Vec<_>>());
+        assert_eq!(*stats.lock()?, [50]);
+        let spawns = std::fs::read_to_string(ffmpeg.exe.with_file_name("spawns"))?;
+        assert_eq!(spawns.lines().count(), 3);
+        Ok(())
+    }
 }
@@ -0,0 +1,183 @@
+// Watches a single pixel (e.g. a status LED, or a "live" indicator) without
+// any of the per-frame image processing a full Trigger would do.
+
+use crate::error::{Error, Result};
+use crate::pipeline::PixelFormat;
+use crate::trigger::{Frame, Trigger, TriggerPayload};
+use crate::trigger_config::TriggerConfig;
+use image::GenericImageView;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+pub type PixelProbeCallback = Arc<dyn Fn(&Frame, [u8; 3]) + Send + Sync>;
+
+/// A Trigger that reads the color of a single pixel, or the average of a small
+/// region, and passes it to a callback as `[r, g, b]`. It's also reported as a
+/// `TriggerPayload::Color` event.
+///
+/// When it's the only Trigger, most of the frame can be cropped away by ffmpeg
+/// so only a few bytes are read per frame, e.g. with
+/// `Hypetrigger::set_global_filter("crop=1:1:100:50")` and a probe at `(0, 0)`.
+pub struct PixelProbeTrigger {
+    pub callback: PixelProbeCallback,
+
+    /// Position of the top left pixel to read, in pixels
+    pub x: u32,
+    pub y: u32,
+
+    /// Size of the region to average, `(1, 1)` by default
+    pub size: (u32, u32),
+
+    /// Optionally run at a lower framerate than the rest of the pipeline
+    pub fps: Option<f64>,
+}
+
+impl Trigger for PixelProbeTrigger {
+    fn on_frame(&self, frame: &Frame) -> Result<()> {
+        self.on_frame_events(frame, &mut vec![]).map(|_| ())
+    }
+
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
+        let color = self.color(frame)?;
+        (self.callback)(frame, color);
+        payloads.push(TriggerPayload::Color(color));
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn get_fps(&self) -> Option<f64> {
+        self.fps
+    }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        Some(PixelFormat::Rgb24)
+    }
+
+    fn get_region(&self, _width: u32, _height: u32) -> Option<(u32, u32, u32, u32)> {
+        let (width, height) = self.size;
+        Some((self.x, self.y, self.x + width, self.y + height))
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.size {
+            (0, _) | (_, 0) => Err(Error::from_display(
+                "the region to probe needs a nonzero width and height",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn to_config(&self) -> Option<TriggerConfig> {
+        Some(TriggerConfig::PixelProbe {
+            x: self.x,
+            y: self.y,
+            size: self.size,
+            fps: self.fps,
+        })
+    }
+}
+
+impl PixelProbeTrigger {
+    pub fn new<F>(x: u32, y: u32, callback: F) -> Self
+    where
+        F: Fn(&Frame, [u8; 3]) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+            x,
+            y,
+            size: (1, 1),
+            fps: None,
+        }
+    }
+
+    /// Setter for the size of the region to average
+    pub fn set_size(mut self, width: u32, height: u32) -> Self {
+        self.size = (width, height);
+        self
+    }
+
+    /// Setter for the framerate to run at
+    pub fn set_fps(mut self, fps: f64) -> Self {
+        self.fps = Some(fps);
+        self
+    }
+
+    /// The average color of the region in a frame.
+    fn color(&self, frame: &Frame) -> Result<[u8; 3]> {
+        let (width, height) = self.size;
+        let (frame_width, frame_height) = frame.image.dimensions();
+        if self.x + width > frame_width || self.y + height > frame_height {
+            return Err(Error::from_display(format!(
+                "the probed region at ({}, {}) doesn't fit in the {}x{} frame",
+                self.x, self.y, frame_width, frame_height
+            )));
+        }
+        let mut totals = [0_u64; 3];
+        for y in self.y..self.y + height {
+            for x in self.x..self.x + width {
+                let pixel = frame.image.get_pixel(x, y).0;
+                for (total, channel) in totals.iter_mut().zip(pixel) {
+                    *total += channel as u64;
+                }
+            }
+        }
+        let num_pixels = width as u64 * height as u64;
+        Ok(totals.map(|total| ((total + num_pixels / 2) / num_pixels) as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PixelProbeTrigger;
+    use crate::{
+        error::Result,
+        frame_source::MockFrameSource,
+        pipeline::{Hypetrigger, PixelFormat},
+        trigger::TriggerPayload,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn pixel_probe() -> Result<()> {
+        // A single pixel frame, as if ffmpeg cropped everything else away
+        let mut source = MockFrameSource::new(1, 1, PixelFormat::Rgb24);
+        source.push_bytes(&[255, 0, 0]).push_bytes(&[0, 0, 0]);
+        let colors = Arc::new(Mutex::new(vec![]));
+        let colors_clone = colors.clone();
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_clone = events.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .add_trigger(PixelProbeTrigger::new(0, 0, move |frame, color| {
+                colors_clone.lock().unwrap().push((frame.frame_num, color));
+            }))
+            .on_trigger_event(move |event| events_clone.lock().unwrap().push(event.clone()));
+        let summary = hypetrigger.attach_source(source)?;
+        assert_eq!(summary.frames_read, 2);
+        assert_eq!(*colors.lock()?, vec![(0, [255, 0, 0]), (1, [0, 0, 0])]);
+        assert!(matches!(
+            events.lock()?[0].payload,
+            TriggerPayload::Color([255, 0, 0])
+        ));
+
+        // Averaging a small region of a bigger frame
+        let mut source = MockFrameSource::new(3, 2, PixelFormat::Rgb24);
+        source.push_pattern(|x, _| vec![x as u8 * 100, 10, 0]);
+        let colors = Arc::new(Mutex::new(vec![]));
+        let colors_clone = colors.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.add_trigger(
+            PixelProbeTrigger::new(1, 0, move |_, color| {
+                colors_clone.lock().unwrap().push(color);
+            })
+            .set_size(2, 2),
+        );
+        hypetrigger.attach_source(source)?;
+        assert_eq!(*colors.lock()?, vec![[150, 10, 0]]);
+        Ok(())
+    }
+}
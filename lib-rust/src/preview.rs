@@ -0,0 +1,269 @@
+// One-off snapshots of the input for setting up Triggers, e.g. in a config UI,
+// or for running them on a few chosen frames, separate from the processing
+// pipeline itself.
+
+use crate::error::{Error, Result};
+use crate::pipeline::{Hypetrigger, PixelFormat};
+use crate::simple_trigger::SimpleTrigger;
+use crate::trigger::{Frame, Trigger};
+use image::{DynamicImage, Rgb, RgbImage};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The outline colors for the regions of each Trigger in `Hypetrigger::preview`,
+/// in the order they were added. They repeat for more Triggers than colors.
+pub const PREVIEW_COLORS: [Rgb<u8>; 6] = [
+    Rgb([255, 0, 0]),
+    Rgb([0, 255, 0]),
+    Rgb([0, 128, 255]),
+    Rgb([255, 255, 0]),
+    Rgb([255, 0, 255]),
+    Rgb([0, 255, 255]),
+];
+
+impl Hypetrigger {
+    /// Grab the frame at the given time of the (main) input, exactly as the
+    /// Triggers would receive it (after `scale`), by running ffmpeg just for
+    /// that frame. The Triggers themselves aren't run.
+    pub fn grab_frame(&self, time: Duration) -> Result<RgbImage> {
+        let image = self.grab_image(time, PixelFormat::Rgb24)?;
+        Ok(image.to_rgb8())
+    }
+
+    /// Run the Triggers on just the frames at the given times of the (main)
+    /// input, e.g. from an index of chapter markers, instead of sampling the
+    /// whole input at `fps`. Each frame is found with a fast seek by a
+    /// separate ffmpeg process, so this is much quicker than sampling
+    /// everything when the times are few and far apart.
+    ///
+    /// Each `Frame` has the exact time it was asked for as its timestamp, and
+    /// its index in `timestamps` as its frame number (which Triggers with a
+    /// lower `get_fps()` sample by, as usual). Returns an error for a time
+    /// past the end of the input, after running the Triggers on the frames
+    /// before it.
+    pub fn run_at_timestamps(&self, timestamps: &[Duration]) -> Result<()> {
+        self.validate()?;
+        let pixel_format = self.output_pixel_format();
+        for (frame_num, &time) in timestamps.iter().enumerate() {
+            log_debug!("[hypetrigger] Grabbing the frame at {:?}", time);
+            let image = self.grab_image(time, pixel_format)?;
+            self.dispatch_frame(&Frame {
+                image,
+                frame_num: frame_num as u64,
+                timestamp: time.as_secs_f64(),
+                input_index: 0,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Grab the frame at the given time in the given pixel format, for
+    /// `grab_frame` and `run_at_timestamps`.
+    fn grab_image(&self, time: Duration, pixel_format: PixelFormat) -> Result<DynamicImage> {
+        let first_frame = Arc::new(Mutex::new(None));
+        let first_frame_clone = first_frame.clone();
+        let mut hypetrigger = self.clone();
+        hypetrigger.extra_inputs.clear();
+        hypetrigger.triggers.clear();
+        hypetrigger.dump_frames_to = None;
+        hypetrigger.dump_crops_to = None;
+        hypetrigger.on_complete_callback = None;
+        hypetrigger
+            .set_start_time(time)
+            .set_duration(Duration::from_secs_f64(1.0 / self.fps as f64))
+            .set_pixel_format(pixel_format)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                // If it's poisoned, the error comes from the `lock` below instead
+                if let Ok(mut first_frame) = first_frame_clone.lock() {
+                    if first_frame.is_none() {
+                        *first_frame = Some(frame.image.clone());
+                    }
+                }
+            }))
+            .run()?;
+        let image = first_frame.lock()?.take();
+        image.ok_or_else(|| Error::from_display(format!("the input has no frame at {:?}", time)))
+    }
+
+    /// Grab the frame at the given time, and outline the region each Trigger
+    /// looks at (see `Trigger::get_region`), in the colors of
+    /// `PREVIEW_COLORS`, labelled with its id in the top left corner. Handy
+    /// for checking crops before processing a whole video. Disabled Triggers
+    /// aren't drawn, but keep their colors and ids.
+    pub fn preview(&self, time: Duration) -> Result<RgbImage> {
+        let mut image = self.grab_frame(time)?;
+        let (width, height) = image.dimensions();
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            if !trigger.get_enabled() {
+                continue;
+            }
+            if let Some(region) = frame_region(trigger.as_ref(), width, height) {
+                let color = PREVIEW_COLORS[i % PREVIEW_COLORS.len()];
+                draw_outline(&mut image, region, color);
+                draw_label(&mut image, (region.0, region.1), &i.to_string(), color);
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// The region a Trigger looks at (see `Trigger::get_region`), mapped back from
+/// the frames it receives onto a `width` by `height` frame of the pipeline.
+pub fn frame_region(
+    trigger: &dyn Trigger,
+    width: u32,
+    height: u32,
+) -> Option<(u32, u32, u32, u32)> {
+    let (scaled_width, scaled_height) = trigger.get_scale().unwrap_or((width, height));
+    let region = match trigger.get_transpose() {
+        Some(transpose) => {
+            let (t_width, t_height) = transpose.output_size(scaled_width, scaled_height);
+            let region = trigger.get_region(t_width, t_height)?;
+            transpose.unmap_region(region, scaled_width, scaled_height)
+        }
+        None => trigger.get_region(scaled_width, scaled_height)?,
+    };
+    let (x1, y1, x2, y2) = region;
+    let scale_x = |x: u32| (x as u64 * width as u64 / scaled_width.max(1) as u64) as u32;
+    let scale_y = |y: u32| (y as u64 * height as u64 / scaled_height.max(1) as u64) as u32;
+    Some((scale_x(x1), scale_y(y1), scale_x(x2), scale_y(y2)))
+}
+
+/// Draw a 2px outline just inside the region `(x1, y1, x2, y2)`.
+pub fn draw_outline(image: &mut RgbImage, region: (u32, u32, u32, u32), color: Rgb<u8>) {
+    const THICKNESS: u32 = 2;
+    let (x1, y1, x2, y2) = region;
+    let x2 = x2.min(image.width());
+    let y2 = y2.min(image.height());
+    for y in y1..y2 {
+        for x in x1..x2 {
+            let is_edge = x < x1 + THICKNESS
+                || x + THICKNESS >= x2
+                || y < y1 + THICKNESS
+                || y + THICKNESS >= y2;
+            if is_edge {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+/// The digits 0-9 of `draw_label`, 3x5 pixels each, one row per byte with the
+/// leftmost pixel in the highest of the bottom 3 bits.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Draw a number in black on a box of the given color, with its top left
+/// corner at `(x, y)`, clipped to the image. Each pixel of the digits is 2x2,
+/// with a 1px margin; anything but digits is skipped.
+pub fn draw_label(image: &mut RgbImage, (x, y): (u32, u32), text: &str, color: Rgb<u8>) {
+    const SCALE: u32 = 2;
+    let digits: Vec<&[u8; 5]> = text
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|digit| &DIGITS[digit as usize])
+        .collect();
+    let width = (digits.len() as u32 * 4 + 1) * SCALE;
+    let height = 7 * SCALE;
+    for dy in 0..height.min(image.height().saturating_sub(y)) {
+        for dx in 0..width.min(image.width().saturating_sub(x)) {
+            // Position in the unscaled label, less the margin
+            let (lx, ly) = ((dx / SCALE).wrapping_sub(1), (dy / SCALE).wrapping_sub(1));
+            let is_ink = ly < 5
+                && lx % 4 < 3
+                && digits
+                    .get(lx as usize / 4)
+                    .is_some_and(|digit| digit[ly as usize] >> (2 - lx % 4) & 1 == 1);
+            let pixel = if is_ink { Rgb([0, 0, 0]) } else { color };
+            image.put_pixel(x + dx, y + dy, pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{draw_label, PREVIEW_COLORS};
+    use crate::{
+        error::Result,
+        pipeline::Hypetrigger,
+        simple_trigger::SimpleTrigger,
+        trigger::{Frame, Trigger},
+    };
+    use image::{Rgb, RgbImage};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Looks at the top left quarter of the frame
+    struct QuarterTrigger;
+    impl Trigger for QuarterTrigger {
+        fn on_frame(&self, _frame: &Frame) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+            Some((0, 0, width / 2, height / 2))
+        }
+    }
+
+    #[test]
+    fn preview() -> Result<()> {
+        let image = Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(QuarterTrigger)
+            .preview(Duration::from_secs(1))?;
+        assert_eq!(image.dimensions(), (64, 36));
+        assert_eq!(*image.get_pixel(0, 0), PREVIEW_COLORS[0]);
+        assert_eq!(*image.get_pixel(31, 10), PREVIEW_COLORS[0]);
+        assert_ne!(*image.get_pixel(10, 10), PREVIEW_COLORS[0]);
+        // Labelled "0", whose top left pixel is inked
+        assert_eq!(*image.get_pixel(2, 2), Rgb([0, 0, 0]));
+        assert_eq!(*image.get_pixel(1, 1), PREVIEW_COLORS[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn label() {
+        let mut image = RgbImage::new(40, 20);
+        let color = PREVIEW_COLORS[1];
+        draw_label(&mut image, (0, 0), "17", color);
+        // "1" has no ink at its top left, where "7" does
+        assert_eq!(*image.get_pixel(2, 2), color);
+        assert_eq!(*image.get_pixel(4, 2), Rgb([0, 0, 0]));
+        assert_eq!(*image.get_pixel(10, 2), Rgb([0, 0, 0]));
+        assert_eq!(*image.get_pixel(17, 2), color);
+        // Clipped at the edge of the image
+        draw_label(&mut image, (36, 16), "8", color);
+        assert_eq!(*image.get_pixel(37, 17), color);
+    }
+
+    #[test]
+    fn run_at_timestamps() -> Result<()> {
+        let frames = Arc::new(Mutex::new(vec![]));
+        let frames_clone = frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                frames_clone
+                    .lock()
+                    .unwrap()
+                    .push((frame.frame_num, frame.timestamp));
+            }));
+        let timestamps = [1.5, 4.0, 7.25].map(Duration::from_secs_f64);
+        hypetrigger.run_at_timestamps(&timestamps)?;
+        assert_eq!(*frames.lock()?, vec![(0, 1.5), (1, 4.0), (2, 7.25)]);
+        Ok(())
+    }
+}
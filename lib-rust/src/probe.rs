@@ -0,0 +1,274 @@
+// Asks the ffmpeg binary what it was built with before a pipeline relies on it,
+// so that e.g. a build without CUDA is reported up front, rather than as a
+// confusing failure partway through ffmpeg's startup.
+
+use crate::error::FfmpegError;
+use crate::pipeline::{HwAccel, Hypetrigger};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// What an ffmpeg binary reports about itself, from `probe_ffmpeg`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegVersion {
+    /// The version, e.g. `6.0`, or something like `N-111111-gabcdef` for
+    /// builds from git
+    pub version: String,
+
+    /// The options ffmpeg was configured with, e.g. `--enable-cuda`
+    pub configuration: Vec<String>,
+
+    /// The hardware acceleration methods built in (`ffmpeg -hwaccels`), e.g.
+    /// `cuda`
+    pub hwaccels: Vec<String>,
+
+    /// The decoders built in (`ffmpeg -decoders`), e.g. `av1_cuvid`
+    pub decoders: Vec<String>,
+}
+
+impl FfmpegVersion {
+    /// Whether ffmpeg was configured with `--enable-<feature>`. Features that
+    /// ffmpeg enables when it detects them (like most hwaccels) don't show up
+    /// in the configuration; see `supports_hwaccel` for those.
+    pub fn has_feature(&self, feature: &str) -> bool {
+        let flag = format!("--enable-{}", feature);
+        self.configuration.contains(&flag)
+    }
+
+    /// Whether ffmpeg can decode with the given hwaccel. `Auto` and `None`
+    /// always work, since ffmpeg falls back to software decoding.
+    pub fn supports_hwaccel(&self, hwaccel: &HwAccel) -> bool {
+        match hwaccel {
+            HwAccel::Auto | HwAccel::None => true,
+            _ => hwaccel
+                .as_ffmpeg_str()
+                .is_some_and(|name| self.hwaccels.iter().any(|method| method == name)),
+        }
+    }
+
+    /// Whether ffmpeg has a decoder with the given name.
+    pub fn supports_decoder(&self, decoder: &str) -> bool {
+        self.decoders.iter().any(|name| name == decoder)
+    }
+}
+
+/// Run `ffmpeg -version`, `ffmpeg -hwaccels` and `ffmpeg -decoders` to find
+/// out the version of an ffmpeg binary, and what it supports.
+pub fn probe_ffmpeg(ffmpeg_exe: &Path) -> Result<FfmpegVersion, FfmpegError> {
+    let version_output = ffmpeg_stdout(ffmpeg_exe, &["-version"])?;
+    let mut version = parse_ffmpeg_version(&version_output).ok_or_else(|| {
+        FfmpegError::InvalidConfig(format!(
+            "{} didn't report an ffmpeg version",
+            ffmpeg_exe.display()
+        ))
+    })?;
+    let hwaccels_output = ffmpeg_stdout(ffmpeg_exe, &["-hide_banner", "-hwaccels"])?;
+    version.hwaccels = parse_ffmpeg_hwaccels(&hwaccels_output);
+    let decoders_output = ffmpeg_stdout(ffmpeg_exe, &["-hide_banner", "-decoders"])?;
+    version.decoders = parse_ffmpeg_decoders(&decoders_output);
+    Ok(version)
+}
+
+/// Run ffmpeg with the given args, and return what it printed on stdout.
+fn ffmpeg_stdout(ffmpeg_exe: &Path, args: &[&str]) -> Result<String, FfmpegError> {
+    let output = Command::new(ffmpeg_exe)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(FfmpegError::SpawnFailed)?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Parses the output of `ffmpeg -version`, which starts like this:
+///
+/// ```text
+/// ffmpeg version 6.0 Copyright (c) 2000-2023 the FFmpeg developers
+/// built with gcc 12.2.0
+/// configuration: --enable-gpl --enable-libx264 --enable-cuda-llvm
+/// ```
+///
+/// The `hwaccels` and `decoders` are left empty.
+pub fn parse_ffmpeg_version(text: &str) -> Option<FfmpegVersion> {
+    let version = text
+        .lines()
+        .find_map(|line| line.strip_prefix("ffmpeg version "))?
+        .split_whitespace()
+        .next()?
+        .to_string();
+    let configuration = text
+        .lines()
+        .find_map(|line| line.strip_prefix("configuration:"))
+        .map(|options| options.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    Some(FfmpegVersion {
+        version,
+        configuration,
+        hwaccels: vec![],
+        decoders: vec![],
+    })
+}
+
+/// Parses the output of `ffmpeg -hwaccels`: a heading, then one method per
+/// line.
+pub fn parse_ffmpeg_hwaccels(text: &str) -> Vec<String> {
+    text.lines()
+        .skip_while(|line| !line.starts_with("Hardware acceleration methods:"))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parses the output of `ffmpeg -decoders`: a legend of the capability flags,
+/// then a line of dashes, then one decoder per line, like this:
+///
+/// ```text
+///  V....D av1_cuvid            Nvidia CUVID AV1 decoder (codec av1)
+/// ```
+pub fn parse_ffmpeg_decoders(text: &str) -> Vec<String> {
+    text.lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(String::from)
+        .collect()
+}
+
+impl Hypetrigger {
+    /// Probe the ffmpeg binary this pipeline would run.
+    pub fn probe_ffmpeg(&self) -> Result<FfmpegVersion, FfmpegError> {
+        probe_ffmpeg(&self.resolve_ffmpeg_exe()?)
+    }
+
+    /// Check that ffmpeg supports the `hwaccel` and the `video_decoder`, if
+    /// specific ones were asked for, probing it only once. Called
+    /// automatically by `run`, `run_async`, `run_supervised` and
+    /// `run_tokio`; with the defaults, ffmpeg isn't probed at all.
+    pub fn check_ffmpeg_support(&self) -> Result<(), FfmpegError> {
+        if matches!(self.hwaccel, HwAccel::Auto | HwAccel::None) && self.video_decoder.is_none() {
+            return Ok(());
+        }
+        let version = self.probe_ffmpeg()?;
+        self.check_hwaccel_in(&version)?;
+        self.check_video_decoder_in(&version)
+    }
+
+    /// Check that ffmpeg supports the `hwaccel`, if a specific one was asked
+    /// for. With `Auto` (the default) or `None`, ffmpeg isn't probed at all.
+    pub fn check_hwaccel(&self) -> Result<(), FfmpegError> {
+        if matches!(self.hwaccel, HwAccel::Auto | HwAccel::None) {
+            return Ok(());
+        }
+        self.check_hwaccel_in(&self.probe_ffmpeg()?)
+    }
+
+    fn check_hwaccel_in(&self, version: &FfmpegVersion) -> Result<(), FfmpegError> {
+        if version.supports_hwaccel(&self.hwaccel) {
+            return Ok(());
+        }
+        let supported = match version.hwaccels.is_empty() {
+            true => "none".to_string(),
+            false => version.hwaccels.join(", "),
+        };
+        Err(FfmpegError::InvalidConfig(format!(
+            "this ffmpeg ({}) doesn't support `-hwaccel {}`; the methods it supports are: {}",
+            version.version,
+            self.hwaccel.as_ffmpeg_str().unwrap_or_default(),
+            supported
+        )))
+    }
+
+    fn check_video_decoder_in(&self, version: &FfmpegVersion) -> Result<(), FfmpegError> {
+        match &self.video_decoder {
+            Some(decoder) if !version.supports_decoder(decoder) => {
+                Err(FfmpegError::InvalidConfig(format!(
+                    "this ffmpeg ({}) doesn't have the `{}` decoder; see `ffmpeg -decoders`",
+                    version.version, decoder
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ffmpeg_decoders, parse_ffmpeg_hwaccels, parse_ffmpeg_version};
+    use crate::{
+        error::Result,
+        pipeline::{HwAccel, Hypetrigger},
+        simple_trigger::SimpleTrigger,
+    };
+
+    #[test]
+    fn parse_version() -> Result<()> {
+        let text = "ffmpeg version n6.1.1 Copyright (c) 2000-2023 the FFmpeg developers\n\
+                    built with gcc 13.2.1 (GCC) 20230801\n\
+                    configuration: --prefix=/usr --enable-gpl --enable-cuda-llvm --enable-nvdec\n\
+                    libavutil      58. 29.100 / 58. 29.100\n";
+        let mut version = parse_ffmpeg_version(text).ok_or("no version")?;
+        assert_eq!(version.version, "n6.1.1");
+        assert!(version.has_feature("cuda-llvm"));
+        assert!(!version.has_feature("cuda"));
+        assert!(parse_ffmpeg_version("not ffmpeg").is_none());
+
+        version.hwaccels =
+            parse_ffmpeg_hwaccels("Hardware acceleration methods:\nvdpau\ncuda\nvaapi\n\n");
+        assert_eq!(version.hwaccels, ["vdpau", "cuda", "vaapi"]);
+        assert!(version.supports_hwaccel(&HwAccel::Cuda));
+        assert!(version.supports_hwaccel(&HwAccel::Auto));
+        assert!(!version.supports_hwaccel(&HwAccel::Qsv));
+
+        version.decoders = parse_ffmpeg_decoders(
+            "Decoders:\n V..... = Video\n A..... = Audio\n ------\n \
+             V....D av1_cuvid            Nvidia CUVID AV1 decoder (codec av1)\n \
+             V....D vp9                  Google VP9\n",
+        );
+        assert_eq!(version.decoders, ["av1_cuvid", "vp9"]);
+        assert!(version.supports_decoder("vp9"));
+        assert!(!version.supports_decoder("vp9_cuvid"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_hwaccel() -> Result<()> {
+        use crate::test_util::fake_ffmpeg;
+
+        // Stand-in for an ffmpeg built with CUDA but not QSV, and with an AV1
+        // decoder for CUDA but not a VP9 one
+        let ffmpeg = fake_ffmpeg(
+            "probe",
+            "#!/bin/sh\n\
+             case \"$*\" in\n\
+               *-hwaccels*) printf 'Hardware acceleration methods:\\ncuda\\n\\n' ;;\n\
+               *-decoders*) printf 'Decoders:\\n ------\\n V....D av1_cuvid  AV1\\n' ;;\n\
+               *) printf 'ffmpeg version 6.1 Copyright\\nconfiguration: --enable-gpl\\n' ;;\n\
+             esac\n",
+        )?;
+
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        let version = hypetrigger.probe_ffmpeg()?;
+        assert_eq!(version.version, "6.1");
+        assert_eq!(version.hwaccels, ["cuda"]);
+
+        hypetrigger.set_hwaccel(HwAccel::Cuda);
+        assert!(hypetrigger.check_hwaccel().is_ok());
+        hypetrigger.set_video_decoder("av1_cuvid");
+        let av1 = hypetrigger.check_ffmpeg_support();
+        hypetrigger.set_video_decoder("vp9_cuvid");
+        let vp9 = hypetrigger.check_ffmpeg_support();
+        hypetrigger.set_hwaccel(HwAccel::Qsv);
+        let error = hypetrigger.run().expect_err("qsv isn't supported");
+        assert!(error.to_string().contains("-hwaccel qsv"));
+        assert!(av1.is_ok());
+        let error = vp9.expect_err("vp9_cuvid isn't built in");
+        assert!(error.to_string().contains("`vp9_cuvid` decoder"));
+        Ok(())
+    }
+}
@@ -0,0 +1,847 @@
+use crate::error::{Error, FfmpegError, Result};
+use crate::log;
+#[cfg(feature = "photon")]
+use crate::photon::CropRegion;
+use crate::pipeline::{
+    check_ffmpeg_exit, HypetriggerOnFfmpegErrorCallback, PipelineSummary, PixelFormat, StreamInfo,
+};
+use crate::trigger::Trigger;
+use std::{
+    io::Write,
+    process::{Child, ChildStdin, ExitStatus},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Join handle for the thread that reads frames from ffmpeg and runs the
+/// Triggers on them (see `Hypetrigger::attach`).
+pub type PipelineJoinHandle = JoinHandle<core::result::Result<PipelineSummary, String>>;
+
+/// A running ffmpeg process along with all the threads attached to it, as
+/// returned from `Hypetrigger::run_async()`.
+///
+/// The stderr thread is scoped to the pipeline thread, so it is joined along
+/// with it.
+pub struct FfmpegSession {
+    /// The ffmpeg process itself. Its stdio handles have already been taken.
+    pub child: Child,
+
+    /// Reads frames from ffmpeg stdout and runs the Triggers on them.
+    pub pipeline_thread: PipelineJoinHandle,
+
+    /// Writes commands to ffmpeg stdin.
+    pub stdin_thread: JoinHandle<()>,
+
+    /// Sends commands to the stdin thread.
+    pub stdin_tx: Sender<FfmpegStdinCommand>,
+
+    /// Called from `shutdown` if ffmpeg failed before producing any frames.
+    pub on_ffmpeg_error_callback: Option<HypetriggerOnFfmpegErrorCallback>,
+
+    /// Filled in with the size, framerate and codec of the input, once ffmpeg
+    /// has detected them.
+    pub stream_info: Arc<Mutex<Option<StreamInfo>>>,
+
+    /// Sends new Triggers to the pipeline thread.
+    pub trigger_tx: Sender<TriggerUpdate>,
+
+    /// The pixel format ffmpeg was started with, which can't change without
+    /// restarting it.
+    pub pixel_format: PixelFormat,
+
+    /// Whether the pipeline thread is paused, set from the stdin thread.
+    pub pause: Arc<PauseSignal>,
+
+    /// When each Trigger last ran on a frame, updated by the pipeline thread.
+    /// See `trigger_health`.
+    pub health: Arc<TriggerHealth>,
+
+    /// The `session_id` of the `Hypetrigger` that started this session.
+    pub session_id: Option<String>,
+}
+
+impl FfmpegSession {
+    /// Tell ffmpeg to exit gracefully, then block until all threads are done
+    /// and the process has exited.
+    pub fn stop(self) -> Result<()> {
+        self.shutdown(None).map(|_| ())
+    }
+
+    /// Tell ffmpeg to exit gracefully, and wait for it to finish flushing its
+    /// last frames. If it hasn't exited by the time the `timeout` elapses, it
+    /// gets killed instead. Blocks until all threads are done.
+    ///
+    /// Returns an error if ffmpeg exited unsuccessfully without producing any
+    /// frames, e.g. because the input doesn't exist.
+    pub fn shutdown(mut self, timeout: Option<Duration>) -> Result<ExitStatus> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        // The stdin thread may have already exited if ffmpeg closed its stdin
+        if self.stdin_tx.send(FfmpegStdinCommand::Stop).is_err() {
+            log_warn!("[ffmpeg.in] stdin thread already exited");
+        }
+
+        // Let the pipeline drain the last frames, or ffmpeg can't exit
+        self.pause.set_paused(false);
+
+        // When the input comes from stdin, closing it ends the input
+        drop(self.child.stdin.take());
+
+        let mut killed = false;
+        let exit_status = match timeout {
+            Some(timeout) => match wait_timeout(&mut self.child, timeout)? {
+                Some(exit_status) => exit_status,
+                None => {
+                    log_warn!("[ffmpeg] Timed out waiting for ffmpeg to exit; killing it");
+                    self.child.kill()?;
+                    killed = true;
+                    self.child.wait()?
+                }
+            },
+            None => self.child.wait()?,
+        };
+
+        let summary = join(self.pipeline_thread)?.map_err(Error::from)?;
+        join(self.stdin_thread)?;
+
+        // A killed ffmpeg always exits unsuccessfully, which isn't its fault
+        if !killed {
+            check_ffmpeg_exit(exit_status, summary, self.on_ffmpeg_error_callback.as_ref())?;
+        }
+        Ok(exit_status)
+    }
+
+    /// Kill ffmpeg right away, without waiting for it to flush its last
+    /// frames, then block until all threads are done. Unlike `stop`, this also
+    /// works when ffmpeg has stopped responding to commands.
+    ///
+    /// Killing ffmpeg closes its stdout and stderr, so the threads reading
+    /// them run out of data and exit. Returns the exit status of the killed
+    /// process.
+    pub fn cancel(mut self) -> Result<ExitStatus> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        log_debug!("[ffmpeg] Cancelling; killing ffmpeg");
+        if let Err(e) = self.child.kill() {
+            log_warn!(
+                "[ffmpeg] Failed to kill ffmpeg (it may have already exited): {}",
+                e
+            );
+        }
+        let exit_status = self.child.wait()?;
+        self.pause.set_paused(false);
+
+        // Closing the channel makes the stdin thread exit without writing to
+        // the dead process
+        drop(self.stdin_tx);
+        join(self.pipeline_thread)?.map_err(Error::from)?;
+        join(self.stdin_thread)?;
+        Ok(exit_status)
+    }
+
+    /// Stop reading frames from ffmpeg, without losing the position in the
+    /// input, e.g. while someone reviews a flagged frame. ffmpeg has no way
+    /// to pause, so it keeps going until the stdout pipe fills up, and then
+    /// blocks. Live inputs will fall behind while paused.
+    pub fn pause(&self) -> Result<()> {
+        self.send(FfmpegStdinCommand::Pause)
+    }
+
+    /// Carry on reading frames after `pause`.
+    pub fn resume(&self) -> Result<()> {
+        self.send(FfmpegStdinCommand::Resume)
+    }
+
+    /// Send a command to a filter while ffmpeg runs, e.g.
+    /// `send_filter_command("scale", "w", "640")`. See
+    /// `FfmpegStdinCommand::SetFilterCommand`.
+    pub fn send_filter_command(&self, target: &str, command: &str, arg: &str) -> Result<()> {
+        self.send(FfmpegStdinCommand::SetFilterCommand {
+            target: target.to_string(),
+            command: command.to_string(),
+            arg: arg.to_string(),
+        })
+    }
+
+    /// Send a command to the stdin thread.
+    pub fn send(&self, command: FfmpegStdinCommand) -> Result<()> {
+        self.stdin_tx
+            .send(command)
+            .map_err(|_| Error::from_display("the ffmpeg stdin thread has already exited"))
+    }
+
+    /// Replace all of the Triggers while ffmpeg keeps running, e.g. to turn
+    /// detectors on and off during a live stream. The new Triggers take over
+    /// from the next frame, and frame numbers carry on counting.
+    ///
+    /// Returns an error if there are no Triggers (`stop` the session
+    /// instead), if one of the Triggers asks for a pixel format wider than the
+    /// one ffmpeg is sending (e.g. `Rgba` when it was started without one), or
+    /// if the pipeline has already finished.
+    pub fn set_triggers(&self, triggers: Vec<Arc<dyn Trigger>>) -> Result<()> {
+        if triggers.is_empty() {
+            return Err(FfmpegError::InvalidConfig(
+                "no Triggers were given, so there would be nothing to run on the frames; stop the session instead"
+                    .to_string(),
+            )
+            .into());
+        }
+        self.check_pixel_formats(&triggers)?;
+        self.send_trigger_update(TriggerUpdate::ReplaceAll(triggers))
+    }
+
+    /// Replace just the Trigger at index `trigger_id`, leaving the others
+    /// running, e.g. with a copy that has a different crop while calibrating
+    /// it against a live stream. Crops are applied to each frame by the
+    /// Trigger rather than by ffmpeg, so nothing needs to be sent to ffmpeg
+    /// for the new crop to take effect from the next frame.
+    ///
+    /// The same errors apply as for `set_triggers`. An index past the end of
+    /// the current Triggers is only logged, since it's checked by the
+    /// pipeline thread.
+    pub fn replace_trigger(&self, trigger_id: usize, trigger: Arc<dyn Trigger>) -> Result<()> {
+        self.check_pixel_formats(std::slice::from_ref(&trigger))?;
+        self.send_trigger_update(TriggerUpdate::Replace(trigger_id, trigger))
+    }
+
+    /// Move the crop of the Trigger at index `trigger_id`, e.g. while
+    /// calibrating it against a live stream, like `replace_trigger` with a
+    /// copy of it that crops to `crop` instead (see `Trigger::with_crop`).
+    /// Nothing is sent to ffmpeg, since crops are applied by the Triggers
+    /// themselves, and it keeps running without a restart.
+    ///
+    /// The copy is made by the pipeline thread, from the Trigger that's
+    /// running at the time; an index past the end, or a Trigger that doesn't
+    /// crop, is only logged.
+    #[cfg(feature = "photon")]
+    pub fn set_crop(&self, trigger_id: usize, crop: CropRegion) -> Result<()> {
+        crop.validate().map_err(Error::from)?;
+        self.send_trigger_update(TriggerUpdate::SetCrop(trigger_id, crop))
+    }
+
+    /// Check that none of the Triggers ask for a wider pixel format than the
+    /// one ffmpeg is sending.
+    fn check_pixel_formats(&self, triggers: &[Arc<dyn Trigger>]) -> Result<()> {
+        let too_wide = triggers
+            .iter()
+            .filter_map(|trigger| trigger.get_pixel_format())
+            .find(|&format| !self.pixel_format.holds(format));
+        match too_wide {
+            Some(format) => Err(FfmpegError::InvalidConfig(format!(
+                "a Trigger asks for {:?}, but ffmpeg is sending {:?}; restart it to change formats",
+                format, self.pixel_format
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    fn send_trigger_update(&self, update: TriggerUpdate) -> Result<()> {
+        self.trigger_tx
+            .send(update)
+            .map_err(|_| Error::from_display("the pipeline has already finished"))
+    }
+
+    /// When each Trigger last ran on a frame, in the order they were added,
+    /// or `None` if it hasn't yet (or was disabled). A Trigger that stops
+    /// getting frames while the session keeps running, e.g. because of an
+    /// error with `TriggerErrorPolicy::Disable`, can be spotted by how long
+    /// ago that was. Triggers with a lower `get_fps()` are expected to fall
+    /// behind by up to one of their own frames.
+    ///
+    /// After `set_triggers` (or `replace_trigger`), the new Triggers start
+    /// out as `None` again.
+    pub fn trigger_health(&self) -> Vec<Option<Instant>> {
+        self.health.last_ran_at()
+    }
+
+    /// Block until the ffmpeg process exits on its own.
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        self.child.wait().map_err(Error::from)
+    }
+
+    /// Check whether the ffmpeg process is still running, without blocking.
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+/// Poll a child process until it exits or the timeout elapses, whichever
+/// comes first. Returns `None` on timeout.
+pub fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(exit_status) = child.try_wait()? {
+            return Ok(Some(exit_status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Join a thread, converting a panic into an `Error`.
+pub(crate) fn join<T>(join_handle: JoinHandle<T>) -> Result<T> {
+    let name = join_handle.thread().name().unwrap_or("unnamed").to_string();
+    join_handle
+        .join()
+        .map_err(|_| Error::from_display(format!("thread {} panicked", name)))
+}
+
+/// A command sent over a channel to the ffmpeg stdin thread
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FfmpegStdinCommand {
+    /// Tell ffmpeg to exit gracefully
+    Stop,
+
+    /// Stop reading frames until `Resume` (see `FfmpegSession::pause`). This
+    /// is handled by the pipeline rather than ffmpeg itself.
+    Pause,
+
+    /// Carry on reading frames after `Pause`
+    Resume,
+
+    /// Write these bytes to ffmpeg's stdin as they are, like keypresses in
+    /// its interactive console (e.g. `"+"` to log more verbosely)
+    SendRaw(String),
+
+    /// Send a command to a filter while ffmpeg runs, like ffmpeg's `c` key,
+    /// e.g. target `scale`, command `w`, arg `640`. Only filters that support
+    /// commands respond to them; see the ffmpeg filter docs.
+    SetFilterCommand {
+        target: String,
+        command: String,
+        arg: String,
+    },
+}
+
+impl FfmpegStdinCommand {
+    /// What to write to ffmpeg's stdin for this command, if anything; `Pause`
+    /// and `Resume` are handled without ffmpeg.
+    pub fn ffmpeg_stdin_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            FfmpegStdinCommand::Stop => Some(b"q\n".to_vec()),
+            FfmpegStdinCommand::Pause | FfmpegStdinCommand::Resume => None,
+            FfmpegStdinCommand::SendRaw(raw) => Some(raw.as_bytes().to_vec()),
+            // Applies to all instances of the target, straight away (`-1`)
+            FfmpegStdinCommand::SetFilterCommand {
+                target,
+                command,
+                arg,
+            } => Some(format!("c{} -1 {} {}\n", target, command, arg).into_bytes()),
+        }
+    }
+}
+
+/// Shared between the stdin and pipeline threads, so the pipeline can wait
+/// while the session is paused.
+#[derive(Debug, Default)]
+pub struct PauseSignal {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl PauseSignal {
+    pub fn set_paused(&self, paused: bool) {
+        if let Ok(mut is_paused) = self.paused.lock() {
+            *is_paused = paused;
+        }
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.lock().map(|paused| *paused).unwrap_or(false)
+    }
+
+    /// Block for as long as it's paused.
+    pub fn wait_while_paused(&self) {
+        if let Ok(paused) = self.paused.lock() {
+            drop(self.resumed.wait_while(paused, |paused| *paused));
+        }
+    }
+}
+
+/// When each Trigger of a running pipeline last ran on a frame, shared between
+/// the pipeline thread and its `FfmpegSession`.
+#[derive(Debug, Default)]
+pub struct TriggerHealth {
+    /// The number of frames each Trigger had run on when it was last
+    /// recorded, and when that changed
+    triggers: Mutex<Vec<(u64, Option<Instant>)>>,
+}
+
+impl TriggerHealth {
+    /// Record the number of frames each Trigger has run on so far (as in
+    /// `PipelineSummary::trigger_frames`); the ones that went up ran just now.
+    pub fn record(&self, trigger_frames: &[u64]) {
+        let Ok(mut triggers) = self.triggers.lock() else {
+            return;
+        };
+        triggers.resize(trigger_frames.len(), (0, None));
+        let now = Instant::now();
+        for (trigger, &count) in triggers.iter_mut().zip(trigger_frames) {
+            if trigger.0 != count {
+                *trigger = (count, Some(now));
+            }
+        }
+    }
+
+    /// Forget about a replaced Trigger, or all of them with `None`.
+    pub fn reset(&self, trigger_id: Option<usize>) {
+        if let Ok(mut triggers) = self.triggers.lock() {
+            match trigger_id {
+                Some(trigger_id) => {
+                    if let Some(trigger) = triggers.get_mut(trigger_id) {
+                        *trigger = (0, None);
+                    }
+                }
+                None => triggers.clear(),
+            }
+        }
+    }
+
+    /// When each Trigger last ran on a frame, if it has.
+    pub fn last_ran_at(&self) -> Vec<Option<Instant>> {
+        match self.triggers.lock() {
+            Ok(triggers) => triggers
+                .iter()
+                .map(|(_, last_ran_at)| *last_ran_at)
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// A change to the Triggers of a running pipeline, sent from an
+/// `FfmpegSession`.
+pub enum TriggerUpdate {
+    /// Replace all of the Triggers (`FfmpegSession::set_triggers`)
+    ReplaceAll(Vec<Arc<dyn Trigger>>),
+
+    /// Replace the Trigger at the given index
+    /// (`FfmpegSession::replace_trigger`)
+    Replace(usize, Arc<dyn Trigger>),
+
+    /// Replace the Trigger at the given index with a copy of itself with a
+    /// different crop (`FfmpegSession::set_crop`)
+    #[cfg(feature = "photon")]
+    SetCrop(usize, CropRegion),
+}
+
+/// The parts of an `FfmpegSession` that reach into its pipeline thread.
+pub struct PipelineControls {
+    /// Receives changes to the Triggers from `FfmpegSession::set_triggers`
+    /// and `FfmpegSession::replace_trigger`
+    pub trigger_rx: Receiver<TriggerUpdate>,
+
+    /// Whether the pipeline should wait before reading the next frame
+    pub pause: Arc<PauseSignal>,
+
+    /// Updated with the Triggers that ran on each frame
+    pub health: Arc<TriggerHealth>,
+}
+
+/// Spawns a thread that owns ffmpeg's stdin, and writes to it when it receives
+/// an `FfmpegStdinCommand`. The thread exits after sending `Stop`, or when the
+/// `Sender` is dropped.
+///
+/// `ffmpeg_stdin` is `None` when stdin is used for the input video instead,
+/// in which case `Stop` can't be sent, and ffmpeg is stopped by closing stdin.
+/// `Pause` and `Resume` are passed on to the pipeline through `pause`.
+/// Its name and log messages include the `session_id`, if there is one.
+pub fn spawn_ffmpeg_stdin_thread(
+    mut ffmpeg_stdin: Option<ChildStdin>,
+    pause: Arc<PauseSignal>,
+    session_id: Option<String>,
+) -> Result<(Sender<FfmpegStdinCommand>, JoinHandle<()>)> {
+    let (tx, rx) = channel::<FfmpegStdinCommand>();
+    let name = log::thread_name("ffmpeg_stdin", session_id.as_deref());
+    let join_handle = thread::Builder::new().name(name).spawn(move || {
+        let _session = log::enter_session(session_id.as_deref());
+        while let Ok(command) = rx.recv() {
+            log_debug!("[ffmpeg.in] Sending command {:?}", command);
+            let result = match (&command, &mut ffmpeg_stdin) {
+                (FfmpegStdinCommand::Pause, _) => {
+                    pause.set_paused(true);
+                    Ok(())
+                }
+                (FfmpegStdinCommand::Resume, _) => {
+                    pause.set_paused(false);
+                    Ok(())
+                }
+                (_, None) => {
+                    log_debug!("[ffmpeg.in] stdin is used for the input; close it to stop");
+                    Ok(())
+                }
+                (command, Some(ffmpeg_stdin)) => match command.ffmpeg_stdin_bytes() {
+                    Some(bytes) => ffmpeg_stdin
+                        .write_all(&bytes)
+                        .and_then(|_| ffmpeg_stdin.flush())
+                        .map_err(Error::from),
+                    None => Ok(()),
+                },
+            };
+            if let Err(e) = result {
+                log_warn!("[ffmpeg.in] Error writing to ffmpeg stdin: {}", e);
+            }
+            if command == FfmpegStdinCommand::Stop {
+                break;
+            }
+        }
+        log_debug!("[ffmpeg.in] ffmpeg stdin thread exiting");
+    })?;
+
+    Ok((tx, join_handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FfmpegStdinCommand;
+    use crate::{
+        error::{NoneError, Result},
+        log::current_session_id,
+        pipeline::Hypetrigger,
+        simple_trigger::SimpleTrigger,
+        trigger::{Frame, Trigger},
+    };
+    use std::io::Write;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn stdin_bytes() {
+        let command = FfmpegStdinCommand::SetFilterCommand {
+            target: "scale".to_string(),
+            command: "w".to_string(),
+            arg: "640".to_string(),
+        };
+        assert_eq!(
+            command.ffmpeg_stdin_bytes(),
+            Some(b"cscale -1 w 640\n".to_vec())
+        );
+        let command = FfmpegStdinCommand::SendRaw("+".to_string());
+        assert_eq!(command.ffmpeg_stdin_bytes(), Some(b"+".to_vec()));
+        assert_eq!(FfmpegStdinCommand::Pause.ffmpeg_stdin_bytes(), None);
+    }
+
+    #[test]
+    fn trigger_health() -> Result<()> {
+        struct Disabled;
+        impl Trigger for Disabled {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn get_enabled(&self) -> bool {
+                false
+            }
+        }
+
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}))
+            .add_trigger(Disabled);
+        let started_at = Instant::now();
+        let session = hypetrigger.run_async()?;
+        let health = session.health.clone();
+        session.stop()?;
+
+        let last_ran_at = health.last_ran_at();
+        assert_eq!(last_ran_at.len(), 2);
+        assert!(last_ran_at[0].is_some_and(|last_ran_at| last_ran_at > started_at));
+        assert!(last_ran_at[1].is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn stop() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        let session = hypetrigger.run_async()?;
+        session.stop()
+    }
+
+    #[test]
+    fn session_id() -> Result<()> {
+        // Two sessions at once, each seen from its own Trigger and callbacks
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut sessions = vec![];
+        for id in ["first", "second"] {
+            let seen_by_trigger = seen.clone();
+            let seen_by_progress = seen.clone();
+            let mut hypetrigger = Hypetrigger::new();
+            hypetrigger
+                .test_input()
+                .set_session_id(id)
+                .add_trigger(SimpleTrigger::new(move |_| {
+                    let thread = std::thread::current();
+                    let thread_name = thread.name().unwrap_or_default().to_string();
+                    seen_by_trigger
+                        .lock()
+                        .unwrap()
+                        .push((id, thread_name, current_session_id()));
+                }))
+                .on_progress(move |_| {
+                    let thread = std::thread::current();
+                    let thread_name = thread.name().unwrap_or_default().to_string();
+                    seen_by_progress
+                        .lock()
+                        .unwrap()
+                        .push((id, thread_name, current_session_id()));
+                });
+            sessions.push(hypetrigger.run_async()?);
+        }
+        for mut session in sessions {
+            let stdin_thread_name = session.stdin_thread.thread().name().ok_or(NoneError)?;
+            let session_id = session.session_id.as_deref().ok_or(NoneError)?;
+            assert_eq!(stdin_thread_name, format!("ffmpeg_stdin-{}", session_id));
+            session.wait()?;
+            session.stop()?;
+        }
+
+        let seen = seen.lock()?;
+        for id in ["first", "second"] {
+            let threads: Vec<&str> = seen
+                .iter()
+                .filter(|(seen_id, _, _)| *seen_id == id)
+                .map(|(_, thread_name, session_id)| {
+                    assert_eq!(session_id.as_deref(), Some(id));
+                    thread_name.as_str()
+                })
+                .collect();
+            assert!(threads.contains(&format!("hypetrigger_pipeline-{}", id).as_str()));
+            assert!(threads.contains(&format!("ffmpeg_stderr-{}", id).as_str()));
+        }
+        assert_eq!(current_session_id(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(|_| {}));
+        let session = hypetrigger.run_async()?;
+        session.shutdown(Some(Duration::from_secs(10)))?;
+        Ok(())
+    }
+
+    #[test]
+    fn cancel() -> Result<()> {
+        let frames = Arc::new(AtomicU64::new(0));
+        let frames_clone = frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(move |_| {
+                frames_clone.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+            }));
+        let session = hypetrigger.run_async()?;
+
+        // Cancel partway through the 20 frames of the test input
+        while frames.load(Ordering::SeqCst) < 2 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let exit_status = session.cancel()?;
+        assert!(!exit_status.success());
+        assert!(frames.load(Ordering::SeqCst) < 20);
+        Ok(())
+    }
+
+    #[test]
+    fn set_triggers() -> Result<()> {
+        let before = Arc::new(AtomicU64::new(0));
+        let before_clone = before.clone();
+        let after = Arc::new(AtomicU64::new(0));
+        let after_clone = after.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(move |_| {
+                before_clone.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+            }));
+        let mut session = hypetrigger.run_async()?;
+
+        // Swap the Trigger out partway through the 20 frames of the test input
+        while before.load(Ordering::SeqCst) < 2 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(session.set_triggers(vec![]).is_err());
+        session.set_triggers(vec![Arc::new(SimpleTrigger::new(move |_| {
+            after_clone.fetch_add(1, Ordering::SeqCst);
+        }))])?;
+        session.wait()?;
+        session.stop()?;
+        assert!(before.load(Ordering::SeqCst) < 20);
+        assert!(after.load(Ordering::SeqCst) > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn replace_trigger() -> Result<()> {
+        let first = Arc::new(AtomicU64::new(0));
+        let first_clone = first.clone();
+        let replaced = Arc::new(AtomicU64::new(0));
+        let replaced_clone = replaced.clone();
+        let replacement = Arc::new(AtomicU64::new(0));
+        let replacement_clone = replacement.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(move |_| {
+                first_clone.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+            }))
+            .add_trigger(SimpleTrigger::new(move |_| {
+                replaced_clone.fetch_add(1, Ordering::SeqCst);
+            }));
+        let mut session = hypetrigger.run_async()?;
+
+        while first.load(Ordering::SeqCst) < 2 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        session.replace_trigger(
+            1,
+            Arc::new(SimpleTrigger::new(move |_| {
+                replacement_clone.fetch_add(1, Ordering::SeqCst);
+            })),
+        )?;
+        session.wait()?;
+        session.stop()?;
+
+        // The other Trigger carries on through every frame
+        assert_eq!(first.load(Ordering::SeqCst), 20);
+        assert!(replaced.load(Ordering::SeqCst) < 20);
+        assert_eq!(
+            replaced.load(Ordering::SeqCst) + replacement.load(Ordering::SeqCst),
+            20
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "photon")]
+    #[test]
+    fn set_crop() -> Result<()> {
+        use crate::photon::{CropRegion, PixelCrop};
+
+        /// Records the crop it had for each frame
+        struct Cropper {
+            crop: Option<CropRegion>,
+            crops: Arc<Mutex<Vec<Option<CropRegion>>>>,
+        }
+
+        impl Trigger for Cropper {
+            fn on_frame(&self, _frame: &Frame) -> Result<()> {
+                self.crops.lock()?.push(self.crop.clone());
+                std::thread::sleep(Duration::from_millis(20));
+                Ok(())
+            }
+
+            fn with_crop(&self, crop: CropRegion) -> Option<Arc<dyn Trigger>> {
+                Some(Arc::new(Cropper {
+                    crop: Some(crop),
+                    crops: self.crops.clone(),
+                }))
+            }
+        }
+
+        let crops = Arc::new(Mutex::new(vec![]));
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.test_input().add_trigger(Cropper {
+            crop: None,
+            crops: crops.clone(),
+        });
+        let mut session = hypetrigger.run_async()?;
+        while crops.lock()?.len() < 2 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let crop = CropRegion::Pixels(PixelCrop {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+        });
+        session.set_crop(0, crop.clone())?;
+        session.wait()?;
+        session.stop()?;
+
+        // The copy with the new crop took over partway through, and ran on
+        // the rest of the frames
+        let crops = crops.lock()?;
+        assert_eq!(crops.len(), 20);
+        assert_eq!(crops[0], None);
+        assert_eq!(crops[19], Some(crop));
+        Ok(())
+    }
+
+    #[test]
+    fn pause() -> Result<()> {
+        let frames = Arc::new(AtomicU64::new(0));
+        let frames_clone = frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_trigger(SimpleTrigger::new(move |_| {
+                frames_clone.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+            }));
+        let mut session = hypetrigger.run_async()?;
+        session.pause()?;
+        std::thread::sleep(Duration::from_millis(200));
+
+        // No more frames arrive once the pause has taken effect
+        let paused_at = frames.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(frames.load(Ordering::SeqCst), paused_at);
+        assert!(session.is_running());
+
+        session.resume()?;
+        session.wait()?;
+        session.stop()?;
+        assert!(frames.load(Ordering::SeqCst) > paused_at);
+        Ok(())
+    }
+
+    #[test]
+    fn stdin_input() -> Result<()> {
+        let frames = Arc::new(AtomicU64::new(0));
+        let frames_clone = frames.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .set_input("pipe:0".to_string())
+            .set_input_format("rawvideo")
+            .add_input_args(&[
+                "-pix_fmt",
+                "rgb24",
+                "-video_size",
+                "64x36",
+                "-framerate",
+                "2",
+            ])
+            .add_trigger(SimpleTrigger::new(move |_| {
+                frames_clone.fetch_add(1, Ordering::SeqCst);
+            }));
+        let mut session = hypetrigger.run_async()?;
+
+        // Write a few raw frames, then close stdin to end the input
+        let mut stdin = session.child.stdin.take().ok_or(NoneError)?;
+        stdin.write_all(&vec![128; 64 * 36 * 3 * 4])?;
+        drop(stdin);
+        session.wait()?;
+        session.stop()?;
+        assert!(frames.load(Ordering::SeqCst) > 0);
+        Ok(())
+    }
+}
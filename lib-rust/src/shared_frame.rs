@@ -0,0 +1,417 @@
+// Hands frames off as reference-counted views of the buffer they were read
+// into (`bytes::Bytes`), instead of copying each one into an image of its own,
+// for consumers that pass the raw pixels on, e.g. to another thread or process.
+
+use crate::concat::concat_list_path;
+use crate::error::{Error, FfmpegError, NoneError, Result};
+use crate::log;
+use crate::pipeline::{
+    check_ffmpeg_exit, join_stderr_thread, read_frame, Hypetrigger, OutputSink, PipelineSummary,
+    PixelFormat, RawFrameBuffer, VsyncMode,
+};
+use crate::trigger::Frame;
+use bytes::{Bytes, BytesMut};
+use std::io::Read;
+use std::ops::Range;
+use std::sync::mpsc::SyncSender;
+use std::thread;
+
+/// How many frames the read buffer of a `SharedFrameReader` has room for
+/// before it's reallocated. Once every view of the oldest frames has been
+/// dropped, their space is reused for the next ones.
+pub const SHARED_FRAME_RING_SIZE: usize = 8;
+
+/// A frame (or part of one) whose pixels are a view of a shared, immutable
+/// buffer. Cloning it, or cutting it up with `crop`, only bumps a reference
+/// count, so the frame of each input and each Trigger's region can be sent to
+/// different threads without copying any pixels.
+///
+/// Rows are `stride` bytes apart in `data`, which is more than the width of
+/// a view into the middle of a wider frame (e.g. one input of several side by
+/// side). Use `row` to get at the pixels, or `to_frame` to copy them into an
+/// image for a Trigger.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedFrame {
+    /// The pixels, starting from the top left pixel of this view
+    pub data: Bytes,
+
+    /// Number of bytes from the start of one row to the next
+    pub stride: usize,
+
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+
+    /// As for `Frame::frame_num`
+    pub frame_num: u64,
+
+    /// As for `Frame::timestamp`
+    pub timestamp: f64,
+
+    /// As for `Frame::input_index`
+    pub input_index: usize,
+}
+
+impl SharedFrame {
+    /// Number of bytes of pixels in each row
+    pub fn row_len(&self) -> usize {
+        self.pixel_format.frame_size(self.width, 1)
+    }
+
+    /// Where the pixels of row `y` are in `data`
+    fn row_range(&self, y: u32) -> Range<usize> {
+        let start = y as usize * self.stride;
+        start..start + self.row_len()
+    }
+
+    /// The pixels of row `y`, or `None` if it's out of bounds
+    pub fn row(&self, y: u32) -> Option<&[u8]> {
+        if y >= self.height {
+            return None;
+        }
+        self.data.get(self.row_range(y))
+    }
+
+    /// Whether the rows follow straight on from each other in `data`, so
+    /// it holds exactly the pixels of the frame, row by row
+    pub fn is_contiguous(&self) -> bool {
+        self.stride == self.row_len() || self.height <= 1
+    }
+
+    /// A view of the given rectangle of this frame, sharing its buffer, or
+    /// `None` if it doesn't fit inside the frame
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<SharedFrame> {
+        if x.checked_add(width)? > self.width || y.checked_add(height)? > self.height {
+            return None;
+        }
+        let start = y as usize * self.stride + self.pixel_format.frame_size(x, 1);
+        let end = match height {
+            0 => start,
+            _ => {
+                start + (height as usize - 1) * self.stride + self.pixel_format.frame_size(width, 1)
+            }
+        };
+        Some(SharedFrame {
+            data: self.data.slice(start..end),
+            width,
+            height,
+            ..self.clone()
+        })
+    }
+
+    /// The pixels as one contiguous buffer: shared when they already are
+    /// (see `is_contiguous`), and copied row by row otherwise
+    pub fn contiguous_bytes(&self) -> Bytes {
+        if self.is_contiguous() {
+            return self
+                .data
+                .slice(..self.pixel_format.frame_size(self.width, self.height));
+        }
+        let mut bytes = BytesMut::with_capacity(self.row_len() * self.height as usize);
+        for y in 0..self.height {
+            bytes.extend_from_slice(&self.data[self.row_range(y)]);
+        }
+        bytes.freeze()
+    }
+
+    /// Copy the pixels into a `Frame`, e.g. to run a Trigger on it
+    pub fn to_frame(&self) -> Option<Frame> {
+        let mut raw = RawFrameBuffer {
+            bytes: self.contiguous_bytes().to_vec(),
+            samples: vec![],
+        };
+        Some(Frame {
+            image: raw.take_image(self.pixel_format, self.width, self.height)?,
+            frame_num: self.frame_num,
+            timestamp: self.timestamp,
+            input_index: self.input_index,
+        })
+    }
+}
+
+/// Reads raw frames of a fixed size (e.g. from ffmpeg stdout) into one shared
+/// buffer with room for several of them at once (`SHARED_FRAME_RING_SIZE`),
+/// handing each one out as `Bytes` rather than allocating a buffer per frame.
+/// The buffer is only reallocated when frames are still held on to further
+/// back than it has room for.
+pub struct SharedFrameReader<R> {
+    reader: R,
+    frame_size: usize,
+    buffer: BytesMut,
+}
+
+impl<R: Read> SharedFrameReader<R> {
+    pub fn new(reader: R, frame_size: usize) -> Self {
+        Self {
+            reader,
+            frame_size,
+            buffer: BytesMut::with_capacity(frame_size * SHARED_FRAME_RING_SIZE),
+        }
+    }
+
+    /// Read the next frame, or `None` at the end of the output. Like
+    /// `pipeline::read_frame`, a frame cut short by the end of the output is
+    /// padded with zeroes, with the number of bytes actually read.
+    pub fn read_frame(&mut self) -> std::io::Result<Option<(Bytes, usize)>> {
+        // Reclaims the space of earlier frames, once nothing refers to them
+        self.buffer.reserve(self.frame_size);
+        self.buffer.resize(self.frame_size, 0);
+        let bytes_read = read_frame(&mut self.reader, &mut self.buffer)?;
+        if bytes_read == 0 {
+            self.buffer.clear();
+            return Ok(None);
+        }
+        self.buffer[bytes_read..].fill(0);
+        Ok(Some((self.buffer.split().freeze(), bytes_read)))
+    }
+}
+
+impl Hypetrigger {
+    /// The `SharedFrame` of each input from one frame read from ffmpeg, as
+    /// views of the same buffer (see `split_inputs`)
+    pub fn split_shared_inputs(
+        &self,
+        data: Bytes,
+        (output_width, output_height): (u32, u32),
+        frame_num: u64,
+    ) -> Vec<SharedFrame> {
+        let num_inputs = self.inputs().count() as u32;
+        let input_width = output_width / num_inputs;
+        let stacked = SharedFrame {
+            stride: self.output_pixel_format().frame_size(output_width, 1),
+            data,
+            width: output_width,
+            height: output_height,
+            pixel_format: self.output_pixel_format(),
+            frame_num,
+            timestamp: self.frame_timestamp(frame_num),
+            input_index: 0,
+        };
+        (0..num_inputs)
+            .filter_map(|input_index| {
+                let input = stacked.crop(input_index * input_width, 0, input_width, output_height);
+                Some(SharedFrame {
+                    input_index: input_index as usize,
+                    ..input?
+                })
+            })
+            .collect()
+    }
+
+    /// Like `run`, but instead of running the Triggers, each frame is sent to
+    /// `frame_tx` as a `SharedFrame` per input, which share the one buffer the
+    /// frame was read into. Blocks until ffmpeg finishes, or `frame_tx`'s
+    /// receiver is dropped.
+    pub fn run_shared(&self, frame_tx: SyncSender<SharedFrame>) -> Result<()> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        log_debug!("[hypetrigger] run_shared()");
+        self.validate_settings(false)?;
+        self.check_ffmpeg_support()?;
+        self.prepare_output_dirs()?;
+        if self.output_sink != OutputSink::Stdout {
+            return Err(FfmpegError::InvalidConfig(
+                "`run_shared` reads the frames from stdout".to_string(),
+            )
+            .into());
+        }
+        if self.is_stdin_input() || self.vsync == VsyncMode::SourcePts || self.frame_markers {
+            return Err(FfmpegError::InvalidConfig(
+                "reading from stdin, `VsyncMode::SourcePts` and `frame_markers` are only supported by `run` and `run_async`"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        let concat_list = self.write_concat_list()?;
+        let mut ffmpeg_child = self.spawn_ffmpeg_child(concat_list_path(&concat_list))?;
+        let mut ffmpeg_stderr = ffmpeg_child.stderr.take().ok_or(NoneError)?;
+        let ffmpeg_stdout = ffmpeg_child.stdout.take().ok_or(NoneError)?;
+        let summary = thread::scope(|scope| {
+            let (output_size_rx, _frame_time_rx, ffmpeg_stderr_join_handle) =
+                self.spawn_ffmpeg_stderr_thread(&mut ffmpeg_stderr, scope)?;
+            let mut frames_read = 0;
+            // Otherwise ffmpeg exited before it got as far as the output
+            if let Ok(output_size) = output_size_rx.recv() {
+                frames_read = self.send_shared_frames(ffmpeg_stdout, output_size, &frame_tx)?;
+            }
+            let summary = PipelineSummary {
+                frames_read,
+                ..Default::default()
+            };
+            let parser =
+                join_stderr_thread(ffmpeg_stderr_join_handle).map_err(Error::from_display)?;
+            Ok::<_, Error>(summary.with_stderr(parser))
+        })?;
+
+        let ffmpeg_exit_status = ffmpeg_child.wait()?;
+        log_debug!(
+            "[ffmpeg] ffmpeg command exited with status {}",
+            ffmpeg_exit_status
+        );
+        check_ffmpeg_exit(
+            ffmpeg_exit_status,
+            summary,
+            self.on_ffmpeg_error_callback.as_ref(),
+        )?;
+        Ok(())
+    }
+
+    /// Read ffmpeg stdout until the end (or `max_frames`), sending the frames
+    /// to `frame_tx`. Takes ownership of stdout so that it's closed on the way
+    /// out, which makes ffmpeg exit if it's stopped early.
+    fn send_shared_frames(
+        &self,
+        ffmpeg_stdout: impl Read,
+        output_size: (u32, u32),
+        frame_tx: &SyncSender<SharedFrame>,
+    ) -> Result<u64> {
+        let frame_size = self
+            .output_pixel_format()
+            .frame_size(output_size.0, output_size.1);
+        let mut reader = SharedFrameReader::new(ffmpeg_stdout, frame_size);
+        let mut frame_num = 0;
+        while self
+            .max_frames
+            .map_or(true, |max_frames| frame_num < max_frames)
+        {
+            let (data, bytes_read) = match reader.read_frame()? {
+                Some(frame) => frame,
+                None => break,
+            };
+            if bytes_read < frame_size && !self.dispatch_partial_frame {
+                log_warn!(
+                    "[ffmpeg.out] Output ended partway through a frame; dropping the last {} bytes",
+                    bytes_read
+                );
+                break;
+            }
+            for frame in self.split_shared_inputs(data, output_size, frame_num) {
+                if frame_tx.send(frame).is_err() {
+                    log_debug!("[ffmpeg.out] Frame receiver dropped; stopping");
+                    return Ok(frame_num);
+                }
+            }
+            frame_num += 1;
+            if bytes_read < frame_size {
+                break;
+            }
+        }
+        log_debug!("[ffmpeg.out] Finished reading from stdout");
+        Ok(frame_num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::FFMPEG_TEST_INPUT;
+    use image::GenericImageView;
+    use std::io::Cursor;
+    use std::sync::mpsc::sync_channel;
+
+    /// A 4x2 Gray8 frame whose pixels are numbered row by row
+    fn numbered_frame() -> SharedFrame {
+        SharedFrame {
+            data: Bytes::from((0..8).collect::<Vec<u8>>()),
+            stride: 4,
+            width: 4,
+            height: 2,
+            pixel_format: PixelFormat::Gray8,
+            frame_num: 3,
+            timestamp: 1.5,
+            input_index: 0,
+        }
+    }
+
+    #[test]
+    fn crop() -> Result<()> {
+        let frame = numbered_frame();
+        assert!(frame.is_contiguous());
+        let right = frame.crop(2, 0, 2, 2).ok_or(NoneError)?;
+        assert_eq!(right.row(0), Some(&[2, 3][..]));
+        assert_eq!(right.row(1), Some(&[6, 7][..]));
+        assert_eq!(right.row(2), None);
+        assert!(!right.is_contiguous());
+        assert_eq!(&right.contiguous_bytes()[..], [2, 3, 6, 7]);
+
+        // The view shares the frame's buffer rather than copying it
+        assert_eq!(right.data.as_ptr(), frame.data[2..].as_ptr());
+        let bottom = frame.crop(0, 1, 4, 1).ok_or(NoneError)?;
+        assert!(bottom.is_contiguous());
+        assert_eq!(bottom.contiguous_bytes().as_ptr(), frame.data[4..].as_ptr());
+
+        assert_eq!(frame.crop(3, 0, 2, 1), None);
+        assert_eq!(frame.crop(0, 1, 1, 2), None);
+
+        let image = right.to_frame().ok_or(NoneError)?;
+        assert_eq!(image.image.dimensions(), (2, 2));
+        assert_eq!(
+            image.image.as_luma8().ok_or(NoneError)?.as_raw(),
+            &[2, 3, 6, 7]
+        );
+        assert_eq!((image.frame_num, image.timestamp), (3, 1.5));
+        Ok(())
+    }
+
+    #[test]
+    fn reader_reuses_buffer() -> Result<()> {
+        let output: Vec<u8> = (0..10).collect();
+        let mut reader = SharedFrameReader::new(Cursor::new(output), 4);
+        let (first, bytes_read) = reader.read_frame()?.ok_or(NoneError)?;
+        assert_eq!((&first[..], bytes_read), (&[0, 1, 2, 3][..], 4));
+
+        // The next frame is read into the same allocation, just after it
+        let (second, _) = reader.read_frame()?.ok_or(NoneError)?;
+        assert_eq!(second.as_ptr(), first.as_ptr().wrapping_add(4));
+        drop((first, second));
+
+        // A frame cut short is padded, and then the output is over
+        let (last, bytes_read) = reader.read_frame()?.ok_or(NoneError)?;
+        assert_eq!((&last[..], bytes_read), (&[8, 9, 0, 0][..], 2));
+        drop(last);
+        assert_eq!(reader.read_frame()?, None);
+
+        // Once the ring is full, the space of the frames that were dropped is
+        // reused from the start
+        let ring_size = SHARED_FRAME_RING_SIZE;
+        let output = vec![1; 4 * (ring_size + 1)];
+        let mut reader = SharedFrameReader::new(Cursor::new(output), 4);
+        let (first, _) = reader.read_frame()?.ok_or(NoneError)?;
+        let first_ptr = first.as_ptr();
+        drop(first);
+        for _ in 1..ring_size {
+            reader.read_frame()?.ok_or(NoneError)?;
+        }
+        let (wrapped, _) = reader.read_frame()?.ok_or(NoneError)?;
+        assert_eq!(wrapped.as_ptr(), first_ptr);
+        Ok(())
+    }
+
+    #[test]
+    fn run_shared() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .add_input(FFMPEG_TEST_INPUT.to_string())
+            .set_scale(64, 36)
+            .set_max_frames(3);
+        let (frame_tx, frame_rx) = sync_channel(16);
+        hypetrigger.run_shared(frame_tx)?;
+        let frames: Vec<SharedFrame> = frame_rx.into_iter().collect();
+        let ids: Vec<_> = frames
+            .iter()
+            .map(|frame| (frame.frame_num, frame.input_index))
+            .collect();
+        assert_eq!(ids, [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]);
+        for pair in frames.chunks(2) {
+            // Both inputs are views of the one buffer the frame was read into
+            assert_eq!((pair[0].width, pair[0].height), (64, 36));
+            assert_eq!(pair[0].stride, 128 * 3);
+            assert_eq!(pair[1].data.as_ptr(), pair[0].data[64 * 3..].as_ptr());
+            let frame = pair[1].to_frame().ok_or(NoneError)?;
+            assert_eq!(frame.image.dimensions(), (64, 36));
+        }
+        assert_eq!(frames[2].timestamp, 0.5);
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
+use crate::audio::AudioChunk;
 use crate::error::Result;
+use crate::pipeline::{Hypetrigger, PixelFormat};
 use crate::trigger::{Frame, Trigger};
-use std::sync::Arc;
+use image::{GrayImage, RgbImage, RgbaImage};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
 
 /// A minimal Trigger implementation that just calls a callback on each frame.
 /// Functionally equivalent to a custom struct that implements `Trigger`, just
@@ -8,6 +12,9 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct SimpleTrigger {
     pub callback: Arc<dyn Fn(&Frame) + Send + Sync>,
+
+    /// The pixel format to receive frames in, if not the pipeline's own.
+    pub pixel_format: Option<PixelFormat>,
 }
 
 impl Trigger for SimpleTrigger {
@@ -15,6 +22,10 @@ impl Trigger for SimpleTrigger {
         (self.callback)(frame);
         Ok(())
     }
+
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.pixel_format
+    }
 }
 
 impl SimpleTrigger {
@@ -24,10 +35,204 @@ impl SimpleTrigger {
     {
         Self {
             callback: Arc::new(on_frame),
+            pixel_format: None,
+        }
+    }
+
+    /// Receive each frame as an `RgbImage`, e.g. to use with `imageproc`,
+    /// regardless of the pipeline's pixel format.
+    pub fn new_rgb<T>(on_frame: T) -> Self
+    where
+        T: Fn(&RgbImage, &Frame) + Send + Sync + 'static,
+    {
+        Self::with_pixel_format(PixelFormat::Rgb24, move |frame| {
+            if let Some(image) = frame.image.as_rgb8() {
+                on_frame(image, frame);
+            }
+        })
+    }
+
+    /// Receive each frame as a `GrayImage`, regardless of the pipeline's
+    /// pixel format.
+    pub fn new_gray<T>(on_frame: T) -> Self
+    where
+        T: Fn(&GrayImage, &Frame) + Send + Sync + 'static,
+    {
+        Self::with_pixel_format(PixelFormat::Gray8, move |frame| {
+            if let Some(image) = frame.image.as_luma8() {
+                on_frame(image, frame);
+            }
+        })
+    }
+
+    /// Receive each frame as an `RgbaImage`, regardless of the pipeline's
+    /// pixel format.
+    pub fn new_rgba<T>(on_frame: T) -> Self
+    where
+        T: Fn(&RgbaImage, &Frame) + Send + Sync + 'static,
+    {
+        Self::with_pixel_format(PixelFormat::Rgba, move |frame| {
+            if let Some(image) = frame.image.as_rgba8() {
+                on_frame(image, frame);
+            }
+        })
+    }
+
+    fn with_pixel_format<T>(pixel_format: PixelFormat, on_frame: T) -> Self
+    where
+        T: Fn(&Frame) + Send + Sync + 'static,
+    {
+        Self {
+            pixel_format: Some(pixel_format),
+            ..Self::new(on_frame)
         }
     }
 }
 
+/// Like `SimpleTrigger`, but calls the callback on each chunk of the input's
+/// audio instead of on each frame.
+#[derive(Clone)]
+pub struct SimpleAudioTrigger {
+    pub callback: Arc<dyn Fn(&AudioChunk) + Send + Sync>,
+}
+
+impl Trigger for SimpleAudioTrigger {
+    fn on_frame(&self, _frame: &Frame) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_audio(&self) -> bool {
+        true
+    }
+
+    fn on_audio(&self, chunk: &AudioChunk) -> Result<()> {
+        (self.callback)(chunk);
+        Ok(())
+    }
+}
+
+impl SimpleAudioTrigger {
+    pub fn new<T>(on_audio: T) -> Self
+    where
+        T: Fn(&AudioChunk) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(on_audio),
+        }
+    }
+}
+
+/// Like `SimpleTrigger`, but the callback decides whether to keep going:
+/// returning `ControlFlow::Break` stops the pipeline after the current frame.
+/// See `Trigger::on_frame_control`.
+#[derive(Clone)]
+pub struct UntilTrigger {
+    pub callback: UntilTriggerCallback,
+}
+
+pub type UntilTriggerCallback = Arc<dyn Fn(&Frame) -> ControlFlow<()> + Send + Sync>;
+
+impl Trigger for UntilTrigger {
+    fn on_frame(&self, frame: &Frame) -> Result<()> {
+        let _ = (self.callback)(frame);
+        Ok(())
+    }
+
+    fn on_frame_control(&self, frame: &Frame) -> Result<ControlFlow<()>> {
+        Ok((self.callback)(frame))
+    }
+}
+
+impl UntilTrigger {
+    pub fn new<T>(on_frame: T) -> Self
+    where
+        T: Fn(&Frame) -> ControlFlow<()> + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(on_frame),
+        }
+    }
+}
+
+/// Something detected in a frame by `Hypetrigger::run_to_completion`, along
+/// with where in the input it was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriggerEvent<T> {
+    pub value: T,
+    pub frame_num: u64,
+    pub timestamp: f64,
+    pub input_index: usize,
+}
+
+impl Hypetrigger {
+    /// The "just give me the results" way to process a whole input: run
+    /// `detect` on every frame (alongside any other Triggers), block until
+    /// ffmpeg finishes, and return everything it detected, in order. Frames
+    /// where it returns `None` are left out.
+    pub fn run_to_completion<T, F>(&self, detect: F) -> Result<Vec<TriggerEvent<T>>>
+    where
+        T: Send + 'static,
+        F: Fn(&Frame) -> Option<T> + Send + Sync + 'static,
+    {
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_clone = events.clone();
+        self.clone()
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                if let Some(value) = detect(frame) {
+                    // If it's poisoned, the error comes from the `lock` below instead
+                    if let Ok(mut events) = events_clone.lock() {
+                        events.push(TriggerEvent {
+                            value,
+                            frame_num: frame.frame_num,
+                            timestamp: frame.timestamp,
+                            input_index: frame.input_index,
+                        });
+                    }
+                }
+            }))
+            .run()?;
+        let events = std::mem::take(&mut *events.lock()?);
+        Ok(events)
+    }
+
+    /// Run `detect` on each frame until it first returns `Some`, then stop
+    /// ffmpeg straight away and return what it found, without reading the
+    /// rest of the input. Returns `None` if it never found anything.
+    pub fn find_first<T, F>(&self, detect: F) -> Result<Option<TriggerEvent<T>>>
+    where
+        T: Send + 'static,
+        F: Fn(&Frame) -> Option<T> + Send + Sync + 'static,
+    {
+        let event = Arc::new(Mutex::new(None));
+        let event_clone = event.clone();
+        self.clone()
+            .add_trigger(UntilTrigger::new(move |frame| {
+                // If it's poisoned, the error comes from the `lock` below instead
+                let Ok(mut event) = event_clone.lock() else {
+                    return ControlFlow::Break(());
+                };
+                if event.is_some() {
+                    return ControlFlow::Break(()); // another input got there first
+                }
+                match detect(frame) {
+                    Some(value) => {
+                        *event = Some(TriggerEvent {
+                            value,
+                            frame_num: frame.frame_num,
+                            timestamp: frame.timestamp,
+                            input_index: frame.input_index,
+                        });
+                        ControlFlow::Break(())
+                    }
+                    None => ControlFlow::Continue(()),
+                }
+            }))
+            .run()?;
+        let event = event.lock()?.take();
+        Ok(event)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::SimpleTrigger;
@@ -35,6 +240,8 @@ mod tests {
         error::{Error, Result},
         pipeline::Hypetrigger,
     };
+    use image::GenericImageView;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn simple_trigger() -> Result<()> {
@@ -52,4 +259,60 @@ mod tests {
             .run()
             .map_err(Error::from_display)
     }
+
+    #[test]
+    fn typed_images() -> Result<()> {
+        let sizes = Arc::new(Mutex::new(vec![]));
+        let (gray_sizes, rgba_sizes) = (sizes.clone(), sizes.clone());
+        Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new_gray(move |image, _| {
+                gray_sizes.lock().unwrap().push(image.as_raw().len());
+            }))
+            .add_trigger(SimpleTrigger::new_rgba(move |image, _| {
+                rgba_sizes.lock().unwrap().push(image.as_raw().len());
+            }))
+            .run()?;
+        let sizes = sizes.lock()?;
+        assert!(!sizes.is_empty());
+        assert_eq!(sizes[..2], [64 * 36, 64 * 36 * 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn run_to_completion() -> Result<()> {
+        let events = Hypetrigger::new()
+            .test_input()
+            .set_scale(64, 36)
+            .run_to_completion(|frame| (frame.frame_num % 2 == 0).then_some(frame.frame_num))?;
+        assert!(!events.is_empty());
+        assert!(events
+            .iter()
+            .enumerate()
+            .all(|(i, event)| event.value == i as u64 * 2 && event.frame_num == event.value));
+        Ok(())
+    }
+
+    #[test]
+    fn find_first() -> Result<()> {
+        let frames_seen = Arc::new(Mutex::new(0));
+        let frames_seen_clone = frames_seen.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_scale(64, 36)
+            .add_trigger(SimpleTrigger::new(move |_| {
+                *frames_seen_clone.lock().unwrap() += 1;
+            }));
+        let event = hypetrigger.find_first(|frame| (frame.frame_num == 3).then_some("found"))?;
+        assert_eq!(
+            event.map(|event| (event.value, event.frame_num)),
+            Some(("found", 3))
+        );
+        assert_eq!(*frames_seen.lock()?, 4); // no frames after the one that stopped it
+
+        assert!(hypetrigger.find_first(|_| None::<()>)?.is_none());
+        Ok(())
+    }
 }
@@ -0,0 +1,262 @@
+// Keeps a long-running pipeline alive when ffmpeg crashes partway through,
+// e.g. on a corrupt packet hours into a stream, by starting it up again.
+
+use crate::concat::concat_list_path;
+use crate::error::{FfmpegError, FfmpegFailure, Result};
+use crate::log;
+use crate::pipeline::{check_ffmpeg_exit, CompletionStats, Hypetrigger};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How `Hypetrigger::run_supervised` restarts ffmpeg after it crashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Give up after restarting this many times in total.
+    pub max_restarts: u32,
+
+    /// How long to wait before the first restart. The wait doubles with each
+    /// restart after that, up to `max_backoff`.
+    pub backoff: Duration,
+
+    /// Upper limit for the wait between restarts.
+    pub max_backoff: Duration,
+
+    /// Whether to seek to where the crashed process left off (the timestamp
+    /// of the next frame), rather than starting over from `start_time`. Turn
+    /// this off for live streams, which can't seek.
+    pub resume: bool,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            resume: true,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// The wait before the given restart (starting at 1).
+    pub fn backoff_for(&self, restart: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(restart.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Passed to the `on_restart` callback each time ffmpeg is restarted.
+#[derive(Debug, Clone)]
+pub struct FfmpegRestart {
+    /// Which restart this is, starting at 1.
+    pub restart: u32,
+
+    /// How the previous ffmpeg process exited, and what it last printed.
+    pub failure: FfmpegFailure,
+
+    /// Where in the input the new ffmpeg process starts from.
+    pub resume_from: Duration,
+
+    /// Number of the first frame the new process will produce, continuing
+    /// from the frames read before the crash.
+    pub first_frame_num: u64,
+}
+
+impl Hypetrigger {
+    /// Like `run`, but if ffmpeg exits unsuccessfully (or stalls for longer
+    /// than the `stdout_read_timeout`), start it again according to the
+    /// `RestartPolicy`, calling the `on_restart` callback each time.
+    /// Frame numbers keep counting up across restarts. Blocks until ffmpeg
+    /// finishes successfully, and returns an error if it's still failing once
+    /// the restarts have run out.
+    pub fn run_supervised(&mut self, policy: &RestartPolicy) -> Result<()> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        self.validate()?;
+        self.check_ffmpeg_support()?;
+        self.prepare_output_dirs()?;
+        if self.has_audio_triggers() {
+            return Err(FfmpegError::InvalidConfig(
+                "audio Triggers are only supported by `run`".to_string(),
+            )
+            .into());
+        }
+        if self.is_stdin_input() {
+            return Err(FfmpegError::InvalidConfig(
+                "an input from stdin can't be restarted".to_string(),
+            )
+            .into());
+        }
+        let started_at = Instant::now();
+        let start_time = self.start_time.unwrap_or_default();
+        let mut frames_read = 0;
+        let mut trigger_frames = vec![0; self.triggers.len()];
+        let mut frames_dropped = 0;
+        let mut ffmpeg_dropped = 0;
+        let mut ffmpeg_duplicated = 0;
+        let mut restarts = 0;
+        let mut attempt = self.clone();
+
+        // `on_complete` is only called once, after the last attempt
+        attempt.on_complete_callback = None;
+
+        // Every attempt reads the same list
+        let concat_list = attempt.write_concat_list()?;
+        loop {
+            let mut ffmpeg_child = attempt.spawn_ffmpeg_child(concat_list_path(&concat_list))?;
+            let summary = attempt.attach_child(&mut ffmpeg_child, frames_read)?;
+            let ffmpeg_exit_status = ffmpeg_child.wait()?;
+            log_debug!(
+                "[ffmpeg] ffmpeg command exited with status {}",
+                ffmpeg_exit_status
+            );
+
+            frames_read += summary.frames_read;
+            frames_dropped += summary.frames_dropped;
+            ffmpeg_dropped += summary.ffmpeg_dropped;
+            ffmpeg_duplicated += summary.ffmpeg_duplicated;
+            for (total, count) in trigger_frames.iter_mut().zip(&summary.trigger_frames) {
+                *total += count;
+            }
+            if ffmpeg_exit_status.success() || summary.stopped_early {
+                break;
+            }
+            if restarts >= policy.max_restarts {
+                if summary.timed_out {
+                    let timeout = self.stdout_read_timeout.unwrap_or_default();
+                    return Err(FfmpegError::StdoutTimeout(timeout).into());
+                }
+                // Still report ffmpeg failing to start at all the usual way
+                if frames_read == 0 {
+                    check_ffmpeg_exit(
+                        ffmpeg_exit_status,
+                        summary.clone(),
+                        self.on_ffmpeg_error_callback.as_ref(),
+                    )?;
+                }
+                return Err(FfmpegError::Exited(FfmpegFailure {
+                    status: ffmpeg_exit_status,
+                    stderr_tail: summary.stderr_tail,
+                })
+                .into());
+            }
+            restarts += 1;
+            metric!(counter!(
+                "hypetrigger_ffmpeg_restarts_total",
+                "input" => self.metrics_input()
+            )
+            .increment(1));
+
+            let resume_from = if policy.resume {
+                let elapsed = Duration::from_secs_f64(frames_read as f64 / self.fps as f64);
+                if let Some(duration) = self.duration {
+                    if elapsed >= duration {
+                        break; // everything was processed before the crash
+                    }
+                    attempt.duration = Some(duration - elapsed);
+                }
+                attempt.start_time = Some(start_time + elapsed);
+                start_time + elapsed
+            } else {
+                start_time
+            };
+
+            let restart = FfmpegRestart {
+                restart: restarts,
+                failure: FfmpegFailure {
+                    status: ffmpeg_exit_status,
+                    stderr_tail: summary.stderr_tail,
+                },
+                resume_from,
+                first_frame_num: frames_read,
+            };
+            log_warn!(
+                "[ffmpeg] ffmpeg exited with {}; restarting ({} of {})",
+                ffmpeg_exit_status,
+                restarts,
+                policy.max_restarts
+            );
+            if let Some(callback) = &self.on_restart_callback {
+                callback(&restart);
+            }
+            thread::sleep(policy.backoff_for(restarts));
+        }
+
+        if let Some(callback) = &self.on_complete_callback {
+            callback(CompletionStats {
+                frames_read,
+                trigger_frames,
+                frames_dropped,
+                ffmpeg_dropped,
+                ffmpeg_duplicated,
+                elapsed: started_at.elapsed(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RestartPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff() {
+        let policy = RestartPolicy {
+            max_restarts: 10,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            resume: true,
+        };
+        assert_eq!(policy.backoff_for(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff_for(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff_for(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff_for(4), Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restart() -> crate::error::Result<()> {
+        use crate::{pipeline::Hypetrigger, simple_trigger::SimpleTrigger, test_util::fake_ffmpeg};
+        use std::sync::{Arc, Mutex};
+
+        // Wrap ffmpeg with a script that crashes the first time it's run,
+        // leaving a marker file next to itself
+        let ffmpeg = fake_ffmpeg(
+            "restart",
+            "#!/bin/sh\n\
+             marker=\"$(dirname \"$0\")/crashed\"\n\
+             if [ -e \"$marker\" ]; then exec ffmpeg \"$@\"; fi\n\
+             touch \"$marker\"\n\
+             ffmpeg \"$@\"\n\
+             exit 1\n",
+        )?;
+
+        let frame_nums = Arc::new(Mutex::new(vec![]));
+        let frame_nums_clone = frame_nums.clone();
+        let restarts = Arc::new(Mutex::new(vec![]));
+        let restarts_clone = restarts.clone();
+        let result = Hypetrigger::new()
+            .set_ffmpeg_exe(&ffmpeg.exe)
+            .test_input()
+            .add_trigger(SimpleTrigger::new(move |frame| {
+                frame_nums_clone.lock().unwrap().push(frame.frame_num);
+            }))
+            .on_restart(move |restart| {
+                restarts_clone.lock().unwrap().push(restart.resume_from);
+            })
+            .run_supervised(&RestartPolicy {
+                backoff: Duration::ZERO,
+                ..Default::default()
+            });
+        result?;
+
+        // The test input is 10 seconds long, read at 2 fps
+        assert_eq!(*restarts.lock()?, vec![Duration::from_secs(10)]);
+        let frame_nums = frame_nums.lock()?;
+        assert!(frame_nums.len() >= 20);
+        assert!(frame_nums.iter().enumerate().all(|(i, &n)| n == i as u64));
+        Ok(())
+    }
+}
@@ -1,11 +1,19 @@
 use crate::{
-    debug::debug_photon_image,
-    error::Result,
-    photon::{ensure_size, ensure_square, rgb_to_photon, rgba32_to_rgb24, Crop},
-    trigger::{Frame, Trigger},
+    debug::{debug_photon_image, save_photon_screenshot, ScreenshotFormat},
+    error::{Error, Result},
+    photon::{dynamic_to_photon, ensure_size, ensure_square, rgba32_to_rgb24, CropRegion},
+    trigger::{Frame, Trigger, TriggerPayload},
+    trigger_config::TriggerConfig,
 };
 use photon_rs::PhotonImage;
-use std::{path::Path, sync::Arc, time::Instant};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tensorflow::{Graph, SavedModelBundle, SessionOptions, SessionRunArgs, Tensor};
 
 /// Side length of the square image that the model expects
@@ -20,10 +28,30 @@ pub const TENSORFLOW_RUNNER: &str = "tensorflow";
 pub type TensorflowTriggerCallback = Arc<dyn Fn(TensorflowResult) + Send + Sync>;
 
 pub struct TensorflowTrigger {
-    pub crop: Option<Crop>,
+    pub crop: Option<CropRegion>,
+
+    /// The directory the model was loaded from
+    pub model_dir: PathBuf,
+
     pub bundle: SavedModelBundle,
     pub graph: Graph,
     pub callback: Option<TensorflowTriggerCallback>,
+
+    /// If set, the same class isn't reported to `on_trigger_event` again until
+    /// the debounce window has passed. See `Trigger::get_debounce`; the
+    /// `callback` still gets every prediction.
+    pub debounce: Option<Duration>,
+
+    /// If set, save the pre-processed image of each frame to this directory
+    /// (as `tensorflow_<trigger_id>_<frame_num>.png` by default), to see
+    /// exactly what the model was given. See `save_screenshot`.
+    pub screenshots_dir: Option<PathBuf>,
+
+    /// How the screenshots are encoded, e.g. JPEG to save disk space.
+    pub screenshot_format: ScreenshotFormat,
+
+    /// Whether to run the model at all. See `Trigger::get_enabled`.
+    pub enabled: bool,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -45,11 +73,28 @@ pub struct TensorflowResult {
 
 impl Trigger for TensorflowTrigger {
     fn on_frame(&self, frame: &Frame) -> Result<()> {
+        self.on_frame_events(frame, &mut vec![]).map(|_| ())
+    }
+
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
         // 1. convert raw image to photon
-        let image = rgb_to_photon(&frame.image);
+        let image = dynamic_to_photon(&frame.image);
 
         // 2. preprocess
         let filtered = self.preprocess_image(image)?;
+        if let Some(screenshots_dir) = &self.screenshots_dir {
+            save_photon_screenshot(
+                screenshots_dir,
+                "tensorflow",
+                frame.frame_num,
+                &filtered,
+                self.screenshot_format,
+            )?;
+        }
 
         // 3. image classification
         let rgba32 = filtered.get_raw_pixels();
@@ -59,6 +104,10 @@ impl Trigger for TensorflowTrigger {
         let prediction = predict(&self.bundle, &self.graph, &tensor)?;
 
         // 4. callback
+        payloads.push(TriggerPayload::Classification {
+            class_index: prediction.class_index,
+            confidence: prediction.confidence,
+        });
         if let Some(callback) = &self.callback {
             let result = TensorflowResult {
                 prediction,
@@ -68,33 +117,130 @@ impl Trigger for TensorflowTrigger {
             callback(result);
         }
 
-        Ok(())
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        self.crop.as_ref().map(|crop| crop.bounds(width, height))
+    }
+
+    fn get_debounce(&self) -> Option<Duration> {
+        self.debounce
+    }
+
+    fn to_config(&self) -> Option<TriggerConfig> {
+        Some(TriggerConfig::Tensorflow(TensorflowConfig {
+            model_dir: self.model_dir.clone(),
+            crop: self.crop.clone(),
+            debounce: self.debounce,
+            screenshots_dir: self.screenshots_dir.clone(),
+            screenshot_format: self.screenshot_format,
+            enabled: self.enabled,
+        }))
+    }
+
+    fn get_enabled(&self) -> bool {
+        self.enabled
     }
+
+    fn get_output_dirs(&self) -> Vec<PathBuf> {
+        self.screenshots_dir.iter().cloned().collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        match &self.crop {
+            Some(crop) => crop.validate().map_err(Error::from),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The settings of a `TensorflowTrigger`, to save it as a `TriggerConfig`.
+/// See the fields of `TensorflowTrigger` for what each one does.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TensorflowConfig {
+    pub model_dir: PathBuf,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub crop: Option<CropRegion>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub debounce: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub screenshots_dir: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub screenshot_format: ScreenshotFormat,
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enabled: bool,
+}
+
+#[cfg(feature = "serde")]
+fn default_enabled() -> bool {
+    true
 }
 
 impl TensorflowTrigger {
+    /// A trigger loading its model from a saved `TensorflowConfig`.
+    pub fn from_config(config: &TensorflowConfig) -> Result<Self> {
+        let mut trigger = Self::new(&config.model_dir, config.crop.clone(), None)?;
+        trigger.debounce = config.debounce;
+        trigger.screenshots_dir = config.screenshots_dir.clone();
+        trigger.screenshot_format = config.screenshot_format;
+        trigger.enabled = config.enabled;
+        Ok(trigger)
+    }
+
     pub fn new<P>(
         model_dir: P,
-        crop: Option<Crop>,
+        crop: Option<CropRegion>,
         callback: Option<TensorflowTriggerCallback>,
     ) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let (bundle, graph) = load_tensorflow_model(model_dir)?;
+        let (bundle, graph) = load_tensorflow_model(&model_dir)?;
         Ok(Self {
+            model_dir: model_dir.as_ref().to_path_buf(),
             bundle,
             graph,
             crop,
             callback,
+            debounce: None,
+            screenshots_dir: None,
+            screenshot_format: ScreenshotFormat::default(),
+            enabled: true,
         })
     }
 
+    /// Setter for whether to run the model at all
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Only report the same class once within the given time window (in the
+    /// input's own time)
+    pub fn set_debounce(&mut self, window: Duration) -> &mut Self {
+        self.debounce = Some(window);
+        self
+    }
+
+    /// Save the pre-processed image of each frame to this directory, in the
+    /// given format.
+    pub fn set_screenshots_dir(
+        &mut self,
+        screenshots_dir: impl Into<PathBuf>,
+        screenshot_format: ScreenshotFormat,
+    ) -> &mut Self {
+        self.screenshots_dir = Some(screenshots_dir.into());
+        self.screenshot_format = screenshot_format;
+        self
+    }
+
     pub fn preprocess_image(&self, mut image: PhotonImage) -> Result<PhotonImage> {
         /// If `true`, pauses execution after each step of image pre-processing.
         const DEBUG: bool = false;
         if DEBUG {
-            println!("[tensorflow] received frame");
+            log_debug!("[tensorflow] received frame");
             debug_photon_image(&image)?;
         }
 
@@ -107,7 +253,7 @@ impl TensorflowTrigger {
         image = ensure_size(image, size, size);
 
         if DEBUG {
-            println!("[tensorflow] center square crop and resize to 224x224 px");
+            log_debug!("[tensorflow] center square crop and resize to 224x224 px");
             debug_photon_image(&image)?;
         }
 
@@ -122,13 +268,13 @@ pub fn load_tensorflow_model<P>(model_dir: P) -> Result<(SavedModelBundle, Graph
 where
     P: AsRef<Path>,
 {
-    println!("[tensorflow] Loading saved model");
+    log_debug!("[tensorflow] Loading saved model");
     let now = Instant::now();
 
     let mut graph = Graph::new();
     let bundle = SavedModelBundle::load(&SessionOptions::new(), ["serve"], &mut graph, model_dir)?;
 
-    println!(
+    log_debug!(
         "[tensorflow] load_tensorflow_model {}ms.",
         now.elapsed().as_millis()
     );
@@ -136,7 +282,7 @@ where
     // Initialize the session by running a dummy input through the graph.
     let dummy = dummy_tensor();
     predict(&bundle, &graph, &dummy)?;
-    println!("[tensorflow] finished test run");
+    log_debug!("[tensorflow] finished test run");
 
     Ok((bundle, graph))
 }
@@ -1,14 +1,21 @@
-use crate::debug::debug_photon_image;
-use crate::error::{NoneError, Result};
-use crate::photon::{ensure_minimum_size, rgb_to_photon, Crop, ThresholdFilter};
-use crate::trigger::{Frame, Trigger};
+use crate::debug::{debug_photon_image, save_photon_screenshot, ScreenshotFormat};
+use crate::error::{Error, NoneError, Result};
+use crate::photon::{dynamic_to_photon, ensure_minimum_size, CropRegion, ThresholdFilter};
+use crate::pipeline::Transpose;
+use crate::trigger::{Frame, Trigger, TriggerPayload};
+use crate::trigger_config::TriggerConfig;
+use image::GenericImageView;
 use photon_rs::transform::padding_uniform;
 use photon_rs::{PhotonImage, Rgba};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::ops::ControlFlow;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     fs::{self, File},
-    path::Path,
+    path::{Path, PathBuf},
     sync::Mutex,
 };
 use tesseract::Tesseract;
@@ -27,31 +34,105 @@ pub struct TesseractTrigger {
     /// The initialized instance of Tesseract that will be used to run this trigger
     pub tesseract: TesseractRef,
 
+    /// The language the `tesseract` instance was initialized with, if it was
+    /// by `with_language` or `from_config`. Without it, the Trigger can't be
+    /// saved as a `TriggerConfig`.
+    pub language: Option<String>,
+
+    /// The datapath the `tesseract` instance was initialized with, or `None`
+    /// for the default (see `init_tesseract`).
+    pub datapath: Option<String>,
+
     /// The region to crop to before running OCR.
-    pub crop: Option<Crop>,
+    pub crop: Option<CropRegion>,
+
+    /// Rotate or flip the frame before cropping, e.g. for sideways text. See
+    /// `Trigger::get_transpose`. The crop is in terms of the transposed frame,
+    /// but with a crop, it's cut out of the frame first and only the cropped
+    /// region is transposed.
+    pub transpose: Option<Transpose>,
 
     /// The threshold filter to apply before running OCR.
     pub threshold_filter: Option<ThresholdFilter>,
 
+    /// Invert the colors before running OCR. Tesseract reads dark text on a
+    /// light background best, so enable this for light text on a dark
+    /// background. See `Trigger::get_invert`; the frame is inverted before the
+    /// crop and threshold filter, so the filter's color is of the inverted
+    /// frame.
+    pub invert: bool,
+
+    /// Only recognize these characters, e.g. `"0123456789"` for a scoreboard.
+    /// Corresponds to the Tesseract `tessedit_char_whitelist` variable. This is
+    /// a setting of the `tesseract` instance, applied by `set_char_whitelist`,
+    /// so it's shared with any other Trigger using the same instance.
+    pub char_whitelist: Option<String>,
+
+    /// Page segmentation mode, i.e. how Tesseract expects the text to be laid
+    /// out, e.g. `7` for a single line of text. Corresponds to the Tesseract
+    /// `--psm` option. If `None`, uses Tesseract's default. Like the
+    /// `char_whitelist`, it's applied to the instance by `set_psm`.
+    pub psm: Option<u8>,
+
     /// The callback to run after OCR is complete.
     pub callback: Option<TesseractTriggerCallback>,
 
+    /// If set, the same text isn't reported to `on_trigger_event` again until
+    /// the debounce window has passed. See `Trigger::get_debounce`; the
+    /// `callback` still gets every result.
+    pub debounce: Option<Duration>,
+
     /// Pause execution after each step of image pre-processing.
     pub enable_debug_breakpoints: bool,
+
+    /// If set, save the pre-processed image of each frame to this directory
+    /// (as `tesseract_<trigger_id>_<frame_num>.png` by default), to see exactly
+    /// what Tesseract read. See `save_screenshot`.
+    pub screenshots_dir: Option<PathBuf>,
+
+    /// How the screenshots are encoded, e.g. JPEG to save disk space.
+    pub screenshot_format: ScreenshotFormat,
+
+    /// Whether to run OCR at all. See `Trigger::get_enabled`.
+    pub enabled: bool,
 }
 
 impl Trigger for TesseractTrigger {
     fn on_frame(&self, frame: &Frame) -> Result<()> {
-        // 1. convert raw image to photon
-        let image = rgb_to_photon(&frame.image);
+        self.on_frame_events(frame, &mut vec![]).map(|_| ())
+    }
 
-        // 2. preprocess
-        let filtered = self.preprocess_image(image)?;
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
+        // 1. convert raw image to photon, cropping first if it's transposed
+        let filtered = match (&self.crop, self.transpose) {
+            (Some(crop), Some(transpose)) => {
+                let (width, height) = (frame.image.width(), frame.image.height());
+                let (width, height) = transpose.output_size(width, height);
+                let region = crop.bounds(width, height);
+                let image = dynamic_to_photon(&transpose.apply_to_region(&frame.image, region));
+                self.preprocess_cropped(image)?
+            }
+            _ => self.preprocess_image(dynamic_to_photon(&frame.image))?,
+        };
+        if let Some(screenshots_dir) = &self.screenshots_dir {
+            save_photon_screenshot(
+                screenshots_dir,
+                "tesseract",
+                frame.frame_num,
+                &filtered,
+                self.screenshot_format,
+            )?;
+        }
 
         // 3. run ocr
         let text = self.ocr(filtered)?;
 
         // 4. callback
+        payloads.push(TriggerPayload::Text(text.clone()));
         if let Some(callback) = &self.callback {
             let result = TesseractResult {
                 text,
@@ -61,7 +142,108 @@ impl Trigger for TesseractTrigger {
             callback(result);
         }
 
-        Ok(())
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn get_invert(&self) -> bool {
+        self.invert
+    }
+
+    fn get_debounce(&self) -> Option<Duration> {
+        self.debounce
+    }
+
+    fn get_transpose(&self) -> Option<Transpose> {
+        // With a crop, just the cropped region is transposed, in `on_frame_events`
+        self.transpose.filter(|_| self.crop.is_none())
+    }
+
+    fn get_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn get_output_dirs(&self) -> Vec<PathBuf> {
+        self.screenshots_dir.iter().cloned().collect()
+    }
+
+    fn get_region(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let crop = self.crop.as_ref()?;
+        Some(match self.transpose {
+            Some(transpose) => {
+                let (t_width, t_height) = transpose.output_size(width, height);
+                transpose.unmap_region(crop.bounds(t_width, t_height), width, height)
+            }
+            None => crop.bounds(width, height),
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        match &self.crop {
+            Some(crop) => crop.validate().map_err(Error::from),
+            None => Ok(()),
+        }
+    }
+
+    fn with_crop(&self, crop: CropRegion) -> Option<Arc<dyn Trigger>> {
+        Some(Arc::new(Self {
+            crop: Some(crop),
+            ..self.clone()
+        }))
+    }
+
+    fn to_config(&self) -> Option<TriggerConfig> {
+        Some(TriggerConfig::Tesseract(TesseractConfig {
+            language: self.language.clone()?,
+            datapath: self.datapath.clone(),
+            crop: self.crop.clone(),
+            transpose: self.transpose,
+            threshold_filter: self.threshold_filter,
+            invert: self.invert,
+            char_whitelist: self.char_whitelist.clone(),
+            psm: self.psm,
+            debounce: self.debounce,
+            screenshots_dir: self.screenshots_dir.clone(),
+            screenshot_format: self.screenshot_format,
+            enabled: self.enabled,
+        }))
+    }
+}
+
+/// The settings of a `TesseractTrigger`, to save it as a `TriggerConfig`.
+/// See the fields of `TesseractTrigger` for what each one does.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct TesseractConfig {
+    pub language: String,
+    pub datapath: Option<String>,
+    pub crop: Option<CropRegion>,
+    pub transpose: Option<Transpose>,
+    pub threshold_filter: Option<ThresholdFilter>,
+    pub invert: bool,
+    pub char_whitelist: Option<String>,
+    pub psm: Option<u8>,
+    pub debounce: Option<Duration>,
+    pub screenshots_dir: Option<PathBuf>,
+    pub screenshot_format: ScreenshotFormat,
+    pub enabled: bool,
+}
+
+impl Default for TesseractConfig {
+    fn default() -> Self {
+        Self {
+            language: "eng".to_string(),
+            datapath: None,
+            crop: None,
+            transpose: None,
+            threshold_filter: None,
+            invert: false,
+            char_whitelist: None,
+            psm: None,
+            debounce: None,
+            screenshots_dir: None,
+            screenshot_format: ScreenshotFormat::default(),
+            enabled: true,
+        }
     }
 }
 
@@ -69,16 +251,136 @@ impl TesseractTrigger {
     pub fn new() -> Self {
         Self {
             tesseract: Arc::new(Mutex::new(None)),
+            language: None,
+            datapath: None,
             crop: None,
+            transpose: None,
             threshold_filter: None,
+            invert: false,
+            char_whitelist: None,
+            psm: None,
             callback: None,
+            debounce: None,
             enable_debug_breakpoints: false,
+            screenshots_dir: None,
+            screenshot_format: ScreenshotFormat::default(),
+            enabled: true,
         }
     }
 
+    /// A trigger with its own Tesseract instance for the given language (e.g.
+    /// `"eng"`), downloading the traineddata if needed. See `init_tesseract`.
+    pub fn with_language(language: &str) -> Result<Self> {
+        Ok(Self {
+            tesseract: init_tesseract(None, language)?,
+            language: Some(language.to_string()),
+            ..Self::new()
+        })
+    }
+
+    /// A trigger with its own Tesseract instance, set up from a saved
+    /// `TesseractConfig`.
+    pub fn from_config(config: &TesseractConfig) -> Result<Self> {
+        let mut trigger = Self {
+            tesseract: init_tesseract(config.datapath.as_deref(), config.language.as_str())?,
+            language: Some(config.language.clone()),
+            datapath: config.datapath.clone(),
+            crop: config.crop.clone(),
+            transpose: config.transpose,
+            threshold_filter: config.threshold_filter,
+            invert: config.invert,
+            debounce: config.debounce,
+            screenshots_dir: config.screenshots_dir.clone(),
+            screenshot_format: config.screenshot_format,
+            enabled: config.enabled,
+            ..Self::new()
+        };
+        if let Some(char_whitelist) = &config.char_whitelist {
+            trigger.set_char_whitelist(char_whitelist)?;
+        }
+        if let Some(psm) = config.psm {
+            trigger.set_psm(psm)?;
+        }
+        Ok(trigger)
+    }
+
+    /// Setter for the crop region
+    pub fn set_crop(&mut self, crop: CropRegion) -> &mut Self {
+        self.crop = Some(crop);
+        self
+    }
+
+    /// Setter for the rotation or flip
+    pub fn set_transpose(&mut self, transpose: Transpose) -> &mut Self {
+        self.transpose = Some(transpose);
+        self
+    }
+
+    /// Setter for whether to run OCR at all
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Setter for the characters to recognize. This configures the
+    /// `tesseract` instance once, up front, so set it up first (e.g. with
+    /// `with_language`).
+    pub fn set_char_whitelist(&mut self, char_whitelist: &str) -> Result<&mut Self> {
+        self.set_variable("tessedit_char_whitelist", char_whitelist)?;
+        self.char_whitelist = Some(char_whitelist.to_string());
+        Ok(self)
+    }
+
+    /// Setter for the page segmentation mode. Like `set_char_whitelist`, this
+    /// configures the `tesseract` instance up front.
+    pub fn set_psm(&mut self, psm: u8) -> Result<&mut Self> {
+        self.set_variable("tessedit_pageseg_mode", &psm.to_string())?;
+        self.psm = Some(psm);
+        Ok(self)
+    }
+
+    /// Set a variable on the `tesseract` instance. Tesseract consumes the
+    /// instance to do so, and doesn't give it back if it fails, so this is
+    /// only done while setting up the Trigger (where the error is returned),
+    /// and never while running OCR on frames.
+    fn set_variable(&self, name: &str, value: &str) -> Result<()> {
+        let mut mutex_guard = self.tesseract.lock()?;
+        let tesseract = mutex_guard.take().ok_or(NoneError)?;
+        let _tesseract = mutex_guard.insert(tesseract.set_variable(name, value)?);
+        Ok(())
+    }
+
+    /// Save the pre-processed image of each frame to this directory, in the
+    /// given format.
+    pub fn set_screenshots_dir(
+        &mut self,
+        screenshots_dir: impl Into<PathBuf>,
+        screenshot_format: ScreenshotFormat,
+    ) -> &mut Self {
+        self.screenshots_dir = Some(screenshots_dir.into());
+        self.screenshot_format = screenshot_format;
+        self
+    }
+
+    /// Only report the same text once within the given time window (in the
+    /// input's own time), e.g. for a message that stays on screen
+    pub fn set_debounce(&mut self, window: Duration) -> &mut Self {
+        self.debounce = Some(window);
+        self
+    }
+
+    /// Setter for the callback to run with the recognized text
+    pub fn on_text<T>(&mut self, callback: T) -> &mut Self
+    where
+        T: Fn(TesseractResult) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
     pub fn preprocess_image(&self, mut image: PhotonImage) -> Result<PhotonImage> {
         if self.enable_debug_breakpoints {
-            println!("[tesseract] received frame");
+            log_debug!("[tesseract] received frame");
             debug_photon_image(&image)?;
         }
 
@@ -86,16 +388,21 @@ impl TesseractTrigger {
         if let Some(crop) = &self.crop {
             image = crop.apply(image);
             if self.enable_debug_breakpoints {
-                println!("[tesseract] crop: {:?}", self.crop);
+                log_debug!("[tesseract] crop: {:?}", self.crop);
                 debug_photon_image(&image)?;
             }
         }
 
+        self.preprocess_cropped(image)
+    }
+
+    /// The rest of `preprocess_image`, for an image that's already cropped.
+    fn preprocess_cropped(&self, mut image: PhotonImage) -> Result<PhotonImage> {
         // Minimum size
         const MIN_TESSERACT_IMAGE_SIZE: u32 = 32;
         image = ensure_minimum_size(&image, MIN_TESSERACT_IMAGE_SIZE);
         if self.enable_debug_breakpoints {
-            println!("[tesseract] resized");
+            log_debug!("[tesseract] resized");
             debug_photon_image(&image)?;
         }
 
@@ -103,7 +410,7 @@ impl TesseractTrigger {
         if let Some(filter) = &self.threshold_filter {
             image = filter.apply(image);
             if self.enable_debug_breakpoints {
-                println!("[tesseract] filter: {:?}", filter);
+                log_debug!("[tesseract] filter: {:?}", filter);
                 debug_photon_image(&image)?;
             }
         }
@@ -112,7 +419,7 @@ impl TesseractTrigger {
         let padding_bg: Rgba = Rgba::new(255, 255, 255, 255);
         image = padding_uniform(&image, MIN_TESSERACT_IMAGE_SIZE, padding_bg);
         if self.enable_debug_breakpoints {
-            println!("[tesseract] padded (done)");
+            log_debug!("[tesseract] padded (done)");
             debug_photon_image(&image)?;
         }
 
@@ -162,7 +469,7 @@ pub fn download_tesseract_traineddata(download_path: &Path) -> Result<()> {
     let body = reqwest::blocking::get(url)?.bytes()?;
 
     // Automatically create needed directories
-    fs::create_dir_all(download_path.parent().unwrap())?;
+    fs::create_dir_all(download_path.parent().ok_or(NoneError)?)?;
 
     // Write to file
     let mut file = File::create(download_path)?;
@@ -185,22 +492,22 @@ where
         .ok_or(NoneError)?;
     let datapath = Into::<Option<&str>>::into(datapath).unwrap_or(default_datapath);
     let language = Into::<Option<&str>>::into(language).unwrap_or("eng");
-    println!("[tesseract] using datapath {}", datapath);
-    println!("[tesseract] using language {}", language);
+    log_debug!("[tesseract] using datapath {}", datapath);
+    log_debug!("[tesseract] using language {}", language);
 
     // Check for trainedata and try downloading if needed
     let traineddata_pathbuf = Path::new(datapath).join(format!("{}.traineddata", language));
     let traineddata_path = traineddata_pathbuf.as_path();
     if !traineddata_path.exists() {
-        println!(
+        log_debug!(
             "[tesseract] could not find traineddata at {}",
             traineddata_path.display()
         );
-        println!("[tesseract] downloading traineddata...");
+        log_debug!("[tesseract] downloading traineddata...");
         download_tesseract_traineddata(traineddata_path)?;
-        println!("[tesseract] traineddata downloaded!");
+        log_debug!("[tesseract] traineddata downloaded!");
     } else {
-        println!("[tesseract] found traineddata")
+        log_debug!("[tesseract] found traineddata")
     }
 
     let tesseract = Tesseract::new(Some(datapath), Some(language))?;
@@ -211,24 +518,44 @@ where
 mod tests {
     use super::{init_tesseract, TesseractTrigger};
     use crate::async_trigger::{AsyncTrigger, TriggerThread};
+    use crate::debug::ScreenshotFormat;
     use crate::error::{Error, Result};
-    use crate::photon::Crop;
+    use crate::photon::{AnchorX, AnchorY, Crop};
     use crate::pipeline::Hypetrigger;
+    use crate::trigger::Trigger;
+    use crate::trigger_config::TriggerConfig;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
 
     #[test]
     fn tesseract() -> Result<()> {
         let tesseract = init_tesseract(None, None)?;
         let trigger = TesseractTrigger {
             tesseract,
-            crop: Some(Crop {
-                left_percent: 25.0,
-                top_percent: 25.0,
-                width_percent: 10.0,
-                height_percent: 10.0,
-            }),
+            language: None,
+            datapath: None,
+            crop: Some(
+                Crop {
+                    left_percent: 25.0,
+                    top_percent: 25.0,
+                    width_percent: 10.0,
+                    height_percent: 10.0,
+                    anchor_x: AnchorX::Left,
+                    anchor_y: AnchorY::Top,
+                }
+                .into(),
+            ),
+            transpose: None,
             threshold_filter: None,
+            invert: false,
+            char_whitelist: None,
+            psm: None,
             callback: None,
+            debounce: None,
             enable_debug_breakpoints: false,
+            screenshots_dir: None,
+            screenshot_format: ScreenshotFormat::default(),
+            enabled: true,
         };
 
         Hypetrigger::new()
@@ -238,21 +565,89 @@ mod tests {
             .map_err(Error::from_display)
     }
 
+    #[test]
+    fn char_whitelist() -> Result<()> {
+        // The test input's counter is all digits, which the whitelist excludes
+        let digits_read = Arc::new(AtomicBool::new(false));
+        let mut unfiltered = TesseractTrigger::with_language("eng")?;
+        let digits_read_clone = digits_read.clone();
+        unfiltered.on_text(move |result| {
+            if result.text.chars().any(|c| c.is_ascii_digit()) {
+                digits_read_clone.store(true, Ordering::SeqCst);
+            }
+        });
+        let mut whitelisted = TesseractTrigger::with_language("eng")?;
+        whitelisted
+            .set_char_whitelist("xyz")?
+            .set_psm(7)?
+            .on_text(|result| {
+                assert!(!result.text.chars().any(|c| c.is_ascii_digit()));
+                assert!(result
+                    .text
+                    .chars()
+                    .all(|c| c.is_whitespace() || "xyz".contains(c)));
+            });
+
+        Hypetrigger::new()
+            .test_input()
+            .add_trigger(unfiltered)
+            .add_trigger(whitelisted)
+            .run()
+            .map_err(Error::from_display)?;
+        assert!(digits_read.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn config() -> Result<()> {
+        let mut trigger = TesseractTrigger::with_language("eng")?;
+        trigger.set_char_whitelist("0123456789")?.set_psm(7)?;
+        let config = trigger.to_config().ok_or(crate::error::NoneError)?;
+        let TriggerConfig::Tesseract(tesseract_config) = &config else {
+            panic!("expected a Tesseract config");
+        };
+        assert_eq!(tesseract_config.language, "eng");
+        assert_eq!(tesseract_config.psm, Some(7));
+
+        // Built again from the config, it has the same settings
+        let built = config.build()?;
+        assert_eq!(built.to_config(), Some(config));
+
+        // Without a known language, it can't be saved
+        assert_eq!(TesseractTrigger::new().to_config(), None);
+        Ok(())
+    }
+
     #[test]
     fn async_trigger() -> Result<()> {
         let runner_thread = TriggerThread::spawn();
         let tesseract = init_tesseract(None, None)?;
         let base_trigger = TesseractTrigger {
             tesseract,
-            crop: Some(Crop {
-                left_percent: 25.0,
-                top_percent: 25.0,
-                width_percent: 10.0,
-                height_percent: 10.0,
-            }),
+            language: None,
+            datapath: None,
+            crop: Some(
+                Crop {
+                    left_percent: 25.0,
+                    top_percent: 25.0,
+                    width_percent: 10.0,
+                    height_percent: 10.0,
+                    anchor_x: AnchorX::Left,
+                    anchor_y: AnchorY::Top,
+                }
+                .into(),
+            ),
+            transpose: None,
             threshold_filter: None,
+            invert: false,
+            char_whitelist: None,
+            psm: None,
             callback: None,
+            debounce: None,
             enable_debug_breakpoints: false,
+            screenshots_dir: None,
+            screenshot_format: ScreenshotFormat::default(),
+            enabled: true,
         };
         let trigger = AsyncTrigger::from_trigger(base_trigger, runner_thread);
 
@@ -0,0 +1,47 @@
+// Fixtures shared by the tests of several modules.
+
+use std::path::PathBuf;
+
+/// A directory under the system temp dir, unique to this test process, which
+/// is deleted when this is dropped (even if the test panics first).
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let path =
+            std::env::temp_dir().join(format!("hypetrigger-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A stand-in for the ffmpeg binary, as a shell script in its own `TempDir`.
+/// Scripts can still run the test ffmpeg on the `PATH` as `ffmpeg "$@"`.
+#[cfg(unix)]
+pub struct FakeFfmpeg {
+    /// Deleted along with the script when this is dropped
+    _dir: TempDir,
+
+    /// The path of the script, to pass to `Hypetrigger::set_ffmpeg_exe`
+    pub exe: PathBuf,
+}
+
+/// Write a fake ffmpeg with the given `#!/bin/sh` script.
+#[cfg(unix)]
+pub fn fake_ffmpeg(name: &str, script: &str) -> std::io::Result<FakeFfmpeg> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new(name)?;
+    let exe = dir.path.join("ffmpeg");
+    std::fs::write(&exe, script)?;
+    std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755))?;
+    Ok(FakeFfmpeg { _dir: dir, exe })
+}
@@ -0,0 +1,541 @@
+// Alternative to the thread-based pipeline, for async applications. Instead of
+// dedicated OS threads, ffmpeg's stdio channels are read by tasks on the tokio
+// runtime, and frames are sent over a channel rather than passed to Triggers.
+
+use crate::buffer_pool::BufferPool;
+use crate::concat::{concat_list_path, ConcatList};
+use crate::error::{Error, FfmpegError, NoneError, Result};
+use crate::log;
+use crate::pipeline::{
+    into_raw_buffer, CompletionStats, FfmpegStderrParser, Hypetrigger, OutputSink, RawFrameBuffer,
+    RunMetrics, VsyncMode,
+};
+use crate::session::FfmpegStdinCommand;
+use crate::trigger::Frame;
+use futures_core::Stream;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+impl Hypetrigger {
+    /// Spawn the ffmpeg process on the tokio runtime, with all stdio channels
+    /// piped. The `concat_list` is as for `ffmpeg_command`.
+    pub fn spawn_ffmpeg_child_tokio(
+        &self,
+        concat_list: Option<&Path>,
+    ) -> core::result::Result<Child, FfmpegError> {
+        let mut cmd = Command::from(self.ffmpeg_command(concat_list)?);
+        cmd.kill_on_drop(true);
+        cmd.spawn().map_err(FfmpegError::SpawnFailed)
+    }
+
+    /// Spawn ffmpeg along with a task for each of its stdio channels. Each frame
+    /// is sent to `frame_tx` instead of running the Triggers. The tasks can be
+    /// awaited to tell when they're done, and how the stdout task ended.
+    pub fn run_tokio(
+        self,
+        frame_tx: mpsc::Sender<Frame>,
+    ) -> Result<(Child, mpsc::UnboundedSender<FfmpegStdinCommand>, TokioTasks)> {
+        self.spawn_tokio_tasks(frame_tx)
+    }
+
+    /// Like `run_tokio`, but the frames are pulled from a `Stream` instead of
+    /// a channel, so they can be handled with the usual stream combinators.
+    /// `capacity` is how many frames ffmpeg can get ahead of the consumer by.
+    pub fn frame_stream(self, capacity: usize) -> Result<FrameStream> {
+        let (frame_tx, frames) = mpsc::channel(capacity);
+        let (child, stdin_tx, tasks) = self.spawn_tokio_tasks(frame_tx)?;
+        Ok(FrameStream {
+            frames,
+            stdout_task: tasks.stdout,
+            child,
+            stdin_tx,
+            stderr_task: tasks.stderr,
+            stdin_task: tasks.stdin,
+            pool: tasks.pool,
+            _concat_list: tasks.concat_list,
+        })
+    }
+
+    /// Common to `run_tokio` and `frame_stream`.
+    fn spawn_tokio_tasks(
+        self,
+        frame_tx: mpsc::Sender<Frame>,
+    ) -> Result<(Child, mpsc::UnboundedSender<FfmpegStdinCommand>, TokioTasks)> {
+        let _session = log::enter_session(self.session_id.as_deref());
+        self.validate_settings(false)?;
+        self.check_ffmpeg_support()?;
+        self.prepare_output_dirs()?;
+        if self.vsync == VsyncMode::SourcePts {
+            return Err(FfmpegError::InvalidConfig(
+                "`VsyncMode::SourcePts` is only supported by the thread-based pipeline".to_string(),
+            )
+            .into());
+        }
+        if self.frame_markers {
+            return Err(FfmpegError::InvalidConfig(
+                "`frame_markers` are only supported by the thread-based pipeline".to_string(),
+            )
+            .into());
+        }
+        let concat_list = self.write_concat_list()?;
+        let mut child = self.spawn_ffmpeg_child_tokio(concat_list_path(&concat_list))?;
+        let stderr = child.stderr.take().ok_or(NoneError)?;
+        let stdout = child.stdout.take().ok_or(NoneError)?;
+        // With stdin as the input, it's left on the child for the caller to write to
+        let stdin = match self.is_stdin_input() {
+            true => None,
+            false => Some(child.stdin.take().ok_or(NoneError)?),
+        };
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "ffmpeg_session",
+            input = %self.input.display(),
+            session_id = self.session_id.as_deref()
+        )
+        .entered();
+
+        let hypetrigger = Arc::new(self);
+        // Enough for every frame in the channel, the one being read, and the
+        // one the consumer is holding
+        let pool = Arc::new(BufferPool::new(frame_tx.max_capacity() + 2));
+        let (output_size_rx, stderr_task) = spawn_ffmpeg_stderr_task(hypetrigger.clone(), stderr);
+        // With the frames going elsewhere, `frame_tx` is dropped straight away
+        let stdout_task = match hypetrigger.output_sink {
+            OutputSink::Stdout => Some(spawn_ffmpeg_stdout_task(
+                hypetrigger,
+                stdout,
+                output_size_rx,
+                frame_tx,
+                pool.clone(),
+            )),
+            _ => None,
+        };
+        let (stdin_tx, stdin_task) = spawn_ffmpeg_stdin_task(stdin);
+
+        let tasks = TokioTasks {
+            stderr: stderr_task,
+            stdin: stdin_task,
+            stdout: stdout_task,
+            pool,
+            concat_list,
+        };
+        Ok((child, stdin_tx, tasks))
+    }
+}
+
+/// Handle to the task started by `spawn_ffmpeg_stdout_task`.
+pub type StdoutTaskHandle = JoinHandle<core::result::Result<(), String>>;
+
+/// The tasks started by `Hypetrigger::run_tokio`.
+pub struct TokioTasks {
+    /// Reads ffmpeg stderr; finishes when ffmpeg exits.
+    pub stderr: JoinHandle<()>,
+
+    /// Writes commands to ffmpeg stdin; finishes after a `Stop`, or once the
+    /// sender is dropped.
+    pub stdin: JoinHandle<()>,
+
+    /// Reads the frames from ffmpeg stdout, and ends with the error that
+    /// stopped it, if any. `None` if the frames aren't written to stdout (see
+    /// `Hypetrigger::output_sink`).
+    pub stdout: Option<StdoutTaskHandle>,
+
+    /// Where the buffers of the frames come from. Passing the frames you're
+    /// done with to `BufferPool::recycle` lets later frames reuse them,
+    /// rather than allocating a buffer for each one.
+    pub pool: Arc<BufferPool>,
+
+    /// The list ffmpeg is reading the `concat_inputs` from, if there are
+    /// any. It's deleted when this is dropped, so keep it until ffmpeg exits.
+    pub concat_list: Option<ConcatList>,
+}
+
+/// The frames of a running ffmpeg process, from `Hypetrigger::frame_stream`.
+/// The stream ends when ffmpeg does, after yielding an error if the frames
+/// couldn't be read (e.g. because the input doesn't exist). Dropping it kills
+/// ffmpeg.
+pub struct FrameStream {
+    frames: mpsc::Receiver<Frame>,
+    stdout_task: Option<StdoutTaskHandle>,
+
+    /// The ffmpeg process, e.g. to wait for its exit status once the stream
+    /// has ended.
+    pub child: Child,
+
+    /// Sends commands to ffmpeg, as with `run_tokio`.
+    pub stdin_tx: mpsc::UnboundedSender<FfmpegStdinCommand>,
+
+    /// The tasks reading ffmpeg stderr and writing to its stdin, as with
+    /// `run_tokio`. Whatever stopped the stdout task is yielded by the stream.
+    pub stderr_task: JoinHandle<()>,
+    pub stdin_task: JoinHandle<()>,
+
+    /// Where the buffers of the frames come from; see `recycle`.
+    pub pool: Arc<BufferPool>,
+
+    /// Deleted along with the stream, which kills ffmpeg
+    _concat_list: Option<ConcatList>,
+}
+
+impl FrameStream {
+    /// Wait for the next frame, without needing a `StreamExt` in scope.
+    pub async fn next(&mut self) -> Option<Result<Frame>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Give back a frame you're done with, so that its buffer is reused for a
+    /// later frame instead of allocating a new one.
+    pub fn recycle(&self, frame: Frame) {
+        self.pool.recycle(frame);
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = Result<Frame>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.frames.poll_recv(cx) {
+            Poll::Ready(Some(frame)) => return Poll::Ready(Some(Ok(frame))),
+            Poll::Ready(None) => {}
+            Poll::Pending => return Poll::Pending,
+        }
+        // Once the frames run out, pass on whatever stopped the stdout task
+        let Some(stdout_task) = &mut self.stdout_task else {
+            return Poll::Ready(None);
+        };
+        let result = match Pin::new(stdout_task).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.stdout_task = None;
+        match result {
+            Ok(Ok(())) => Poll::Ready(None),
+            Ok(Err(e)) => Poll::Ready(Some(Err(Error::from_display(e)))),
+            Err(e) => Poll::Ready(Some(Err(Error::from_std(e)))),
+        }
+    }
+}
+
+/// Reads ffmpeg stderr line by line, sending back the output size once it's
+/// found, as in `Hypetrigger::spawn_ffmpeg_stderr_thread`.
+pub fn spawn_ffmpeg_stderr_task(
+    hypetrigger: Arc<Hypetrigger>,
+    ffmpeg_stderr: ChildStderr,
+) -> (oneshot::Receiver<(u32, u32)>, JoinHandle<()>) {
+    let (output_size_tx, output_size_rx) = oneshot::channel::<(u32, u32)>();
+    let join_handle = spawn_in_current_span(async move {
+        let mut output_size_tx = Some(output_size_tx);
+        let mut parser = FfmpegStderrParser::default();
+        if let Some(size) = hypetrigger.known_output_size() {
+            parser.output_size = Some(size);
+            if let Some(output_size_tx) = output_size_tx.take() {
+                let _ = output_size_tx.send(size);
+            }
+        }
+        let mut reader = BufReader::new(ffmpeg_stderr);
+        let mut chunk = Vec::new();
+        loop {
+            chunk.clear();
+            match reader.read_until(b'\n', &mut chunk).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    log_warn!("[ffmpeg.err] Error reading ffmpeg stderr: {}", e);
+                    break;
+                }
+            }
+
+            // Progress lines are terminated with `\r` rather than `\n`
+            let chunk = String::from_utf8_lossy(&chunk);
+            for text in chunk.split(['\r', '\n']).filter(|t| !t.trim().is_empty()) {
+                if let Some(size) = parser.parse_line(&hypetrigger, text) {
+                    if let Some(output_size_tx) = output_size_tx.take() {
+                        let _ = output_size_tx.send(size);
+                    }
+                }
+                log_trace!("[ffmpeg.err] {}", text.trim_end());
+            }
+        }
+        log_debug!("[ffmpeg.err] ffmpeg stderr task exiting");
+    });
+
+    (output_size_rx, join_handle)
+}
+
+/// Reads raw frames from ffmpeg stdout, and sends them over a channel. Stops
+/// when ffmpeg exits, or when the receiving end of the channel is dropped.
+/// Each frame is read into a buffer from the `pool`.
+pub fn spawn_ffmpeg_stdout_task(
+    hypetrigger: Arc<Hypetrigger>,
+    ffmpeg_stdout: ChildStdout,
+    output_size_rx: oneshot::Receiver<(u32, u32)>,
+    frame_tx: mpsc::Sender<Frame>,
+    pool: Arc<BufferPool>,
+) -> StdoutTaskHandle {
+    spawn_in_current_span(async move {
+        let started_at = Instant::now();
+        let (output_width, output_height) = output_size_rx.await.map_err(|_| {
+            "ffmpeg exited before sending output size. This is likely due to an invalid input file.".to_string()
+        })?;
+
+        let pixel_format = hypetrigger.output_pixel_format();
+        let buf_size = pixel_format.frame_size(output_width, output_height);
+        let num_inputs = hypetrigger.inputs().count();
+        let buffer_size = hypetrigger.stdout_buffer_size.unwrap_or(0);
+        let mut ffmpeg_stdout = BufReader::with_capacity(buffer_size, ffmpeg_stdout);
+        let mut frame_num = 0;
+        let mut partial_frame = false;
+        let run_metrics = RunMetrics::new(&hypetrigger);
+        while !partial_frame
+            && hypetrigger
+                .max_frames
+                .map_or(true, |max_frames| frame_num < max_frames)
+        {
+            let mut buffer = pool.take(buf_size);
+            match read_frame(&mut ffmpeg_stdout, &mut buffer).await {
+                Ok(0) => break,
+                Ok(bytes_read) if bytes_read < buf_size => {
+                    if !hypetrigger.dispatch_partial_frame {
+                        log_warn!(
+                            "[ffmpeg.out] Output ended partway through a frame; dropping the last {} bytes",
+                            bytes_read
+                        );
+                        break;
+                    }
+                    buffer[bytes_read..].fill(0);
+                    partial_frame = true;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log_warn!("[ffmpeg.out] Error reading from stdout: {}", e);
+                    break;
+                }
+            }
+            // 16-bit frames are converted into new samples, so the bytes can
+            // go straight back into the pool
+            let mut raw = RawFrameBuffer {
+                bytes: buffer,
+                samples: vec![],
+            };
+            let image = raw
+                .take_image(pixel_format, output_width, output_height)
+                .ok_or("unable to convert vec to imagebuffer (size mismatch)")?;
+            if pixel_format.bit_depth() == 16 {
+                pool.put(raw.bytes);
+            }
+            let frames = if num_inputs == 1 {
+                vec![hypetrigger.frame(image, frame_num, 0)]
+            } else {
+                // The frame of each input is a copy, so the buffer can be reused
+                let frames = hypetrigger.split_inputs(&image, frame_num);
+                if pixel_format.bit_depth() == 8 {
+                    pool.put(into_raw_buffer(image));
+                }
+                frames
+            };
+            run_metrics.frame_read();
+            for frame in frames {
+                if frame_tx.send(frame).await.is_err() {
+                    log_debug!("[ffmpeg.out] Frame receiver dropped; stopping");
+                    return Ok(());
+                }
+            }
+            frame_num += 1;
+        }
+
+        log_debug!("[ffmpeg.out] Finished reading from stdout");
+        if let Some(callback) = &hypetrigger.on_complete_callback {
+            callback(CompletionStats {
+                frames_read: frame_num,
+                trigger_frames: vec![],
+                frames_dropped: 0,
+                ffmpeg_dropped: 0,
+                ffmpeg_duplicated: 0,
+                elapsed: started_at.elapsed(),
+            });
+        }
+        Ok(())
+    })
+}
+
+/// Async version of `pipeline::read_frame`: returns how many bytes of the
+/// frame were read before the end of the output.
+async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+    buffer: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut bytes_read = 0;
+    while bytes_read < buffer.len() {
+        match reader.read(&mut buffer[bytes_read..]).await {
+            Ok(0) => break,
+            Ok(n) => bytes_read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// Writes commands to ffmpeg stdin, as in `spawn_ffmpeg_stdin_thread`.
+pub fn spawn_ffmpeg_stdin_task(
+    mut ffmpeg_stdin: Option<ChildStdin>,
+) -> (mpsc::UnboundedSender<FfmpegStdinCommand>, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<FfmpegStdinCommand>();
+    let join_handle = spawn_in_current_span(async move {
+        while let Some(command) = rx.recv().await {
+            log_debug!("[ffmpeg.in] Sending command {:?}", command);
+            let result = match (&command, &mut ffmpeg_stdin) {
+                (FfmpegStdinCommand::Pause | FfmpegStdinCommand::Resume, _) => {
+                    // Frames only arrive as fast as they're received anyway
+                    log_warn!(
+                        "[ffmpeg.in] To pause with `run_tokio`, stop receiving frames instead"
+                    );
+                    Ok(())
+                }
+                (_, None) => {
+                    log_debug!("[ffmpeg.in] stdin is used for the input; close it to stop");
+                    Ok(())
+                }
+                (command, Some(ffmpeg_stdin)) => match command.ffmpeg_stdin_bytes() {
+                    Some(bytes) => match ffmpeg_stdin.write_all(&bytes).await {
+                        Ok(()) => ffmpeg_stdin.flush().await,
+                        Err(e) => Err(e),
+                    },
+                    None => Ok(()),
+                },
+            };
+            if let Err(e) = result {
+                log_warn!("[ffmpeg.in] Error writing to ffmpeg stdin: {}", e);
+            }
+            if command == FfmpegStdinCommand::Stop {
+                break;
+            }
+        }
+        log_debug!("[ffmpeg.in] ffmpeg stdin task exiting");
+    });
+
+    (tx, join_handle)
+}
+
+/// `tokio::spawn`, but the task stays inside the caller's tracing span (if
+/// any), and tags its log messages with the caller's `current_session_id`, so
+/// they're attributed to the right ffmpeg session.
+fn spawn_in_current_span<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "tracing")]
+    let future = tracing::Instrument::in_current_span(future);
+    tokio::spawn(InSession {
+        session_id: log::current_session_id(),
+        future: Box::pin(future),
+    })
+}
+
+/// Polls a future with its session entered, since a task can move between
+/// threads (which each have their own `current_session_id`) from one poll to
+/// the next.
+struct InSession<F> {
+    session_id: Option<String>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for InSession<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        let _session = log::enter_session(this.session_id.as_deref());
+        this.future.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{Error, NoneError, Result},
+        log::current_session_id,
+        pipeline::Hypetrigger,
+    };
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn run_tokio() -> Result<()> {
+        let (frame_tx, mut frame_rx) = mpsc::channel(4);
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.test_input().set_scale(64, 36);
+        let (mut child, _stdin_tx, tasks) = hypetrigger.run_tokio(frame_tx)?;
+
+        let mut frame_num = 0;
+        while let Some(frame) = frame_rx.recv().await {
+            assert_eq!(frame.frame_num, frame_num);
+            frame_num += 1;
+            tasks.pool.recycle(frame);
+        }
+        assert!(frame_num > 0);
+        child.wait().await?;
+
+        // The tasks finish along with ffmpeg, and the buffers were reused
+        let stdout_task = tasks.stdout.ok_or(NoneError)?;
+        assert_eq!(stdout_task.await.map_err(Error::from_std)?, Ok(()));
+        tasks.stderr.await.map_err(Error::from_std)?;
+        assert!(tasks.pool.allocations() <= 4 + 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn frame_stream() -> Result<()> {
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.test_input().set_scale(64, 36);
+        let mut stream = hypetrigger.frame_stream(4)?;
+        let mut frame_num = 0;
+        while let Some(frame) = stream.next().await {
+            assert_eq!(frame?.frame_num, frame_num);
+            frame_num += 1;
+        }
+        assert_eq!(frame_num, 20);
+        assert!(stream.child.wait().await?.success());
+
+        // Callbacks from the tasks know which session they're in
+        let session_ids = Arc::new(Mutex::new(vec![]));
+        let session_ids_clone = session_ids.clone();
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger
+            .test_input()
+            .set_scale(64, 36)
+            .set_session_id("tokio-1")
+            .on_progress(move |_| session_ids_clone.lock().unwrap().push(current_session_id()));
+        let mut stream = hypetrigger.frame_stream(4)?;
+        while stream.next().await.is_some() {}
+        stream.stderr_task.await.map_err(Error::from_std)?;
+        assert!(!session_ids.lock()?.is_empty());
+        assert!(session_ids
+            .lock()?
+            .iter()
+            .all(|session_id| session_id.as_deref() == Some("tokio-1")));
+
+        // So does a bad configuration, before ffmpeg is started
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.test_input().set_video_decoder("");
+        assert!(hypetrigger.frame_stream(4).is_err());
+
+        // A missing input ends the stream with an error
+        let mut hypetrigger = Hypetrigger::new();
+        hypetrigger.set_input("does-not-exist.mp4");
+        let mut stream = hypetrigger.frame_stream(4)?;
+        assert!(stream.next().await.expect("an error").is_err());
+        assert!(stream.next().await.is_none());
+        Ok(())
+    }
+}
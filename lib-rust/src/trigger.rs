@@ -1,24 +1,303 @@
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+#[cfg(feature = "photon")]
+use crate::photon::CropRegion;
 use crate::{
     async_trigger::{AsyncTrigger, TriggerThread},
+    audio::AudioChunk,
+    color_filter::ColorFilter,
     error::Result,
+    pipeline::{PixelFormat, Transpose},
+    trigger_config::TriggerConfig,
 };
-use image::RgbImage;
+use image::{DynamicImage, RgbImage};
 
 /// Represents a single frame of the input, including the raw image pixels as
 /// well as the time it appears in the input (frame_num and/or timestamp)
+///
+/// Triggers borrow the same frame, so running more of them doesn't copy it.
+/// The thread-based pipeline also reads every frame into one reused buffer,
+/// which the image takes ownership of while the Triggers run; only frames that
+/// outlive the pipeline (e.g. sent to an `AsyncTrigger`, or over the channel
+/// from `run_tokio`) are copied or allocated separately. To hand off the raw
+/// pixels without that, see `SharedFrame` (with the `bytes` feature).
 #[derive(Clone, Debug, PartialEq)]
 pub struct Frame {
-    pub image: RgbImage,
+    pub image: DynamicImage,
+
+    /// Index of this frame among all the frames sampled from the input,
+    /// starting at 0. Counts every sample, including the ones skipped by
+    /// Triggers with a lower `get_fps()`.
+    pub frame_num: u64,
+
+    /// Time at which this frame appears in the input, in seconds.
+    pub timestamp: f64,
+
+    /// Which input this frame came from, when processing multiple inputs at
+    /// once. `0` is the main input.
+    pub input_index: usize,
+}
+
+impl Frame {
+    /// The timestamp as a `Duration` since the start of the input.
+    pub fn time(&self) -> Duration {
+        Duration::from_secs_f64(self.timestamp)
+    }
+
+    /// Number of bits per channel of the image: 8, or 16 for the high bit
+    /// depth pixel formats (`Rgb48`, `Gray16` and `Rgba64`).
+    pub fn bit_depth(&self) -> u16 {
+        let color = self.image.color();
+        color.bits_per_pixel() / color.channel_count() as u16
+    }
+
+    /// The image as an `RgbImage`, which is what `image` used to be before
+    /// Triggers could ask for other pixel formats. It's borrowed when the
+    /// frame is already `Rgb24` (the default), and converted otherwise.
+    pub fn rgb(&self) -> Cow<'_, RgbImage> {
+        match self.image.as_rgb8() {
+            Some(image) => Cow::Borrowed(image),
+            None => Cow::Owned(self.image.to_rgb8()),
+        }
+    }
+}
+
+/// Something a Trigger found in a frame, e.g. the text it read, along with
+/// where it was found. Passed to `Hypetrigger::on_trigger_event`.
+#[derive(Clone, Debug)]
+pub struct PipelineEvent {
+    /// Index of the Trigger among the Triggers of the pipeline
+    pub trigger_id: usize,
+
+    /// Which input the frame came from (see `Frame::input_index`)
+    pub input_index: usize,
+
+    /// Index of the frame it was found in (see `Frame::frame_num`)
     pub frame_num: u64,
+
+    /// Time at which the frame appears in the input, in seconds
     pub timestamp: f64,
+
+    pub payload: TriggerPayload,
+}
+
+/// What a Trigger found in a frame, as reported by `Trigger::on_frame_events`.
+#[derive(Clone, Debug)]
+pub enum TriggerPayload {
+    /// Text that was read, e.g. with OCR
+    Text(String),
+
+    /// The class predicted by an image classifier, with its confidence from 0
+    /// to 1
+    Classification { class_index: usize, confidence: f32 },
+
+    /// The color of a pixel (or the average of a small region), as
+    /// `[r, g, b]`, e.g. from a `PixelProbeTrigger`
+    Color([u8; 3]),
+
+    /// Any other result, e.g. from a `CachedTrigger`. See `custom`.
+    Custom(Arc<dyn Any + Send + Sync>),
+}
+
+impl TriggerPayload {
+    /// Wrap any other kind of result.
+    pub fn custom<T: Any + Send + Sync>(value: T) -> Self {
+        TriggerPayload::Custom(Arc::new(value))
+    }
+
+    /// Whether two payloads are the same result, for debouncing (see
+    /// `Trigger::get_debounce`): the same text, class (whatever the
+    /// confidence), or color, or the very same `Custom` value.
+    pub fn is_same_result(&self, other: &TriggerPayload) -> bool {
+        match (self, other) {
+            (TriggerPayload::Text(a), TriggerPayload::Text(b)) => a == b,
+            (
+                TriggerPayload::Classification { class_index: a, .. },
+                TriggerPayload::Classification { class_index: b, .. },
+            ) => a == b,
+            (TriggerPayload::Color(a), TriggerPayload::Color(b)) => a == b,
+            (TriggerPayload::Custom(a), TriggerPayload::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// The result of a `Custom` payload, if it's a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            TriggerPayload::Custom(value) => value.downcast_ref(),
+            _ => None,
+        }
+    }
 }
 
 //// Triggers
 pub trait Trigger: Send + Sync {
     fn on_frame(&self, frame: &Frame) -> Result<()>;
 
+    /// Like `on_frame`, but also says whether the pipeline should carry on.
+    /// Returning `ControlFlow::Break` stops it once the rest of the Triggers
+    /// have had the current frame, and ffmpeg is shut down, e.g. to bail out
+    /// as soon as the first occurrence of an event is found. By default this
+    /// just calls `on_frame` and carries on.
+    fn on_frame_control(&self, frame: &Frame) -> Result<ControlFlow<()>> {
+        self.on_frame(frame).map(|()| ControlFlow::Continue(()))
+    }
+
+    /// Like `on_frame_control`, but also reports what the Trigger found in the
+    /// frame by pushing it onto `payloads`, which the pipeline passes on to
+    /// `Hypetrigger::on_trigger_event`. This is what the pipeline calls. By
+    /// default it just calls `on_frame_control`, and reports nothing.
+    fn on_frame_events(
+        &self,
+        frame: &Frame,
+        _payloads: &mut Vec<TriggerPayload>,
+    ) -> Result<ControlFlow<()>> {
+        self.on_frame_control(frame)
+    }
+
+    /// Optionally run this Trigger at a lower framerate than the rest of the
+    /// pipeline (e.g. for expensive OCR), by skipping some of the frames. It
+    /// can't run faster than the framerate of the `Hypetrigger` it's added to.
+    fn get_fps(&self) -> Option<f64> {
+        None
+    }
+
+    /// Optionally receive frames in a specific pixel format, regardless of the
+    /// `pixel_format` of the `Hypetrigger` it's added to. For example, a
+    /// Trigger that looks for semi-transparent overlays can ask for `Rgba` to
+    /// get the alpha channel.
+    fn get_pixel_format(&self) -> Option<PixelFormat> {
+        None
+    }
+
+    /// Optionally receive a black and white mask of the pixels close to a
+    /// color instead of the frame itself, e.g. to detect colored indicators.
+    /// See `ColorFilter`.
+    fn get_color_filter(&self) -> Option<ColorFilter> {
+        None
+    }
+
+    /// Optionally receive frames with their colors inverted, e.g. for OCR of
+    /// light text on a dark background. This happens after any color filter
+    /// (so it flips the mask) and scale, and before anything the Trigger does
+    /// itself. Only the `get_region()` is inverted, if there is one; the rest
+    /// of the frame is left as it was. With
+    /// `Hypetrigger::spawn_ffmpeg_multi_pipe_session`, ffmpeg inverts the
+    /// Trigger's whole frame instead, with a `negate` filter after its scale.
+    fn get_invert(&self) -> bool {
+        false
+    }
+
+    /// Optionally suppress repeats of the same event (see
+    /// `TriggerPayload::is_same_result`) within this window, in the input's
+    /// own time, e.g. for text that stays on screen for many frames. A
+    /// different result is let through straight away. This applies to the
+    /// events passed to `Hypetrigger::on_trigger_event`, and the pipeline
+    /// keeps track of it for each Trigger; see `Debouncer`.
+    fn get_debounce(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Optionally receive frames resized to exactly `(width, height)`, e.g. a
+    /// small thumbnail for a classifier alongside full resolution frames for
+    /// OCR. Frames are resized after they're read from ffmpeg (with a bicubic
+    /// filter), so the `scale` of the `Hypetrigger` should be the largest size
+    /// any of its Triggers needs: a bigger size is rejected by `validate`, or
+    /// warned about once ffmpeg's frames turn out to be smaller. Any transpose
+    /// happens after resizing.
+    ///
+    /// With `run` and `run_async`, every Trigger's frames are made from the
+    /// one stream of frames ffmpeg writes to stdout, so Triggers of any size
+    /// and pixel format can't get out of step. With
+    /// `Hypetrigger::spawn_ffmpeg_multi_pipe_session`, ffmpeg scales them
+    /// itself instead, in a `scale=W:H` filter on the Trigger's own output,
+    /// and they're read straight into a buffer of that size.
+    fn get_scale(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    /// Optionally receive frames rotated or flipped, e.g. to read text from a
+    /// capture source that's mounted sideways. This happens before anything
+    /// the Trigger does itself, so its crop is in terms of the transposed
+    /// frame. See `Transpose`.
+    ///
+    /// A Trigger that crops can return `None` here and transpose just its
+    /// region with `Transpose::apply_to_region` instead, as `TesseractTrigger`
+    /// does. With `Hypetrigger::spawn_ffmpeg_multi_pipe_session`, ffmpeg
+    /// transposes the Trigger's frames itself, with `transpose`, `hflip` or
+    /// `vflip` filters on its own output.
+    fn get_transpose(&self) -> Option<Transpose> {
+        None
+    }
+
+    /// The region of a frame of the given size that this Trigger looks at, as
+    /// `(x1, y1, x2, y2)` in pixels, if it only looks at part of it (e.g. a
+    /// crop before OCR). Used to draw previews; see `Hypetrigger::preview`.
+    /// With a `get_scale()` or `get_transpose()`, the size and region are of
+    /// the frame the Trigger receives (see `pipeline::trigger_frame_size`).
+    fn get_region(&self, _width: u32, _height: u32) -> Option<(u32, u32, u32, u32)> {
+        None
+    }
+
+    /// Whether this Trigger runs at all. A disabled Trigger is kept in its
+    /// place among the others, so the ids they're reported by (e.g. in
+    /// `PipelineSummary::trigger_frames` or `on_trigger_error`) stay the same,
+    /// but it never gets any frames or audio, e.g. to switch a detector off in
+    /// a config file. It's checked on every frame, so a Trigger can also
+    /// switch itself on and off while running.
+    fn get_enabled(&self) -> bool {
+        true
+    }
+
+    /// Directories this Trigger writes files to, e.g. screenshots of each
+    /// frame. They're created when the pipeline starts, if they don't exist
+    /// yet (see `Hypetrigger::create_output_dirs`).
+    fn get_output_dirs(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    /// Whether this Trigger listens to the input's audio rather than looking
+    /// at its frames. Audio Triggers get each `AudioChunk` in `on_audio`, and
+    /// never have `on_frame` called. They're only supported by
+    /// `Hypetrigger::run`.
+    fn is_audio(&self) -> bool {
+        false
+    }
+
+    /// Called with each chunk of the input's audio, for Triggers whose
+    /// `is_audio()` is true.
+    fn on_audio(&self, _chunk: &AudioChunk) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check the Trigger's own configuration (e.g. that its crop region fits
+    /// inside the frame), so that mistakes are reported by
+    /// `Hypetrigger::validate` before ffmpeg is even started.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The settings of this Trigger as a `TriggerConfig`, if it's one of the
+    /// built-in ones, so it can be saved with the rest of a `Hypetrigger`
+    /// (with the `serde` feature). Other Triggers aren't saved.
+    fn to_config(&self) -> Option<TriggerConfig> {
+        None
+    }
+
+    /// A copy of this Trigger cropping to `crop` instead, if it crops frames
+    /// itself, for `FfmpegSession::set_crop`. Triggers that don't crop return
+    /// `None`.
+    #[cfg(feature = "photon")]
+    fn with_crop(&self, _crop: CropRegion) -> Option<Arc<dyn Trigger>> {
+        None
+    }
+
     /// Convert this Trigger into a `AsyncTrigger`, running on a separate thread.
     fn into_async(self, runner_thread: Arc<TriggerThread>) -> AsyncTrigger
     where
@@ -27,3 +306,59 @@ pub trait Trigger: Send + Sync {
         AsyncTrigger::from_trigger(self, runner_thread)
     }
 }
+
+thread_local! {
+    static TRIGGER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// The index (in the order they were added) of the Trigger running on the
+/// current thread, while a pipeline is running it, including on the worker
+/// of a `TriggerThread`. Used to name screenshots, so that several Triggers of
+/// the same type can share a `screenshots_dir`.
+pub fn current_trigger_id() -> Option<usize> {
+    TRIGGER_ID.with(Cell::get)
+}
+
+/// Marks the Trigger running on the current thread until it's dropped, when
+/// the previous one (if any) is restored.
+pub(crate) struct TriggerIdGuard {
+    previous: Option<usize>,
+}
+
+impl Drop for TriggerIdGuard {
+    fn drop(&mut self) {
+        TRIGGER_ID.with(|trigger_id| trigger_id.set(self.previous));
+    }
+}
+
+/// Mark the given Trigger as the one running on the current thread.
+pub(crate) fn enter_trigger(trigger_id: Option<usize>) -> TriggerIdGuard {
+    let previous = TRIGGER_ID.with(|current| current.replace(trigger_id));
+    TriggerIdGuard { previous }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Frame;
+    use image::{DynamicImage, GrayImage, RgbImage};
+    use std::borrow::Cow;
+
+    #[test]
+    fn rgb() {
+        let frame = Frame {
+            image: DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, image::Rgb([1, 2, 3]))),
+            frame_num: 0,
+            timestamp: 0.0,
+            input_index: 0,
+        };
+        assert!(matches!(frame.rgb(), Cow::Borrowed(_)));
+        assert_eq!(frame.rgb().get_pixel(1, 1).0, [1, 2, 3]);
+
+        let gray = Frame {
+            image: DynamicImage::ImageLuma8(GrayImage::from_pixel(2, 2, image::Luma([7]))),
+            ..frame
+        };
+        assert!(matches!(gray.rgb(), Cow::Owned(_)));
+        assert_eq!(gray.rgb().get_pixel(0, 0).0, [7, 7, 7]);
+    }
+}
@@ -0,0 +1,129 @@
+// A description of the built-in Triggers as plain data, so that a pipeline's
+// Triggers can be saved along with the rest of its configuration (with the
+// `serde` feature), and built again when it's loaded.
+
+use crate::error::Result;
+use crate::pixel_probe::PixelProbeTrigger;
+#[cfg(feature = "tensorflow")]
+use crate::tensorflow::{TensorflowConfig, TensorflowTrigger};
+#[cfg(feature = "tesseract")]
+use crate::tesseract::{TesseractConfig, TesseractTrigger};
+use crate::trigger::Trigger;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The settings of one of the built-in Triggers, from `Trigger::to_config`,
+/// which `build` turns back into the Trigger. Saved by its `kind`, e.g.
+/// `{ "kind": "pixel_probe", "x": 10, "y": 20 }`.
+///
+/// Callbacks can't be saved, so the Triggers that are built have none, and
+/// their results are only reported through `Hypetrigger::on_trigger_event`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "kind", rename_all = "snake_case")
+)]
+pub enum TriggerConfig {
+    /// A `PixelProbeTrigger`
+    PixelProbe {
+        x: u32,
+        y: u32,
+        #[cfg_attr(feature = "serde", serde(default = "default_probe_size"))]
+        size: (u32, u32),
+        #[cfg_attr(feature = "serde", serde(default))]
+        fps: Option<f64>,
+    },
+
+    /// A `TesseractTrigger`, with its own Tesseract instance
+    #[cfg(feature = "tesseract")]
+    Tesseract(TesseractConfig),
+
+    /// A `TensorflowTrigger`, loading its model from the `model_dir`
+    #[cfg(feature = "tensorflow")]
+    Tensorflow(TensorflowConfig),
+}
+
+#[cfg(feature = "serde")]
+fn default_probe_size() -> (u32, u32) {
+    (1, 1)
+}
+
+impl TriggerConfig {
+    /// Make the Trigger this describes, e.g. initializing Tesseract or
+    /// loading a model.
+    pub fn build(&self) -> Result<Arc<dyn Trigger>> {
+        Ok(match self {
+            TriggerConfig::PixelProbe { x, y, size, fps } => {
+                let mut trigger = PixelProbeTrigger::new(*x, *y, |_, _| {});
+                trigger.size = *size;
+                trigger.fps = *fps;
+                Arc::new(trigger)
+            }
+            #[cfg(feature = "tesseract")]
+            TriggerConfig::Tesseract(config) => Arc::new(TesseractTrigger::from_config(config)?),
+            #[cfg(feature = "tensorflow")]
+            TriggerConfig::Tensorflow(config) => Arc::new(TensorflowTrigger::from_config(config)?),
+        })
+    }
+}
+
+/// Saves `Hypetrigger::triggers` as a list of `TriggerConfig`s, skipping the
+/// ones that can't be (see `Trigger::to_config`), and builds them again when
+/// they're loaded.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_triggers {
+    use super::TriggerConfig;
+    use crate::trigger::Trigger;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+    use std::sync::Arc;
+
+    pub fn serialize<S: Serializer>(
+        triggers: &[Arc<dyn Trigger>],
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let configs: Vec<TriggerConfig> = triggers
+            .iter()
+            .filter_map(|trigger| trigger.to_config())
+            .collect();
+        configs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Vec<Arc<dyn Trigger>>, D::Error> {
+        Vec::<TriggerConfig>::deserialize(deserializer)?
+            .iter()
+            .map(|config| config.build().map_err(de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TriggerConfig;
+    use crate::pixel_probe::PixelProbeTrigger;
+    use crate::trigger::Trigger;
+
+    #[test]
+    fn pixel_probe_config() -> crate::error::Result<()> {
+        let probe = PixelProbeTrigger::new(3, 4, |_, _| {})
+            .set_size(2, 2)
+            .set_fps(1.0);
+        let config = probe.to_config();
+        assert_eq!(
+            config,
+            Some(TriggerConfig::PixelProbe {
+                x: 3,
+                y: 4,
+                size: (2, 2),
+                fps: Some(1.0),
+            })
+        );
+        let built = config.unwrap().build()?;
+        assert_eq!(built.get_region(10, 10), Some((3, 4, 5, 6)));
+        assert_eq!(built.get_fps(), Some(1.0));
+        Ok(())
+    }
+}
@@ -1,6 +1,12 @@
+use std::env;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
 
 use regex::Regex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// Convert a Command to a string that can be run in a shell (for debug
 /// purposes).
@@ -11,14 +17,14 @@ use regex::Regex;
 /// readibility.
 pub fn command_to_string(cmd: &Command) -> String {
     let mut command_string = String::new();
-    command_string.push_str(cmd.get_program().to_str().unwrap());
+    command_string.push_str(&cmd.get_program().to_string_lossy());
 
     for arg in cmd.get_args() {
-        let arg_str = arg.to_str().unwrap();
+        let arg_str = arg.to_string_lossy();
         command_string.push(' ');
         if arg_str.starts_with('-') {
             command_string.push_str("\\\n\t");
-            command_string.push_str(arg_str);
+            command_string.push_str(&arg_str);
         } else {
             command_string.push_str(format!("{:?}", arg_str).as_str());
         }
@@ -27,6 +33,22 @@ pub fn command_to_string(cmd: &Command) -> String {
     command_string
 }
 
+/// Search each directory in the `PATH` environment variable for a file with
+/// the given name, returning the first match.
+pub fn find_on_path(filename: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(filename))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Whether an ffmpeg input is an HTTP(S) URL, which supports the `-reconnect`
+/// family of options.
+pub fn is_http_url(input: &str) -> bool {
+    let input = input.to_ascii_lowercase();
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
 /// Parses a line of ffmpeg stderr output, looking for the video size.
 /// We're looking for a line like this:
 ///
@@ -36,16 +58,258 @@ pub fn parse_ffmpeg_output_size(text: &str) -> Option<(u32, u32)> {
         static ref REGEX_SIZE: Regex = Regex::new(r"  Stream .* Video: .* (\d+)x(\d+),? ").unwrap();
     }
 
-    match REGEX_SIZE.captures(text) {
-        Some(capture) => {
-            let width = capture.get(1).unwrap().as_str().parse::<u32>().unwrap();
-            let height = capture.get(2).unwrap().as_str().parse::<u32>().unwrap();
-            Some((width, height))
+    let capture = REGEX_SIZE.captures(text)?;
+    let width = capture.get(1)?.as_str().parse::<u32>().ok()?;
+    let height = capture.get(2)?.as_str().parse::<u32>().ok()?;
+    Some((width, height))
+}
+
+/// Parses a timestamp in the format ffmpeg uses in its logs, e.g.
+/// `"01:23:45.67"`.
+pub fn parse_ffmpeg_timestamp(text: &str) -> Option<Duration> {
+    let mut parts = text.trim().splitn(3, ':');
+    let hours = parts.next()?.parse::<u64>().ok()?;
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = parts.next()?.parse::<f64>().ok()?;
+    if seconds.is_sign_negative() {
+        return None;
+    }
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Parses a line of ffmpeg stderr output, looking for the input duration.
+/// We're looking for a line like this:
+///
+/// `  Duration: 00:00:10.00, start: 0.000000, bitrate: N/A`
+pub fn parse_ffmpeg_duration(text: &str) -> Option<Duration> {
+    lazy_static! {
+        static ref REGEX_DURATION: Regex = Regex::new(r"^\s*Duration: (\d+:\d+:[\d.]+)").unwrap();
+    }
+
+    let capture = REGEX_DURATION.captures(text)?;
+    parse_ffmpeg_timestamp(capture.get(1)?.as_str())
+}
+
+/// Properties of the input video stream, as detected by ffmpeg
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StreamInfo {
+    /// Width of the input video, in pixels (before any `scale`)
+    pub width: u32,
+
+    /// Height of the input video, in pixels (before any `scale`)
+    pub height: u32,
+
+    /// Native framerate of the input video, if ffmpeg reported it
+    pub fps: Option<f64>,
+
+    /// Name of the codec, e.g. `h264`
+    pub codec: String,
+}
+
+/// Parses a line of ffmpeg stderr output, looking for a description of a
+/// video stream. We're looking for a line like this:
+///
+/// `  Stream #0:0: Video: h264 (High), yuv420p(progressive), 1920x1080 [SAR 1:1 DAR 16:9], 60 fps, 60 tbr, 1k tbn`
+pub fn parse_ffmpeg_stream_info(text: &str) -> Option<StreamInfo> {
+    lazy_static! {
+        static ref REGEX_CODEC: Regex = Regex::new(r"^\s*Stream #.* Video: ([^\s,]+)").unwrap();
+        static ref REGEX_FPS: Regex = Regex::new(r"([\d.]+) fps").unwrap();
+    }
+
+    let codec = REGEX_CODEC.captures(text)?.get(1)?.as_str().to_string();
+    let (width, height) = parse_ffmpeg_output_size(text)?;
+    let fps = REGEX_FPS
+        .captures(text)
+        .and_then(|capture| capture.get(1)?.as_str().parse::<f64>().ok());
+    Some(StreamInfo {
+        width,
+        height,
+        fps,
+        codec,
+    })
+}
+
+/// Parses a line of ffmpeg stderr output, looking for the current progress.
+/// We're looking for a line like this:
+///
+/// `frame=   20 fps=0.0 q=-0.0 size=   54000kB time=00:00:10.00 bitrate=44236.8kbits/s speed=29.6x`
+pub fn parse_ffmpeg_progress_time(text: &str) -> Option<Duration> {
+    lazy_static! {
+        static ref REGEX_TIME: Regex = Regex::new(r"time=\s*(\d+:\d+:[\d.]+)").unwrap();
+    }
+
+    let capture = REGEX_TIME.captures(text)?;
+    parse_ffmpeg_timestamp(capture.get(1)?.as_str())
+}
+
+/// Parses a line of ffmpeg stderr output, looking for how many frames ffmpeg
+/// has dropped and duplicated so far to sync the output to its timestamps, as
+/// `(dropped, duplicated)`. They're on the progress lines, but only once any
+/// frames have been dropped or duplicated:
+///
+/// `frame=  120 fps= 60 q=-0.0 size=  81000kB time=00:00:04.00 bitrate=165888.0kbits/s dup=2 drop=5 speed=1.99x`
+pub fn parse_ffmpeg_drop_dup(text: &str) -> Option<(u64, u64)> {
+    lazy_static! {
+        static ref REGEX_DUP: Regex = Regex::new(r"\bdup=\s*(\d+)").unwrap();
+        static ref REGEX_DROP: Regex = Regex::new(r"\bdrop=\s*(\d+)").unwrap();
+    }
+
+    if !text.trim_start().starts_with("frame=") {
+        return None;
+    }
+    let count = |regex: &Regex| {
+        regex
+            .captures(text)
+            .and_then(|capture| capture.get(1)?.as_str().parse::<u64>().ok())
+    };
+    match (count(&REGEX_DROP), count(&REGEX_DUP)) {
+        (None, None) => None,
+        (dropped, duplicated) => Some((dropped.unwrap_or(0), duplicated.unwrap_or(0))),
+    }
+}
+
+/// Parses a line of ffmpeg stderr output, looking for the index and timestamp
+/// (in seconds) of a frame logged by the `showinfo` filter (see
+/// `VsyncMode::SourcePts`). We're looking for a line like this:
+///
+/// `[Parsed_showinfo_2 @ 0x55d5c7d0] n:   1 pts:  15360 pts_time:1.2     duration:  512 duration_time:0.04 fmt:rgb24`
+pub fn parse_ffmpeg_showinfo_pts_time(text: &str) -> Option<(u64, f64)> {
+    lazy_static! {
+        static ref REGEX_PTS_TIME: Regex = Regex::new(
+            r"^\[Parsed_showinfo_\d+ @ [^\]]*\]\s*n:\s*(\d+)\s+pts:\s*-?\d+\s+pts_time:(\S+)"
+        )
+        .unwrap();
+    }
+
+    let capture = REGEX_PTS_TIME.captures(text)?;
+    let frame_num = capture.get(1)?.as_str().parse::<u64>().ok()?;
+    let pts_time = capture.get(2)?.as_str().parse::<f64>().ok()?;
+    Some((frame_num, pts_time))
+}
+
+/// How much ffmpeg logs, from least to most (the `-loglevel` arg). Lines of
+/// stderr are tagged with the level they were logged at, so that `Error` <
+/// `Warning` means an error is more severe than a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FfmpegLogLevel {
+    /// Nothing at all
+    Quiet,
+    /// Only errors that make ffmpeg crash
+    Panic,
+    /// Only errors that ffmpeg can't recover from
+    Fatal,
+    /// All errors, including recoverable ones
+    Error,
+    /// Warnings about possibly incorrect output, as well as errors
+    Warning,
+    /// Informative messages, like the description of each stream (ffmpeg's default)
+    Info,
+    /// More detailed messages than `Info`
+    Verbose,
+    /// Everything, including debugging information
+    Debug,
+    /// Extremely verbose debugging, for ffmpeg developers
+    Trace,
+}
+
+impl FfmpegLogLevel {
+    /// The corresponding value for the ffmpeg `-loglevel` arg
+    pub fn as_ffmpeg_str(&self) -> &'static str {
+        match self {
+            FfmpegLogLevel::Quiet => "quiet",
+            FfmpegLogLevel::Panic => "panic",
+            FfmpegLogLevel::Fatal => "fatal",
+            FfmpegLogLevel::Error => "error",
+            FfmpegLogLevel::Warning => "warning",
+            FfmpegLogLevel::Info => "info",
+            FfmpegLogLevel::Verbose => "verbose",
+            FfmpegLogLevel::Debug => "debug",
+            FfmpegLogLevel::Trace => "trace",
         }
-        None => None,
     }
 }
 
+/// Works out how severe a line of ffmpeg stderr is. With `-loglevel level+...`
+/// ffmpeg tags each line with its level, which is removed from the returned
+/// text, e.g.
+///
+/// `[h264 @ 0x5581] [error] non-existing PPS 0 referenced`
+///
+/// Untagged lines are guessed from whether they sound like an error or a
+/// warning, and are otherwise `Info`.
+pub fn parse_ffmpeg_log_level(text: &str) -> (FfmpegLogLevel, String) {
+    lazy_static! {
+        static ref REGEX_LEVEL: Regex =
+            Regex::new(r"\[(quiet|panic|fatal|error|warning|info|verbose|debug|trace)\] ").unwrap();
+    }
+
+    if let Some(capture) = REGEX_LEVEL.captures(text) {
+        let level = match capture.get(1).map(|m| m.as_str()) {
+            Some("quiet") => FfmpegLogLevel::Quiet,
+            Some("panic") => FfmpegLogLevel::Panic,
+            Some("fatal") => FfmpegLogLevel::Fatal,
+            Some("error") => FfmpegLogLevel::Error,
+            Some("warning") => FfmpegLogLevel::Warning,
+            Some("verbose") => FfmpegLogLevel::Verbose,
+            Some("debug") => FfmpegLogLevel::Debug,
+            Some("trace") => FfmpegLogLevel::Trace,
+            _ => FfmpegLogLevel::Info,
+        };
+        return (level, REGEX_LEVEL.replace(text, "").to_string());
+    }
+
+    let lowercase = text.to_lowercase();
+    let is_error = ["error", "failed", "invalid", "no such file"]
+        .iter()
+        .any(|word| lowercase.contains(word));
+    let level = if is_error {
+        FfmpegLogLevel::Error
+    } else if lowercase.contains("warning") {
+        FfmpegLogLevel::Warning
+    } else {
+        FfmpegLogLevel::Info
+    };
+    (level, text.to_string())
+}
+
+/// Like `BufRead::lines()`, but also splits on carriage returns (`\r`), which
+/// ffmpeg uses to overwrite its progress line in place. The returned lines
+/// don't include the terminator, and may be empty (e.g. between `\r\n`).
+pub fn split_lines_cr_lf<R: BufRead>(mut reader: R) -> impl Iterator<Item = io::Result<String>> {
+    std::iter::from_fn(move || {
+        let mut line = Vec::new();
+        loop {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if available.is_empty() {
+                // EOF
+                return if line.is_empty() {
+                    None
+                } else {
+                    Some(Ok(String::from_utf8_lossy(&line).into_owned()))
+                };
+            }
+            match available.iter().position(|&b| b == b'\n' || b == b'\r') {
+                Some(i) => {
+                    line.extend_from_slice(&available[..i]);
+                    reader.consume(i + 1);
+                    return Some(Ok(String::from_utf8_lossy(&line).into_owned()));
+                }
+                None => {
+                    line.extend_from_slice(available);
+                    let len = available.len();
+                    reader.consume(len);
+                }
+            }
+        }
+    })
+}
+
 /// prints as e.g. `"1:23:45.5"`
 pub fn format_seconds(seconds: f64) -> String {
     let mut time_left = seconds;
@@ -78,3 +342,239 @@ pub fn format_seconds(seconds: f64) -> String {
     }
     string
 }
+
+/// Evaluate an arithmetic expression in the style of ffmpeg filter options,
+/// e.g. `(in_w-200)/2`, with the given variables. Supports numbers, `+ - * /`,
+/// parentheses, and the functions `min(a, b)` and `max(a, b)`.
+pub fn eval_expression(expression: &str, variables: &[(&str, f64)]) -> Result<f64, String> {
+    let mut parser = ExpressionParser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        position: 0,
+        variables,
+    };
+    let value = parser.sum()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected `{}` in expression `{}`", c, expression)),
+    }
+}
+
+/// Recursive descent parser for `eval_expression`, evaluating as it goes.
+struct ExpressionParser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    variables: &'a [(&'a str, f64)],
+}
+
+impl ExpressionParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(c) => Err(format!("expected `{}`, found `{}`", expected, c)),
+            None => Err(format!(
+                "expected `{}` at the end of the expression",
+                expected
+            )),
+        }
+    }
+
+    /// Terms separated by `+` or `-`
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.position += 1;
+            let rhs = self.product()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    /// Factors separated by `*` or `/`
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.position += 1;
+            let rhs = self.factor()?;
+            value = if op == '*' { value * rhs } else { value / rhs };
+        }
+        Ok(value)
+    }
+
+    /// A number, variable, function call, parenthesized or negated expression
+    fn factor(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                Ok(-self.factor()?)
+            }
+            Some('(') => {
+                self.position += 1;
+                let value = self.sum()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.position += 1;
+                }
+                let number: String = self.chars[start..self.position].iter().collect();
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid number `{}`", number))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.position;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    self.position += 1;
+                }
+                let name: String = self.chars[start..self.position].iter().collect();
+                if self.peek() == Some('(') {
+                    self.position += 1;
+                    let a = self.sum()?;
+                    self.expect(',')?;
+                    let b = self.sum()?;
+                    self.expect(')')?;
+                    return match name.as_str() {
+                        "min" => Ok(a.min(b)),
+                        "max" => Ok(a.max(b)),
+                        _ => Err(format!("unknown function `{}`", name)),
+                    };
+                }
+                self.variables
+                    .iter()
+                    .find(|(variable, _)| *variable == name)
+                    .map(|(_, value)| *value)
+                    .ok_or_else(|| format!("unknown variable `{}`", name))
+            }
+            Some(c) => Err(format!("unexpected `{}`", c)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        eval_expression, is_http_url, parse_ffmpeg_drop_dup, parse_ffmpeg_duration,
+        parse_ffmpeg_log_level, parse_ffmpeg_progress_time, parse_ffmpeg_showinfo_pts_time,
+        parse_ffmpeg_stream_info, split_lines_cr_lf, FfmpegLogLevel, StreamInfo,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn expressions() {
+        let variables = [("in_w", 1920.0), ("in_h", 1080.0)];
+        let eval = |expression| eval_expression(expression, &variables);
+        assert_eq!(eval("(in_w-200)/2"), Ok(860.0));
+        assert_eq!(eval("in_h - 2 * 40"), Ok(1000.0));
+        assert_eq!(eval("-in_w/-4"), Ok(480.0));
+        assert_eq!(eval("min(in_w, 3 * in_h) / 10"), Ok(192.0));
+        assert_eq!(eval("max(0.5,0.25)"), Ok(0.5));
+        assert!(eval("in_w +").is_err());
+        assert!(eval("(in_w").is_err());
+        assert!(eval("out_w").is_err());
+        assert!(eval("in_w in_h").is_err());
+    }
+
+    #[test]
+    fn parse_duration() {
+        let line = "  Duration: 01:02:03.50, start: 0.000000, bitrate: 1234 kb/s";
+        assert_eq!(
+            parse_ffmpeg_duration(line),
+            Some(Duration::from_secs_f64(3723.5))
+        );
+        assert_eq!(parse_ffmpeg_duration("  Duration: N/A, bitrate: N/A"), None);
+    }
+
+    #[test]
+    fn parse_progress_time() {
+        let line = "frame=   20 fps=0.0 q=-0.0 size=N/A time=00:00:10.00 bitrate=N/A speed=29.6x";
+        assert_eq!(
+            parse_ffmpeg_progress_time(line),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn parse_stream_info() {
+        let line = "  Stream #0:0(und): Video: h264 (High) (avc1 / 0x31637661), yuv420p(tv, bt709, progressive), 1920x1080 [SAR 1:1 DAR 16:9], 5884 kb/s, 59.94 fps, 59.94 tbr, 60k tbn (default)";
+        assert_eq!(
+            parse_ffmpeg_stream_info(line),
+            Some(StreamInfo {
+                width: 1920,
+                height: 1080,
+                fps: Some(59.94),
+                codec: "h264".to_string(),
+            })
+        );
+        let audio = "  Stream #0:1(und): Audio: aac (LC), 48000 Hz, stereo, fltp, 128 kb/s";
+        assert_eq!(parse_ffmpeg_stream_info(audio), None);
+    }
+
+    #[test]
+    fn parse_log_level() {
+        let line = "[h264 @ 0x5581] [error] non-existing PPS 0 referenced";
+        assert_eq!(
+            parse_ffmpeg_log_level(line),
+            (
+                FfmpegLogLevel::Error,
+                "[h264 @ 0x5581] non-existing PPS 0 referenced".to_string()
+            )
+        );
+        let line = "[info] Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'input.mp4':";
+        assert_eq!(parse_ffmpeg_log_level(line).0, FfmpegLogLevel::Info);
+        assert!(parse_ffmpeg_log_level(line).1.starts_with("Input #"));
+
+        // Untagged
+        let line = "input.mp4: No such file or directory";
+        assert_eq!(parse_ffmpeg_log_level(line).0, FfmpegLogLevel::Error);
+        let line = "Press [q] to stop, [?] for help";
+        assert_eq!(parse_ffmpeg_log_level(line).0, FfmpegLogLevel::Info);
+    }
+
+    #[test]
+    fn parse_drop_dup() {
+        let line = "frame=  120 fps= 60 q=-0.0 size=  81000kB time=00:00:04.00 bitrate=165888.0kbits/s dup=2 drop=5 speed=1.99x";
+        assert_eq!(parse_ffmpeg_drop_dup(line), Some((5, 2)));
+        let line = "frame=   20 fps=0.0 q=-0.0 size=   54000kB time=00:00:10.00 bitrate=44236.8kbits/s speed=29.6x";
+        assert_eq!(parse_ffmpeg_drop_dup(line), None);
+        assert_eq!(parse_ffmpeg_drop_dup("  Metadata: drop=1"), None);
+    }
+
+    #[test]
+    fn parse_showinfo() {
+        let line = "[Parsed_showinfo_2 @ 0x55d5c7d0] n:   1 pts:  15360 pts_time:1.2     duration:  512 duration_time:0.04 fmt:rgb24";
+        assert_eq!(parse_ffmpeg_showinfo_pts_time(line), Some((1, 1.2)));
+        let line =
+            "[Parsed_showinfo_2 @ 0x55d5c7d0] config in time_base: 1/12800, frame_rate: 30/1";
+        assert_eq!(parse_ffmpeg_showinfo_pts_time(line), None);
+        let line = "frame=   20 fps=0.0 q=-0.0 size=   54000kB time=00:00:10.00";
+        assert_eq!(parse_ffmpeg_showinfo_pts_time(line), None);
+    }
+
+    #[test]
+    fn urls() {
+        assert!(is_http_url("https://example.com/playlist.m3u8"));
+        assert!(!is_http_url("C:\\Videos\\match.mp4"));
+        assert!(is_http_url("HTTPS://example.com/playlist.m3u8"));
+        assert!(!is_http_url("rtmp://live.twitch.tv/app/stream"));
+    }
+
+    #[test]
+    fn split_lines() {
+        let input = "a\r\nb\rc\nd".as_bytes();
+        let lines: Vec<String> = split_lines_cr_lf(input).map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["a", "", "b", "c", "d"]);
+    }
+}